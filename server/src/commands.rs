@@ -0,0 +1,646 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{ExecuteCommandParams, TextEdit};
+
+use crate::format::{Formatter, format_changed_lines, minimal_edits, reindent};
+use crate::{DelphiLsp, lsp_error};
+use crate::lsp_types::*;
+use crate::projects::*;
+use crate::files::dproj::{get_project_guid, get_project_version, get_version_info, product_version_of};
+use crate::state::{COMPILER_CONFIGURATIONS, PROJECTS_DATA, SERVER_SETTINGS};
+use crate::utils::rank_matches;
+
+pub const BUILD_HISTORY: &str = "ddk.buildHistory";
+pub const RESOLVE_UNIT: &str = "ddk.resolveUnit";
+pub const FORMAT_CHANGED_LINES: &str = "ddk.formatChangedLines";
+pub const PREVIEW_FORMAT: &str = "ddk.previewFormat";
+pub const DETECT_PROJECT_COMPILER: &str = "ddk.detectProjectCompiler";
+pub const LIST_FORMATTER_PROFILES: &str = "ddk.listFormatterProfiles";
+pub const LAST_BUILD_DIAGNOSTICS: &str = "ddk.lastBuildDiagnostics";
+pub const LINT_PROJECT: &str = "ddk.lintProject";
+pub const SEARCH_PROJECTS: &str = "ddk.searchProjects";
+pub const REINDENT: &str = "ddk.reindent";
+pub const RUN_TESTS: &str = "ddk.runTests";
+pub const ALL_DIAGNOSTICS: &str = "ddk.allDiagnostics";
+pub const SKIP_CURRENT_PROJECT: &str = "ddk.skipCurrentProject";
+pub const AUDIT_PATHS: &str = "ddk.auditPaths";
+pub const CLEAR_DIAGNOSTICS: &str = "ddk.clearDiagnostics";
+pub const GET_BUILD_MATRIX: &str = "ddk.getBuildMatrix";
+pub const CHANGED_SINCE_LAST_BUILD: &str = "ddk.changedSinceLastBuild";
+pub const CAN_MOVE_PROJECT: &str = "ddk.canMoveProject";
+pub const COMPILE: &str = "ddk.compile";
+pub const REBUILD: &str = "ddk.rebuild";
+pub const CANCEL_COMPILE: &str = "ddk.cancelCompile";
+pub const FORMAT: &str = "ddk.format";
+pub const DISCOVER_PROJECTS: &str = "ddk.discoverProjects";
+pub const CANCEL_DISCOVERY: &str = "ddk.cancelDiscovery";
+pub const COMPILE_AND_REVEAL: &str = "ddk.compileAndReveal";
+pub const GET_WORKSPACE_PROJECTS: &str = "ddk.getWorkspaceProjects";
+pub const EXPORT_DIAGNOSTICS_SARIF: &str = "ddk.exportDiagnosticsSarif";
+pub const GET_PROJECT_VERSION_INFO: &str = "ddk.getProjectVersionInfo";
+pub const FIND_ORPHANED_LINKS: &str = "ddk.findOrphanedLinks";
+pub const CLEAN_PROJECT: &str = "ddk.cleanProject";
+
+pub const COMMANDS: &[&str] = &[
+    BUILD_HISTORY,
+    RESOLVE_UNIT,
+    FORMAT_CHANGED_LINES,
+    PREVIEW_FORMAT,
+    DETECT_PROJECT_COMPILER,
+    LIST_FORMATTER_PROFILES,
+    LAST_BUILD_DIAGNOSTICS,
+    LINT_PROJECT,
+    SEARCH_PROJECTS,
+    REINDENT,
+    RUN_TESTS,
+    ALL_DIAGNOSTICS,
+    SKIP_CURRENT_PROJECT,
+    AUDIT_PATHS,
+    CLEAR_DIAGNOSTICS,
+    GET_BUILD_MATRIX,
+    CHANGED_SINCE_LAST_BUILD,
+    CAN_MOVE_PROJECT,
+    COMPILE,
+    REBUILD,
+    CANCEL_COMPILE,
+    FORMAT,
+    DISCOVER_PROJECTS,
+    CANCEL_DISCOVERY,
+    COMPILE_AND_REVEAL,
+    GET_WORKSPACE_PROJECTS,
+    EXPORT_DIAGNOSTICS_SARIF,
+    GET_PROJECT_VERSION_INFO,
+    FIND_ORPHANED_LINKS,
+    CLEAN_PROJECT,
+];
+
+fn arguments<T: DeserializeOwned>(arguments: Vec<Value>) -> jsonrpc::Result<T> {
+    let value = arguments
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    return serde_json::from_value(value).map_err(|error| {
+        jsonrpc::Error::invalid_params(format!("Invalid command arguments: {}", error))
+    });
+}
+
+fn result<T: serde::Serialize>(value: T) -> jsonrpc::Result<Option<Value>> {
+    return serde_json::to_value(value)
+        .map(Some)
+        .map_err(|error| jsonrpc::Error::invalid_params(format!("Failed to serialize result: {}", error)));
+}
+
+/// The build's diagnostics with paths relative to the configured `diagnostic_base_path`.
+async fn reported_diagnostics(outcome: &BuildOutcome) -> BTreeMap<String, Vec<CompilerLineDiagnostic>> {
+    let base_path = SERVER_SETTINGS.read().await.diagnostic_base_path();
+    return relative_diagnostics(outcome.diagnostics_by_file(), base_path.as_deref());
+}
+
+impl DelphiLsp {
+    pub(crate) async fn execute_ddk_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> jsonrpc::Result<Option<Value>> {
+        match params.command.as_str() {
+            BUILD_HISTORY => {
+                let params: BuildHistoryParams = arguments(params.arguments)?;
+                return result(self.build_history(params).await);
+            }
+            RESOLVE_UNIT => {
+                let params: ResolveUnitParams = arguments(params.arguments)?;
+                return result(self.resolve_unit(params).await?);
+            }
+            FORMAT_CHANGED_LINES => {
+                let params: FormatChangedLinesParams = arguments(params.arguments)?;
+                return result(self.format_changed_lines(params).await?);
+            }
+            PREVIEW_FORMAT => {
+                let params: PreviewFormatParams = arguments(params.arguments)?;
+                return result(self.preview_format(params).await?);
+            }
+            DETECT_PROJECT_COMPILER => {
+                let params: DetectProjectCompilerParams = arguments(params.arguments)?;
+                return result(self.detect_project_compiler(params).await?);
+            }
+            LIST_FORMATTER_PROFILES => {
+                return result(self.list_formatter_profiles().await?);
+            }
+            LAST_BUILD_DIAGNOSTICS => {
+                let base_path = SERVER_SETTINGS.read().await.diagnostic_base_path();
+                return result(relative_diagnostics(last_build_diagnostics(), base_path.as_deref()));
+            }
+            LINT_PROJECT => {
+                let params: LintProjectParams = arguments(params.arguments)?;
+                return result(self.lint_project(params).await?);
+            }
+            SEARCH_PROJECTS => {
+                let params: SearchProjectsParams = arguments(params.arguments)?;
+                return result(self.search_projects(params).await);
+            }
+            REINDENT => {
+                let params: ReindentParams = arguments(params.arguments)?;
+                return result(self.reindent(params).await?);
+            }
+            RUN_TESTS => {
+                let params: RunTestsParams = arguments(params.arguments)?;
+                return result(self.run_tests(params).await?);
+            }
+            ALL_DIAGNOSTICS => {
+                return result(all_diagnostics());
+            }
+            SKIP_CURRENT_PROJECT => {
+                return result(skip_current_project());
+            }
+            AUDIT_PATHS => {
+                return result(self.audit_paths().await);
+            }
+            CLEAR_DIAGNOSTICS => {
+                let params: ClearDiagnosticsParams = arguments(params.arguments)?;
+                self.clear_diagnostics(params).await;
+                return Ok(None);
+            }
+            GET_BUILD_MATRIX => {
+                let params: GetBuildMatrixParams = arguments(params.arguments)?;
+                return result(self.get_build_matrix(params).await?);
+            }
+            CHANGED_SINCE_LAST_BUILD => {
+                let params: ChangedSinceLastBuildParams = arguments(params.arguments)?;
+                return result(self.changed_since_last_build(params).await?);
+            }
+            CAN_MOVE_PROJECT => {
+                let params: CanMoveProjectParams = arguments(params.arguments)?;
+                return result(self.can_move_project(params).await);
+            }
+            COMPILE => {
+                let params: CompileProjectParams = arguments(params.arguments)?;
+                return result(self.start_compilation(params));
+            }
+            REBUILD => {
+                let mut params: CompileProjectParams = arguments(params.arguments)?;
+                params.set_rebuild(true);
+                return result(self.start_compilation(params));
+            }
+            CANCEL_COMPILE => {
+                cancel_compilations();
+                return result(CommandAcceptance::accepted());
+            }
+            FORMAT => {
+                let params: CustomDocumentFormat = arguments(params.arguments)?;
+                return result(self.custom_document_format(params).await?);
+            }
+            DISCOVER_PROJECTS => {
+                let params: DiscoverProjectsParams = arguments(params.arguments)?;
+                return result(self.discover_projects(params).await?);
+            }
+            CANCEL_DISCOVERY => {
+                cancel_discovery();
+                return Ok(None);
+            }
+            COMPILE_AND_REVEAL => {
+                let params: CompileAndRevealParams = arguments(params.arguments)?;
+                return result(self.compile_and_reveal(params).await?);
+            }
+            GET_WORKSPACE_PROJECTS => {
+                let params: GetWorkspaceProjectsParams = arguments(params.arguments)?;
+                return result(self.get_workspace_projects(params).await?);
+            }
+            EXPORT_DIAGNOSTICS_SARIF => {
+                let params: ExportDiagnosticsSarifParams = arguments(params.arguments)?;
+                return Ok(Some(self.export_diagnostics_sarif(params).await?));
+            }
+            GET_PROJECT_VERSION_INFO => {
+                let params: GetProjectVersionInfoParams = arguments(params.arguments)?;
+                return result(self.get_project_version_info(params).await?);
+            }
+            FIND_ORPHANED_LINKS => {
+                return result(self.find_orphaned_links().await);
+            }
+            CLEAN_PROJECT => {
+                let params: CleanProjectParams = arguments(params.arguments)?;
+                return result(self.clean_project(params).await?);
+            }
+            command => Err(jsonrpc::Error::invalid_params(format!("Unknown command: {}", command))),
+        }
+    }
+
+    async fn build_history(&self, params: BuildHistoryParams) -> Vec<BuildHistoryEntry> {
+        return BuildHistory::new().recent(params.limit);
+    }
+
+    async fn resolve_unit(&self, params: ResolveUnitParams) -> jsonrpc::Result<Option<String>> {
+        let projects_data = PROJECTS_DATA.read().await;
+        let project = projects_data.get_project(params.project_id).ok_or_else(|| {
+            jsonrpc::Error::invalid_params(format!("Project with id {} not found", params.project_id))
+        })?;
+        let compiler = projects_data.compiler_for_project(project.id).await;
+        return Ok(project
+            .resolve_unit(&params.unit, &compiler)
+            .map(|path| path.to_string_lossy().to_string()));
+    }
+
+    async fn format_changed_lines(&self, params: FormatChangedLinesParams) -> jsonrpc::Result<Vec<TextEdit>> {
+        let path = params.uri.to_file_path().map_err(|_| {
+            jsonrpc::Error::invalid_params(format!("Not a file uri: {}", params.uri))
+        })?;
+        return format_changed_lines(&path, params.profile.as_deref()).await.map_err(|error| {
+            lsp_error!(self.client, "Failed to format changed lines: {}", error);
+            jsonrpc::Error::invalid_params(format!("Failed to format changed lines: {}", error))
+        });
+    }
+
+    async fn preview_format(&self, params: PreviewFormatParams) -> jsonrpc::Result<PreviewFormatResponse> {
+        let content = match params.content {
+            Some(content) => content,
+            None => {
+                let path = params.uri.to_file_path().map_err(|_| {
+                    jsonrpc::Error::invalid_params(format!("Not a file uri: {}", params.uri))
+                })?;
+                std::fs::read_to_string(&path).map_err(|error| {
+                    jsonrpc::Error::invalid_params(format!("Failed to read {}: {}", path.display(), error))
+                })?
+            }
+        };
+        let formatter = Formatter::new(content.clone(), params.profile.as_deref()).map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to initialize formatter: {}", error))
+        })?;
+        let formatted = formatter.execute().await.map_err(|error| {
+            lsp_error!(self.client, "Failed to format document: {}", error);
+            jsonrpc::Error::invalid_params(format!("Failed to format document: {}", error))
+        })?;
+        let edits = minimal_edits(&content, &formatted);
+        return Ok(PreviewFormatResponse { formatted, edits });
+    }
+
+    async fn detect_project_compiler(
+        &self,
+        params: DetectProjectCompilerParams,
+    ) -> jsonrpc::Result<DetectProjectCompilerResponse> {
+        let dproj = PathBuf::from(&params.dproj_path);
+        let read_error = |error: anyhow::Error| {
+            jsonrpc::Error::invalid_params(format!("Failed to read {}: {}", params.dproj_path, error))
+        };
+        let project_version = get_project_version(&dproj).map_err(read_error)?;
+        let project_guid = get_project_guid(&dproj).map_err(read_error)?;
+        let product_version = project_version.as_deref().and_then(product_version_of);
+        let compiler = match product_version {
+            Some(product_version) => COMPILER_CONFIGURATIONS.read().await.find_by_product_version(product_version),
+            None => None,
+        };
+        return Ok(DetectProjectCompilerResponse {
+            project_version,
+            project_guid,
+            product_version,
+            compiler,
+        });
+    }
+
+    async fn list_formatter_profiles(&self) -> jsonrpc::Result<Vec<String>> {
+        return Formatter::list_profiles().map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to list formatter profiles: {}", error))
+        });
+    }
+
+    /// Builds a project for its diagnostics only, leaving the editor's diagnostics untouched.
+    async fn lint_project(&self, params: LintProjectParams) -> jsonrpc::Result<LintProjectResponse> {
+        let outcome = self.lint(params.project_id).await?;
+        return Ok(LintProjectResponse {
+            success: outcome.success,
+            diagnostics: reported_diagnostics(&outcome).await,
+        });
+    }
+
+    async fn lint(&self, project_id: usize) -> jsonrpc::Result<BuildOutcome> {
+        let compile_params = CompileProjectParams::project(project_id);
+        return BuildRequest::new(self.client.clone(), &compile_params)
+            .without_publishing()
+            .run()
+            .await
+            .map_err(|error| {
+                jsonrpc::Error::invalid_params(format!("Failed to lint project: {}", error))
+            });
+    }
+
+    async fn search_projects(&self, params: SearchProjectsParams) -> Vec<ProjectSearchResult> {
+        let projects_data = PROJECTS_DATA.read().await;
+        return rank_matches(&params.query, projects_data.projects.iter(), |project| &project.name, params.mode)
+            .into_iter()
+            .map(|(project, score)| ProjectSearchResult {
+                project_id: project.id,
+                name: project.name.clone(),
+                dproj: project.dproj.clone(),
+                score,
+            })
+            .collect();
+    }
+
+    async fn reindent(&self, params: ReindentParams) -> jsonrpc::Result<Vec<TextEdit>> {
+        let content = match params.content {
+            Some(content) => content,
+            None => {
+                let path = params.uri.to_file_path().map_err(|_| {
+                    jsonrpc::Error::invalid_params(format!("Not a file uri: {}", params.uri))
+                })?;
+                std::fs::read_to_string(&path).map_err(|error| {
+                    jsonrpc::Error::invalid_params(format!("Failed to read {}: {}", path.display(), error))
+                })?
+            }
+        };
+        let indent_width = match params.indent_width {
+            Some(indent_width) => indent_width,
+            None => SERVER_SETTINGS.read().await.indent_width,
+        };
+        return Ok(minimal_edits(&content, &reindent(&content, indent_width)));
+    }
+
+    async fn run_tests(&self, params: RunTestsParams) -> jsonrpc::Result<TestRun> {
+        return run_tests(&self.client, params.project_id).await.map_err(|error| {
+            lsp_error!(self.client, "Failed to run tests: {}", error);
+            jsonrpc::Error::invalid_params(format!("Failed to run tests: {}", error))
+        });
+    }
+
+    async fn audit_paths(&self) -> PathAudit {
+        let projects_data = PROJECTS_DATA.read().await;
+        let compilers = COMPILER_CONFIGURATIONS.read().await;
+        return audit_paths(&projects_data, &compilers);
+    }
+
+    async fn clear_diagnostics(&self, params: ClearDiagnosticsParams) {
+        match params.uri {
+            Some(uri) => clear_file_diagnostics(&self.client, uri).await,
+            None => clear_published_diagnostics(&self.client).await,
+        }
+    }
+
+    async fn get_build_matrix(&self, params: GetBuildMatrixParams) -> jsonrpc::Result<Vec<(String, String)>> {
+        let projects_data = PROJECTS_DATA.read().await;
+        let project = projects_data.get_project(params.project_id).ok_or_else(|| {
+            jsonrpc::Error::invalid_params(format!("Project with id {} not found", params.project_id))
+        })?;
+        return project.build_matrix().map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to get build matrix: {}", error))
+        });
+    }
+
+    async fn changed_since_last_build(&self, params: ChangedSinceLastBuildParams) -> jsonrpc::Result<ChangedSinceLastBuild> {
+        let projects_data = PROJECTS_DATA.read().await;
+        let project = projects_data.get_project(params.project_id).ok_or_else(|| {
+            jsonrpc::Error::invalid_params(format!("Project with id {} not found", params.project_id))
+        })?;
+        return BuildCache::changed_since_last_build(project).map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to compare against the last build: {}", error))
+        });
+    }
+
+    async fn can_move_project(&self, params: CanMoveProjectParams) -> CanMoveProjectResponse {
+        let projects_data = PROJECTS_DATA.read().await;
+        return match projects_data.can_move_project_link(params.project_link_id, params.drop_target) {
+            Ok(()) => CanMoveProjectResponse { allowed: true, reason: None },
+            Err(error) => CanMoveProjectResponse { allowed: false, reason: Some(error.to_string()) },
+        };
+    }
+
+    /// Checks what can be checked up front and runs the compilation in the background;
+    /// progress and errors are reported through the usual notifications.
+    fn start_compilation(&self, params: CompileProjectParams) -> CommandAcceptance {
+        if let Some(platform) = params.platform() && let Err(error) = validate_platform(platform) {
+            return CommandAcceptance::rejected(error.to_string());
+        }
+        if params.targets().is_some_and(|targets| targets.iter().all(|target| target.trim().is_empty())) {
+            return CommandAcceptance::rejected("The list of MSBuild targets must not be empty.".to_string());
+        }
+        let lsp = self.clone();
+        tokio::spawn(async move {
+            let _ = lsp.projects_compile(params).await;
+        });
+        return CommandAcceptance::accepted();
+    }
+
+    async fn discover_projects(&self, params: DiscoverProjectsParams) -> jsonrpc::Result<DiscoveryResult> {
+        return discover_projects(&self.client, &PathBuf::from(&params.directory), params.workspace_id)
+            .await
+            .map_err(|error| {
+                lsp_error!(self.client, "Failed to discover projects: {}", error);
+                jsonrpc::Error::invalid_params(format!("Failed to discover projects: {}", error))
+            });
+    }
+
+    async fn compile_and_reveal(&self, params: CompileAndRevealParams) -> jsonrpc::Result<CompileAndRevealResponse> {
+        let compile_params = CompileProjectParams::project(params.project_id);
+        let outcome = BuildRequest::new(self.client.clone(), &compile_params)
+            .run()
+            .await
+            .map_err(|error| {
+                jsonrpc::Error::invalid_params(format!("Failed to compile project: {}", error))
+            })?;
+        if !outcome.success {
+            return Ok(CompileAndRevealResponse {
+                success: false,
+                output_dir: None,
+                diagnostics: reported_diagnostics(&outcome).await,
+            });
+        }
+        let output_dir = PROJECTS_DATA
+            .read()
+            .await
+            .get_project(params.project_id)
+            .and_then(|project| project.output_directory())
+            .map(|dir| dir.to_string_lossy().to_string());
+        return Ok(CompileAndRevealResponse {
+            success: true,
+            output_dir,
+            diagnostics: BTreeMap::new(),
+        });
+    }
+
+    async fn get_workspace_projects(&self, params: GetWorkspaceProjectsParams) -> jsonrpc::Result<Vec<WorkspaceProject>> {
+        let projects_data = PROJECTS_DATA.read().await;
+        let projects = projects_data.workspace_projects(params.workspace_id).map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to get workspace projects: {}", error))
+        })?;
+        return Ok(projects
+            .into_iter()
+            .map(|(link, project)| WorkspaceProject {
+                project_link_id: link.id,
+                project: project.clone(),
+                last_build: last_project_result(project.id),
+            })
+            .collect());
+    }
+
+    async fn export_diagnostics_sarif(&self, params: ExportDiagnosticsSarifParams) -> jsonrpc::Result<Value> {
+        let outcome = self.lint(params.project_id).await?;
+        let directory = match PROJECTS_DATA.read().await.get_project(params.project_id) {
+            Some(project) => PathBuf::from(&project.directory),
+            None => return Err(jsonrpc::Error::invalid_params(format!("Project not found: {}", params.project_id))),
+        };
+        let base_path = SERVER_SETTINGS.read().await.diagnostic_base_path();
+        return Ok(diagnostics_to_sarif(&outcome.diagnostics_by_file(), &directory, base_path.as_deref()));
+    }
+
+    async fn get_project_version_info(&self, params: GetProjectVersionInfoParams) -> jsonrpc::Result<BTreeMap<String, String>> {
+        let dproj = match PROJECTS_DATA.read().await.get_project(params.project_id) {
+            Some(project) => project.dproj.clone(),
+            None => return Err(jsonrpc::Error::invalid_params(format!("Project not found: {}", params.project_id))),
+        };
+        let Some(dproj) = dproj else {
+            return Ok(BTreeMap::new());
+        };
+        return get_version_info(&PathBuf::from(&dproj)).map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to read version info from {}: {}", dproj, error))
+        });
+    }
+
+    async fn find_orphaned_links(&self) -> Vec<usize> {
+        return PROJECTS_DATA.read().await.orphaned_links();
+    }
+
+    async fn clean_project(&self, params: CleanProjectParams) -> jsonrpc::Result<CleanProjectResponse> {
+        let project = match PROJECTS_DATA.read().await.get_project(params.project_id) {
+            Some(project) => project.clone(),
+            None => return Err(jsonrpc::Error::invalid_params(format!("Project not found: {}", params.project_id))),
+        };
+        let directory = PathBuf::from(&project.directory);
+        let mut compile_params = CompileProjectParams::project(params.project_id);
+        compile_params.set_targets(Some(vec!["Clean".to_string()]));
+        let outcome = BuildRequest::new(self.client.clone(), &compile_params)
+            .without_publishing()
+            .run()
+            .await
+            .map_err(|error| {
+                jsonrpc::Error::invalid_params(format!("Failed to clean project: {}", error))
+            })?;
+        // with its outputs gone the project must not count as up to date anymore
+        let _ = BuildCache::forget(&project);
+        // leftovers are removed even if msbuild failed, e.g. because the compiler is missing
+        let mut settings = project.clean.clone().unwrap_or_default();
+        if let Some(extensions) = params.extensions {
+            settings.extensions = extensions;
+        }
+        if let Some(folders) = params.folders {
+            settings.folders = folders;
+        }
+        let report = clean_directory(&directory, &project.output_directories(), &settings).map_err(|error| {
+            jsonrpc::Error::invalid_params(format!("Failed to delete build leftovers in {}: {}", directory.display(), error))
+        })?;
+        let display = |path: PathBuf| path.to_string_lossy().to_string();
+        return Ok(CleanProjectResponse {
+            success: outcome.success,
+            deleted: report.deleted.into_iter().map(display).collect(),
+            failed: report.failed.into_iter().map(|(path, error)| (display(path), error)).collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{test_client, test_session};
+    use tower_lsp::LspService;
+    use tower_lsp::lsp_types::Url;
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn an_already_formatted_file_previews_no_edits() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        crate::state::testing::stub_formatter(directory.path(), "exit 0").await;
+        let unit = directory.path().join("Unit1.pas");
+        std::fs::write(&unit, "unit Unit1;\n\ninterface\n\nimplementation\n\nend.\n").unwrap();
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+
+        let params = PreviewFormatParams { uri: Url::from_file_path(&unit).unwrap(), content: None, profile: None };
+        let preview = service.inner().preview_format(params).await.unwrap();
+
+        assert_eq!(preview.formatted, std::fs::read_to_string(&unit).unwrap());
+        assert!(preview.edits.is_empty());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn only_a_successful_build_reveals_the_output_directory() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        crate::state::testing::stub_msbuild(directory.path(), "case \"$1\" in *Broken*) echo \"Broken.dpr(2,1): error E2003: Undeclared identifier: 'X'\"; exit 1;; esac").await;
+        let (_, project_ids) = crate::state::testing::workspace_with_projects(directory.path(), &["App", "Broken"]).await;
+        let output_dir = directory.path().join("Win32").join("Debug");
+        PROJECTS_DATA.write().await.get_project_mut(project_ids[0]).unwrap().exe =
+            Some(output_dir.join("App.exe").to_string_lossy().to_string());
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+
+        let built = service.inner().compile_and_reveal(CompileAndRevealParams { project_id: project_ids[0] }).await.unwrap();
+        assert!(built.success);
+        assert_eq!(built.output_dir, Some(output_dir.to_string_lossy().to_string()));
+        assert!(built.diagnostics.is_empty());
+
+        let failed = service.inner().compile_and_reveal(CompileAndRevealParams { project_id: project_ids[1] }).await.unwrap();
+        assert!(!failed.success);
+        assert_eq!(failed.output_dir, None);
+        let codes: Vec<&str> = failed.diagnostics.values().flatten().map(|diagnostic| diagnostic.code.as_str()).collect();
+        assert_eq!(codes, vec!["E2003"]);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn workspace_projects_come_in_rank_order() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        crate::state::testing::stub_msbuild(directory.path(), "exit 0").await;
+        let (workspace_id, project_ids) = crate::state::testing::workspace_with_projects(directory.path(), &["A", "B", "C"]).await;
+        BuildRequest::new(test_client(), &CompileProjectParams::project(project_ids[1])).without_publishing().run().await.unwrap();
+        {
+            let mut projects_data = PROJECTS_DATA.write().await;
+            // new links are ranked when the data is committed
+            projects_data.repair_ranks();
+            // stored out of order, e.g. by a client snapshot that was never sorted
+            projects_data.get_workspace_mut(workspace_id).unwrap().project_links.reverse();
+        }
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+
+        let projects = service.inner().get_workspace_projects(GetWorkspaceProjectsParams { workspace_id }).await.unwrap();
+
+        let resolved: Vec<(usize, &str, Option<String>)> = projects
+            .iter()
+            .map(|entry| (entry.project.id, entry.project.name.as_str(), entry.project.dpr.clone()))
+            .collect();
+        let expected: Vec<(usize, &str, Option<String>)> = project_ids
+            .iter()
+            .zip(["A", "B", "C"])
+            .map(|(id, name)| (*id, name, Some(directory.path().join(format!("{name}.dpr")).to_string_lossy().to_string())))
+            .collect();
+        assert_eq!(resolved, expected);
+        let last_build = projects[1].last_build.as_ref().unwrap();
+        assert_eq!((last_build.project_id, last_build.success), (project_ids[1], true));
+
+        let error = service.inner().get_workspace_projects(GetWorkspaceProjectsParams { workspace_id: 12345 }).await.unwrap_err();
+        assert_eq!(error.message, "Failed to get workspace projects: Workspace not found: 12345");
+    }
+
+    #[tokio::test]
+    async fn the_project_version_picks_the_compiler_that_wrote_it() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = directory.path().join("App.dproj");
+        std::fs::write(&dproj, r#"<Project xmlns="http://schemas.microsoft.com/developer/msbuild/2003">
+    <PropertyGroup>
+        <ProjectGuid>{6A1E3B2C-0F4D-4B8E-9C71-2D5F8A3E7B10}</ProjectGuid>
+        <ProjectVersion>20.1</ProjectVersion>
+        <MainSource>App.dpr</MainSource>
+    </PropertyGroup>
+</Project>"#).unwrap();
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+
+        let params = DetectProjectCompilerParams { dproj_path: dproj.to_string_lossy().to_string() };
+        let detected = service.inner().detect_project_compiler(params).await.unwrap();
+
+        assert_eq!(detected.project_version.as_deref(), Some("20.1"));
+        assert_eq!(detected.project_guid.as_deref(), Some("{6A1E3B2C-0F4D-4B8E-9C71-2D5F8A3E7B10}"));
+        assert_eq!(detected.product_version, Some(23));
+        assert_eq!(detected.compiler.as_deref(), Some("12.0"));
+        assert_eq!(product_version_of("12.0"), Some(6));
+        assert_eq!(product_version_of("99.9"), None);
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// Names of the packages listed in the `requires` clause of a `.dpk` file.
+pub fn get_required_packages(dpk_path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(dpk_path)?;
+    return Ok(parse_requires(&strip_comments(&content)));
+}
+
+/// Removes `//`, `{ }` and `(* *)` comments, which includes `{$IFDEF}` style directives.
+fn strip_comments(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+                result.push(' ');
+            }
+            '(' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == ')' {
+                        break;
+                    }
+                    last = c;
+                }
+                result.push(' ');
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        result.push('\n');
+                        break;
+                    }
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    return result;
+}
+
+fn parse_requires(content: &str) -> Vec<String> {
+    let lowercase = content.to_lowercase();
+    let Some(start) = lowercase
+        .match_indices("requires")
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let before = lowercase[..index].chars().next_back();
+            let after = lowercase[index + "requires".len()..].chars().next();
+            let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+            !is_word(before) && !is_word(after)
+        })
+    else {
+        return Vec::new();
+    };
+    let clause = &content[start + "requires".len()..];
+    let clause = &clause[..clause.find(';').unwrap_or(clause.len())];
+    return clause
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_directives_are_blanked_out() {
+        let content = "package A; // requires B\n{$R *.res}\n(* requires\n C; *)\nend.";
+        assert_eq!(strip_comments(content), "package A; \n \n \nend.");
+    }
+
+    #[test]
+    fn the_requires_clause_lists_the_packages_up_to_its_semicolon() {
+        let content = "package B;\n\
+            {$DEFINE RequiresVcl}\n\
+            Requires\n  rtl,\n  vcl, // the VCL\n  {$IFDEF DEBUG} (* design time *) {$ENDIF}\n  A;\n\
+            contains\n  B.Main in 'B.Main.pas';\nend.";
+        assert_eq!(parse_requires(&strip_comments(content)), vec!["rtl", "vcl", "A"]);
+    }
+
+    #[test]
+    fn requires_only_counts_as_a_whole_word() {
+        assert_eq!(parse_requires("package A;\ncontains RequiresHelper in 'RequiresHelper.pas';\nend."), Vec::<String>::new());
+        assert_eq!(parse_requires("package A;\nrequires_x := 1;\nrequires rtl;\nend."), vec!["rtl"]);
+    }
+}
@@ -1,6 +1,14 @@
 use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
+use crate::utils::expand_macros;
+
+lazy_static::lazy_static! {
+    static ref CONFIG_CONDITION: regex::Regex = regex::Regex::new(r"'\$\(Config\)'\s*==\s*'([^']+)'").unwrap();
+    static ref PLATFORM_CONDITION: regex::Regex = regex::Regex::new(r"'\$\(Platform\)'\s*==\s*'([^']+)'").unwrap();
+}
+
 pub fn get_main_source(dproj_path: &PathBuf) -> Result<PathBuf> {
     let content = std::fs::read_to_string(dproj_path)?;
     let parent_directory = dproj_path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?;
@@ -60,4 +68,352 @@ pub fn find_dproj_file(main_file_path: &PathBuf) -> Result<PathBuf> {
     } else {
         anyhow::bail!("DPROJ file not found for main file: {}", main_file_path.display());
     }
-}
\ No newline at end of file
+}
+
+pub fn get_unit_search_paths(dproj_path: &PathBuf, variables: &HashMap<&str, String>) -> Result<Vec<PathBuf>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let parent_directory = dproj_path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    let mut search_paths = Vec::new();
+    for search_path_node in xml_content.descendants().filter(|n| n.has_tag_name("DCC_UnitSearchPath")) {
+        if let Some(paths) = search_path_node.text() {
+            for path in paths.split(';').map(str::trim) {
+                if path.is_empty() || path.eq_ignore_ascii_case("$(DCC_UnitSearchPath)") {
+                    continue;
+                }
+                let search_path = parent_directory.join(expand_macros(path, variables));
+                if !search_paths.contains(&search_path) {
+                    search_paths.push(search_path);
+                }
+            }
+        }
+    }
+    return Ok(search_paths);
+}
+
+/// Directories the compiler writes the project's executable, units and packages to. Settings
+/// whose macros `variables` can't resolve are skipped; without a unit output directory the
+/// units are written next to the `.dproj`, which is then included.
+pub fn get_output_directories(dproj_path: &PathBuf, variables: &HashMap<&str, String>) -> Result<Vec<PathBuf>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let parent_directory = dproj_path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory"))?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    let mut directories = Vec::new();
+    let mut has_unit_output = false;
+    for output_node in xml_content.descendants().filter(|n| {
+        ["DCC_ExeOutput", "DCC_DcuOutput", "DCC_BplOutput", "DCC_DcpOutput"]
+            .iter()
+            .any(|tag| n.has_tag_name(*tag))
+    }) {
+        has_unit_output |= output_node.has_tag_name("DCC_DcuOutput");
+        let Some(path) = output_node.text().map(str::trim).filter(|path| !path.is_empty()) else {
+            continue;
+        };
+        let path = expand_macros(path, variables);
+        if path.contains("$(") {
+            continue;
+        }
+        let directory = parent_directory.join(path);
+        if !directories.contains(&directory) {
+            directories.push(directory);
+        }
+    }
+    if !has_unit_output {
+        directories.push(parent_directory.to_path_buf());
+    }
+    return Ok(directories);
+}
+
+/// Names of the build configurations (`<BuildConfiguration Include="Debug">`) declared by
+/// the project, without the implicit `Base` configuration.
+pub fn get_build_configurations(dproj_path: &PathBuf) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    return Ok(xml_content
+        .descendants()
+        .filter(|n| n.has_tag_name("BuildConfiguration"))
+        .filter_map(|n| n.attribute("Include"))
+        .filter(|name| !name.eq_ignore_ascii_case("Base"))
+        .map(str::to_string)
+        .collect());
+}
+
+/// Platforms enabled in the IDE project options (`<Platform value="Win64">True</Platform>`),
+/// falling back to `Win32` for projects that don't list any.
+pub fn get_enabled_platforms(dproj_path: &PathBuf) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    let enabled = xml_content
+        .descendants()
+        .filter(|n| n.has_tag_name("Platforms"))
+        .flat_map(|n| n.children().filter(|child| child.has_tag_name("Platform")))
+        .filter(|n| n.text().is_some_and(|text| text.trim().eq_ignore_ascii_case("true")))
+        .filter_map(|n| n.attribute("value"));
+    // a platform may be listed by several `Platforms` blocks; keep the first mention
+    let mut platforms: Vec<String> = Vec::new();
+    for platform in enabled {
+        if !platforms.iter().any(|known| known.eq_ignore_ascii_case(platform)) {
+            platforms.push(platform.to_string());
+        }
+    }
+    if platforms.is_empty() {
+        platforms.push("Win32".to_string());
+    }
+    return Ok(platforms);
+}
+
+/// Valid (platform, config) combinations of the project. A configuration is available on
+/// every enabled platform unless all of its `'$(Config)'=='…'` property groups are also
+/// conditioned on `'$(Platform)'=='…'`, in which case it is limited to those platforms.
+pub fn get_build_matrix(dproj_path: &PathBuf) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    // `None` means the configuration has an unrestricted property group
+    let mut restrictions: HashMap<String, Option<Vec<String>>> = HashMap::new();
+    for property_group in xml_content.descendants().filter(|n| n.has_tag_name("PropertyGroup")) {
+        let Some(condition) = property_group.attribute("Condition") else {
+            continue;
+        };
+        let Some(config) = CONFIG_CONDITION.captures(condition).map(|captures| captures[1].to_lowercase()) else {
+            continue;
+        };
+        let platforms: Vec<String> = PLATFORM_CONDITION
+            .captures_iter(condition)
+            .map(|captures| captures[1].to_string())
+            .collect();
+        let entry = restrictions.entry(config).or_insert_with(|| Some(Vec::new()));
+        match entry {
+            Some(allowed) if !platforms.is_empty() => allowed.extend(platforms),
+            _ => *entry = None,
+        }
+    }
+    let platforms = get_enabled_platforms(dproj_path)?;
+    let mut matrix = Vec::new();
+    for config in get_build_configurations(dproj_path)? {
+        let allowed = restrictions.get(&config.to_lowercase()).and_then(Clone::clone);
+        for platform in &platforms {
+            let available = match &allowed {
+                Some(allowed) => allowed.iter().any(|candidate| candidate.eq_ignore_ascii_case(platform)),
+                None => true,
+            };
+            if available {
+                matrix.push((platform.clone(), config.clone()));
+            }
+        }
+    }
+    return Ok(matrix);
+}
+
+fn get_first_text(dproj_path: &PathBuf, tag_name: &str) -> Result<Option<String>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    return Ok(xml_content
+        .descendants()
+        .find(|n| n.has_tag_name(tag_name))
+        .and_then(|n| n.text())
+        .map(|text| text.trim().to_string()));
+}
+
+pub fn get_project_version(dproj_path: &PathBuf) -> Result<Option<String>> {
+    return get_first_text(dproj_path, "ProjectVersion");
+}
+
+pub fn get_project_guid(dproj_path: &PathBuf) -> Result<Option<String>> {
+    return get_first_text(dproj_path, "ProjectGuid");
+}
+
+/// The version info keys (`FileVersion`, `ProductName`, `CompanyName`, ...) compiled into the
+/// project, empty if version info isn't included. Read from the base property groups, where
+/// later (platform-specific) ones override earlier ones; per-configuration overrides are ignored.
+/// Falls back to the `VersionInfoKeys` section that older IDEs write.
+pub fn get_version_info(dproj_path: &PathBuf) -> Result<BTreeMap<String, String>> {
+    let content = std::fs::read_to_string(dproj_path)?;
+    let xml_content = roxmltree::Document::parse(&content)?;
+    let mut include: Option<bool> = None;
+    let mut keys: Option<&str> = None;
+    for property_group in xml_content.descendants().filter(|n| n.has_tag_name("PropertyGroup")) {
+        if property_group.attribute("Condition").is_some_and(|condition| condition.contains("$(Cfg_")) {
+            continue;
+        }
+        for node in property_group.children() {
+            if node.has_tag_name("VerInfo_IncludeVerInfo") {
+                include = node.text().map(|text| text.trim().eq_ignore_ascii_case("true"));
+            } else if node.has_tag_name("VerInfo_Keys") {
+                keys = node.text();
+            }
+        }
+    }
+    if include.is_some() || keys.is_some() {
+        if include != Some(true) {
+            return Ok(BTreeMap::new());
+        }
+        return Ok(keys
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .filter(|(key, _)| !key.trim().is_empty())
+            .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+            .collect());
+    }
+
+    let included = xml_content
+        .descendants()
+        .find(|n| n.has_tag_name("VersionInfo") && n.attribute("Name") == Some("IncludeVerInfo"))
+        .and_then(|n| n.text())
+        .is_some_and(|text| text.trim().eq_ignore_ascii_case("true"));
+    if !included {
+        return Ok(BTreeMap::new());
+    }
+    return Ok(xml_content
+        .descendants()
+        .filter(|n| n.has_tag_name("VersionInfoKeys"))
+        .filter_map(|n| Some((n.attribute("Name")?.to_string(), n.text().unwrap_or_default().to_string())))
+        .collect());
+}
+
+/// `<ProjectVersion>` values written by each IDE, mapped to the BDS product version.
+const PROJECT_VERSIONS: &[(&[&str], usize)] = &[
+    // Delphi 2010 writes the same version as Delphi 2009
+    (&["12.0"], 6),
+    (&["12.2", "12.3"], 8),
+    (&["13.4"], 9),
+    (&["14.3", "14.4"], 10),
+    (&["14.6"], 11),
+    (&["15.1", "15.3"], 12),
+    (&["15.4"], 14),
+    (&["16.0", "16.1"], 15),
+    (&["17.2"], 16),
+    (&["18.0", "18.1"], 17),
+    (&["18.2"], 18),
+    (&["18.3", "18.4"], 19),
+    (&["18.5", "18.6", "18.7", "18.8"], 20),
+    (&["19.0", "19.1", "19.2"], 21),
+    (&["19.3", "19.4", "19.5"], 22),
+    (&["20.1", "20.2", "20.3"], 23),
+];
+
+pub fn product_version_of(project_version: &str) -> Option<usize> {
+    return PROJECT_VERSIONS
+        .iter()
+        .find(|(versions, _)| versions.contains(&project_version))
+        .map(|(_, product_version)| *product_version);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DPROJ: &str = r#"<Project xmlns="http://schemas.microsoft.com/developer/msbuild/2003">
+    <PropertyGroup Condition="'$(Config)'=='Debug' or '$(Cfg_1)'!=''">
+        <DCC_Define>DEBUG</DCC_Define>
+    </PropertyGroup>
+    <PropertyGroup Condition="'$(Config)'=='Release' or '$(Cfg_2)'!=''">
+        <DCC_Define>RELEASE</DCC_Define>
+    </PropertyGroup>
+    <PropertyGroup Condition="'$(Config)'=='Server' and '$(Platform)'=='Win64'">
+        <DCC_Define>SERVER</DCC_Define>
+    </PropertyGroup>
+    <ItemGroup>
+        <BuildConfiguration Include="Base"/>
+        <BuildConfiguration Include="Debug"/>
+        <BuildConfiguration Include="Release"/>
+        <BuildConfiguration Include="Server"/>
+    </ItemGroup>
+    <ProjectExtensions>
+        <BorlandProject>
+            <Platforms>
+                <Platform value="Win32">True</Platform>
+                <Platform value="Win64">True</Platform>
+                <Platform value="Linux64">False</Platform>
+            </Platforms>
+        </BorlandProject>
+    </ProjectExtensions>
+</Project>
+"#;
+
+    #[test]
+    fn platform_restricted_configs_are_only_built_there() {
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = directory.path().join("App.dproj");
+        std::fs::write(&dproj, DPROJ).unwrap();
+
+        let matrix = get_build_matrix(&dproj).unwrap();
+
+        let pair = |platform: &str, config: &str| (platform.to_string(), config.to_string());
+        assert_eq!(
+            matrix,
+            vec![
+                pair("Win32", "Debug"),
+                pair("Win64", "Debug"),
+                pair("Win32", "Release"),
+                pair("Win64", "Release"),
+                pair("Win64", "Server"),
+            ]
+        );
+    }
+
+    #[test]
+    fn platforms_listed_by_several_blocks_are_enabled_once_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = dproj_with(&directory, r#"
+            <ProjectExtensions>
+                <BorlandProject>
+                    <Platforms>
+                        <Platform value="Win64">True</Platform>
+                        <Platform value="Win32">True</Platform>
+                    </Platforms>
+                </BorlandProject>
+                <Platforms>
+                    <Platform value="Linux64">True</Platform>
+                    <Platform value="Win64">True</Platform>
+                </Platforms>
+            </ProjectExtensions>"#);
+
+        assert_eq!(get_enabled_platforms(&dproj).unwrap(), vec!["Win64", "Win32", "Linux64"]);
+    }
+
+    fn dproj_with(directory: &tempfile::TempDir, property_groups: &str) -> PathBuf {
+        let dproj = directory.path().join("App.dproj");
+        let content = format!(r#"<Project xmlns="http://schemas.microsoft.com/developer/msbuild/2003">{property_groups}</Project>"#);
+        std::fs::write(&dproj, content).unwrap();
+        return dproj;
+    }
+
+    #[test]
+    fn version_info_comes_from_the_base_property_groups() {
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = dproj_with(&directory, r#"
+            <PropertyGroup Condition="'$(Base)'!=''">
+                <VerInfo_IncludeVerInfo>true</VerInfo_IncludeVerInfo>
+                <VerInfo_Keys>CompanyName=Example;FileVersion=1.0.0.0;ProductName=App;Comments=</VerInfo_Keys>
+            </PropertyGroup>
+            <PropertyGroup Condition="'$(Base_Win64)'!=''">
+                <VerInfo_Keys>CompanyName=Example;FileVersion=1.2.0.0;ProductName=App 64</VerInfo_Keys>
+            </PropertyGroup>
+            <PropertyGroup Condition="'$(Cfg_2)'!=''">
+                <VerInfo_Keys>CompanyName=Example;FileVersion=9.9.9.9;ProductName=Release</VerInfo_Keys>
+            </PropertyGroup>"#);
+
+        let info = get_version_info(&dproj).unwrap();
+
+        let expected: BTreeMap<String, String> = [("CompanyName", "Example"), ("FileVersion", "1.2.0.0"), ("ProductName", "App 64")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(info, expected);
+    }
+
+    #[test]
+    fn projects_without_version_info_have_none() {
+        let directory = tempfile::tempdir().unwrap();
+        let excluded = dproj_with(&directory, r#"
+            <PropertyGroup Condition="'$(Base)'!=''">
+                <VerInfo_IncludeVerInfo>false</VerInfo_IncludeVerInfo>
+                <VerInfo_Keys>FileVersion=1.0.0.0</VerInfo_Keys>
+            </PropertyGroup>"#);
+        assert!(get_version_info(&excluded).unwrap().is_empty());
+
+        let missing = dproj_with(&directory, "<PropertyGroup><MainSource>App.dpr</MainSource></PropertyGroup>");
+        assert!(get_version_info(&missing).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod groupproj;
 pub mod dproj;
+pub mod dpk;
 pub use groupproj::*;
-pub use dproj::*;
\ No newline at end of file
+pub use dproj::*;
+pub use dpk::*;
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+use super::Formatter;
+use crate::utils::Document;
+
+/// Zero-based, inclusive line ranges of `file` that differ from `HEAD`.
+/// Returns `None` when the file is not tracked in a git repository.
+pub fn changed_line_ranges(file: &Path) -> Result<Option<Vec<(u32, u32)>>> {
+    let directory = file.parent().context("Failed to get parent directory")?;
+    let tracked = Command::new("git")
+        .current_dir(directory)
+        .args(["ls-files", "--error-unmatch"])
+        .arg(file)
+        .output();
+    match tracked {
+        Ok(output) if output.status.success() => {}
+        _ => return Ok(None),
+    }
+    let output = Command::new("git")
+        .current_dir(directory)
+        .args(["diff", "--no-color", "--unified=0", "HEAD", "--"])
+        .arg(file)
+        .output()
+        .context("Failed to execute git diff")?;
+    if !output.status.success() {
+        anyhow::bail!("git diff failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+    return Ok(Some(parse_hunks(&String::from_utf8_lossy(&output.stdout))));
+}
+
+/// Parses the new-file side of `@@ -a,b +c,d @@` hunk headers.
+fn parse_hunks(diff: &str) -> Vec<(u32, u32)> {
+    let mut ranges = Vec::new();
+    for line in diff.lines().filter(|line| line.starts_with("@@")) {
+        let Some(new_side) = line.split_whitespace().find(|part| part.starts_with('+')) else {
+            continue;
+        };
+        let mut parts = new_side[1..].split(',');
+        let start: u32 = match parts.next().and_then(|s| s.parse().ok()) {
+            Some(start) => start,
+            None => continue,
+        };
+        let count: u32 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(1);
+        if count == 0 {
+            // pure deletion, nothing left to format
+            continue;
+        }
+        ranges.push((start.saturating_sub(1), start.saturating_sub(1) + count - 1));
+    }
+    return ranges;
+}
+
+/// Formats only the lines of `file` changed against `HEAD`, one range format per hunk.
+/// Falls back to formatting the whole file when it isn't tracked by git.
+pub async fn format_changed_lines(file: &Path, profile: Option<&str>) -> Result<Vec<TextEdit>> {
+    let content = std::fs::read_to_string(file).context("Failed to read file")?;
+    let hunks = match changed_line_ranges(file)? {
+        Some(hunks) => hunks,
+        None => {
+            let new_text = Formatter::new(content.clone(), profile)?.execute().await?;
+            return Ok(vec![TextEdit {
+                range: Range::new(Position::new(0, 0), Document::new(&content).end_position()),
+                new_text,
+            }]);
+        }
+    };
+    return format_hunks(&content, hunks, profile).await;
+}
+
+/// Formats each of the zero-based, inclusive line ranges of `content` on its own.
+async fn format_hunks(content: &str, hunks: Vec<(u32, u32)>, profile: Option<&str>) -> Result<Vec<TextEdit>> {
+    let document = Document::new(content);
+    let mut edits = Vec::new();
+    for (first_line, last_line) in hunks {
+        let range = Range::new(Position::new(first_line, 0), Position::new(last_line + 1, 0));
+        let original = document.range(range);
+        let new_text = Formatter::new(original.to_string(), profile)?.execute().await?;
+        if new_text != original {
+            edits.push(TextEdit { range, new_text });
+        }
+    }
+    return Ok(edits);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git a/Unit1.pas b/Unit1.pas
+--- a/Unit1.pas
++++ b/Unit1.pas
+@@ -2 +2 @@ begin
+-  X:=1;
++  X:=2;
+@@ -5,0 +6,2 @@ begin
++  Y:=1;
++  Z:=1;
+@@ -9,1 +10,0 @@ end;
+-  W:=1;
+";
+
+    #[test]
+    fn hunks_are_the_new_side_of_the_diff() {
+        assert_eq!(parse_hunks(DIFF), vec![(1, 1), (5, 6)]);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn edits_stay_within_the_changed_hunks() {
+        let _session = crate::state::testing::test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        crate::state::testing::stub_formatter(directory.path(), "sed -i 's/:=/ := /' \"$5\"").await;
+        let content = "begin\n  X:=2;\n  A:=1;\n  B:=1;\n  C:=1;\n  Y:=1;\n  Z:=1;\n  D:=1;\nend.\n";
+
+        let edits = format_hunks(content, parse_hunks(DIFF), None).await.unwrap();
+
+        let line_range = |first: u32, last: u32| Range::new(Position::new(first, 0), Position::new(last + 1, 0));
+        assert_eq!(edits, vec![
+            TextEdit { range: line_range(1, 1), new_text: "  X := 2;\n".to_string() },
+            TextEdit { range: line_range(5, 6), new_text: "  Y := 1;\n  Z := 1;\n".to_string() },
+        ]);
+    }
+}
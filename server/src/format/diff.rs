@@ -0,0 +1,150 @@
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+use crate::utils::Document;
+
+/// Above this many LCS table cells the changed middle section is replaced as a whole.
+const MAX_LCS_CELLS: usize = 16 * 1024 * 1024;
+
+/// Computes the line-level edits turning `original` into `formatted`.
+/// Lines are compared including their terminators, so line ending changes are edits too.
+pub fn minimal_edits(original: &str, formatted: &str) -> Vec<TextEdit> {
+    let old: Vec<&str> = original.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    if old_middle.is_empty() && new_middle.is_empty() {
+        return Vec::new();
+    }
+
+    let document = Document::new(original);
+    let position = |line: usize| {
+        if line >= old.len() {
+            document.end_position()
+        } else {
+            Position::new(line as u32, 0)
+        }
+    };
+
+    let hunks = if (old_middle.len() + 1) * (new_middle.len() + 1) > MAX_LCS_CELLS {
+        vec![Hunk {
+            old_start: 0,
+            old_end: old_middle.len(),
+            new_text: new_middle.concat(),
+        }]
+    } else {
+        lcs_hunks(old_middle, new_middle)
+    };
+    return hunks
+        .into_iter()
+        .map(|hunk| TextEdit {
+            range: Range::new(position(prefix + hunk.old_start), position(prefix + hunk.old_end)),
+            new_text: hunk.new_text,
+        })
+        .collect();
+}
+
+/// Moves edits computed for a slice of a document to the slice's position in that document.
+pub fn offset_edits(edits: Vec<TextEdit>, origin: Position) -> Vec<TextEdit> {
+    let offset = |position: Position| {
+        if position.line == 0 {
+            Position::new(origin.line, origin.character + position.character)
+        } else {
+            Position::new(origin.line + position.line, position.character)
+        }
+    };
+    return edits
+        .into_iter()
+        .map(|edit| TextEdit {
+            range: Range::new(offset(edit.range.start), offset(edit.range.end)),
+            new_text: edit.new_text,
+        })
+        .collect();
+}
+
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    new_text: String,
+}
+
+fn lcs_hunks(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let (n, m) = (old.len(), new.len());
+    // lengths[i][j] = LCS length of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    let index = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[index(i, j)] = if old[i] == new[j] {
+                lengths[index(i + 1, j + 1)] + 1
+            } else {
+                lengths[index(i + 1, j)].max(lengths[index(i, j + 1)])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            if let Some(hunk) = current.take() {
+                hunks.push(hunk);
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let hunk = current.get_or_insert_with(|| Hunk {
+            old_start: i,
+            old_end: i,
+            new_text: String::new(),
+        });
+        if j >= m || (i < n && lengths[index(i + 1, j)] >= lengths[index(i, j + 1)]) {
+            i += 1;
+            hunk.old_end = i;
+        } else {
+            hunk.new_text.push_str(new[j]);
+            j += 1;
+        }
+    }
+    if let Some(hunk) = current {
+        hunks.push(hunk);
+    }
+    return hunks;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_texts_need_no_edits() {
+        let text = "unit Unit1;\r\n\r\ninterface\r\n\r\nend.";
+        assert!(minimal_edits(text, text).is_empty());
+    }
+
+    #[test]
+    fn large_rewrites_replace_the_changed_middle_as_a_whole() {
+        // just enough lines for the LCS table to exceed MAX_LCS_CELLS
+        let lines = (MAX_LCS_CELLS as f64).sqrt() as usize;
+        let original: String = (0..lines).map(|line| format!("old {line}\n")).collect();
+        let rewritten: String = (0..lines).map(|line| format!("new {line}\n")).collect();
+        let original = format!("begin\n{original}end.\n");
+        let formatted = format!("begin\n{rewritten}end.\n");
+
+        let edits = minimal_edits(&original, &formatted);
+
+        assert_eq!(edits, vec![TextEdit {
+            range: Range::new(Position::new(1, 0), Position::new(lines as u32 + 1, 0)),
+            new_text: rewritten,
+        }]);
+    }
+}
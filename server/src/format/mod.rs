@@ -2,7 +2,14 @@ use std::path::PathBuf;
 use anyhow::{Result, Context};
 use scopeguard::defer;
 
-use crate::{projects::CompilerConfigurations};
+use crate::{projects::CompilerConfigurations, state::SERVER_SETTINGS, utils::config_directory};
+
+mod changed_lines;
+mod diff;
+mod reindent;
+pub use changed_lines::*;
+pub use diff::*;
+pub use reindent::*;
 
 const DEFAULT_FORMATTER_CONFIG: &str = include_str!("presets/ddk_formatter.config");
 
@@ -12,41 +19,176 @@ pub struct Formatter {
 }
 
 impl Formatter {
-    pub fn new(content: String) -> Result<Self> {
-        let config_path = dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Failed to get config dir"))?
-            .join("ddk")
-            .join("ddk_formatter.config");
+    pub fn new(content: String, profile: Option<&str>) -> Result<Self> {
+        let config_path = match profile {
+            Some(profile) => Self::profile_path(profile)?,
+            None => Self::default_config_path()?,
+        };
+        Ok(Formatter { config_path, content })
+    }
+
+    fn config_dir() -> Result<PathBuf> {
+        return Ok(config_directory().clone());
+    }
+
+    /// The shared formatter config, written from the bundled preset if it doesn't exist yet.
+    pub fn default_config_path() -> Result<PathBuf> {
+        let config_path = Self::config_dir()?.join("ddk_formatter.config");
         if !config_path.exists() {
             if let Some(parent) = config_path.parent() {
                 std::fs::create_dir_all(parent).ok();
             }
             std::fs::write(&config_path, DEFAULT_FORMATTER_CONFIG).ok();
         }
+        return Ok(config_path);
+    }
 
-        Ok(Formatter { config_path, content })
+    fn profiles_dir() -> Result<PathBuf> {
+        return Ok(Self::config_dir()?.join("formatter_profiles"));
+    }
+
+    fn profile_path(profile: &str) -> Result<PathBuf> {
+        if profile.is_empty() || profile.contains(['/', '\\']) || profile.contains("..") {
+            anyhow::bail!("Invalid formatter profile name: {}", profile);
+        }
+        let path = Self::profiles_dir()?.join(format!("{}.config", profile));
+        if !path.is_file() {
+            anyhow::bail!("Formatter profile not found: {} (expected {})", profile, path.display());
+        }
+        return Ok(path);
+    }
+
+    /// Names of the `.config` files in the formatter profiles directory, sorted.
+    pub fn list_profiles() -> Result<Vec<String>> {
+        let directory = Self::profiles_dir()?;
+        if !directory.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut profiles: Vec<String> = std::fs::read_dir(&directory)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("config")))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+            .collect();
+        profiles.sort();
+        return Ok(profiles);
+    }
+
+    /// The `formatter_path` setting if set, otherwise the first compiler installation shipping a formatter.
+    async fn formatter_path() -> Result<PathBuf> {
+        if let Some(path) = SERVER_SETTINGS.read().await.formatter_path.clone() {
+            let path = PathBuf::from(path);
+            if !path.is_file() {
+                anyhow::bail!("Configured formatter_path does not exist: {}", path.display());
+            }
+            return Ok(path);
+        }
+        return CompilerConfigurations::first_available_formatter()
+            .await
+            .context("No formatters found (all compiler paths have been searched)");
     }
 
     pub async fn execute(self) -> Result<String> {
+        if self.content.trim().is_empty() {
+            // nothing to format; don't spawn the formatter for empty or whitespace-only buffers
+            return Ok(self.content);
+        }
         let temp_file = tempfile::NamedTempFile::with_suffix(".pas")?;
         std::fs::write(temp_file.path(), &self.content)?;
         let temp_file_path = temp_file.into_temp_path();
         defer! {
             std::fs::remove_file(&temp_file_path).ok();
         }
-        let formatter = CompilerConfigurations::first_available_formatter()
-            .await
-            .context("No formatters found (all compiler paths have been searched)")?;
-        let status = std::process::Command::new(&formatter)
+        let formatter = Self::formatter_path().await?;
+        // killed if the request formatting the code times out and drops this future
+        let status = tokio::process::Command::new(&formatter)
             .args(&["-e", "utf-8", "-config"])
             .arg(&self.config_path)
             .arg(&temp_file_path)
+            .kill_on_drop(true)
             .status()
-            .context("Failed to execute formatter")?;
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Formatter could not be started ({}): {}. Make sure it is a working Formatter.exe for this system, or point the `formatter_path` setting at one.",
+                    formatter.display(),
+                    e
+                )
+            })?;
         if !status.success() {
-            anyhow::bail!("Formatter failed with exit code: {}", status);
+            match status.code() {
+                // NTSTATUS exception codes (0xC0000000 and up) mean the process crashed
+                Some(code) if (code as u32) >= 0xC000_0000 => anyhow::bail!(
+                    "Formatter crashed with exception code {:#010X} ({}). The binary may be corrupt or built for a different architecture; consider setting `formatter_path`.",
+                    code as u32,
+                    formatter.display()
+                ),
+                Some(code) => anyhow::bail!("Formatter failed with exit code: {}", code),
+                None => anyhow::bail!("Formatter was terminated without an exit code: {}", status),
+            }
         }
         let content = std::fs::read_to_string(&temp_file_path)
             .context("Failed to read formatted code")?;
         return Ok(content.strip_prefix('\u{feff}').unwrap_or(&content).to_string());
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+
+    #[tokio::test]
+    async fn blank_content_is_returned_without_running_the_formatter() {
+        let _session = test_session().await;
+        // no formatter is configured, so running one would fail
+        for content in ["", "\n", " \t\r\n  \n"] {
+            let formatted = Formatter::new(content.to_string(), None).unwrap().execute().await.unwrap();
+            assert_eq!(formatted, content);
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn formatters_that_cannot_run_or_fail_are_told_apart() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let format = || async { Formatter::new("x:=1;".to_string(), None).unwrap().execute().await.unwrap_err().to_string() };
+
+        let missing = directory.path().join("missing");
+        SERVER_SETTINGS.write().await.formatter_path = Some(missing.to_string_lossy().to_string());
+        assert_eq!(format().await, format!("Configured formatter_path does not exist: {}", missing.display()));
+
+        let not_executable = directory.path().join("Formatter.exe");
+        std::fs::write(&not_executable, "MZ").unwrap();
+        SERVER_SETTINGS.write().await.formatter_path = Some(not_executable.to_string_lossy().to_string());
+        let error = format().await;
+        assert!(error.starts_with("Formatter could not be started"), "{error}");
+        assert!(error.contains("`formatter_path`"), "{error}");
+
+        crate::state::testing::stub_formatter(directory.path(), "exit 3").await;
+        assert_eq!(format().await, "Formatter failed with exit code: 3");
+
+        crate::state::testing::stub_formatter(directory.path(), "kill -9 $$").await;
+        assert!(format().await.starts_with("Formatter was terminated without an exit code"));
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn a_profile_formats_with_its_own_config() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        // "formats" the file into the config it was given
+        crate::state::testing::stub_formatter(directory.path(), "cp \"$4\" \"$5\"").await;
+        let profiles = Formatter::profiles_dir().unwrap();
+        std::fs::create_dir_all(&profiles).unwrap();
+        std::fs::write(profiles.join("tight.config"), "tight profile").unwrap();
+
+        let format = |profile: Option<&'static str>| async move {
+            return Formatter::new("x:=1;".to_string(), profile).unwrap().execute().await.unwrap();
+        };
+        assert_eq!(format(Some("tight")).await, "tight profile");
+        let default_config = std::fs::read_to_string(Formatter::default_config_path().unwrap()).unwrap();
+        assert_eq!(format(None).await, default_config.trim_start_matches('\u{feff}'));
+        assert!(Formatter::new("x:=1;".to_string(), Some("missing")).is_err());
+    }
+}
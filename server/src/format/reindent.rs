@@ -0,0 +1,267 @@
+/// Block kinds that indent their contents by one level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Begin,
+    Case,
+    Try,
+    Repeat,
+    Asm,
+    Record,
+    Class,
+}
+
+/// Keywords that open a declaration section indenting its contents by one level.
+const SECTION_STARTERS: &[&str] = &["type", "var", "const", "resourcestring", "threadvar", "label"];
+
+/// Keywords that end an open declaration section when they start a line at the top level.
+const SECTION_ENDERS: &[&str] = &[
+    "begin", "asm", "procedure", "function", "constructor", "destructor", "operator", "class",
+    "implementation", "interface", "initialization", "finalization", "exports", "uses",
+    "program", "unit", "library", "end",
+];
+
+const VISIBILITY: &[&str] = &["private", "protected", "public", "published", "strict", "automated"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LexState {
+    Code,
+    BraceComment,
+    ParenComment,
+    MultilineString,
+}
+
+/// Line by line tokenizer skipping comments and string literals, carrying
+/// block comments and multiline strings over line boundaries.
+struct Lexer {
+    state: LexState,
+}
+
+impl Lexer {
+    /// Significant tokens of `line`: lowercased words and single character symbols.
+    fn tokens(&mut self, line: &str) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        if self.state == LexState::MultilineString {
+            let indent = chars.iter().take_while(|c| c.is_whitespace()).count();
+            if !line[line.len() - line.trim_start().len()..].starts_with("'''") {
+                return tokens;
+            }
+            self.state = LexState::Code;
+            i = indent + 3;
+        }
+        while i < chars.len() {
+            match self.state {
+                LexState::BraceComment => {
+                    match chars[i..].iter().position(|c| *c == '}') {
+                        Some(end) => {
+                            i += end + 1;
+                            self.state = LexState::Code;
+                        }
+                        None => return tokens,
+                    }
+                    continue;
+                }
+                LexState::ParenComment => {
+                    match chars[i..].windows(2).position(|pair| pair == ['*', ')']) {
+                        Some(end) => {
+                            i += end + 2;
+                            self.state = LexState::Code;
+                        }
+                        None => return tokens,
+                    }
+                    continue;
+                }
+                LexState::MultilineString | LexState::Code => {}
+            }
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '/' && next == Some('/') {
+                return tokens;
+            } else if c == '{' {
+                self.state = LexState::BraceComment;
+                i += 1;
+            } else if c == '(' && next == Some('*') {
+                self.state = LexState::ParenComment;
+                i += 2;
+            } else if c == '\'' {
+                let rest: String = chars[i..].iter().collect();
+                if rest.starts_with("'''") && rest[3..].trim().is_empty() {
+                    self.state = LexState::MultilineString;
+                    return tokens;
+                }
+                i += 1;
+                while i < chars.len() {
+                    if chars[i] == '\'' {
+                        if chars.get(i + 1) == Some(&'\'') {
+                            i += 2;
+                            continue;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    i += 1;
+                }
+            } else if c.is_alphanumeric() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(chars[start..i].iter().collect::<String>().to_lowercase());
+            } else {
+                tokens.push(c.to_string());
+                i += 1;
+            }
+        }
+        return tokens;
+    }
+}
+
+/// Whether the `class`/`interface` token at `index` opens a body that is closed by `end`,
+/// as opposed to `class of`, forward declarations and bodiless classes like `class(Exception);`.
+fn opens_type_body(tokens: &[String], index: usize) -> bool {
+    let declared = match index {
+        0 => false,
+        _ if tokens[index - 1] == "=" => true,
+        _ => index >= 2 && tokens[index - 1] == "packed" && tokens[index - 2] == "=",
+    };
+    if !declared {
+        return false;
+    }
+    let mut next = index + 1;
+    if tokens.get(next).is_some_and(|token| token == "(") {
+        let mut depth = 0;
+        while next < tokens.len() {
+            match tokens[next].as_str() {
+                "(" => depth += 1,
+                ")" => depth -= 1,
+                _ => {}
+            }
+            next += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+    return !matches!(tokens.get(next).map(|token| token.as_str()), Some(";") | Some("of"));
+}
+
+/// Re-indents Delphi source by block nesting only, touching nothing but leading whitespace.
+/// Lines inside block comments and multiline strings are left as they are.
+pub fn reindent(content: &str, indent_width: usize) -> String {
+    let mut lexer = Lexer { state: LexState::Code };
+    let mut stack: Vec<Block> = Vec::new();
+    let mut section = false;
+    let mut result = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let starts_in_code = lexer.state == LexState::Code;
+        let tokens = lexer.tokens(line);
+        let first = tokens.first().map(|token| token.as_str()).unwrap_or("");
+
+        if stack.is_empty() && section && (SECTION_ENDERS.contains(&first) || SECTION_STARTERS.contains(&first)) {
+            section = false;
+        }
+        let mut level = stack.len() + section as usize;
+        let dedent = match first {
+            "end" | "until" => !stack.is_empty(),
+            "except" | "finally" => stack.last() == Some(&Block::Try),
+            _ if VISIBILITY.contains(&first) => matches!(stack.last(), Some(Block::Class) | Some(Block::Record)),
+            _ => false,
+        };
+        if dedent {
+            level = level.saturating_sub(1);
+        }
+
+        let code = line.trim_start_matches([' ', '\t']);
+        if !starts_in_code || code.trim().is_empty() {
+            result.push_str(line);
+        } else {
+            result.push_str(&" ".repeat(level * indent_width));
+            result.push_str(code);
+        }
+
+        if stack.is_empty() && SECTION_STARTERS.contains(&first) {
+            section = true;
+        }
+        for (index, token) in tokens.iter().enumerate() {
+            match token.as_str() {
+                "begin" => stack.push(Block::Begin),
+                // variant parts of records share the record's `end`
+                "case" if stack.last() != Some(&Block::Record) => stack.push(Block::Case),
+                "try" => stack.push(Block::Try),
+                "repeat" => stack.push(Block::Repeat),
+                "asm" => stack.push(Block::Asm),
+                "record" => stack.push(Block::Record),
+                "class" | "interface" | "dispinterface" | "object" if opens_type_body(&tokens, index) => {
+                    stack.push(Block::Class)
+                }
+                "end" => {
+                    stack.pop();
+                }
+                "until" if stack.last() == Some(&Block::Repeat) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> String {
+        return lines.iter().map(|line| format!("{line}\n")).collect();
+    }
+
+    /// `expected` with every line's leading whitespace replaced by a tab.
+    fn misindented(expected: &str) -> String {
+        return expected.lines().map(|line| format!("\t{}\n", line.trim_start())).collect();
+    }
+
+    #[test]
+    fn nested_blocks_indent_one_level_each() {
+        let expected = lines(&[
+            "procedure P;",
+            "begin",
+            "  if x then",
+            "  begin",
+            "    try",
+            "      y;",
+            "    finally",
+            "      z;",
+            "    end;",
+            "  end;",
+            "end;",
+        ]);
+        assert_eq!(reindent(&misindented(&expected), 2), expected);
+    }
+
+    #[test]
+    fn case_branches_are_indented_inside_the_case() {
+        let expected = lines(&[
+            "begin",
+            "    case x of",
+            "        1: a;",
+            "        2: begin",
+            "            b;",
+            "        end;",
+            "        else",
+            "        c;",
+            "    end;",
+            "end.",
+        ]);
+        assert_eq!(reindent(&misindented(&expected), 4), expected);
+    }
+
+    #[test]
+    fn lines_inside_a_multiline_string_are_left_alone() {
+        let content = lines(&["begin", "s := '''", "    keep   this", "not this either", "  ''';", "      t;", "end;"]);
+        let expected = lines(&["begin", "  s := '''", "    keep   this", "not this either", "  ''';", "  t;", "end;"]);
+        assert_eq!(reindent(&content, 2), expected);
+    }
+}
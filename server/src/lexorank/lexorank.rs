@@ -15,13 +15,28 @@ impl LexoRank {
     }
 
     pub fn from_string(value: &str) -> ParseResult<Self> {
-        let parts = value.split('|').collect::<Vec<&str>>();
-        let bucket = Bucket::new(parts[0].parse::<u8>()?)?;
-        let rank = Rank::new(parts[1])?;
+        let (bucket, rank) = value
+            .split_once('|')
+            .ok_or_else(|| ParseError(format!("LexoRank must have the form <bucket>|<rank>. Found: {}", value)))?;
+        let bucket = Bucket::new(bucket.parse::<u8>()?)?;
+        let rank = Rank::new(rank)?;
 
         Ok(LexoRank::new(bucket, rank))
     }
 
+    /// Keeps an unparsable value around so it can be detected with `is_valid` and repaired,
+    /// instead of failing to load the whole file.
+    fn from_string_lenient(value: &str) -> Self {
+        LexoRank::from_string(value).unwrap_or_else(|_| LexoRank::new(
+            Bucket::new(1).unwrap(),
+            Rank::unchecked(value),
+        ))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        Rank::new(self.rank.value()).is_ok()
+    }
+
     pub fn from_string_or_default(value: &str) -> Self {
         LexoRank::from_string(value).unwrap_or_else(|_| LexoRank::default())
     }
@@ -50,8 +65,8 @@ impl LexoRank {
 
     pub fn apply(list: &mut Vec<&mut dyn HasLexoRank>) {
         let count = list.len();
-        for i in 0..count {
-            list[i].set_lexorank(Self::new(
+        for (i, item) in list.iter_mut().enumerate() {
+            item.set_lexorank(Self::new(
                 Bucket::new(1).unwrap(),
                 Rank::from_range(i, count)
             ));
@@ -64,6 +79,19 @@ pub trait HasLexoRank {
     fn set_lexorank(&mut self, lexorank: LexoRank);
 }
 
+/// Rebalances the ranks of `list`, keeping its order, if any rank is invalid or duplicated.
+/// Returns whether the ranks were rewritten.
+pub fn repair(list: &mut Vec<&mut dyn HasLexoRank>) -> bool {
+    let mut seen = std::collections::HashSet::new();
+    let broken = list
+        .iter()
+        .any(|item| !item.get_lexorank().is_valid() || !seen.insert(item.get_lexorank().to_string()));
+    if broken {
+        LexoRank::apply(list);
+    }
+    return broken;
+}
+
 lazy_static::lazy_static! {
     static ref MIDDLE: LexoRank = LexoRank::new(
         Bucket::new(1).unwrap(),
@@ -116,6 +144,6 @@ impl<'de> Deserialize<'de> for LexoRank {
         where
             D: serde::Deserializer<'de> {
         let s = String::deserialize(deserializer)?;
-        LexoRank::from_string(&s).map_err(serde::de::Error::custom)
+        Ok(LexoRank::from_string_lenient(&s))
     }
 }
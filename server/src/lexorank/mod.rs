@@ -3,7 +3,7 @@ pub mod error;
 mod lexorank;
 mod rank;
 
-pub use lexorank::{LexoRank, HasLexoRank};
+pub use lexorank::{LexoRank, HasLexoRank, repair};
 pub use bucket::Bucket;
 pub use error::ParseError;
 pub use rank::Rank;
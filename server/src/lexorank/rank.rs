@@ -24,6 +24,11 @@ impl Rank {
         }
     }
 
+    /// Wraps `value` without validation; only for carrying corrupt persisted ranks until they are repaired.
+    pub(super) fn unchecked(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+
     pub fn from_range(index: usize, total: usize) -> Self {
         // we need to generate the string between "0" and "z" that is evenly spaced based on total
         let max_value = 36_usize.pow(4); // using 4 characters for
@@ -1,7 +1,10 @@
-use tower_lsp::lsp_types::{MessageType, Range, notification::Notification};
+use tower_lsp::lsp_types::{MessageType, Range, TextEdit, Url, notification::Notification};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use crate::projects::*;
+use crate::state::{COMPILER_CONFIGURATIONS, PROJECTS_DATA};
+use crate::utils::MatchMode;
 
 pub enum EventDone {}
 
@@ -83,7 +86,7 @@ impl ProjectsUpdate {
     pub async fn notify(client: &tower_lsp::Client) {
         client.log_message(MessageType::INFO, "Projects updated").await;
         client.send_notification::<ProjectsUpdate>(ProjectsUpdateParams {
-            projects: ProjectsData::new(),
+            projects: PROJECTS_DATA.read().await.clone(),
         }).await;
     }
 }
@@ -104,7 +107,7 @@ impl CompilersUpdate {
     pub async fn notify(client: &tower_lsp::Client) {
         client.log_message(MessageType::INFO, "Compilers updated").await;
         client.send_notification::<CompilersUpdate>(CompilersUpdateParams {
-            compilers: CompilerConfigurations::new(),
+            compilers: COMPILER_CONFIGURATIONS.read().await.clone(),
         }).await;
     }
 }
@@ -124,25 +127,43 @@ pub enum CompilerProgress {}
 #[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
 #[serde(tag = "kind")]
 pub enum CompilerProgressParams {
+    /// The compilation waits for `position` compilations ahead of it.
+    Queued {
+        position: usize,
+    },
     Start {
         lines: Vec<String>,
     },
     Stdout {
         line: String,
+        /// Set when `line` is a parsed compiler diagnostic; `line` keeps the formatted text.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        diagnostic: Option<CompilerLineDiagnostic>,
     },
     Stderr {
         line: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        diagnostic: Option<CompilerLineDiagnostic>,
     },
     Completed {
         success: bool,
         code: isize,
         lines: Vec<String>,
+        /// Every project of a multi-project build; the overall `success` requires all of them.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        projects: Vec<ProjectResult>,
+        /// msbuild binary logs of the build, one per project, if the compiler writes them.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        binary_logs: Vec<String>,
     },
     SingleProjectCompleted {
         project_id: usize,
         success: bool,
         code: isize,
         lines: Vec<String>,
+        /// Files with errors, absolute unless the compiler reported them otherwise.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        failed_units: Vec<String>,
     },
 }
 
@@ -152,6 +173,12 @@ impl Notification for CompilerProgress {
 }
 
 impl CompilerProgress {
+    pub async fn notify_queued(client: &tower_lsp::Client, position: usize) {
+        client.send_notification::<CompilerProgress>(CompilerProgressParams::Queued {
+            position,
+        }).await;
+    }
+
     pub async fn notify_start(client: &tower_lsp::Client, lines: Vec<String>) {
         client.send_notification::<CompilerProgress>(CompilerProgressParams::Start {
             lines,
@@ -161,29 +188,170 @@ impl CompilerProgress {
     pub async fn notify_stdout(client: &tower_lsp::Client, line: String) {
         client.send_notification::<CompilerProgress>(CompilerProgressParams::Stdout {
             line,
+            diagnostic: None,
+        }).await;
+    }
+
+    pub async fn notify_stdout_diagnostic(client: &tower_lsp::Client, diagnostic: CompilerLineDiagnostic) {
+        client.send_notification::<CompilerProgress>(CompilerProgressParams::Stdout {
+            line: diagnostic.to_string(),
+            diagnostic: Some(diagnostic),
         }).await;
     }
 
     pub async fn notify_stderr(client: &tower_lsp::Client, line: String) {
         client.send_notification::<CompilerProgress>(CompilerProgressParams::Stderr {
             line,
+            diagnostic: None,
+        }).await;
+    }
+
+    pub async fn notify_stderr_diagnostic(client: &tower_lsp::Client, diagnostic: CompilerLineDiagnostic) {
+        client.send_notification::<CompilerProgress>(CompilerProgressParams::Stderr {
+            line: diagnostic.to_string(),
+            diagnostic: Some(diagnostic),
         }).await;
     }
 
-    pub async fn notify_completed(client: &tower_lsp::Client, success: bool, code: isize, lines: Vec<String>) {
+    pub async fn notify_completed(
+        client: &tower_lsp::Client,
+        success: bool,
+        code: isize,
+        lines: Vec<String>,
+        projects: Vec<ProjectResult>,
+        binary_logs: Vec<String>,
+    ) {
         client.send_notification::<CompilerProgress>(CompilerProgressParams::Completed {
             success,
             code,
             lines,
+            projects,
+            binary_logs,
         }).await;
     }
 
-    pub async fn notify_single_project_completed(client: &tower_lsp::Client, project_id: usize, success: bool, code: isize, lines: Vec<String>) {
+    pub async fn notify_single_project_completed(
+        client: &tower_lsp::Client,
+        project_id: usize,
+        success: bool,
+        code: isize,
+        lines: Vec<String>,
+        failed_units: Vec<String>,
+    ) {
         client.send_notification::<CompilerProgress>(CompilerProgressParams::SingleProjectCompleted {
             project_id,
             success,
             code,
             lines,
+            failed_units,
+        }).await;
+    }
+}
+
+pub enum DiagnosticStream {}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DiagnosticStreamParams {
+    pub diagnostic: CompilerLineDiagnostic,
+}
+
+impl Notification for DiagnosticStream {
+    type Params = DiagnosticStreamParams;
+    const METHOD: &'static str = "$/notifications/diagnostic/stream";
+}
+
+impl DiagnosticStream {
+    pub async fn notify(client: &tower_lsp::Client, diagnostic: CompilerLineDiagnostic) {
+        client.send_notification::<DiagnosticStream>(DiagnosticStreamParams {
+            diagnostic,
+        }).await;
+    }
+}
+
+pub enum ProjectSourcesChanged {}
+
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+pub struct ProjectSourcesChangedParams {
+    pub files: Vec<String>,
+}
+
+impl Notification for ProjectSourcesChanged {
+    type Params = ProjectSourcesChangedParams;
+    const METHOD: &'static str = "notifications/projects/sources-changed";
+}
+
+impl ProjectSourcesChanged {
+    pub async fn notify(client: &tower_lsp::Client, files: Vec<String>) {
+        client.send_notification::<ProjectSourcesChanged>(ProjectSourcesChangedParams {
+            files,
+        }).await;
+    }
+}
+
+pub enum Progress {}
+
+/// Progress of long-running work other than compilations.
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum ProgressParams {
+    Start {
+        operation: String,
+    },
+    Report {
+        operation: String,
+        message: String,
+    },
+    Completed {
+        operation: String,
+        message: String,
+        cancelled: bool,
+    },
+}
+
+impl Notification for Progress {
+    type Params = ProgressParams;
+    const METHOD: &'static str = "$/notifications/progress";
+}
+
+impl Progress {
+    pub async fn notify_start(client: &tower_lsp::Client, operation: &str) {
+        client.send_notification::<Progress>(ProgressParams::Start {
+            operation: operation.to_string(),
+        }).await;
+    }
+
+    pub async fn notify_report(client: &tower_lsp::Client, operation: &str, message: String) {
+        client.send_notification::<Progress>(ProgressParams::Report {
+            operation: operation.to_string(),
+            message,
+        }).await;
+    }
+
+    pub async fn notify_completed(client: &tower_lsp::Client, operation: &str, message: String, cancelled: bool) {
+        client.send_notification::<Progress>(ProgressParams::Completed {
+            operation: operation.to_string(),
+            message,
+            cancelled,
+        }).await;
+    }
+}
+
+pub enum FormatterUpdated {}
+
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+pub struct FormatterUpdatedParams {
+    pub path: String,
+}
+
+impl Notification for FormatterUpdated {
+    type Params = FormatterUpdatedParams;
+    const METHOD: &'static str = "$/notifications/formatter/updated";
+}
+
+impl FormatterUpdated {
+    pub async fn notify(client: &tower_lsp::Client, path: &std::path::Path) {
+        client.send_notification::<FormatterUpdated>(FormatterUpdatedParams {
+            path: path.to_string_lossy().to_string(),
         }).await;
     }
 }
@@ -194,22 +362,168 @@ pub enum CompileProjectParams {
     Project {
         project_id: usize,
         project_link_id: Option<usize>,
+        #[serde(default)]
         rebuild: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<String>,
+        /// MSBuild targets replacing the default `Clean,Build` / `Clean,Make`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        targets: Option<Vec<String>>,
+        /// Overrides the `stop_on_first_failure` setting for this compilation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_on_error: Option<bool>,
+        #[serde(default)]
         event_id: String,
     },
     AllInWorkspace {
         workspace_id: usize,
+        #[serde(default)]
         rebuild: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<String>,
+        /// MSBuild targets replacing the default `Clean,Build` / `Clean,Make`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        targets: Option<Vec<String>>,
+        /// Overrides the `stop_on_first_failure` setting for this compilation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_on_error: Option<bool>,
+        #[serde(default)]
         event_id: String,
     },
     AllInGroupProject {
+        #[serde(default)]
         rebuild: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<String>,
+        /// MSBuild targets replacing the default `Clean,Build` / `Clean,Make`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        targets: Option<Vec<String>>,
+        /// Overrides the `stop_on_first_failure` setting for this compilation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_on_error: Option<bool>,
+        #[serde(default)]
         event_id: String,
     },
     FromLink {
         project_link_id: usize,
+        #[serde(default)]
+        rebuild: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<String>,
+        /// MSBuild targets replacing the default `Clean,Build` / `Clean,Make`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        targets: Option<Vec<String>>,
+        /// Overrides the `stop_on_first_failure` setting for this compilation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_on_error: Option<bool>,
+        #[serde(default)]
+        event_id: String,
+    },
+    Selection {
+        project_link_ids: Vec<usize>,
+        #[serde(default)]
         rebuild: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<String>,
+        /// MSBuild targets replacing the default `Clean,Build` / `Clean,Make`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        targets: Option<Vec<String>>,
+        /// Overrides the `stop_on_first_failure` setting for this compilation.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_on_error: Option<bool>,
+        #[serde(default)]
         event_id: String,
+    },
+}
+
+impl CompileProjectParams {
+    /// Builds one project with the defaults: no link, the workspace's platform and configuration,
+    /// the default targets and the `stop_on_first_failure` setting.
+    pub fn project(project_id: usize) -> Self {
+        return CompileProjectParams::Project {
+            project_id,
+            project_link_id: None,
+            rebuild: false,
+            platform: None,
+            config: None,
+            targets: None,
+            stop_on_error: None,
+            event_id: String::new(),
+        };
+    }
+
+    /// The platform requested for this compilation, if any.
+    pub fn platform(&self) -> Option<&str> {
+        match self {
+            CompileProjectParams::Project { platform, .. }
+            | CompileProjectParams::AllInWorkspace { platform, .. }
+            | CompileProjectParams::AllInGroupProject { platform, .. }
+            | CompileProjectParams::FromLink { platform, .. }
+            | CompileProjectParams::Selection { platform, .. } => platform.as_deref(),
+        }
+    }
+
+    /// The build configuration requested for this compilation, if any.
+    pub fn config(&self) -> Option<&str> {
+        match self {
+            CompileProjectParams::Project { config, .. }
+            | CompileProjectParams::AllInWorkspace { config, .. }
+            | CompileProjectParams::AllInGroupProject { config, .. }
+            | CompileProjectParams::FromLink { config, .. }
+            | CompileProjectParams::Selection { config, .. } => config.as_deref(),
+        }
+    }
+
+    /// The MSBuild targets requested for this compilation, if any.
+    pub fn targets(&self) -> Option<&[String]> {
+        match self {
+            CompileProjectParams::Project { targets, .. }
+            | CompileProjectParams::AllInWorkspace { targets, .. }
+            | CompileProjectParams::AllInGroupProject { targets, .. }
+            | CompileProjectParams::FromLink { targets, .. }
+            | CompileProjectParams::Selection { targets, .. } => targets.as_deref(),
+        }
+    }
+
+    pub fn set_rebuild(&mut self, value: bool) {
+        match self {
+            CompileProjectParams::Project { rebuild, .. }
+            | CompileProjectParams::AllInWorkspace { rebuild, .. }
+            | CompileProjectParams::AllInGroupProject { rebuild, .. }
+            | CompileProjectParams::FromLink { rebuild, .. }
+            | CompileProjectParams::Selection { rebuild, .. } => *rebuild = value,
+        }
+    }
+
+    pub fn set_targets(&mut self, value: Option<Vec<String>>) {
+        match self {
+            CompileProjectParams::Project { targets, .. }
+            | CompileProjectParams::AllInWorkspace { targets, .. }
+            | CompileProjectParams::AllInGroupProject { targets, .. }
+            | CompileProjectParams::FromLink { targets, .. }
+            | CompileProjectParams::Selection { targets, .. } => *targets = value,
+        }
+    }
+
+    /// Whether this compilation should stop at its first failing project, if requested.
+    pub fn stop_on_error(&self) -> Option<bool> {
+        match self {
+            CompileProjectParams::Project { stop_on_error, .. }
+            | CompileProjectParams::AllInWorkspace { stop_on_error, .. }
+            | CompileProjectParams::AllInGroupProject { stop_on_error, .. }
+            | CompileProjectParams::FromLink { stop_on_error, .. }
+            | CompileProjectParams::Selection { stop_on_error, .. } => *stop_on_error,
+        }
     }
 }
 
@@ -226,4 +540,197 @@ pub struct CancelCompilationParams {}
 pub struct CustomDocumentFormat {
     pub content: String,
     pub range: Option<Range>,
-}
\ No newline at end of file
+    pub profile: Option<String>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BuildHistoryParams {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResolveUnitParams {
+    pub project_id: usize,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FormatChangedLinesParams {
+    pub uri: Url,
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PreviewFormatParams {
+    pub uri: Url,
+    pub content: Option<String>,
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PreviewFormatResponse {
+    pub formatted: String,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DetectProjectCompilerParams {
+    pub dproj_path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DetectProjectCompilerResponse {
+    pub project_version: Option<String>,
+    pub project_guid: Option<String>,
+    pub product_version: Option<usize>,
+    pub compiler: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LintProjectParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LintProjectResponse {
+    pub success: bool,
+    pub diagnostics: BTreeMap<String, Vec<CompilerLineDiagnostic>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchProjectsParams {
+    pub query: String,
+    #[serde(default)]
+    pub mode: MatchMode,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProjectSearchResult {
+    pub project_id: usize,
+    pub name: String,
+    pub dproj: Option<String>,
+    pub score: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReindentParams {
+    pub uri: Url,
+    pub content: Option<String>,
+    pub indent_width: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunTestsParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClearDiagnosticsParams {
+    /// File to clear; all files when omitted.
+    pub uri: Option<Url>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetBuildMatrixParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangedSinceLastBuildParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CanMoveProjectParams {
+    pub project_link_id: usize,
+    pub drop_target: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CanMoveProjectResponse {
+    pub allowed: bool,
+    /// Why the move is not allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Answer to commands that start work in the background.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CommandAcceptance {
+    pub accepted: bool,
+    /// Why the command was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl CommandAcceptance {
+    pub fn accepted() -> Self {
+        return CommandAcceptance { accepted: true, reason: None };
+    }
+
+    pub fn rejected(reason: String) -> Self {
+        return CommandAcceptance { accepted: false, reason: Some(reason) };
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DiscoverProjectsParams {
+    pub directory: String,
+    pub workspace_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompileAndRevealParams {
+    pub project_id: usize,
+}
+
+/// On success `output_dir` holds the folder to reveal; on failure the diagnostics explain why.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompileAndRevealResponse {
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub diagnostics: BTreeMap<String, Vec<CompilerLineDiagnostic>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetWorkspaceProjectsParams {
+    pub workspace_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WorkspaceProject {
+    pub project_link_id: usize,
+    pub project: Project,
+    /// `None` if the project hasn't been built since the server started.
+    pub last_build: Option<ProjectResult>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExportDiagnosticsSarifParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetProjectVersionInfoParams {
+    pub project_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CleanProjectParams {
+    pub project_id: usize,
+    /// Extensions of the files deleted from the output directories after msbuild's `Clean`;
+    /// the project's clean settings when omitted.
+    pub extensions: Option<Vec<String>>,
+    /// Names of the folders deleted after msbuild's `Clean`; the project's clean settings when omitted.
+    pub folders: Option<Vec<String>>,
+}
+
+/// `success` is msbuild's `Clean`; `deleted` lists the leftovers removed afterwards and
+/// `failed` those that could not be removed, with the reason.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CleanProjectResponse {
+    pub success: bool,
+    pub deleted: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
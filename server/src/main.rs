@@ -5,10 +5,16 @@ pub mod files;
 pub mod utils;
 pub mod format;
 pub mod state;
+pub mod settings;
+pub mod commands;
 
-use std::sync::atomic::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use anyhow::Result;
 use tokio::io::{stdin, stdout};
+use tokio::sync::RwLock;
 use tower_lsp::{Client, async_trait, jsonrpc};
 use tower_lsp::{LanguageServer, LspService, Server};
 use tower_lsp::lsp_types::*;
@@ -16,25 +22,60 @@ use tower_lsp::lsp_types::*;
 pub(crate) use lsp_types::*;
 use projects::*;
 use state::*;
-use crate::format::Formatter;
+use crate::format::{Formatter, minimal_edits, offset_edits};
+use crate::settings::{Features, InitializationOptions, set_log_level};
 
 #[derive(Debug, Clone)]
 struct DelphiLsp {
     client: Client,
+    features: Arc<OnceLock<Features>>,
+    /// Contents of the documents the client has open, kept in sync as they are edited.
+    documents: Arc<RwLock<HashMap<Url, String>>>,
 }
 
 impl DelphiLsp {
     pub fn new(client: Client) -> Self {
-        return DelphiLsp { client }
+        return DelphiLsp {
+            client,
+            features: Arc::new(OnceLock::new()),
+            documents: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Creates the data files if needed and starts watching them.
+    fn load_data(&self) {
+        let watcher_client = self.client.clone();
+        let source_client = self.client.clone();
+        tokio::spawn(async move {
+            let _ = ProjectsData::initialize()
+                .expect("Failed to initialize projects data");
+            let _ = CompilerConfigurations::initialize()
+                .expect("Failed to initialize compiler configuration");
+            if let Err(e) = start_file_watchers(watcher_client) {
+                eprintln!("File watcher error: {}", e);
+            }
+            if let Err(e) = start_source_watcher(source_client).await {
+                eprintln!("Source watcher error: {}", e);
+            }
+        });
+    }
+
+    fn features(&self) -> Features {
+        return self.features.get().cloned().unwrap_or_default();
     }
 
     async fn projects_compile(
         &self,
         params: CompileProjectParams,
     ) -> tower_lsp::jsonrpc::Result<()> {
-        if let Err(e) = Compiler::new(self.client.clone(), &params).await.compile().await {
-            NotifyError::notify(&self.client, format!("Failed to compile project: {}", e), None).await;
-        }
+        // the request returns once the build is queued; the build reports its progress itself
+        let outcome = BuildRequest::new(self.client.clone(), &params).queue().await;
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Ok(Err(e)) = outcome.await {
+                NotifyError::notify(&client, format!("Failed to compile project: {}", e), None).await;
+            }
+        });
         try_finish_event!(self.client, params);
     }
 
@@ -42,7 +83,7 @@ impl DelphiLsp {
         &self,
         _params: CancelCompilationParams,
     ) -> tower_lsp::jsonrpc::Result<()> {
-        CANCEL_COMPILATION.store(true, std::sync::atomic::Ordering::SeqCst);
+        cancel_compilations();
         try_finish_event!(self.client, "compilation cancelled");
     }
 
@@ -51,16 +92,13 @@ impl DelphiLsp {
         _params: serde_json::Value,
     ) -> tower_lsp::jsonrpc::Result<ConfigurationFetchResponse> {
         Ok(ConfigurationFetchResponse {
-            projects: ProjectsData::new(),
-            compilers: CompilerConfigurations::new(),
+            projects: PROJECTS_DATA.read().await.clone(),
+            compilers: COMPILER_CONFIGURATIONS.read().await.clone(),
         })
     }
 
-    async fn custom_document_format(
-        &self,
-        params: CustomDocumentFormat,
-    ) -> tower_lsp::jsonrpc::Result<TextEdit> {
-        let formatter = Formatter::new(params.content)
+    async fn format_content(&self, content: String, profile: Option<&str>) -> jsonrpc::Result<String> {
+        let formatter = Formatter::new(content, profile)
             .map_err(|error| {
                 lsp_error!(self.client, "Failed to initialize formatter: {}", error);
                 jsonrpc::Error::invalid_params(format!(
@@ -68,59 +106,175 @@ impl DelphiLsp {
                     error
                 ))
             })?;
-        let new_text = formatter.execute().await.map_err(|error| {
+        return formatter.execute().await.map_err(|error| {
             lsp_error!(self.client, "Failed to format document: {}", error);
             jsonrpc::Error::invalid_params(format!(
                 "Failed to format document: {}",
                 error
             ))
-        })?;
+        });
+    }
+
+    /// Runs a request handler within `request_timeout_seconds`, so a hanging formatter can't
+    /// keep the request from ever being answered.
+    async fn with_timeout<T>(
+        &self,
+        method: &str,
+        handler: impl Future<Output = jsonrpc::Result<T>>,
+    ) -> jsonrpc::Result<T> {
+        let seconds = SERVER_SETTINGS.read().await.request_timeout_seconds;
+        if seconds == 0 {
+            return handler.await;
+        }
+        match tokio::time::timeout(Duration::from_secs(seconds), handler).await {
+            Ok(result) => return result,
+            Err(_) => {
+                let message = format!("{} timed out after {} seconds", method, seconds);
+                self.client.log_message(MessageType::WARNING, &message).await;
+                return Err(jsonrpc::Error {
+                    code: jsonrpc::ErrorCode::InternalError,
+                    message: message.into(),
+                    data: None,
+                });
+            }
+        }
+    }
+
+    async fn custom_document_format(
+        &self,
+        params: CustomDocumentFormat,
+    ) -> tower_lsp::jsonrpc::Result<TextEdit> {
+        let format = self.format_content(params.content, params.profile.as_deref());
+        let new_text = self.with_timeout("custom/document/format", format).await?;
         let range = params.range.unwrap_or(Range::new(Position::new(0,0), Position::new(u32::MAX, u32::MAX)));
         return Ok(TextEdit {
             range,
             new_text,
         });
     }
+
+    /// The open buffer of `uri`, or the file on disk if the client hasn't opened it.
+    async fn document_content(&self, uri: &Url) -> Option<String> {
+        if let Some(content) = self.documents.read().await.get(uri) {
+            return Some(content.clone());
+        }
+        if let Ok(path) = uri.to_file_path()
+            && let Ok(content) = std::fs::read_to_string(path)
+        {
+            return Some(content);
+        }
+        NotifyError::notify(&self.client, format!("Failed to format document - cannot read {}", uri), None).await;
+        return None;
+    }
+
+    /// Formats `content` with the default profile; failures, such as no formatter being
+    /// configured, are shown to the user instead of failing the request.
+    async fn format_or_notify(&self, content: String) -> Option<String> {
+        let formatted = match Formatter::new(content, None) {
+            Ok(formatter) => formatter.execute().await,
+            Err(error) => Err(error),
+        };
+        match formatted {
+            Ok(new_text) => return Some(new_text),
+            Err(error) => {
+                NotifyError::notify(&self.client, format!("Failed to format document: {}", error), None).await;
+                return None;
+            }
+        }
+    }
+
+    async fn format_document(&self, params: DocumentFormattingParams) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let Some(content) = self.document_content(&params.text_document.uri).await else {
+            return Ok(None);
+        };
+        let Some(new_text) = self.format_or_notify(content.clone()).await else {
+            return Ok(None);
+        };
+        // only the changed regions, so the editor keeps its cursor position and undo granularity
+        return Ok(Some(minimal_edits(&content, &new_text)));
+    }
+
+    async fn format_range(&self, params: DocumentRangeFormattingParams) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let range = params.range;
+        if range.start >= range.end {
+            return Ok(None);
+        }
+        let Some(content) = self.document_content(&params.text_document.uri).await else {
+            return Ok(None);
+        };
+        let original = utils::Document::new(&content).range(range).to_string();
+        let Some(mut new_text) = self.format_or_notify(original.clone()).await else {
+            return Ok(None);
+        };
+        if !original.ends_with('\n') {
+            // the slice ends mid-line or at the end of the file; don't push the rest onto a new line
+            new_text.truncate(new_text.trim_end_matches(['\r', '\n']).len());
+        }
+        return Ok(Some(offset_edits(minimal_edits(&original, &new_text), range.start)));
+    }
 }
 
 #[macro_export]
 macro_rules! lsp_debug {
     ($client:expr, $($arg:tt)*) => {
-        let inner = $client.clone();
-        let inner_message = format!($($arg)*);
-        tokio::spawn(async move {
-            inner.log_message(tower_lsp::lsp_types::MessageType::LOG, inner_message).await;
-        });
+        if $crate::settings::log_enabled($crate::settings::LogLevel::Debug) {
+            let inner = $client.clone();
+            let inner_message = format!($($arg)*);
+            tokio::spawn(async move {
+                inner.log_message(tower_lsp::lsp_types::MessageType::LOG, inner_message).await;
+            });
+        }
     };
 }
 
 #[macro_export]
 macro_rules! lsp_info {
     ($client:expr, $($arg:tt)*) => {
-        let inner = $client.clone();
-        let inner_message = format!($($arg)*);
-        tokio::spawn(async move {
-            inner.log_message(tower_lsp::lsp_types::MessageType::INFO, inner_message).await;
-        });
+        if $crate::settings::log_enabled($crate::settings::LogLevel::Info) {
+            let inner = $client.clone();
+            let inner_message = format!($($arg)*);
+            tokio::spawn(async move {
+                inner.log_message(tower_lsp::lsp_types::MessageType::INFO, inner_message).await;
+            });
+        }
     };
 }
 
 #[macro_export]
 macro_rules! lsp_error {
     ($client:expr, $($arg:tt)*) => {
-        let inner = $client.clone();
-        let inner_message = format!($($arg)*);
-        tokio::spawn(async move {
-            inner.log_message(tower_lsp::lsp_types::MessageType::ERROR, inner_message).await;
-        });
+        if $crate::settings::log_enabled($crate::settings::LogLevel::Error) {
+            let inner = $client.clone();
+            let inner_message = format!($($arg)*);
+            tokio::spawn(async move {
+                inner.log_message(tower_lsp::lsp_types::MessageType::ERROR, inner_message).await;
+            });
+        }
     };
 }
 
 #[async_trait]
 impl LanguageServer for DelphiLsp {
-    async fn initialize(&self, _params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+        let options: InitializationOptions = match params.initialization_options {
+            Some(options) => serde_json::from_value(options).map_err(|error| {
+                jsonrpc::Error::invalid_params(format!("Invalid initialization options: {}", error))
+            })?,
+            None => InitializationOptions::default(),
+        };
+        use_data_files(&options)?;
+        if let Some(level) = options.log_level {
+            set_log_level(level);
+        }
+        if let Some(settings) = options.settings {
+            *SERVER_SETTINGS.write().await = settings;
+        }
+        // the data files are only touched from here on, so the paths above apply to all of them
+        self.load_data();
+        let capabilities = server_capabilities(&options.features);
+        let _ = self.features.set(options.features);
         return Ok(InitializeResult {
-            capabilities: ServerCapabilities::default(), // none
+            capabilities,
             server_info: Some(ServerInfo {
                 name: "DDK - Delphi Server".to_string(),
                 version: Some("0.1.0".to_string()),
@@ -130,10 +284,23 @@ impl LanguageServer for DelphiLsp {
 
     async fn initialized(&self, _params: InitializedParams) {
         lsp_info!(self.client, "Delphi LSP Relay server initialized");
+        if !self.features().client_file_watchers {
+            return;
+        }
+        let registration = Registration {
+            id: "ddk-watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers: client_file_watchers(),
+            }).ok(),
+        };
+        if let Err(error) = self.client.register_capability(vec![registration]).await {
+            lsp_error!(self.client, "Failed to register file watchers: {}", error);
+        }
     }
 
     async fn shutdown(&self) -> jsonrpc::Result<()> {
-        CANCEL_COMPILATION.store(true, Ordering::SeqCst);
+        cancel_compilations();
         return Ok(())
     }
 
@@ -143,26 +310,123 @@ impl LanguageServer for DelphiLsp {
         if let Err(error) = projects::update(settings.clone(), client).await {
             lsp_error!(self.client, "Failed to apply configuration changes: {}", error);
             NotifyError::notify_json(&self.client, format!("Failed to apply configuration changes: {}", error), &settings).await;
+        } else if let Err(error) = sync_source_watch_roots().await {
+            lsp_error!(self.client, "Failed to update source watchers: {}", error);
         }
         try_finish_event!(self.client, settings, ());
     }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if let Err(error) = handle_watched_file_changes(params.changes, &self.client).await {
+            lsp_error!(self.client, "Failed to handle watched file changes: {}", error);
+        }
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if self.features().document_formatting {
+            self.documents.write().await.insert(uri.clone(), params.text_document.text);
+        }
+        if let Ok(file) = uri.to_file_path() {
+            follow_active_document(&self.client, file).await;
+        }
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // full sync: the last change holds the whole document
+        if let Some(change) = params.content_changes.into_iter().last() {
+            self.documents.write().await.insert(params.text_document.uri, change.text);
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents.write().await.remove(&params.text_document.uri);
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if let Ok(file) = params.text_document.uri.to_file_path() {
+            follow_active_document(&self.client, file).await;
+        }
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        return self.with_timeout("textDocument/formatting", self.format_document(params)).await;
+    }
+
+    async fn range_formatting(&self, params: DocumentRangeFormattingParams) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        return self.with_timeout("textDocument/rangeFormatting", self.format_range(params)).await;
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> jsonrpc::Result<Option<Vec<SymbolInformation>>> {
+        return self.with_timeout("workspace/symbol", async {
+            let projects_data = PROJECTS_DATA.read().await;
+            return Ok(Some(workspace_symbols(&projects_data, &params.query)));
+        }).await;
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> jsonrpc::Result<Option<serde_json::Value>> {
+        return self.execute_ddk_command(params).await;
+    }
+}
+
+/// The capabilities of a server with `features` enabled.
+fn server_capabilities(features: &Features) -> ServerCapabilities {
+    let execute_command_provider = features.execute_commands.then(|| ExecuteCommandOptions {
+        commands: commands::COMMANDS.iter().map(|command| command.to_string()).collect(),
+        work_done_progress_options: Default::default(),
+    });
+    return ServerCapabilities {
+        execute_command_provider,
+        document_formatting_provider: features.document_formatting.then_some(OneOf::Left(true)),
+        document_range_formatting_provider: features.document_formatting.then_some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        text_document_sync: Some(text_document_sync(features.document_formatting)),
+        ..ServerCapabilities::default()
+    };
+}
+
+/// Open and closed documents are always reported so the active project can follow the editor;
+/// their contents are only synced for formatting, which formats the unsaved buffer.
+fn text_document_sync(document_formatting: bool) -> TextDocumentSyncCapability {
+    return TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+        open_close: Some(true),
+        change: Some(if document_formatting { TextDocumentSyncKind::FULL } else { TextDocumentSyncKind::NONE }),
+        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+        ..TextDocumentSyncOptions::default()
+    });
+}
+
+/// Points the data files at the paths given in the `initialize` request. All of them are
+/// checked before any is applied, so a rejected option changes nothing.
+fn use_data_files(options: &InitializationOptions) -> jsonrpc::Result<()> {
+    let overrides = [
+        (&utils::CONFIG_DIRECTORY, &options.config_dir, "config_dir"),
+        (&PROJECTS_DATA_PATH, &options.projects_data_path, "projects_data_path"),
+        (&COMPILERS_PATH, &options.compilers_path, "compilers_path"),
+    ];
+    for (_, path, option) in &overrides {
+        if let Some(path) = path && !Path::new(path).is_absolute() {
+            return Err(jsonrpc::Error::invalid_params(format!("`{}` must be an absolute path: {}", option, path)));
+        }
+    }
+    for (file_path, path, option) in overrides {
+        if let Some(path) = path {
+            use_data_file(file_path, path, option)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Points a data file at the path given in the `initialize` request.
+fn use_data_file(file_path: &OnceLock<PathBuf>, path: &str, option: &str) -> jsonrpc::Result<()> {
+    return file_path.set(PathBuf::from(path)).map_err(|_| {
+        jsonrpc::Error::invalid_params(format!("`{}` can't be applied, the data file is already in use", option))
+    });
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let (service, socket) = LspService::build(|client| {
-        let watcher_client = client.clone();
-        tokio::spawn(async move {
-            let _ = ProjectsData::initialize()
-                .expect("Failed to initialize projects data");
-            let _ = CompilerConfigurations::initialize()
-                .expect("Failed to initialize compiler configuration");
-            if let Err(e) = start_file_watchers(watcher_client) {
-                eprintln!("File watcher error: {}", e);
-            }
-        });
-        DelphiLsp::new(client)
-    })
+    let (service, socket) = LspService::build(DelphiLsp::new)
         .custom_method("projects/compile", DelphiLsp::projects_compile)
         .custom_method("configuration/fetch", DelphiLsp::configuration_fetch)
         .custom_method("projects/compile-cancel", DelphiLsp::projects_compile_cancel)
@@ -172,4 +436,122 @@ async fn main() -> Result<()> {
     Server::new(stdin(), stdout(), socket).serve(service).await;
 
     return Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{FilePath, config_directory};
+
+    #[tokio::test]
+    async fn a_zero_width_range_is_not_formatted() {
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+        let position = Position::new(3, 4);
+        let params = DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier::new(Url::parse("file:///project/Unit1.pas").unwrap()),
+            range: Range::new(position, position),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        assert_eq!(service.inner().range_formatting(params).await.unwrap(), None);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn a_one_line_change_is_formatted_as_one_small_edit() {
+        let _session = crate::state::testing::test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        crate::state::testing::stub_formatter(directory.path(), "sed -i 's/X:=1;/X := 1;/' \"$5\"").await;
+        let unit = directory.path().join("Unit1.pas");
+        let lines: Vec<String> = (0..100).map(|line| format!("  // line {line}\n")).collect();
+        std::fs::write(&unit, format!("{}  X:=1;\n{}", lines[..50].concat(), lines[50..].concat())).unwrap();
+
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(Url::from_file_path(&unit).unwrap()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        let edits = service.inner().formatting(params).await.unwrap().unwrap();
+        assert_eq!(edits, vec![TextEdit {
+            range: Range::new(Position::new(50, 0), Position::new(51, 0)),
+            new_text: "  X := 1;\n".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn contents_are_only_synced_for_formatting() {
+        let change = |document_formatting: bool| match text_document_sync(document_formatting) {
+            TextDocumentSyncCapability::Options(options) => options.change,
+            TextDocumentSyncCapability::Kind(kind) => Some(kind),
+        };
+        assert_eq!(change(true), Some(TextDocumentSyncKind::FULL));
+        assert_eq!(change(false), Some(TextDocumentSyncKind::NONE));
+    }
+
+    #[tokio::test]
+    async fn hanging_requests_time_out() {
+        let _session = crate::state::testing::test_session().await;
+        let configured = std::mem::replace(&mut SERVER_SETTINGS.write().await.request_timeout_seconds, 1);
+        let (service, _socket) = LspService::new(DelphiLsp::new);
+        let hanging = async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            return Ok(None::<Vec<TextEdit>>);
+        };
+        let error = service.inner().with_timeout("textDocument/formatting", hanging).await.unwrap_err();
+        SERVER_SETTINGS.write().await.request_timeout_seconds = configured;
+        assert_eq!(error.message, "textDocument/formatting timed out after 1 seconds");
+    }
+
+    #[tokio::test]
+    async fn a_session_saves_to_its_own_files() {
+        let _session = crate::state::testing::test_session().await;
+        let session = config_directory().join("session");
+        assert_eq!(ProjectsData::get_file_path(), &session.join("projects.ron"));
+        assert_eq!(CompilerConfigurations::get_file_path(), &session.join("compilers.ron"));
+        assert_eq!(BuildHistory::get_file_path().parent(), Some(session.as_path()));
+
+        let change_set = ChangeSet {
+            changes: vec![Change::AddWorkspace { name: "Session".to_string(), compiler: "12.0".to_string() }],
+            atomic: true,
+        };
+        change_set.execute().await.unwrap();
+        let saved = std::fs::read_to_string(session.join("projects.ron")).unwrap();
+        assert!(saved.contains("Session"));
+        assert!(!config_directory().join("projects.ron").exists());
+    }
+
+    #[test]
+    fn capabilities_follow_the_enabled_features() {
+        let sync_kind = |capabilities: &ServerCapabilities| match &capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Options(options)) => options.change,
+            _ => None,
+        };
+        let defaults = server_capabilities(&InitializationOptions::default().features);
+        assert!(defaults.execute_command_provider.as_ref().unwrap().commands.contains(&commands::RUN_TESTS.to_string()));
+        assert_eq!(defaults.document_formatting_provider, Some(OneOf::Left(true)));
+        assert_eq!(defaults.document_range_formatting_provider, Some(OneOf::Left(true)));
+        assert_eq!(sync_kind(&defaults), Some(TextDocumentSyncKind::FULL));
+
+        let options: InitializationOptions = serde_json::from_value(serde_json::json!({
+            "features": { "execute_commands": false, "document_formatting": false },
+        })).unwrap();
+        assert!(options.features.client_file_watchers);
+        let capabilities = server_capabilities(&options.features);
+        assert_eq!(capabilities.execute_command_provider, None);
+        assert_eq!(capabilities.document_formatting_provider, None);
+        assert_eq!(capabilities.document_range_formatting_provider, None);
+        assert_eq!(sync_kind(&capabilities), Some(TextDocumentSyncKind::NONE));
+        assert_eq!(capabilities.workspace_symbol_provider, Some(OneOf::Left(true)));
+    }
+
+    #[test]
+    fn data_paths_must_be_absolute() {
+        let options = InitializationOptions {
+            projects_data_path: Some("projects.ron".to_string()),
+            ..InitializationOptions::default()
+        };
+        let error = use_data_files(&options).unwrap_err();
+        assert_eq!(error.message, "`projects_data_path` must be an absolute path: projects.ron");
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tower_lsp::Client;
+
+use crate::state::{PROJECTS_DATA, SERVER_SETTINGS, Stateful};
+use crate::{ProjectsUpdate, lsp_error};
+
+/// Quiet period after the last opened/saved document before the active project follows it.
+const ACTIVE_PROJECT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the project owning `file` the active one once no other document was focused
+/// for [`ACTIVE_PROJECT_DEBOUNCE`]. Does nothing unless `auto_select_active_project` is set.
+pub async fn follow_active_document(client: &Client, file: PathBuf) {
+    if !SERVER_SETTINGS.read().await.auto_select_active_project {
+        return;
+    }
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let client = client.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(ACTIVE_PROJECT_DEBOUNCE).await;
+        if GENERATION.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        match select_project_of(&file).await {
+            Ok(true) => ProjectsUpdate::notify(&client).await,
+            Ok(false) => {}
+            Err(error) => lsp_error!(client, "Failed to select the active project: {}", error),
+        }
+    });
+}
+
+async fn select_project_of(file: &Path) -> Result<bool> {
+    let mut projects_data = PROJECTS_DATA.write().await;
+    let lock = projects_data.lock_latest().await?;
+    let candidates: Vec<usize> = projects_data
+        .find_project_containing_file(file)
+        .iter()
+        .map(|project| project.id)
+        .collect();
+    // with several equally specific candidates there is no telling which one is meant
+    let project_id = match candidates.as_slice() {
+        [project_id] => *project_id,
+        _ => return Ok(false),
+    };
+    if projects_data.active_project_id() == Some(project_id) {
+        return Ok(false);
+    }
+    projects_data.select_project(project_id)?;
+    projects_data.commit(&lock)?;
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{test_client, test_session, workspace_with_projects};
+
+    #[tokio::test]
+    async fn the_active_project_follows_the_last_focused_document() {
+        let _session = test_session().await;
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (workspace_id, project_ids) = workspace_with_projects(first.path(), &["First"]).await;
+        let second_dpr = second.path().join("Second.dpr");
+        std::fs::write(&second_dpr, "program Second;\nbegin\nend.\n").unwrap();
+        PROJECTS_DATA.write().await.new_project(&second_dpr.to_string_lossy().to_string(), workspace_id).unwrap();
+        let second_id = PROJECTS_DATA.read().await.projects.last().unwrap().id;
+        // selecting catches up with the file first
+        PROJECTS_DATA.read().await.save().await.unwrap();
+        let client = test_client();
+        let settled = || tokio::time::sleep(ACTIVE_PROJECT_DEBOUNCE * 2);
+
+        follow_active_document(&client, first.path().join("Unit1.pas")).await;
+        settled().await;
+        assert_eq!(PROJECTS_DATA.read().await.active_project_id(), None, "the setting is off by default");
+
+        SERVER_SETTINGS.write().await.auto_select_active_project = true;
+        follow_active_document(&client, second.path().join("Unit1.pas")).await;
+        follow_active_document(&client, first.path().join("Unit1.pas")).await;
+        settled().await;
+        assert_eq!(PROJECTS_DATA.read().await.active_project_id(), Some(project_ids[0]));
+
+        follow_active_document(&client, second_dpr).await;
+        settled().await;
+        assert_eq!(PROJECTS_DATA.read().await.active_project_id(), Some(second_id));
+    }
+}
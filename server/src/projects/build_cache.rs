@@ -0,0 +1,272 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::state::write_replacing;
+use crate::utils::{FilePath, Load, comparable_path};
+
+use crate::files::dproj::get_unit_search_paths;
+
+use super::*;
+
+/// Files that influence a build's output; compiled artifacts are deliberately left out.
+const HASHED_EXTENSIONS: &[&str] = &["pas", "inc", "dpr", "dpk", "dproj", "dfm", "fmx", "res", "rc"];
+
+/// Content hashes of the sources each project was last built successfully from, keyed by
+/// the project's `.dproj` (see `cache_key`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub hashes: HashMap<String, String>,
+    /// Per project, the hash of every source file (by path relative to the project directory).
+    #[serde(default)]
+    pub sources: HashMap<String, BTreeMap<String, String>>,
+}
+
+/// Source files of a project that differ from its last successful build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedSinceLastBuild {
+    /// `false` if no successful build was recorded, which only happens with the
+    /// `content_hash_cache` setting on; `changed` is empty then.
+    pub known: bool,
+    pub rebuild_needed: bool,
+    /// Added, modified and deleted files.
+    pub changed: Vec<String>,
+}
+
+impl BuildCache {
+    pub fn new() -> Self {
+        return Self::load_from_file(Self::get_file_path());
+    }
+
+    pub fn is_up_to_date(project: &Project, hash: &str) -> bool {
+        return Self::new().hashes.get(&cache_key(project)).is_some_and(|stored| stored == hash);
+    }
+
+    pub fn store(project: &Project, hash: String) -> Result<()> {
+        return Self::update(|cache| {
+            cache.hashes.insert(cache_key(project), hash);
+        });
+    }
+
+    /// Drops the project's hash, e.g. after its outputs were cleaned, so the next build runs.
+    pub fn forget(project: &Project) -> Result<()> {
+        return Self::update(|cache| {
+            cache.hashes.remove(&cache_key(project));
+        });
+    }
+
+    /// Records the project's source files after a successful build.
+    pub fn store_sources(project: &Project) -> Result<()> {
+        let sources = source_hashes(project)?;
+        return Self::update(|cache| {
+            cache.sources.insert(cache_key(project), sources);
+        });
+    }
+
+    pub fn changed_since_last_build(project: &Project) -> Result<ChangedSinceLastBuild> {
+        let Some(recorded) = Self::new().sources.remove(&cache_key(project)) else {
+            return Ok(ChangedSinceLastBuild {
+                known: false,
+                rebuild_needed: true,
+                changed: Vec::new(),
+            });
+        };
+        let current = source_hashes(project)?;
+        let root = PathBuf::from(&project.directory);
+        let changed: Vec<String> = current
+            .iter()
+            .filter(|(file, hash)| recorded.get(*file) != Some(*hash))
+            .map(|(file, _)| file)
+            .chain(recorded.keys().filter(|file| !current.contains_key(*file)))
+            .map(|file| root.join(file).to_string_lossy().to_string())
+            .collect();
+        return Ok(ChangedSinceLastBuild {
+            known: true,
+            rebuild_needed: !changed.is_empty(),
+            changed,
+        });
+    }
+
+    fn update(change: impl FnOnce(&mut BuildCache)) -> Result<()> {
+        // parallel builds finish concurrently; don't let their read-modify-write cycles interleave
+        static STORE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _guard = STORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut cache = Self::new();
+        change(&mut cache);
+        let path = Self::get_file_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_replacing::<BuildCache>(path, &ron::to_string(&cache)?)?;
+        Ok(())
+    }
+}
+
+/// Ids are handed out anew when the data is rebuilt, the `.dproj` stays the same.
+fn cache_key(project: &Project) -> String {
+    let file = project.dproj.as_deref().unwrap_or(&project.directory);
+    return comparable_path(Path::new(file)).to_string_lossy().to_string();
+}
+
+impl FilePath for BuildCache {
+    fn get_file_path() -> &'static PathBuf {
+        lazy_static::lazy_static! {
+            static ref PATH: PathBuf = data_directory().join("build_cache.ron");
+        }
+        return &PATH;
+    }
+}
+
+impl Load for BuildCache {}
+
+/// Hashes the project's source files (paths relative to the project directory, and contents)
+/// and the units in its `.dproj` search paths, together with everything else that changes
+/// the build output.
+pub fn project_content_hash(
+    project: &Project,
+    configuration: &CompilerConfiguration,
+    build_arguments: &[String],
+    variant: &BuildVariant,
+) -> Result<String> {
+    let root = PathBuf::from(&project.directory);
+    let mut files = Vec::new();
+    collect_sources(&root, &mut files, true)?;
+    if let Some(dproj) = &project.dproj {
+        let variables = configuration.macro_variables(variant.platform.as_deref());
+        let comparable_root = comparable_path(&root);
+        for directory in get_unit_search_paths(&PathBuf::from(dproj), &variables).unwrap_or_default() {
+            // search paths aren't searched recursively; ones that don't exist don't matter either
+            if !comparable_path(&directory).starts_with(&comparable_root) {
+                let _ = collect_sources(&directory, &mut files, false);
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(configuration.installation_path.as_bytes());
+    for argument in build_arguments {
+        hasher.update(b"\0");
+        hasher.update(argument.as_bytes());
+    }
+    hasher.update(variant.msbuild_properties().as_bytes());
+    for file in files {
+        let relative = file.strip_prefix(&root).unwrap_or(&file);
+        hasher.update(b"\0");
+        hasher.update(relative.to_string_lossy().to_lowercase().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&std::fs::read(&file)?);
+    }
+    return Ok(hasher.finalize().to_hex().to_string());
+}
+
+fn source_hashes(project: &Project) -> Result<BTreeMap<String, String>> {
+    let root = PathBuf::from(&project.directory);
+    let mut files = Vec::new();
+    collect_sources(&root, &mut files, true)?;
+    let mut hashes = BTreeMap::new();
+    for file in files {
+        let relative = file.strip_prefix(&root).unwrap_or(&file).to_string_lossy().to_string();
+        hashes.insert(relative, blake3::hash(&std::fs::read(&file)?).to_hex().to_string());
+    }
+    return Ok(hashes);
+}
+
+fn collect_sources(directory: &Path, files: &mut Vec<PathBuf>, recursive: bool) -> Result<()> {
+    for entry in std::fs::read_dir(directory)?.flatten() {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if recursive {
+                collect_sources(&path, files, recursive)?;
+            }
+        } else if file_type.is_file() && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| HASHED_EXTENSIONS.iter().any(|hashed| ext.eq_ignore_ascii_case(hashed)))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+
+    /// `<root>/app/App.dproj` with `../shared` as a unit search path, and a unit in each.
+    fn project_with_search_path(root: &Path) -> Project {
+        let app = root.join("app");
+        let shared = root.join("shared");
+        std::fs::create_dir_all(&app).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        let dproj = app.join("App.dproj");
+        std::fs::write(&dproj, "<Project><PropertyGroup><DCC_UnitSearchPath>..\\shared;../shared;$(DCC_UnitSearchPath)</DCC_UnitSearchPath></PropertyGroup></Project>").unwrap();
+        std::fs::write(app.join("Main.pas"), "unit Main;").unwrap();
+        std::fs::write(shared.join("Shared.pas"), "unit Shared;").unwrap();
+        return Project {
+            id: 1,
+            name: "App".to_string(),
+            name_customized: false,
+            directory: app.to_string_lossy().to_string(),
+            dproj: Some(dproj.to_string_lossy().to_string()),
+            dpr: None,
+            dpk: None,
+            exe: None,
+            ini: None,
+            output_dir_override: None,
+            test_project_id: None,
+            project_guid: None,
+            clean: None,
+        };
+    }
+
+    fn hash(project: &Project) -> String {
+        let configuration = CompilerConfigurations::default().get("12.0").unwrap().clone();
+        let variant = BuildVariant { config: None, platform: None };
+        return project_content_hash(project, &configuration, &[], &variant).unwrap();
+    }
+
+    #[test]
+    fn units_in_search_paths_are_hashed() {
+        let root = tempfile::tempdir().unwrap();
+        let project = project_with_search_path(root.path());
+        let before = hash(&project);
+        assert_eq!(hash(&project), before);
+
+        std::fs::write(root.path().join("shared").join("Shared.pas"), "unit Shared; // changed").unwrap();
+        assert_ne!(hash(&project), before);
+    }
+
+    #[tokio::test]
+    async fn only_changed_files_are_reported() {
+        let _session = test_session().await;
+        let root = tempfile::tempdir().unwrap();
+        let project = project_with_search_path(root.path());
+        assert!(!BuildCache::changed_since_last_build(&project).unwrap().known);
+
+        std::fs::write(root.path().join("app").join("Other.pas"), "unit Other;").unwrap();
+        BuildCache::store_sources(&project).unwrap();
+        std::fs::write(root.path().join("app").join("Main.pas"), "unit Main; // changed").unwrap();
+        let changed = BuildCache::changed_since_last_build(&project).unwrap();
+        assert!(changed.known && changed.rebuild_needed);
+        assert_eq!(changed.changed, [root.path().join("app").join("Main.pas").to_string_lossy().to_string()]);
+    }
+
+    #[tokio::test]
+    async fn the_cache_outlives_the_project_id() {
+        let _session = test_session().await;
+        let root = tempfile::tempdir().unwrap();
+        let project = project_with_search_path(root.path());
+        BuildCache::store(&project, hash(&project)).unwrap();
+
+        let mut reloaded = project.clone();
+        reloaded.id = 42;
+        assert!(BuildCache::is_up_to_date(&reloaded, &hash(&reloaded)));
+        BuildCache::forget(&reloaded).unwrap();
+        assert!(!BuildCache::is_up_to_date(&project, &hash(&project)));
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::state::write_replacing;
+use crate::utils::{FilePath, Load};
+use super::data_directory;
+
+const MAX_ENTRIES: usize = 50;
+const MAX_FILE_SIZE: usize = 64 * 1024;
+
+lazy_static::lazy_static! {
+    /// Two builds finishing together would otherwise both read the log and drop each other's entry.
+    static ref APPENDING: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildHistoryEntry {
+    pub scope: String,
+    pub name: String,
+    pub success: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    pub timestamp: DateTime<Local>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildHistory {
+    pub entries: Vec<BuildHistoryEntry>,
+}
+
+impl BuildHistory {
+    pub fn new() -> Self {
+        return Self::load_from_file(Self::get_file_path());
+    }
+
+    /// Appends an entry and persists the log. Once either the entry cap or the file size cap
+    /// is exceeded, the oldest entries rotate into `build_history.1.ron`, which is capped the same way.
+    pub fn append(entry: BuildHistoryEntry) -> Result<()> {
+        let _appending = APPENDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut history = Self::new();
+        history.entries.push(entry);
+        let (serialized, rotated) = history.capped()?;
+        let path = Self::get_file_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if !rotated.is_empty() {
+            let rotated_path = rotated_file_path();
+            let mut previous = Self::load_from_file(&rotated_path);
+            previous.entries.extend(rotated);
+            let (serialized, _) = previous.capped()?;
+            write_replacing::<BuildHistory>(&rotated_path, &serialized)?;
+        }
+        write_replacing::<BuildHistory>(path, &serialized)?;
+        return Ok(());
+    }
+
+    /// Drops the oldest entries until both caps hold, returning the serialized
+    /// history and the dropped entries, oldest first.
+    fn capped(&mut self) -> Result<(String, Vec<BuildHistoryEntry>)> {
+        let overflow = self.entries.len().saturating_sub(MAX_ENTRIES);
+        let mut dropped: Vec<BuildHistoryEntry> = self.entries.drain(..overflow).collect();
+        let mut serialized = ron::to_string(self)?;
+        while serialized.len() > MAX_FILE_SIZE && self.entries.len() > 1 {
+            dropped.push(self.entries.remove(0));
+            serialized = ron::to_string(self)?;
+        }
+        return Ok((serialized, dropped));
+    }
+
+    /// Most recent entries first.
+    pub fn recent(&self, limit: Option<usize>) -> Vec<BuildHistoryEntry> {
+        return self.entries
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(MAX_ENTRIES))
+            .cloned()
+            .collect();
+    }
+}
+
+impl FilePath for BuildHistory {
+    fn get_file_path() -> &'static PathBuf {
+        lazy_static::lazy_static! {
+            static ref PATH: PathBuf = data_directory().join("build_history.ron");
+        }
+        return &PATH;
+    }
+}
+
+impl Load for BuildHistory {}
+
+fn rotated_file_path() -> PathBuf {
+    return BuildHistory::get_file_path().with_file_name("build_history.1.ron");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+
+    fn entry(name: &str) -> BuildHistoryEntry {
+        return BuildHistoryEntry {
+            scope: "Project".to_string(),
+            name: name.to_string(),
+            success: true,
+            errors: 0,
+            warnings: 0,
+            timestamp: Local::now(),
+            duration_ms: 0,
+        };
+    }
+
+    #[tokio::test]
+    async fn the_oldest_entries_rotate_into_the_previous_log() {
+        let _session = test_session().await;
+        let _ = std::fs::remove_file(BuildHistory::get_file_path());
+        let _ = std::fs::remove_file(rotated_file_path());
+
+        for index in 0..MAX_ENTRIES + 2 {
+            BuildHistory::append(entry(&format!("Build{index}"))).unwrap();
+        }
+
+        let history = BuildHistory::new();
+        assert_eq!(history.entries.len(), MAX_ENTRIES);
+        assert_eq!(history.entries[0].name, "Build2");
+        let rotated = BuildHistory::load_from_file(&rotated_file_path());
+        let names: Vec<&str> = rotated.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Build0", "Build1"]);
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use super::*;
+
+/// Variables an environment script set, captured for an installation.
+struct EnvironmentSnapshot {
+    installation_path: String,
+    variables: HashMap<String, String>,
+}
+
+lazy_static::lazy_static! {
+    /// Snapshots by environment script; one taken for another installation path is stale.
+    static ref ENVIRONMENT_SNAPSHOTS: Mutex<HashMap<PathBuf, EnvironmentSnapshot>> = Mutex::new(HashMap::new());
+}
+
+/// Runs a build command in the platform shell after sourcing the compiler's environment
+/// script: `cmd /C "call <script> && <command>"` on Windows, `sh -c ". <script> && exec <command>"` elsewhere.
+/// With `cache_environment`, the script's variables are captured once and the program is
+/// started directly with them instead.
+pub struct BuildInvocation {
+    env_script: Option<PathBuf>,
+    environment: Option<HashMap<String, String>>,
+}
+
+impl BuildInvocation {
+    pub async fn new(configuration: &CompilerConfiguration) -> Result<Self> {
+        let env_script = configuration.env_script_path();
+        if let Some(script) = &env_script && !script.exists() {
+            if configuration.env_script.is_none() && !cfg!(windows) {
+                anyhow::bail!(
+                    "Cannot find rsvars.bat at path: {} (set `env_script` on the compiler to source another environment script, or to an empty string to skip it)",
+                    script.to_string_lossy()
+                );
+            }
+            anyhow::bail!("Cannot find environment script at path: {}", script.to_string_lossy());
+        }
+        let environment = match &env_script {
+            Some(script) if configuration.cache_environment => {
+                Some(cached_environment(script, &configuration.installation_path).await?)
+            }
+            _ => None,
+        };
+        return Ok(BuildInvocation { env_script, environment });
+    }
+
+    pub fn command(&self, program: &str, arguments: &str, current_dir: &Path) -> Command {
+        let mut command = match &self.environment {
+            Some(environment) => {
+                let mut command = direct_command(program, arguments);
+                command.envs(environment);
+                command
+            }
+            None => self.shell_command(&format!("{} {}", program, arguments)),
+        };
+        command.current_dir(current_dir);
+        return command;
+    }
+
+    fn shell_command(&self, command_line: &str) -> Command {
+        if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            let command_line = match &self.env_script {
+                Some(script) => format!("call \"{}\" && {}", script.to_string_lossy(), command_line),
+                None => command_line.to_string(),
+            };
+            command.args(["/C", command_line.as_str()]);
+            return command;
+        }
+        let mut command = Command::new("sh");
+        // `exec` lets cancelling the build kill the build tool instead of only the shell
+        let command_line = match &self.env_script {
+            Some(script) => format!(". '{}' && exec {}", script.to_string_lossy(), command_line),
+            None => format!("exec {}", command_line),
+        };
+        command.args(["-c", command_line.as_str()]);
+        return command;
+    }
+}
+
+/// `program` with `arguments` passed through verbatim, as the shell would have received them.
+#[cfg(windows)]
+fn direct_command(program: &str, arguments: &str) -> Command {
+    let mut command = Command::new(program);
+    command.raw_arg(arguments);
+    return command;
+}
+
+/// Without a way to pass a raw command line, `sh` still splits the arguments; the script is skipped.
+#[cfg(not(windows))]
+fn direct_command(program: &str, arguments: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.args(["-c", format!("exec {} {}", program, arguments).as_str()]);
+    return command;
+}
+
+async fn cached_environment(script: &Path, installation_path: &str) -> Result<HashMap<String, String>> {
+    let mut snapshots = ENVIRONMENT_SNAPSHOTS.lock().await;
+    if let Some(snapshot) = snapshots.get(script)
+        && snapshot.installation_path == installation_path
+    {
+        return Ok(snapshot.variables.clone());
+    }
+    let variables = capture_environment(script).await?;
+    snapshots.insert(
+        script.to_path_buf(),
+        EnvironmentSnapshot {
+            installation_path: installation_path.to_string(),
+            variables: variables.clone(),
+        },
+    );
+    return Ok(variables);
+}
+
+/// Runs `script` and lists the environment it leaves behind.
+async fn capture_environment(script: &Path) -> Result<HashMap<String, String>> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", format!("call \"{}\" && set", script.to_string_lossy()).as_str()]);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.args(["-c", format!(". '{}' && env", script.to_string_lossy()).as_str()]);
+        command
+    };
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Failed to run environment script: {}", script.display()))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Environment script failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let variables = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, value)| (name.to_string(), value.trim_end_matches('\r').to_string()))
+        .collect();
+    return Ok(variables);
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    fn compiler_with_script(script: &Path, installation_path: &str) -> CompilerConfiguration {
+        let mut configuration = CompilerConfigurations::default().get("12.0").cloned().unwrap();
+        configuration.env_script = Some(script.to_string_lossy().to_string());
+        configuration.installation_path = installation_path.to_string();
+        configuration.cache_environment = true;
+        return configuration;
+    }
+
+    async fn snapshot_value(configuration: &CompilerConfiguration, directory: &Path) -> String {
+        let invocation = BuildInvocation::new(configuration).await.unwrap();
+        let output = invocation.command("printenv", "DDK_SNAPSHOT", directory).output().await.unwrap();
+        return String::from_utf8_lossy(&output.stdout).trim().to_string();
+    }
+
+    #[tokio::test]
+    async fn the_cached_environment_is_applied_until_the_installation_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("rsvars.sh");
+        std::fs::write(&script, "export DDK_SNAPSHOT=first\n").unwrap();
+        let first = compiler_with_script(&script, "/opt/Studio/23.0");
+        assert_eq!(snapshot_value(&first, directory.path()).await, "first");
+
+        // the script isn't run again for the same installation
+        std::fs::write(&script, "export DDK_SNAPSHOT=second\n").unwrap();
+        assert_eq!(snapshot_value(&first, directory.path()).await, "first");
+
+        let moved = compiler_with_script(&script, "/opt/Studio/37.0");
+        assert_eq!(snapshot_value(&moved, directory.path()).await, "second");
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn commands_run_in_the_given_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let script = directory.path().join("rsvars.sh");
+        std::fs::write(&script, "export DDK_STUB=1\n").unwrap();
+        std::fs::create_dir(directory.path().join("project")).unwrap();
+        let project_directory = directory.path().join("project").canonicalize().unwrap();
+
+        for cache_environment in [false, true] {
+            let configuration = CompilerConfiguration {
+                cache_environment,
+                ..compiler_with_script(&script, &directory.path().to_string_lossy())
+            };
+            let invocation = BuildInvocation::new(&configuration).await.unwrap();
+            let output = invocation.command("pwd", "-P", &project_directory).output().await.unwrap();
+            let working_directory = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            assert_eq!(PathBuf::from(working_directory), project_directory, "cache_environment: {cache_environment}");
+        }
+    }
+}
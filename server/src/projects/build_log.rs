@@ -0,0 +1,194 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use tokio::sync::oneshot;
+
+use super::data_directory;
+
+/// MSBuild binary logs are large, so only the ten most recent are kept, regardless of `build_logs`.
+const BINARY_LOGS_KEPT: usize = 10;
+
+enum LogMessage {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Combined stdout/stderr of one build, kept in `logs/` in the config directory.
+/// Lines are written by a dedicated thread so the output readers never wait on the disk.
+#[derive(Clone)]
+pub struct BuildLog {
+    path: Arc<PathBuf>,
+    sender: mpsc::Sender<LogMessage>,
+    /// Label of the project whose output this handle logs; parallel builds share one file.
+    project: Option<Arc<str>>,
+}
+
+impl BuildLog {
+    /// Starts a new `build-<timestamp>.log`, removing the oldest logs so that at most `keep`
+    /// remain. Returns `None` if `keep` is 0 (logging disabled).
+    pub fn create(keep: usize) -> Result<Option<Self>> {
+        if keep == 0 {
+            return Ok(None);
+        }
+        let directory = logs_directory()?;
+        // leaves room for the new log
+        prune(&directory, "build-", ".log", keep - 1)?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+        let path = directory.join(format!("build-{timestamp}.log"));
+        let file = File::create(&path)?;
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || write_lines(BufWriter::new(file), receiver));
+        return Ok(Some(BuildLog {
+            path: Arc::new(path),
+            sender,
+            project: None,
+        }));
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    /// A handle to the same log that prefixes every line with `[<project_name>]`.
+    pub fn for_project(&self, project_name: &str) -> Self {
+        return BuildLog {
+            project: Some(Arc::from(project_name)),
+            ..self.clone()
+        };
+    }
+
+    /// Queues a line; write errors are ignored so that logging never fails a build.
+    pub fn append(&self, line: &str) {
+        let line = match &self.project {
+            Some(project) => format!("[{project}] {line}"),
+            None => line.to_string(),
+        };
+        let _ = self.sender.send(LogMessage::Line(line));
+    }
+
+    /// Waits until every line queued so far is on disk.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(LogMessage::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Removes the oldest binary logs so that at most `BINARY_LOGS_KEPT` remain. Called once
+    /// before a build starts, so its own logs are never pruned while it runs.
+    pub fn prune_binary_logs() -> Result<()> {
+        return prune(&logs_directory()?, "binlog-", ".binlog", BINARY_LOGS_KEPT);
+    }
+
+    /// A new `binlog-<timestamp>-<project>.binlog` for msbuild's `/bl`, next to the build logs.
+    /// The file is created up front so an unwritable directory is reported before the build.
+    pub fn binary_log_path(project_name: &str) -> Result<PathBuf> {
+        let directory = logs_directory()?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+        let project_name: String = project_name
+            .chars()
+            .map(|ch| if ch.is_alphanumeric() || ch == '-' || ch == '.' { ch } else { '_' })
+            .collect();
+        let path = directory.join(format!("binlog-{timestamp}-{project_name}.binlog"));
+        File::create(&path)?;
+        return Ok(path);
+    }
+}
+
+/// Runs until every handle of the log is dropped, flushing whenever the queue runs dry.
+fn write_lines(mut file: BufWriter<File>, receiver: mpsc::Receiver<LogMessage>) {
+    let mut next = receiver.recv();
+    while let Ok(message) = next {
+        match message {
+            LogMessage::Line(line) => {
+                let _ = writeln!(file, "{}", line);
+            }
+            LogMessage::Flush(done) => {
+                let _ = file.flush();
+                let _ = done.send(());
+            }
+        }
+        next = match receiver.try_recv() {
+            Ok(message) => Ok(message),
+            Err(_) => {
+                let _ = file.flush();
+                receiver.recv()
+            }
+        };
+    }
+}
+
+fn logs_directory() -> Result<PathBuf> {
+    let directory = data_directory().join("logs");
+    std::fs::create_dir_all(&directory)?;
+    return Ok(directory);
+}
+
+/// Removes the oldest `<prefix>*<extension>` files so that at most `keep` remain.
+fn prune(directory: &Path, prefix: &str, extension: &str, keep: usize) -> Result<()> {
+    let mut logs: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|log| {
+            log.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix) && name.ends_with(extension))
+        })
+        .collect();
+    // timestamps sort chronologically by name
+    logs.sort();
+    let excess = logs.len().saturating_sub(keep);
+    for log in &logs[..excess] {
+        // a log another session pruned first is gone already
+        if let Err(e) = std::fs::remove_file(log) && e.kind() != ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+
+    #[tokio::test]
+    async fn project_lines_are_labelled() {
+        let _session = test_session().await;
+        let log = BuildLog::create(1).unwrap().unwrap();
+        log.append("header");
+        let first = log.for_project("First");
+        let second = log.for_project("Second");
+        first.append("compiling Unit1.pas");
+        second.append("compiling Unit2.pas");
+        log.flush().await;
+
+        let content = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(content, "header\n[First] compiling Unit1.pas\n[Second] compiling Unit2.pas\n");
+    }
+
+    #[tokio::test]
+    async fn a_build_keeps_all_of_its_binary_logs() {
+        let _session = test_session().await;
+        let directory = logs_directory().unwrap();
+        for index in 0..3 {
+            std::fs::write(directory.join(format!("binlog-20000101-00000{index}.000-Old.binlog")), "").unwrap();
+        }
+        BuildLog::prune_binary_logs().unwrap();
+        let logs: Vec<PathBuf> = (0..BINARY_LOGS_KEPT + 2)
+            .map(|index| BuildLog::binary_log_path(&format!("Project{index}")).unwrap())
+            .collect();
+        assert!(logs.iter().all(|log| log.exists()));
+
+        BuildLog::prune_binary_logs().unwrap();
+        let remaining = std::fs::read_dir(&directory)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".binlog"))
+            .count();
+        assert_eq!(remaining, BINARY_LOGS_KEPT);
+        assert!(logs[2..].iter().all(|log| log.exists()));
+    }
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use crate::projects::*;
 use crate::state::*;
@@ -7,24 +7,108 @@ use crate::state::*;
 #[derive(Serialize, Deserialize)]
 pub struct ChangeSet {
     pub changes: Vec<Change>,
+    /// Apply all changes to one copy of the data and save once, or save nothing if any of them
+    /// fails. Otherwise every change is saved on its own and a failure keeps the earlier ones.
+    #[serde(default = "atomic_default")]
+    pub atomic: bool,
+}
+
+fn atomic_default() -> bool {
+    true
 }
 
 impl ChangeSet {
-    pub async fn execute(self) -> Result<()> {
-        for change in self.changes {
-            change.execute().await?;
+    pub async fn execute(mut self) -> Result<()> {
+        // detection reads the registry, so it runs once here rather than under the locks
+        self.changes = self.changes.into_iter().map(Change::with_detected_compilers).collect();
+        if self.atomic {
+            // fail with the first invalid change before waiting for the file locks
+            {
+                let projects_data = PROJECTS_DATA.read().await;
+                let compilers = COMPILER_CONFIGURATIONS.read().await;
+                self.validate(&projects_data, &compilers)?;
+            }
+            return Self::transaction(&self.changes).await;
+        }
+        for change in &self.changes {
+            Self::transaction(std::slice::from_ref(change)).await?;
+        }
+        Ok(())
+    }
+
+    /// Pre-flights the whole batch without touching the data: the changes are applied in order
+    /// to copies, so a change may refer to what earlier ones created or moved, and the result
+    /// has to be consistent.
+    pub fn validate(&self, data: &ProjectsData, compilers: &CompilerConfigurations) -> Result<()> {
+        Self::dry_run(&self.changes, data, compilers)?;
+        return Ok(());
+    }
+
+    fn dry_run(
+        changes: &[Change],
+        data: &ProjectsData,
+        compilers: &CompilerConfigurations,
+    ) -> Result<(ProjectsData, CompilerConfigurations)> {
+        let mut new_projects_data = data.clone();
+        let mut new_compilers = compilers.clone();
+        for (index, change) in changes.iter().enumerate() {
+            change.clone()
+                .apply(&mut new_projects_data, &mut new_compilers)
+                .with_context(|| format!("Change {} of {} is invalid", index + 1, changes.len()))?;
+        }
+        new_compilers.validate_changes(compilers)
+            .context("The changes would leave invalid compilers")?;
+        new_projects_data.validate_with(&new_compilers)
+            .context("The changes would leave invalid projects data")?;
+        return Ok((new_projects_data, new_compilers));
+    }
+
+    /// Validates the changes under one lock and replaces the data with the result only if
+    /// all of them succeed; otherwise nothing is saved.
+    async fn transaction(changes: &[Change]) -> Result<()> {
+        let mut projects_data = PROJECTS_DATA.write().await;
+        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
+        let projects_lock = projects_data.lock_latest().await?;
+        let compilers_lock = compilers.lock_latest().await?;
+        let (mut new_projects_data, new_compilers) = Self::dry_run(changes, &projects_data, &compilers)?;
+        let destructive = changes.iter().any(Change::is_destructive);
+
+        // both files are written before either is replaced, so a failing write saves neither
+        let compilers_changed = *compilers != new_compilers;
+        let projects_changed = *projects_data != new_projects_data;
+        let compilers_save = compilers_changed
+            .then(|| new_compilers.prepare_save(&compilers_lock))
+            .transpose()?;
+        let projects_save = projects_changed
+            .then(|| new_projects_data.prepare_commit(&projects_lock))
+            .transpose()?;
+        if projects_changed && destructive {
+            ProjectsData::backup(SERVER_SETTINGS.read().await.config_backups)?;
+        }
+        // compilers first: the projects may refer to a compiler added in the same set
+        if let Some(save) = compilers_save {
+            save.finish()?;
+            *compilers = new_compilers;
+        }
+        if let Some(save) = projects_save {
+            save.finish()?;
+            *projects_data = new_projects_data;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkspaceUpdateData {
     pub name: Option<String>,
     pub compiler: Option<String>,
+    /// An empty string clears the default.
+    pub default_platform: Option<String>,
+    /// An empty string clears the default.
+    pub default_config: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectUpdateData {
     pub name: Option<String>,
     pub directory: Option<String>,
@@ -33,198 +117,310 @@ pub struct ProjectUpdateData {
     pub dpk: Option<String>,
     pub exe: Option<String>,
     pub ini: Option<String>,
+    /// An empty string clears the override.
+    pub output_dir_override: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Change {
     NewProject { file_path: String, workspace_id: usize },
     AddProject { project_id: usize, workspace_id: usize },
     RemoveProject { project_link_id: usize },
+    RemoveOrphanedLinks,
     MoveProject { project_link_id: usize, drop_target: usize },
     RefreshProject { project_id: usize },
     UpdateProject { project_id: usize, data: ProjectUpdateData },
     SelectProject { project_id: usize },
+    SetTestProject { project_id: usize, test_project_id: Option<usize> },
+    SetCleanSettings { project_id: usize, settings: Option<CleanSettings> },
     AddWorkspace { name: String, compiler: String },
     RemoveWorkspace { workspace_id: usize },
+    DuplicateWorkspace { workspace_id: usize, new_name: String },
     MoveWorkspace { workspace_id: usize, drop_target: usize },
     UpdateWorkspace { workspace_id: usize, data: WorkspaceUpdateData },
     AddCompiler { key: String, config: CompilerConfiguration },
     RemoveCompiler { compiler: String },
     UpdateCompiler { key: String, data: PartialCompilerConfiguration },
+    DetectCompilers {
+        /// Filled in by `ChangeSet::execute`, so applying the change doesn't detect again.
+        #[serde(skip)]
+        detected: Option<CompilerConfigurations>,
+    },
     SetGroupProject { groupproj_path: String },
     RemoveGroupProject,
     SetGroupProjectCompiler { compiler: String },
+    RestoreProjectsBackup,
+    RestoreCompilersBackup,
 }
 
 impl Change {
-    pub async fn execute(self) -> Result<()> {
+    /// Removals also drop projects no longer linked anywhere, so the saved file is backed up first.
+    fn is_destructive(&self) -> bool {
+        return matches!(
+            self,
+            Change::RemoveProject { .. } | Change::RemoveWorkspace { .. } | Change::RemoveGroupProject
+        );
+    }
+
+    fn with_detected_compilers(self) -> Change {
+        match self {
+            Change::DetectCompilers { detected: None } => {
+                return Change::DetectCompilers { detected: Some(CompilerConfigurations::detect_installed()) };
+            }
+            change => return change,
+        }
+    }
+
+    fn apply(self, projects_data: &mut ProjectsData, compilers: &mut CompilerConfigurations) -> Result<()> {
         match self {
             Change::NewProject { file_path, workspace_id } => {
-                return Self::new_project(file_path, workspace_id).await;
+                return projects_data.new_project(&file_path, workspace_id);
             }
             Change::AddProject { project_id, workspace_id } => {
-                return Self::add_project_link(project_id, workspace_id).await;
+                return projects_data.add_project_link(project_id, workspace_id);
             }
             Change::RemoveProject { project_link_id } => {
-                return Self::remove_project_link(project_link_id).await;
+                return projects_data.remove_project_link(project_link_id);
+            }
+            Change::RemoveOrphanedLinks => {
+                return projects_data.remove_orphaned_links();
             }
             Change::MoveProject { project_link_id, drop_target } => {
-                return Self::move_project(project_link_id, drop_target).await;
+                return projects_data.move_project_link(project_link_id, drop_target);
             }
             Change::RefreshProject { project_id } => {
-                return Self::refresh_project(project_id).await;
+                return projects_data.refresh_project_paths(project_id);
             }
             Change::UpdateProject { project_id, data } => {
-                return Self::update_project(project_id, data).await;
+                return projects_data.update_project(project_id, data);
             }
             Change::SelectProject { project_id } => {
-                return Self::select_project(project_id).await;
+                return projects_data.select_project(project_id);
+            }
+            Change::SetTestProject { project_id, test_project_id } => {
+                return projects_data.set_test_project(project_id, test_project_id);
+            }
+            Change::SetCleanSettings { project_id, settings } => {
+                return projects_data.set_clean_settings(project_id, settings);
             }
             Change::AddWorkspace { name, compiler } => {
-                return Self::add_workspace(name, compiler).await;
+                return projects_data.new_workspace(&name, &compiler, compilers);
             }
             Change::RemoveWorkspace { workspace_id } => {
-                return Self::remove_workspace(workspace_id).await;
+                return projects_data.remove_workspace(workspace_id);
+            }
+            Change::DuplicateWorkspace { workspace_id, new_name } => {
+                return projects_data.duplicate_workspace(workspace_id, &new_name);
             }
             Change::MoveWorkspace { workspace_id, drop_target } => {
-                return Self::move_workspace(workspace_id, drop_target).await;
+                return projects_data.move_workspace(workspace_id, drop_target);
             }
             Change::UpdateWorkspace { workspace_id, data } => {
-                return Self::update_workspace(workspace_id, data).await;
+                return projects_data.update_workspace(workspace_id, &data, compilers);
             }
             Change::AddCompiler { key, config } => {
-                return Self::add_compiler(key, config).await;
+                compilers.insert(key, config);
             }
             Change::RemoveCompiler { compiler } => {
-                return Self::remove_compiler(compiler).await;
+                if compilers.remove(&compiler).is_none() {
+                    anyhow::bail!("Unable to remove compiler - compiler not found: {}", compiler);
+                }
             }
             Change::UpdateCompiler { key, data } => {
-                return Self::update_compiler(key, data).await;
+                match compilers.get_mut(&key) {
+                    Some(compiler) => compiler.update(&data),
+                    None => anyhow::bail!("Unable to update compiler - compiler not found: {}", key),
+                }
+            }
+            Change::DetectCompilers { detected } => {
+                compilers.merge_detected(detected.unwrap_or_else(CompilerConfigurations::detect_installed));
             }
             Change::SetGroupProject { groupproj_path} => {
-                return Self::set_group_project(groupproj_path).await;
+                return projects_data.set_group_project(&groupproj_path);
             }
             Change::RemoveGroupProject => {
-                return Self::remove_group_project().await;
+                projects_data.remove_group_project();
             }
             Change::SetGroupProjectCompiler { compiler } => {
-                return Self::set_group_project_compiler(compiler).await;
+                if !compilers.contains_key(&compiler) {
+                    anyhow::bail!(
+                        "Unable to set group project compiler - compiler not found: {}",
+                        compiler
+                    );
+                }
+                projects_data.group_project_compiler_id = compiler;
+            }
+            Change::RestoreProjectsBackup => {
+                // the version keeps counting up so stale client snapshots are still rejected
+                let version = projects_data.version;
+                *projects_data = ProjectsData::load_backup()?;
+                projects_data.version = version;
+            }
+            Change::RestoreCompilersBackup => {
+                *compilers = CompilerConfigurations::load_backup()?;
             }
         }
+        return Ok(());
     }
+}
 
-    async fn new_project(file_path: String, workspace_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.new_project(&file_path, workspace_id)?;
-        return projects_data.save().await;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+    use crate::utils::FilePath;
 
-    async fn add_project_link(project_id: usize, workspace_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.add_project_link(project_id, workspace_id)?;
-        return projects_data.save().await;
+    fn saved_files() -> (String, String) {
+        return (
+            std::fs::read_to_string(ProjectsData::get_file_path()).unwrap(),
+            std::fs::read_to_string(CompilerConfigurations::get_file_path()).unwrap(),
+        );
     }
 
-    async fn remove_project_link(project_link_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.remove_project_link(project_link_id);
-        return projects_data.save().await;
+    #[tokio::test]
+    async fn invalid_results_are_not_saved() {
+        let _session = test_session().await;
+        let before = saved_files();
+        let duplicate_names = ChangeSet {
+            changes: vec![
+                Change::AddWorkspace { name: "Main".to_string(), compiler: "12.0".to_string() },
+                Change::AddWorkspace { name: "Main".to_string(), compiler: "12.0".to_string() },
+            ],
+            atomic: true,
+        };
+        assert!(duplicate_names.execute().await.is_err());
+        assert_eq!(saved_files(), before);
+        assert!(PROJECTS_DATA.read().await.workspaces.is_empty());
     }
 
-    async fn move_project(project_link_id: usize, drop_target: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.move_project_link(project_link_id, drop_target)?;
-        return projects_data.save().await;
+    #[tokio::test]
+    async fn a_failing_removal_rejects_the_whole_set() {
+        let _session = test_session().await;
+        let before = saved_files();
+        let change_set = ChangeSet {
+            changes: vec![
+                Change::AddWorkspace { name: "Main".to_string(), compiler: "12.0".to_string() },
+                Change::RemoveWorkspace { workspace_id: 12345 },
+            ],
+            atomic: true,
+        };
+        let error = change_set.execute().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Change 2 of 2"));
+        assert_eq!(saved_files(), before);
     }
 
-    async fn refresh_project(project_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.refresh_project_paths(project_id)?;
-        return projects_data.save().await;
+    #[tokio::test]
+    async fn compilers_in_use_are_not_removed() {
+        let _session = test_session().await;
+        let before = saved_files();
+        // the group project compiler still refers to it
+        let remove_compiler = ChangeSet {
+            changes: vec![Change::RemoveCompiler { compiler: "12.0".to_string() }],
+            atomic: true,
+        };
+        assert!(remove_compiler.execute().await.is_err());
+        assert_eq!(saved_files(), before);
+        assert!(COMPILER_CONFIGURATIONS.read().await.contains_key("12.0"));
     }
 
-    async fn select_project(project_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.select_project(project_id)?;
-        return projects_data.save().await;
+    #[tokio::test]
+    async fn valid_changes_save_both_files() {
+        let _session = test_session().await;
+        let installation = tempfile::tempdir().unwrap();
+        let mut compiler = COMPILER_CONFIGURATIONS.read().await.get("12.0").unwrap().clone();
+        compiler.product_name = "Custom".to_string();
+        compiler.installation_path = installation.path().to_string_lossy().to_string();
+        compiler.env_script = Some(String::new());
+        let change_set = ChangeSet {
+            changes: vec![
+                Change::AddCompiler { key: "custom".to_string(), config: compiler },
+                Change::AddWorkspace { name: "Main".to_string(), compiler: "custom".to_string() },
+            ],
+            atomic: true,
+        };
+        change_set.execute().await.unwrap();
+        let (projects, compilers) = saved_files();
+        assert!(projects.contains("custom"));
+        assert!(compilers.contains("Custom"));
     }
 
-    async fn update_project(project_id: usize, data: ProjectUpdateData) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.update_project(project_id, data)?;
-        return projects_data.save().await;
-    }
+    #[tokio::test]
+    async fn a_removed_workspace_can_be_recovered_from_the_backup() {
+        let _session = test_session().await;
+        let path = ProjectsData::get_file_path();
+        let backups = path.parent().unwrap().join("backups");
+        let _ = std::fs::remove_dir_all(&backups);
+        let add = ChangeSet {
+            changes: vec![Change::AddWorkspace { name: "Doomed".to_string(), compiler: "12.0".to_string() }],
+            atomic: true,
+        };
+        add.execute().await.unwrap();
+        let workspace_id = PROJECTS_DATA.read().await.workspaces[0].id;
 
-    async fn add_workspace(name: String, compiler: String) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.new_workspace(&name, &compiler).await?;
-        return projects_data.save().await;
-    }
+        let remove = ChangeSet { changes: vec![Change::RemoveWorkspace { workspace_id }], atomic: true };
+        remove.execute().await.unwrap();
 
-    async fn remove_workspace(workspace_id: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.remove_workspace(workspace_id);
-        return projects_data.save().await;
+        assert!(PROJECTS_DATA.read().await.workspaces.is_empty());
+        let saved: Vec<std::path::PathBuf> = std::fs::read_dir(&backups).unwrap().flatten().map(|entry| entry.path()).collect();
+        assert_eq!(saved.len(), 1);
+        let backup: ProjectsData = crate::utils::deserialize_data(path, &std::fs::read_to_string(&saved[0]).unwrap()).unwrap();
+        let names: Vec<&str> = backup.workspaces.iter().map(|workspace| workspace.name.as_str()).collect();
+        assert_eq!(names, vec!["Doomed"]);
     }
 
-    async fn move_workspace(workspace_id: usize, drop_target: usize) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.move_workspace(workspace_id, drop_target)?;
-        return projects_data.save().await;
+    fn is_sorted(data: &ProjectsData) -> bool {
+        return data.workspaces.windows(2).all(|pair| pair[0].sort_rank < pair[1].sort_rank)
+            && data.workspaces.iter().all(|workspace| {
+                workspace.project_links.windows(2).all(|pair| pair[0].sort_rank < pair[1].sort_rank)
+            });
     }
 
-    async fn update_workspace(workspace_id: usize, data: WorkspaceUpdateData) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.update_workspace(workspace_id, &data).await?;
-        return projects_data.save().await;
-    }
-
-    async fn add_compiler(key: String, config: CompilerConfiguration) -> Result<()> {
-        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
-        compilers.insert(key, config);
-        return compilers.save().await;
-    }
-
-    async fn remove_compiler(compiler: String) -> Result<()> {
-        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
-        if compilers.remove(&compiler).is_none() {
-            anyhow::bail!("Unable to remove compiler - compiler not found: {}", compiler);
+    #[tokio::test]
+    async fn data_is_kept_in_rank_order_after_adds_and_moves() {
+        let _session = test_session().await;
+        let apply = |change: Change| async move {
+            ChangeSet { changes: vec![change], atomic: false }.execute().await.unwrap();
+        };
+        for name in ["A", "B", "C"] {
+            apply(Change::AddWorkspace { name: name.to_string(), compiler: "12.0".to_string() }).await;
         }
-        return compilers.save().await;
-    }
+        let workspace_ids: Vec<usize> = PROJECTS_DATA.read().await.workspaces.iter().map(|ws| ws.id).collect();
+        for project in ["X", "Y", "Z"] {
+            apply(Change::NewProject { file_path: format!("/projects/{project}.dpr"), workspace_id: workspace_ids[0] }).await;
+        }
+        let link_ids: Vec<usize> = PROJECTS_DATA.read().await.workspaces[0].project_links.iter().map(|link| link.id).collect();
+        apply(Change::MoveProject { project_link_id: link_ids[2], drop_target: link_ids[0] }).await;
+        apply(Change::MoveWorkspace { workspace_id: workspace_ids[2], drop_target: workspace_ids[0] }).await;
 
-    async fn update_compiler(key: String, data: PartialCompilerConfiguration) -> Result<()> {
-        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
-        if let Some(compiler) = compilers.get_mut(&key) {
-            compiler.update(&data);
-            return compilers.save().await;
-        } else {
-            anyhow::bail!("Unable to update compiler - compiler not found: {}", key);
+        let path = ProjectsData::get_file_path();
+        let saved: ProjectsData = crate::utils::deserialize_data(path, &std::fs::read_to_string(path).unwrap()).unwrap();
+        for data in [&*PROJECTS_DATA.read().await, &saved] {
+            assert!(is_sorted(data));
+            let workspaces: Vec<&str> = data.workspaces.iter().map(|ws| ws.name.as_str()).collect();
+            assert_eq!(workspaces, ["C", "A", "B"]);
+            let links: Vec<usize> = data.workspaces[1].project_links.iter().map(|link| link.id).collect();
+            assert_eq!(links, [link_ids[2], link_ids[0], link_ids[1]]);
         }
     }
 
-    async fn set_group_project(groupproj_path: String) -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.set_group_project(&groupproj_path)?;
-        return projects_data.save().await;
-    }
+    #[tokio::test]
+    async fn detected_compilers_are_applied_without_detecting_again() {
+        let _session = test_session().await;
+        let change: Change = serde_json::from_value(serde_json::json!({ "type": "DetectCompilers" })).unwrap();
+        assert!(matches!(change, Change::DetectCompilers { detected: None }));
 
-    async fn remove_group_project() -> Result<()> {
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.remove_group_project();
-        return projects_data.save().await;
-    }
+        let installation = tempfile::tempdir().unwrap();
+        let installation_path = installation.path().to_string_lossy().to_string();
+        std::fs::create_dir(installation.path().join("bin")).unwrap();
+        std::fs::write(installation.path().join("bin").join("rsvars.bat"), "").unwrap();
+        let mut detected = CompilerConfigurations::default();
+        detected.get_mut("12.0").unwrap().installation_path = installation_path.clone();
+        let change = Change::DetectCompilers { detected: Some(detected) };
+        assert!(matches!(change.clone().with_detected_compilers(), Change::DetectCompilers { detected: Some(_) }));
 
-    async fn set_group_project_compiler(compiler: String) -> Result<()> {
-        if !compiler_exists(&compiler).await {
-            anyhow::bail!(
-                "Unable to set group project compiler - compiler not found: {}",
-                compiler
-            );
-        }
-        let mut projects_data = PROJECTS_DATA.write().await;
-        projects_data.group_project_compiler_id = compiler.clone();
-        return projects_data.save().await;
+        ChangeSet { changes: vec![change], atomic: true }.execute().await.unwrap();
+        let compilers = COMPILER_CONFIGURATIONS.read().await;
+        assert_eq!(compilers.get("12.0").unwrap().installation_path, installation_path);
     }
 }
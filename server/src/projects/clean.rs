@@ -0,0 +1,164 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Build leftovers msbuild's `Clean` target doesn't always remove.
+pub const DEFAULT_CLEAN_EXTENSIONS: &[&str] = &["dcu", "dcp", "bpl", "o", "identcache"];
+/// IDE backup folders.
+pub const DEFAULT_CLEAN_FOLDERS: &[&str] = &["__history", "__recovery"];
+
+/// What the clean command deletes after msbuild's `Clean`, saved per project.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CleanSettings {
+    /// Extensions of the files deleted from the project's output directories.
+    pub extensions: Vec<String>,
+    /// Names of the folders deleted anywhere under the project directory.
+    pub folders: Vec<String>,
+}
+
+impl Default for CleanSettings {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        return CleanSettings {
+            extensions: names(DEFAULT_CLEAN_EXTENSIONS),
+            folders: names(DEFAULT_CLEAN_FOLDERS),
+        };
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CleanReport {
+    pub deleted: Vec<PathBuf>,
+    /// Entries that could not be deleted or read, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Deletes the files with one of the `settings` extensions directly in `output_directories` and
+/// the folders named like one of the `settings` folders anywhere under `root`. Output directories
+/// outside `root` are left alone, as are the subdirectories of output directories, so vendored
+/// precompiled units next to the sources survive. Symlinks are neither followed nor deleted;
+/// hidden directories such as `.git` are skipped. A failing entry doesn't stop the others.
+pub fn clean_directory(root: &Path, output_directories: &[PathBuf], settings: &CleanSettings) -> Result<CleanReport> {
+    let root = root.canonicalize()?;
+    let extensions: Vec<String> = settings
+        .extensions
+        .iter()
+        .map(|extension| extension.trim_start_matches('.').to_lowercase())
+        .collect();
+    let mut report = CleanReport::default();
+    let mut cleaned: Vec<PathBuf> = Vec::new();
+    for directory in output_directories {
+        // output directories that don't exist yet have nothing to clean
+        let Ok(directory) = directory.canonicalize() else {
+            continue;
+        };
+        if !directory.starts_with(&root) || cleaned.contains(&directory) {
+            continue;
+        }
+        remove_files(&directory, &extensions, &mut report);
+        cleaned.push(directory);
+    }
+    remove_folders(&root, &settings.folders, &mut report);
+    report.deleted.sort();
+    return Ok(report);
+}
+
+fn remove_files(directory: &Path, extensions: &[String], report: &mut CleanReport) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.failed.push((directory.to_path_buf(), e.to_string()));
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // `file_type` describes the entry itself, a symlink is never reported as a file
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let matches = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extensions.contains(&extension.to_lowercase()));
+        if !matches {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => report.deleted.push(path),
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+    }
+}
+
+fn remove_folders(root: &Path, folders: &[String], report: &mut CleanReport) {
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.failed.push((current, e.to_string()));
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if folders.iter().any(|folder| folder.eq_ignore_ascii_case(&name)) {
+                match std::fs::remove_dir_all(&path) {
+                    Ok(()) => report.deleted.push(path),
+                    Err(e) => report.failed.push((path, e.to_string())),
+                }
+            } else if !name.starts_with('.') {
+                pending.push(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn only_output_directories_lose_their_units() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+        let output = root.join("Win32").join("Debug");
+        touch(&output.join("Main.dcu"));
+        touch(&output.join("Main.exe"));
+        touch(&root.join("vendor").join("Library.dcu"));
+        touch(&root.join("__history").join("Main.pas.~1~"));
+        touch(&root.join("Main.pas"));
+
+        let report = clean_directory(&root, std::slice::from_ref(&output), &CleanSettings::default()).unwrap();
+
+        assert_eq!(report.deleted, vec![output.join("Main.dcu"), root.join("__history")]);
+        assert!(report.failed.is_empty());
+        assert!(output.join("Main.exe").exists());
+        assert!(root.join("vendor").join("Library.dcu").exists());
+        assert!(root.join("Main.pas").exists());
+    }
+
+    #[test]
+    fn output_directories_outside_the_project_are_skipped() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap().join("Project");
+        let shared = directory.path().canonicalize().unwrap().join("Shared");
+        touch(&root.join("Project.dproj"));
+        touch(&shared.join("Other.dcu"));
+
+        let report = clean_directory(&root, std::slice::from_ref(&shared), &CleanSettings::default()).unwrap();
+
+        assert!(report.deleted.is_empty());
+        assert!(shared.join("Other.dcu").exists());
+    }
+}
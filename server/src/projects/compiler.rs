@@ -1,65 +1,505 @@
 use super::*;
-use crate::state::PROJECTS_DATA;
-use crate::{CompileProjectParams, CompilerProgress, defer_async};
+use crate::files::{get_build_configurations, get_required_packages};
+use crate::state::{PROJECTS_DATA, SERVER_SETTINGS};
+use crate::{CompileProjectParams, CompilerProgress, DiagnosticStream, lsp_error};
+use crate::settings::DiagnosticStreamSettings;
+use crate::utils::{comparable_path, expand_variables};
+use chrono::Local;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use scopeguard::defer;
-use std::path::PathBuf;
-use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tower_lsp::lsp_types::{Diagnostic, Url};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+use tokio::process::Child;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinHandle, JoinSet};
+use tower_lsp::lsp_types::Diagnostic;
 
 pub struct Compiler {
     client: tower_lsp::Client,
     params: CompileProjectParams,
     projects_data: ProjectsData,
+    publish: bool,
+    /// Parsed diagnostics of this build, across all of its parts.
+    diagnostics: Arc<std::sync::Mutex<Vec<CompilerLineDiagnostic>>>,
+    control: Arc<BuildControl>,
+    /// `QUEUE_GENERATION` when the build was queued.
+    generation: usize,
 }
 
-static ACTIVE: AtomicBool = AtomicBool::new(false);
-static SUCCESS: AtomicBool = AtomicBool::new(false);
-static CODE: AtomicIsize = AtomicIsize::new(-1);
-pub static CANCEL_COMPILATION: AtomicBool = AtomicBool::new(false);
+/// What a finished build produced, so callers don't depend on state a later build overwrites.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOutcome {
+    pub success: bool,
+    pub results: Vec<ProjectResult>,
+    pub diagnostics: Vec<CompilerLineDiagnostic>,
+}
+
+impl BuildOutcome {
+    /// The parsed diagnostics grouped by file.
+    pub fn diagnostics_by_file(&self) -> BTreeMap<String, Vec<CompilerLineDiagnostic>> {
+        return group_by_file(&self.diagnostics);
+    }
+}
+
+/// Workspaces and the group project: link containers whose projects are compiled together.
+trait CompilableContainer: ProjectLinkContainer {
+    /// What the container is called in the build banners.
+    fn entity_type(&self) -> &'static str;
+    async fn compiler(&self, projects_data: &ProjectsData) -> CompilerConfiguration;
+    /// The workspace whose default platform and configuration apply, `None` for the group project.
+    fn as_workspace(&self) -> Option<&Workspace>;
+}
+
+impl CompilableContainer for Workspace {
+    fn entity_type(&self) -> &'static str {
+        return "Workspace";
+    }
+
+    async fn compiler(&self, _projects_data: &ProjectsData) -> CompilerConfiguration {
+        return Workspace::compiler(self).await;
+    }
+
+    fn as_workspace(&self) -> Option<&Workspace> {
+        return Some(self);
+    }
+}
+
+impl CompilableContainer for GroupProject {
+    fn entity_type(&self) -> &'static str {
+        return "Group Project";
+    }
+
+    async fn compiler(&self, projects_data: &ProjectsData) -> CompilerConfiguration {
+        return projects_data.group_projects_compiler().await;
+    }
+
+    fn as_workspace(&self) -> Option<&Workspace> {
+        return None;
+    }
+}
+
+/// Exit code reported for a project killed after exceeding the compiler's `timeout_seconds`.
+pub const TIMEOUT_EXIT_CODE: isize = -2;
+/// How long the output readers of a killed build may keep draining before they are aborted.
+const READER_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Compilations running or waiting for their turn.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+/// Bumped on cancellation; queued compilations from an older generation don't run.
+static QUEUE_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static::lazy_static! {
+    /// Builds run one at a time, in request order, on a worker task of their own.
+    static ref BUILD_QUEUE: std::sync::Mutex<Option<mpsc::UnboundedSender<QueuedBuild>>> =
+        std::sync::Mutex::new(None);
+    /// The build the worker is running, for the cancel and skip commands to steer.
+    static ref RUNNING_BUILD: std::sync::Mutex<Option<Arc<BuildControl>>> = std::sync::Mutex::new(None);
+    static ref LAST_BUILD_DIAGNOSTICS: std::sync::Mutex<Vec<CompilerLineDiagnostic>> =
+        std::sync::Mutex::new(Vec::new());
+    /// Outcome of the most recent build of each project since the server started.
+    static ref LAST_PROJECT_RESULTS: std::sync::Mutex<HashMap<usize, ProjectResult>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// Parsed diagnostics of the last (or currently running) build, grouped by file. Callers that
+/// started the build themselves use the `BuildOutcome` instead, which a later build can't replace.
+pub fn last_build_diagnostics() -> BTreeMap<String, Vec<CompilerLineDiagnostic>> {
+    let Ok(diagnostics) = LAST_BUILD_DIAGNOSTICS.lock() else {
+        return BTreeMap::new();
+    };
+    return group_by_file(&diagnostics);
+}
+
+fn group_by_file(diagnostics: &[CompilerLineDiagnostic]) -> BTreeMap<String, Vec<CompilerLineDiagnostic>> {
+    let mut grouped: BTreeMap<String, Vec<CompilerLineDiagnostic>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        grouped
+            .entry(diagnostic.file.clone())
+            .or_default()
+            .push(diagnostic.clone());
+    }
+    return grouped;
+}
+
+pub fn last_project_result(project_id: usize) -> Option<ProjectResult> {
+    return LAST_PROJECT_RESULTS
+        .lock()
+        .ok()
+        .and_then(|results| results.get(&project_id).cloned());
+}
+
+/// A build waiting for its turn on the build worker.
+struct QueuedBuild {
+    request: BuildRequest,
+    /// `QUEUE_GENERATION` when it was queued.
+    generation: usize,
+    done: oneshot::Sender<Result<BuildOutcome>>,
+}
+
+/// Hands the build to the worker, starting one if none is running; a worker that stopped
+/// (e.g. after a panic) is replaced.
+fn send_to_worker(build: QueuedBuild) -> std::result::Result<(), Box<QueuedBuild>> {
+    let Ok(mut queue) = BUILD_QUEUE.lock() else {
+        return Err(Box::new(build));
+    };
+    let sender = queue.get_or_insert_with(spawn_build_worker);
+    if sender.is_closed() {
+        *sender = spawn_build_worker();
+    }
+    return sender.send(build).map_err(|mpsc::error::SendError(build)| Box::new(build));
+}
+
+fn spawn_build_worker() -> mpsc::UnboundedSender<QueuedBuild> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<QueuedBuild>();
+    tokio::spawn(async move {
+        while let Some(build) = receiver.recv().await {
+            let outcome = if QUEUE_GENERATION.load(Ordering::SeqCst) != build.generation {
+                Err(anyhow::anyhow!("Queued compilation cancelled by user."))
+            } else {
+                // the data is read now, so edits made while the build was queued are built
+                Compiler::new(build.request.client, &build.request.params, build.request.publish, build.generation)
+                    .await
+                    .compile()
+                    .await
+            };
+            PENDING.fetch_sub(1, Ordering::SeqCst);
+            let _ = build.done.send(outcome);
+        }
+    });
+    return sender;
+}
+
+/// A compilation to run on the build worker once the builds requested before it have finished.
+pub struct BuildRequest {
+    client: tower_lsp::Client,
+    params: CompileProjectParams,
+    publish: bool,
+}
+
+impl BuildRequest {
+    pub fn new(client: tower_lsp::Client, params: &CompileProjectParams) -> Self {
+        return BuildRequest {
+            client,
+            params: params.clone(),
+            publish: true,
+        };
+    }
+
+    /// Parses diagnostics as usual but doesn't publish them to the editor.
+    pub fn without_publishing(mut self) -> Self {
+        self.publish = false;
+        return self;
+    }
+
+    /// Queues the build and returns right away; the receiver gets the outcome once it ran.
+    pub async fn queue(self) -> oneshot::Receiver<Result<BuildOutcome>> {
+        let (done, outcome) = oneshot::channel();
+        let client = self.client.clone();
+        let ahead = PENDING.fetch_add(1, Ordering::SeqCst);
+        let build = QueuedBuild {
+            request: self,
+            generation: QUEUE_GENERATION.load(Ordering::SeqCst),
+            done,
+        };
+        if let Err(build) = send_to_worker(build) {
+            PENDING.fetch_sub(1, Ordering::SeqCst);
+            let _ = build.done.send(Err(anyhow::anyhow!("The build worker has stopped.")));
+        } else if ahead > 0 {
+            CompilerProgress::notify_queued(&client, ahead).await;
+        }
+        return outcome;
+    }
+
+    /// Queues the build and waits for its outcome.
+    pub async fn run(self) -> Result<BuildOutcome> {
+        return self.queue().await.await.unwrap_or_else(|_| Err(anyhow::anyhow!("The build was dropped.")));
+    }
+}
+
+/// Steers one build; shared by its project builds and the cancel and skip commands.
+#[derive(Default)]
+struct BuildControl {
+    cancelled: AtomicBool,
+    /// Skip flags of the projects being built, in the order they started.
+    running: std::sync::Mutex<Vec<Arc<AtomicBool>>>,
+}
+
+impl BuildControl {
+    fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::SeqCst);
+    }
+
+    /// Registers a project whose build starts; it is killed once the returned flag is set.
+    fn start_project(&self) -> Arc<AtomicBool> {
+        let skip = Arc::new(AtomicBool::new(false));
+        if let Ok(mut running) = self.running.lock() {
+            running.push(skip.clone());
+        }
+        return skip;
+    }
+
+    fn finish_project(&self, skip: &Arc<AtomicBool>) {
+        if let Ok(mut running) = self.running.lock() {
+            running.retain(|flag| !Arc::ptr_eq(flag, skip));
+        }
+    }
+
+    /// Skips the longest running project that isn't being skipped already.
+    fn skip_one(&self) -> bool {
+        let Ok(running) = self.running.lock() else {
+            return false;
+        };
+        let Some(skip) = running.iter().find(|skip| !skip.load(Ordering::SeqCst)) else {
+            return false;
+        };
+        skip.store(true, Ordering::SeqCst);
+        return true;
+    }
+}
+
+fn running_build() -> Option<Arc<BuildControl>> {
+    return RUNNING_BUILD.lock().ok().and_then(|running| running.clone());
+}
+
+/// Cancels the running compilation and every queued one.
+pub fn cancel_compilations() {
+    QUEUE_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Some(control) = running_build() {
+        control.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Asks the running build to abandon one of its projects (with parallel builds, the one
+/// that started first); `false` if no project is building.
+pub fn skip_current_project() -> bool {
+    return running_build().is_some_and(|control| control.skip_one());
+}
+
+/// Waits for the build process, killing it when the build is cancelled or the project skipped.
+/// Returns `None` for a killed build.
+async fn wait_or_skip(child_process: &mut Child, control: &BuildControl, skip: &AtomicBool) -> Result<Option<ExitStatus>> {
+    loop {
+        tokio::select! {
+            status = child_process.wait() => return Ok(Some(status?)),
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {
+                if control.is_cancelled() || skip.load(Ordering::SeqCst) {
+                    kill_process_tree(child_process).await?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// What an output reader saw of one project's build.
+#[derive(Default)]
+struct DiagnosticCounts {
+    errors: usize,
+    warnings: usize,
+    hints: usize,
+    /// Files with at least one error, relative paths resolved against the project directory,
+    /// keyed by `comparable_path` so each unit is listed once.
+    failed_units: BTreeMap<PathBuf, String>,
+    /// The diagnostics to publish, by file resolved against the project directory.
+    diagnostics: BTreeMap<PathBuf, Vec<Diagnostic>>,
+}
+
+impl DiagnosticCounts {
+    fn count(&mut self, diagnostic: &CompilerLineDiagnostic, project_directory: &Path) {
+        match diagnostic.kind {
+            DiagnosticKind::ERROR => {
+                self.errors += 1;
+                let unit = project_directory.join(&diagnostic.file);
+                self.failed_units
+                    .entry(comparable_path(&unit))
+                    .or_insert_with(|| unit.to_string_lossy().to_string());
+            }
+            DiagnosticKind::WARN => self.warnings += 1,
+            DiagnosticKind::HINT => self.hints += 1,
+        }
+    }
+
+    fn merge(mut self, other: DiagnosticCounts) -> Self {
+        self.errors += other.errors;
+        self.warnings += other.warnings;
+        self.hints += other.hints;
+        self.failed_units.extend(other.failed_units);
+        for (file, diagnostics) in other.diagnostics {
+            self.diagnostics.entry(file).or_default().extend(diagnostics);
+        }
+        return self;
+    }
+}
+
+/// Joins an output reader. Processes started by a killed build may keep the pipes open,
+/// so after a kill the reader only gets a grace period before it is aborted.
+async fn join_reader(mut reader: JoinHandle<DiagnosticCounts>, killed: bool) -> Result<DiagnosticCounts> {
+    if !killed {
+        return Ok(reader.await?);
+    }
+    match tokio::time::timeout(READER_GRACE_PERIOD, &mut reader).await {
+        Ok(counts) => return Ok(counts?),
+        Err(_) => {
+            reader.abort();
+            return Ok(DiagnosticCounts::default());
+        }
+    }
+}
+
+/// msbuild runs as a child of `cmd` on Windows, so killing `cmd` alone would leave it building.
+async fn kill_process_tree(child_process: &mut Child) -> Result<()> {
+    #[cfg(windows)]
+    if let Some(pid) = child_process.id() {
+        tokio::process::Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        child_process.wait().await?;
+        return Ok(());
+    }
+    child_process.kill().await?;
+    Ok(())
+}
+
+/// Target platforms msbuild accepts for Delphi projects.
+pub const PLATFORMS: &[&str] = &["Win32", "Win64", "Linux64", "OSX64", "Android", "iOSDevice64"];
+
+/// Returns the canonical spelling of `platform`, failing for unknown platforms.
+pub fn validate_platform(platform: &str) -> Result<&'static str> {
+    return PLATFORMS
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(platform))
+        .copied()
+        .ok_or_else(|| {
+            anyhow::anyhow!("Unknown platform '{}'. Expected one of: {}", platform, PLATFORMS.join(", "))
+        });
+}
+
+/// Effective configuration and platform of a compilation; `None` keeps the .dproj default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildVariant {
+    pub config: Option<String>,
+    pub platform: Option<String>,
+}
+
+impl BuildVariant {
+    /// The `/p:` properties selecting this variant, each with a leading space.
+    pub fn msbuild_properties(&self) -> String {
+        let mut properties = String::new();
+        if let Some(config) = &self.config {
+            properties.push_str(&format!(" /p:Config={}", config));
+        }
+        if let Some(platform) = &self.platform {
+            properties.push_str(&format!(" /p:Platform={}", platform));
+        }
+        return properties;
+    }
+
+    fn describe(&self) -> Option<String> {
+        return match (&self.config, &self.platform) {
+            (None, None) => None,
+            (config, platform) => Some(format!(
+                "{} | {}",
+                config.as_deref().unwrap_or("(default)"),
+                platform.as_deref().unwrap_or("(default)")
+            )),
+        };
+    }
+
+    /// Checks the configuration against the ones each project declares in its .dproj.
+    fn validate_for(&self, projects: &[&Project]) -> Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        for project in projects {
+            let Some(dproj) = &project.dproj else {
+                continue;
+            };
+            let available = get_build_configurations(&PathBuf::from(dproj)).unwrap_or_default();
+            if !available.is_empty() && !available.iter().any(|name| name.eq_ignore_ascii_case(config)) {
+                anyhow::bail!(
+                    "Project {} has no build configuration '{}'. Available: {}",
+                    project.name,
+                    config,
+                    available.join(", ")
+                );
+            }
+        }
+        return Ok(());
+    }
+}
 
 impl Compiler {
-    pub async fn new(client: tower_lsp::Client, params: &CompileProjectParams) -> Self {
+    async fn new(client: tower_lsp::Client, params: &CompileProjectParams, publish: bool, generation: usize) -> Self {
         Compiler {
             client,
             params: params.clone(),
             projects_data: PROJECTS_DATA.read().await.clone(),
+            publish,
+            diagnostics: Arc::new(std::sync::Mutex::new(Vec::new())),
+            control: Arc::new(BuildControl::default()),
+            generation,
         }
     }
 
+    /// The requested configuration and platform, falling back to the workspace's defaults.
+    fn variant(&self, workspace: Option<&Workspace>) -> Result<BuildVariant> {
+        let platform = self
+            .params
+            .platform()
+            .map(str::to_string)
+            .or_else(|| workspace.and_then(|ws| ws.default_platform.clone()));
+        let config = self
+            .params
+            .config()
+            .map(str::to_string)
+            .or_else(|| workspace.and_then(|ws| ws.default_config.clone()));
+        return Ok(BuildVariant {
+            config,
+            platform: platform.map(|platform| validate_platform(&platform).map(str::to_string)).transpose()?,
+        });
+    }
+
     async fn get_project_parameters<'a>(
         &'a self,
         project_id: usize,
         project_link_id: Option<usize>,
         rebuild: bool,
     ) -> Result<CompilationParameters<'a>> {
-        let configuration;
+        let (configuration, variant, workspace_name);
         let project = self
             .projects_data
             .get_project(project_id)
             .ok_or_else(|| anyhow::anyhow!("Project with id {} not found", project_id))?;
         if let Some(link_id) = project_link_id {
-            if self.projects_data.is_project_link_in_group_project(link_id) {
-                configuration = self.projects_data.group_projects_compiler().await;
-            } else if let Some(workspace_id) = self
-                .projects_data
-                .get_workspace_id_containing_project_link(link_id)
-            {
-                let workspace =
-                    self.projects_data
-                        .get_workspace(workspace_id)
-                        .ok_or_else(|| {
-                            anyhow::anyhow!("Workspace with id {} not found", workspace_id)
-                        })?;
-                configuration = workspace.compiler().await;
-            } else {
-                anyhow::bail!(
+            match self.projects_data.container_of_link(link_id) {
+                Some(LinkContainer::GroupProject) => {
+                    configuration = self.projects_data.group_projects_compiler().await;
+                    variant = self.variant(None)?;
+                    workspace_name = self.projects_data.group_project.as_ref().map(|gp| gp.name.clone());
+                }
+                Some(LinkContainer::Workspace { workspace_id }) => {
+                    let workspace =
+                        self.projects_data
+                            .get_workspace(workspace_id)
+                            .ok_or_else(|| {
+                                anyhow::anyhow!("Workspace with id {} not found", workspace_id)
+                            })?;
+                    configuration = workspace.compiler().await;
+                    variant = self.variant(Some(workspace))?;
+                    workspace_name = Some(workspace.name.clone());
+                }
+                None => anyhow::bail!(
                     "No workspace or group project contains project link with id {}",
                     link_id
-                );
+                ),
             }
         } else {
             let workspace_id = self
@@ -83,17 +523,21 @@ impl Compiler {
                         project_id
                     )
                 })?;
-            configuration = self
+            let workspace = self
                 .projects_data
                 .get_workspace(workspace_id)
-                .ok_or_else(|| anyhow::anyhow!("Workspace with id {} not found", workspace_id))?
-                .compiler().await;
+                .ok_or_else(|| anyhow::anyhow!("Workspace with id {} not found", workspace_id))?;
+            configuration = workspace.compiler().await;
+            variant = self.variant(Some(workspace))?;
+            workspace_name = Some(workspace.name.clone());
         }
         let target = project.get_project_file()?;
         let compiler_name = configuration.product_name.clone();
         return Ok(CompilationParameters {
             projects: vec![project],
+            dependencies: Vec::new(),
             configuration,
+            workspace_name,
             rebuild,
             single: true,
             header: CompHeader::new(
@@ -102,6 +546,7 @@ impl Compiler {
                 target.to_string_lossy().to_string(),
                 compiler_name.clone(),
                 rebuild,
+                variant.describe(),
             ),
             footer: CompFooter::new(
                 "Project".to_string(),
@@ -109,11 +554,8 @@ impl Compiler {
                 target.to_string_lossy().to_string(),
                 compiler_name,
                 rebuild,
-                Box::new(|| {
-                    // Determine success based on compilation result
-                    SUCCESS.load(Ordering::SeqCst)
-                }),
             ),
+            variant,
         });
     }
 
@@ -126,41 +568,7 @@ impl Compiler {
             Some(ws) => ws,
             _ => anyhow::bail!("Workspace with id {} not found", workspace_id),
         };
-        let configuration = workspace.compiler().await;
-        let projects = workspace
-            .project_links
-            .iter()
-            .map(|link| {
-                self.projects_data
-                    .get_project(link.project_id)
-                    .ok_or_else(|| anyhow::anyhow!("Project with id {} not found", link.project_id))
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let compiler_name = configuration.product_name.clone();
-        return Ok(CompilationParameters {
-            projects,
-            configuration,
-            rebuild,
-            single: false,
-            header: CompHeader::new(
-                "Workspace".to_string(),
-                workspace.name.clone(),
-                format!("Projects of Workspace '{}'", workspace.name),
-                compiler_name.clone(),
-                rebuild,
-            ),
-            footer: CompFooter::new(
-                "Workspace".to_string(),
-                workspace.name.clone(),
-                format!("Projects of Workspace '{}'", workspace.name),
-                compiler_name,
-                rebuild,
-                Box::new(|| {
-                    // Determine success based on compilation result
-                    SUCCESS.load(Ordering::SeqCst)
-                }),
-            ),
-        });
+        return self.get_all_container_parameters(workspace, rebuild).await;
     }
 
     async fn get_all_group_project_parameters<'a>(
@@ -171,9 +579,20 @@ impl Compiler {
             Some(gp) => gp,
             _ => anyhow::bail!("No group project defined"),
         };
-        let configuration = self.projects_data.group_projects_compiler().await;
-        let projects = group_project
-            .project_links
+        return self.get_all_container_parameters(group_project, rebuild).await;
+    }
+
+    async fn get_all_container_parameters<'a, C: CompilableContainer>(
+        &'a self,
+        container: &'a C,
+        rebuild: bool,
+    ) -> Result<CompilationParameters<'a>> {
+        let configuration = container.compiler(&self.projects_data).await;
+        let variant = self.variant(container.as_workspace())?;
+        let name = container.get_name();
+        let entity_type = container.entity_type();
+        let projects = container
+            .get_project_links()
             .iter()
             .map(|link| {
                 self.projects_data
@@ -184,27 +603,27 @@ impl Compiler {
         let compiler_name = configuration.product_name.clone();
         return Ok(CompilationParameters {
             projects,
+            dependencies: Vec::new(),
             configuration,
+            workspace_name: Some(name.clone()),
             rebuild,
             single: false,
             header: CompHeader::new(
-                "Group Project".to_string(),
-                group_project.name.clone(),
-                format!("Projects of Group Project '{}'", group_project.name),
+                entity_type.to_string(),
+                name.clone(),
+                format!("Projects of {} '{}'", entity_type, name),
                 compiler_name.clone(),
                 rebuild,
+                variant.describe(),
             ),
             footer: CompFooter::new(
-                "Group Project".to_string(),
-                group_project.name.clone(),
-                format!("Projects of Group Project '{}'", group_project.name),
+                entity_type.to_string(),
+                name.clone(),
+                format!("Projects of {} '{}'", entity_type, name),
                 compiler_name,
                 rebuild,
-                Box::new(|| {
-                    // Determine success based on compilation result
-                    SUCCESS.load(Ordering::SeqCst)
-                }),
             ),
+            variant,
         });
     }
 
@@ -213,7 +632,7 @@ impl Compiler {
         project_link_id: usize,
         rebuild: bool,
     ) -> Result<CompilationParameters<'a>> {
-        let (projects, configuration, header, footer);
+        let (projects, configuration, variant, workspace_name, header, footer);
         if let Some(workspace_id) = self
             .projects_data
             .get_workspace_id_containing_project_link(project_link_id)
@@ -234,6 +653,8 @@ impl Compiler {
                     })
                     .collect::<Result<Vec<_>>>()?;
                 configuration = workspace.compiler().await;
+                variant = self.variant(Some(workspace))?;
+                workspace_name = Some(workspace.name.clone());
                 let project_name = projects
                     .first()
                     .map(|p| p.name.clone())
@@ -247,6 +668,7 @@ impl Compiler {
                     ),
                     configuration.product_name.clone(),
                     rebuild,
+                    variant.describe(),
                 );
                 footer = CompFooter::new(
                     format!("Workspace '{}'", workspace.name),
@@ -257,10 +679,6 @@ impl Compiler {
                     ),
                     configuration.product_name.clone(),
                     rebuild,
-                    Box::new(|| {
-                        // Determine success based on compilation result
-                        SUCCESS.load(Ordering::SeqCst)
-                    }),
                 );
             } else {
                 anyhow::bail!(
@@ -282,6 +700,8 @@ impl Compiler {
                     })
                     .collect::<Result<Vec<_>>>()?;
                 configuration = self.projects_data.group_projects_compiler().await;
+                variant = self.variant(None)?;
+                workspace_name = Some(group_project.name.clone());
                 let project_name = projects
                     .first()
                     .map(|p| p.name.clone())
@@ -295,6 +715,7 @@ impl Compiler {
                     ),
                     configuration.product_name.clone(),
                     rebuild,
+                    variant.describe(),
                 );
                 footer = CompFooter::new(
                     format!("Group Project '{}'", group_project.name),
@@ -305,10 +726,6 @@ impl Compiler {
                     ),
                     configuration.product_name.clone(),
                     rebuild,
-                    Box::new(|| {
-                        // Determine success based on compilation result
-                        SUCCESS.load(Ordering::SeqCst)
-                    }),
                 );
             } else {
                 anyhow::bail!(
@@ -325,7 +742,10 @@ impl Compiler {
         }
         return Ok(CompilationParameters {
             projects,
+            dependencies: Vec::new(),
             configuration,
+            variant,
+            workspace_name,
             rebuild,
             single: false,
             header,
@@ -333,201 +753,850 @@ impl Compiler {
         });
     }
 
-    pub async fn compile(&self) -> Result<()> {
-        if ACTIVE.load(Ordering::SeqCst) {
-            anyhow::bail!(
-                "Another compilation is already in progress. Please wait until it finishes."
-            );
+    /// Resolves an ad-hoc list of links, which may live in different workspaces, into one
+    /// set of parameters per consecutive run of links sharing a compiler.
+    async fn get_selection_parameters<'a>(
+        &'a self,
+        project_link_ids: &[usize],
+        rebuild: bool,
+    ) -> Result<Vec<CompilationParameters<'a>>> {
+        if project_link_ids.is_empty() {
+            anyhow::bail!("No projects selected");
+        }
+        let mut groups: Vec<(CompilerConfiguration, BuildVariant, Option<String>, Vec<&'a Project>)> = Vec::new();
+        for link_id in project_link_ids {
+            let (configuration, variant, workspace_name, link) = match self.projects_data.container_of_link(*link_id) {
+                Some(LinkContainer::GroupProject) => {
+                    let group_project = self.projects_data.group_project.as_ref()
+                        .ok_or_else(|| anyhow::anyhow!("No group project defined"))?;
+                    let link = group_project.index_of(*link_id).map(|index| &group_project.project_links[index]);
+                    let name = Some(group_project.name.clone());
+                    (self.projects_data.group_projects_compiler().await, self.variant(None)?, name, link)
+                }
+                Some(LinkContainer::Workspace { workspace_id }) => {
+                    let workspace = self.projects_data.get_workspace(workspace_id)
+                        .ok_or_else(|| anyhow::anyhow!("Workspace with id {} not found", workspace_id))?;
+                    let link = workspace.index_of(*link_id).map(|index| &workspace.project_links[index]);
+                    let name = Some(workspace.name.clone());
+                    (workspace.compiler().await, self.variant(Some(workspace))?, name, link)
+                }
+                None => anyhow::bail!(
+                    "No workspace or group project contains project link with id {}",
+                    link_id
+                ),
+            };
+            let link = link.ok_or_else(|| anyhow::anyhow!("Project link with id {} not found", link_id))?;
+            let project = self.projects_data
+                .get_project(link.project_id)
+                .ok_or_else(|| anyhow::anyhow!("Project with id {} not found", link.project_id))?;
+            match groups.last_mut() {
+                Some((last, last_variant, last_workspace_name, projects))
+                    if *last == configuration && *last_variant == variant && *last_workspace_name == workspace_name =>
+                {
+                    projects.push(project)
+                }
+                _ => groups.push((configuration, variant, workspace_name, vec![project])),
+            }
+        }
+        let total = groups.len();
+        return Ok(groups
+            .into_iter()
+            .enumerate()
+            .map(|(index, (configuration, variant, workspace_name, projects))| {
+                let name = if total > 1 {
+                    format!("{} project(s), part {}/{}", projects.len(), index + 1, total)
+                } else {
+                    format!("{} project(s)", projects.len())
+                };
+                let target = projects.iter().map(|project| project.name.as_str()).collect::<Vec<_>>().join(", ");
+                let compiler_name = configuration.product_name.clone();
+                let description = variant.describe();
+                CompilationParameters {
+                    projects,
+                    dependencies: Vec::new(),
+                    configuration,
+                    variant,
+                    workspace_name,
+                    rebuild,
+                    single: false,
+                    header: CompHeader::new(
+                        "Selection".to_string(),
+                        name.clone(),
+                        target.clone(),
+                        compiler_name.clone(),
+                        rebuild,
+                        description,
+                    ),
+                    footer: CompFooter::new(
+                        "Selection".to_string(),
+                        name,
+                        target,
+                        compiler_name,
+                        rebuild,
+                    ),
+                }
+            })
+            .collect());
+    }
+
+    /// The requested MSBuild targets; an explicitly empty list is rejected.
+    fn targets(&self) -> Result<Option<Vec<String>>> {
+        let Some(targets) = self.params.targets() else {
+            return Ok(None);
+        };
+        let targets: Vec<String> = targets
+            .iter()
+            .map(|target| target.trim().to_string())
+            .filter(|target| !target.is_empty())
+            .collect();
+        if targets.is_empty() {
+            anyhow::bail!("The list of MSBuild targets must not be empty.");
+        }
+        return Ok(Some(targets));
+    }
+
+    /// The request's `stop_on_error`, falling back to the `stop_on_first_failure` setting.
+    async fn stop_on_first_failure(&self) -> bool {
+        if let Some(stop_on_error) = self.params.stop_on_error() {
+            return stop_on_error;
+        }
+        return SERVER_SETTINGS.read().await.stop_on_first_failure;
+    }
+
+    /// Runs the compilation; only the build worker calls it, so builds never overlap.
+    async fn compile(&self) -> Result<BuildOutcome> {
+        if let Ok(mut running) = RUNNING_BUILD.lock() {
+            *running = Some(self.control.clone());
         }
-        ACTIVE.store(true, Ordering::SeqCst);
         defer! {
-            ACTIVE.store(false, Ordering::SeqCst);
+            if let Ok(mut running) = RUNNING_BUILD.lock() {
+                *running = None;
+            }
+        }
+        // a cancellation between leaving the queue and becoming the running build
+        if QUEUE_GENERATION.load(Ordering::SeqCst) != self.generation {
+            anyhow::bail!("Queued compilation cancelled by user.");
+        }
+        if let Some(platform) = self.params.platform() {
+            validate_platform(platform)?;
         }
-        let parameters = match self.params {
+        let targets = self.targets()?;
+        self.validate_compilers().await?;
+        let mut parameter_sets = match self.params {
             CompileProjectParams::Project {
                 project_id,
                 project_link_id,
                 rebuild,
-                event_id: _
-            } => self.get_project_parameters(project_id, project_link_id, rebuild).await?,
+                ..
+            } => vec![self.get_project_parameters(project_id, project_link_id, rebuild).await?],
             CompileProjectParams::AllInWorkspace {
                 workspace_id,
                 rebuild,
-                event_id: _
-            } => self.get_all_workspace_parameters(workspace_id, rebuild).await?,
-            CompileProjectParams::AllInGroupProject { rebuild, event_id: _ } => {
-                self.get_all_group_project_parameters(rebuild).await?
+                ..
+            } => vec![self.get_all_workspace_parameters(workspace_id, rebuild).await?],
+            CompileProjectParams::AllInGroupProject { rebuild, .. } => {
+                vec![self.get_all_group_project_parameters(rebuild).await?]
             }
             CompileProjectParams::FromLink {
                 project_link_id,
                 rebuild,
-                event_id: _,
-            } => self.get_from_link_parameters(project_link_id, rebuild).await?,
+                ..
+            } => vec![self.get_from_link_parameters(project_link_id, rebuild).await?],
+            CompileProjectParams::Selection {
+                ref project_link_ids,
+                rebuild,
+                ..
+            } => self.get_selection_parameters(project_link_ids, rebuild).await?,
+        };
+        for parameters in &mut parameter_sets {
+            check_installation_reachable(&parameters.configuration)?;
+            parameters.header.targets = targets.clone();
+            parameters.footer.targets = targets.clone();
+            parameters.variant.validate_for(&parameters.projects)?;
+            (parameters.projects, parameters.dependencies) =
+                order_by_package_dependencies(std::mem::take(&mut parameters.projects))?;
+        }
+        let project_ids: Vec<usize> = parameter_sets
+            .iter()
+            .flat_map(|parameters| parameters.projects.iter().map(|project| project.id))
+            .collect();
+        // pruned here rather than per project, so a large build keeps all of its own binary logs
+        if parameter_sets.iter().any(|parameters| parameters.configuration.binary_log)
+            && let Err(e) = BuildLog::prune_binary_logs()
+        {
+            lsp_error!(self.client, "Failed to remove old binary logs: {}", e);
+        }
+        let stop_on_first_failure = self.stop_on_first_failure().await;
+        let mut outcome = BuildOutcome { success: true, ..BuildOutcome::default() };
+        for (index, parameters) in parameter_sets.iter().enumerate() {
+            let started = Instant::now();
+            if index == 0 {
+                self.start(parameters, &project_ids).await?;
+            } else {
+                self.continue_with(parameters).await;
+            }
+            let log = self.open_build_log(parameters).await;
+            let results = self.do_compile(parameters, log.as_ref()).await?;
+            outcome.success &= results.iter().all(|result| result.success);
+            outcome.results.extend(results.iter().cloned());
+            self.finish(parameters, started, results, log.as_ref()).await?;
+            if stop_on_first_failure && !outcome.success {
+                break;
+            }
+        }
+        if let Ok(diagnostics) = self.diagnostics.lock() {
+            outcome.diagnostics = diagnostics.clone();
+        }
+        return Ok(outcome);
+    }
+
+    /// Compilers can be removed out-of-band (e.g. by editing `compilers.ron`), so check that
+    /// every compiler the requested scope depends on still exists before spawning anything.
+    async fn validate_compilers(&self) -> Result<()> {
+        let data = &self.projects_data;
+        let link_owner = |link_id: usize| match data.container_of_link(link_id) {
+            Some(LinkContainer::GroupProject) => {
+                Some((&data.group_project_compiler_id, "the group project".to_string()))
+            }
+            Some(LinkContainer::Workspace { workspace_id }) => data
+                .get_workspace(workspace_id)
+                .map(|workspace| (&workspace.compiler_id, format!("workspace '{}'", workspace.name))),
+            None => None,
+        };
+        let owners: Vec<(&String, String)> = match &self.params {
+            CompileProjectParams::Project { project_link_id: Some(link_id), .. }
+            | CompileProjectParams::FromLink { project_link_id: link_id, .. } => {
+                link_owner(*link_id).into_iter().collect()
+            }
+            CompileProjectParams::Selection { project_link_ids, .. } => {
+                project_link_ids.iter().filter_map(|link_id| link_owner(*link_id)).collect()
+            }
+            // like `compiler_for_project`: the first linking workspace, else the group project
+            CompileProjectParams::Project { project_id, project_link_id: None, .. } => vec![data
+                .workspaces
+                .iter()
+                .find(|ws| ws.project_links.iter().any(|link| link.project_id == *project_id))
+                .map(|workspace| (&workspace.compiler_id, format!("workspace '{}'", workspace.name)))
+                .unwrap_or_else(|| (&data.group_project_compiler_id, "the group project".to_string()))],
+            CompileProjectParams::AllInWorkspace { workspace_id, .. } => data
+                .get_workspace(*workspace_id)
+                .map(|workspace| (&workspace.compiler_id, format!("workspace '{}'", workspace.name)))
+                .into_iter()
+                .collect(),
+            CompileProjectParams::AllInGroupProject { .. } => {
+                vec![(&data.group_project_compiler_id, "the group project".to_string())]
+            }
         };
-        self.start(&parameters).await?;
-        self.do_compile(&parameters).await?;
-        self.finish(&parameters).await?;
+        for (compiler_id, owner) in owners {
+            if !compiler_exists(compiler_id).await {
+                anyhow::bail!(
+                    "Compiler '{}' used by {} is not configured. Configure compiler '{}' or select a different compiler for {}.",
+                    compiler_id,
+                    owner,
+                    compiler_id,
+                    owner
+                );
+            }
+        }
         return Ok(());
     }
 
-    async fn start(&self, parameters: &CompilationParameters<'_>) -> Result<()> {
-        CompilerProgress::notify_start(&self.client, parameters.header.into_vec()).await;
+    /// Starts the build of `project_ids`, withdrawing what their previous builds published.
+    /// Builds that don't publish leave the last build's diagnostics to the one that did.
+    async fn start(&self, parameters: &CompilationParameters<'_>, project_ids: &[usize]) -> Result<()> {
+        if self.publish {
+            next_build_version();
+            clear_project_diagnostics(&self.client, project_ids).await;
+            if let Ok(mut diagnostics) = LAST_BUILD_DIAGNOSTICS.lock() {
+                diagnostics.clear();
+            }
+        }
+        let banner_width = SERVER_SETTINGS.read().await.banner_width();
+        CompilerProgress::notify_start(&self.client, parameters.header.into_vec(banner_width)).await;
         Ok(())
     }
 
-    async fn finish(&self, parameters: &CompilationParameters<'_>) -> Result<()> {
-        CANCEL_COMPILATION.store(false, Ordering::SeqCst);
+    /// Starts a follow-up part of a multi-compiler build without clearing the client's
+    /// output and diagnostics of the earlier parts.
+    async fn continue_with(&self, parameters: &CompilationParameters<'_>) {
+        let banner_width = SERVER_SETTINGS.read().await.banner_width();
+        for line in parameters.header.into_vec(banner_width) {
+            CompilerProgress::notify_stdout(&self.client, line).await;
+        }
+    }
+
+    /// Starts the log file of this part of the build, beginning with its header.
+    async fn open_build_log(&self, parameters: &CompilationParameters<'_>) -> Option<BuildLog> {
+        let (keep, banner_width) = {
+            let settings = SERVER_SETTINGS.read().await;
+            (settings.build_logs, settings.banner_width())
+        };
+        match BuildLog::create(keep) {
+            Ok(Some(log)) => {
+                for line in parameters.header.into_vec(banner_width) {
+                    log.append(&line);
+                }
+                return Some(log);
+            }
+            Ok(None) => return None,
+            Err(e) => {
+                lsp_error!(self.client, "Failed to create build log: {}", e);
+                return None;
+            }
+        }
+    }
+
+    async fn finish(
+        &self,
+        parameters: &CompilationParameters<'_>,
+        started: Instant,
+        results: Vec<ProjectResult>,
+        log: Option<&BuildLog>,
+    ) -> Result<()> {
+        let banner_width = SERVER_SETTINGS.read().await.banner_width();
+        let first_failure = results.iter().find(|result| !result.success);
+        let (success, code) = (first_failure.is_none(), first_failure.map_or(0, |result| result.code));
+        let entry = BuildHistoryEntry {
+            scope: parameters.header.entity_type.clone(),
+            name: parameters.header.entity_name.clone(),
+            success,
+            errors: results.iter().map(|result| result.errors).sum(),
+            warnings: results.iter().map(|result| result.warnings).sum(),
+            timestamp: Local::now(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+        if let Err(e) = BuildHistory::append(entry) {
+            lsp_error!(self.client, "Failed to append build history: {}", e);
+        }
+        let footer = parameters.footer.into_vec(banner_width, success, &results, started.elapsed(), log.map(BuildLog::path));
+        if let Some(log) = log {
+            for line in &footer {
+                log.append(line);
+            }
+            // the footer links the log, so it has to be complete by the time the client shows it
+            log.flush().await;
+        }
+        let binary_logs = results.iter().filter_map(|result| result.binary_log.clone()).collect();
         CompilerProgress::notify_completed(
             &self.client,
-            SUCCESS.load(Ordering::SeqCst),
-            CODE.load(Ordering::SeqCst),
-            parameters.footer.into_vec(),
+            success,
+            code,
+            footer,
+            // single project builds report through `SingleProjectCompleted`
+            if parameters.single { Vec::new() } else { results },
+            binary_logs,
         )
         .await;
         Ok(())
     }
 
-    async fn do_compile(&self, parameters: &CompilationParameters<'_>) -> Result<()> {
-        for project in &parameters.projects {
-            if CANCEL_COMPILATION.load(Ordering::SeqCst) {
-                SUCCESS.store(false, Ordering::SeqCst);
-                CODE.store(-1, Ordering::SeqCst);
-                return Err(anyhow::anyhow!("Compilation cancelled by user."));
+    /// Builds the projects, up to the compiler's `max_parallelism` at a time; a project only
+    /// starts once the projects it depends on have finished.
+    async fn do_compile(&self, parameters: &CompilationParameters<'_>, log: Option<&BuildLog>) -> Result<Vec<ProjectResult>> {
+        let stop_on_first_failure = self.stop_on_first_failure().await;
+        let (banner_width, content_hash_cache, diagnostic_stream, diagnostic_base_path) = {
+            let settings = SERVER_SETTINGS.read().await;
+            (
+                settings.banner_width(),
+                settings.content_hash_cache,
+                settings.diagnostic_stream.clone(),
+                settings.diagnostic_base_path(),
+            )
+        };
+        let max_parallelism = parameters.configuration.max_parallelism.max(1);
+        let mut started = vec![false; parameters.projects.len()];
+        let mut finished = vec![false; parameters.projects.len()];
+        let mut running: JoinSet<(usize, Result<ProjectResult>)> = JoinSet::new();
+        let mut outcomes: Vec<ProjectResult> = Vec::new();
+        loop {
+            while running.len() < max_parallelism
+                && !self.control.is_cancelled()
+                && !(stop_on_first_failure && outcomes.iter().any(|outcome| !outcome.success))
+            {
+                // the first project in build order whose dependencies have all finished
+                let Some(index) = (0..parameters.projects.len()).find(|&index| {
+                    !started[index] && parameters.dependencies[index].iter().all(|&dependency| finished[dependency])
+                }) else {
+                    break;
+                };
+                started[index] = true;
+                let project = parameters.projects[index];
+                let target = project.get_project_file()?.to_string_lossy().to_string();
+                let build = ProjectBuild {
+                    client: self.client.clone(),
+                    project: (*project).clone(),
+                    configuration: parameters.configuration.clone(),
+                    variant: parameters.variant.clone(),
+                    workspace_name: parameters.workspace_name.clone(),
+                    rebuild: parameters.rebuild,
+                    targets: parameters.header.targets.clone(),
+                    log: log.map(|log| log.for_project(&project.name)),
+                    diagnostics: self.diagnostics.clone(),
+                    control: self.control.clone(),
+                    publish: self.publish,
+                    content_hash_cache,
+                    diagnostic_stream: diagnostic_stream.clone(),
+                    diagnostic_base_path: diagnostic_base_path.clone(),
+                };
+                let single_project = parameters.single;
+                running.spawn(async move {
+                    let client = build.client.clone();
+                    let project_id = build.project.id;
+                    let project_name = build.project.name.clone();
+                    let compiler_name = build.configuration.product_name.clone();
+                    let build_rebuild = build.rebuild;
+                    let build_targets = build.targets.clone();
+                    let started = Instant::now();
+                    let outcome = build.run().await;
+                    if single_project {
+                        let (success, code, failed_units) = match &outcome {
+                            Ok(outcome) => (outcome.success, outcome.code, outcome.failed_units.clone()),
+                            Err(_) => (false, -1, Vec::new()),
+                        };
+                        let mut footer = SingleProjectCompFooter::new(
+                            build_rebuild,
+                            compiler_name,
+                            project_name,
+                            target,
+                            success,
+                            code == TIMEOUT_EXIT_CODE,
+                            started.elapsed(),
+                        );
+                        footer.targets = build_targets;
+                        CompilerProgress::notify_single_project_completed(
+                            &client,
+                            project_id,
+                            success,
+                            code,
+                            footer.into_vec(banner_width),
+                            failed_units,
+                        )
+                        .await;
+                    }
+                    return (index, outcome);
+                });
             }
-            let client_deferred = self.client.clone();
-            let project_id = project.id;
-            let single_project = parameters.single;
-            let single_project_footer = SingleProjectCompFooter::new(
-                parameters.rebuild,
-                parameters.configuration.product_name.clone(),
-                project.name.clone(),
-                project.get_project_file()?.to_string_lossy().to_string(),
-                Box::new(|| {
-                    // Determine success based on compilation result
-                    SUCCESS.load(Ordering::SeqCst)
-                }),
-            );
-
-            defer_async! {
-                if single_project {
-                    CompilerProgress::notify_single_project_completed(
-                        &client_deferred,
-                        project_id,
-                        SUCCESS.load(Ordering::SeqCst),
-                        CODE.load(Ordering::SeqCst),
-                        single_project_footer.into_vec()
-                    ).await
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            let (index, outcome) = match joined {
+                Ok((index, Ok(outcome))) => (index, outcome),
+                Ok((_, Err(error))) => {
+                    running.abort_all();
+                    return Err(error);
                 }
+                Err(error) => {
+                    running.abort_all();
+                    return Err(error.into());
+                }
+            };
+            finished[index] = true;
+            let remaining = started.iter().filter(|&&started| !started).count();
+            if stop_on_first_failure && !outcome.success && remaining > 0 && outcomes.iter().all(|o| o.success) {
+                CompilerProgress::notify_stderr(
+                    &self.client,
+                    format!("Project {} failed - aborting remaining {} project(s).", outcome.name, remaining),
+                ).await;
+            }
+            if !parameters.single {
+                CompilerProgress::notify_stdout(
+                    &self.client,
+                    format!(
+                        "⏱ Project {} {} in {}",
+                        outcome.name,
+                        if outcome.success { "succeeded" } else { "failed" },
+                        format_elapsed(Duration::from_millis(outcome.duration_ms))
+                    ),
+                ).await;
+            }
+            if let Ok(mut results) = LAST_PROJECT_RESULTS.lock() {
+                results.insert(outcome.project_id, outcome.clone());
             }
+            outcomes.push(outcome);
+        }
+        if self.control.is_cancelled() {
+            return Err(anyhow::anyhow!("Compilation cancelled by user."));
+        }
+        return Ok(outcomes);
+    }
+}
 
-            let rsvars_path = PathBuf::from(&parameters.configuration.installation_path)
-                .join("bin")
-                .join("rsvars.bat");
-            if !rsvars_path.exists() {
-                anyhow::bail!(
-                    "Cannot find rsvars.bat at path: {}",
-                    rsvars_path.to_string_lossy()
-                );
+/// Installations on network or removable drives can disappear after the configuration was
+/// validated; without this the build would fail later with a confusing environment script error.
+fn check_installation_reachable(configuration: &CompilerConfiguration) -> Result<()> {
+    let path = Path::new(&configuration.installation_path);
+    if !path.is_dir() {
+        anyhow::bail!(
+            "Compiler install path unavailable (is the drive connected?): {} ({})",
+            configuration.installation_path,
+            configuration.product_name
+        );
+    }
+    return Ok(());
+}
+
+/// Result of one project of a build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectResult {
+    pub project_id: usize,
+    pub name: String,
+    pub success: bool,
+    pub code: isize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub hints: usize,
+    pub duration_ms: u64,
+    /// Files with errors, for jumping straight to the units that failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_units: Vec<String>,
+    /// The msbuild binary log, if the compiler has them enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_log: Option<String>,
+}
+
+/// Everything needed to build one project, owned so that builds can run on their own tasks.
+struct ProjectBuild {
+    client: tower_lsp::Client,
+    project: Project,
+    configuration: CompilerConfiguration,
+    variant: BuildVariant,
+    workspace_name: Option<String>,
+    rebuild: bool,
+    /// Overrides the targets `rebuild` selects.
+    targets: Option<Vec<String>>,
+    log: Option<BuildLog>,
+    /// Shared with the `Compiler`, which collects the diagnostics of all projects.
+    diagnostics: Arc<std::sync::Mutex<Vec<CompilerLineDiagnostic>>>,
+    control: Arc<BuildControl>,
+    publish: bool,
+    content_hash_cache: bool,
+    diagnostic_stream: DiagnosticStreamSettings,
+    /// Streamed diagnostics are reported relative to it.
+    diagnostic_base_path: Option<PathBuf>,
+}
+
+impl ProjectBuild {
+    /// `build_arguments` with `$(ProjectName)`, `$(WorkspaceName)`, `$(ProjectDir)`,
+    /// `$(Platform)` and `$(Config)` expanded; other macros are left to msbuild.
+    fn build_arguments(&self) -> Vec<String> {
+        let mut variables = HashMap::from([
+            ("ProjectName", self.project.name.clone()),
+            ("ProjectDir", self.project.directory.clone()),
+        ]);
+        let optional = [
+            ("WorkspaceName", &self.workspace_name),
+            ("Platform", &self.variant.platform),
+            ("Config", &self.variant.config),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                variables.insert(name, value.clone());
             }
-            let rsvars_path = rsvars_path.to_string_lossy();
-            let project_file = project.get_project_file()?;
-            let args = format!(
-                "/t:Clean,{} {}",
-                if parameters.rebuild { "Build" } else { "Make" },
-                parameters.configuration.build_arguments.join(" ")
-            );
-            let mut child_process = Command::new("cmd")
-                .args([
-                    "/C",
-                    format!(
-                        "call {rsvars_path} && msbuild \"{}\" {args}",
-                        project_file.to_string_lossy()
-                    )
-                    .as_str(),
-                ])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?;
-
-            let stdout = child_process.stdout.take().unwrap();
-            let stderr = child_process.stderr.take().unwrap();
-
-            let mut out_lines = BufReader::new(stdout).lines();
-            let mut err_lines = BufReader::new(stderr).lines();
-
-            let stdout_client = self.client.clone();
-            let stderr_client = self.client.clone();
-
-            let stdout_compiler_name = parameters.configuration.product_name.clone();
-            let stderr_compiler_name = parameters.configuration.product_name.clone();
-
-            let stdout_task = tokio::spawn(async move {
-                let mut diagnostics: Vec<Diagnostic> = Vec::new();
-                let mut last_file: String = String::new();
-                while let Ok(Some(line)) = out_lines.next_line().await {
-                    if CANCEL_COMPILATION.load(Ordering::SeqCst) {
-                        break;
-                    }
-                    if let Some(diagnostic) =
-                        CompilerLineDiagnostic::from_line(&line, stdout_compiler_name.clone())
-                    {
-                        if last_file != diagnostic.file && !diagnostics.is_empty() {
-                            publish_diagnostics(&stdout_client, &last_file, &diagnostics).await;
-                            diagnostics.clear();
-                        }
-                        last_file = diagnostic.file.clone();
-                        CompilerProgress::notify_stdout(&stdout_client, format!("{}", &diagnostic))
-                            .await;
-                        diagnostics.push(diagnostic.into());
-                        continue;
-                    }
-                    CompilerProgress::notify_stdout(&stdout_client, line).await;
-                }
-            });
-
-            let stderr_task = tokio::spawn(async move {
-                let mut diagnostics: Vec<Diagnostic> = Vec::new();
-                let mut last_file: String = String::new();
-                while let Ok(Some(line)) = err_lines.next_line().await {
-                    if CANCEL_COMPILATION.load(Ordering::SeqCst) {
-                        break;
-                    }
-                    if let Some(diagnostic) =
-                        CompilerLineDiagnostic::from_line(&line, stderr_compiler_name.clone())
-                    {
-                        if last_file != diagnostic.file && !diagnostics.is_empty() {
-                            publish_diagnostics(&stderr_client, &last_file, &diagnostics).await;
-                            diagnostics.clear();
-                        }
-                        last_file = diagnostic.file.clone();
-                        CompilerProgress::notify_stderr(&stderr_client, format!("{}", &diagnostic))
-                            .await;
-                        diagnostics.push(diagnostic.into());
-                        continue;
+        }
+        return self
+            .configuration
+            .build_arguments
+            .iter()
+            .map(|argument| expand_variables(argument, &variables))
+            .collect();
+    }
+
+    /// A fresh binary log path if the compiler has them enabled; a log that can't be
+    /// created is reported and the build runs without it.
+    fn binary_log(&self) -> Option<PathBuf> {
+        if !self.configuration.binary_log {
+            return None;
+        }
+        match BuildLog::binary_log_path(&self.project.name) {
+            Ok(path) => return Some(path),
+            Err(e) => {
+                lsp_error!(self.client, "Failed to create binary log: {}", e);
+                return None;
+            }
+        }
+    }
+
+    async fn run(self) -> Result<ProjectResult> {
+        let project = &self.project;
+        let started = Instant::now();
+        let outcome = |success: bool, code: isize, counts: DiagnosticCounts| ProjectResult {
+            project_id: project.id,
+            name: project.name.clone(),
+            success,
+            code,
+            errors: counts.errors,
+            warnings: counts.warnings,
+            hints: counts.hints,
+            duration_ms: started.elapsed().as_millis() as u64,
+            failed_units: counts.failed_units.into_values().collect(),
+            binary_log: None,
+        };
+        let content_hash = if self.content_hash_cache && !self.rebuild && self.targets.is_none() {
+            project_content_hash(project, &self.configuration, &self.build_arguments(), &self.variant).ok()
+        } else {
+            None
+        };
+        if let Some(hash) = &content_hash && BuildCache::is_up_to_date(project, hash) {
+            CompilerProgress::notify_stdout(
+                &self.client,
+                format!("Project {} is up to date (sources unchanged) - skipped.", project.name),
+            ).await;
+            return Ok(outcome(true, 0, DiagnosticCounts::default()));
+        }
+
+        let pattern = self.configuration.diagnostic_pattern()?;
+        let invocation = BuildInvocation::new(&self.configuration).await?;
+        let project_file = project.get_project_file()?;
+        let binary_log = self.binary_log();
+        let outcome = |success: bool, code: isize, counts: DiagnosticCounts| ProjectResult {
+            binary_log: binary_log.as_ref().map(|path| path.to_string_lossy().to_string()),
+            ..outcome(success, code, counts)
+        };
+        let args = format!(
+            "/t:{} {}{}{}",
+            msbuild_targets(self.rebuild, self.targets.as_deref()),
+            self.build_arguments().join(" "),
+            self.variant.msbuild_properties(),
+            binary_log.as_deref().map(binary_log_argument).unwrap_or_default()
+        );
+        let skip = self.control.start_project();
+        defer! {
+            self.control.finish_project(&skip);
+        }
+        // pre/post-build events in the .dproj resolve relative paths against the CWD
+        let mut child_process = invocation
+            .command(
+                "msbuild",
+                format!("\"{}\" {args}", project_file.to_string_lossy()).as_str(),
+                Path::new(&project.directory),
+            )
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdout = child_process.stdout.take().unwrap();
+        let stderr = child_process.stderr.take().unwrap();
+
+        let reader = OutputReader {
+            client: self.client.clone(),
+            compiler_name: self.configuration.product_name.clone(),
+            pattern,
+            stream: self.diagnostic_stream.clone(),
+            base_path: self.diagnostic_base_path.clone(),
+            directory: PathBuf::from(&project.directory),
+            log: self.log.clone(),
+            collected: self.diagnostics.clone(),
+            control: self.control.clone(),
+            publish: self.publish,
+        };
+        let stdout_task = tokio::spawn(reader.clone().read(BufReader::new(stdout).lines(), OutputChannel::Stdout));
+        let stderr_task = tokio::spawn(reader.read(BufReader::new(stderr).lines(), OutputChannel::Stderr));
+
+        let mut timed_out = false;
+        let status = match self.configuration.timeout_seconds {
+            Some(seconds) => {
+                match tokio::time::timeout(Duration::from_secs(seconds), wait_or_skip(&mut child_process, &self.control, &skip)).await {
+                    Ok(status) => status?,
+                    Err(_) => {
+                        kill_process_tree(&mut child_process).await?;
+                        timed_out = true;
+                        None
                     }
-                    CompilerProgress::notify_stderr(&stderr_client, line).await;
                 }
-            });
+            }
+            None => wait_or_skip(&mut child_process, &self.control, &skip).await?,
+        };
+        let counts = join_reader(stdout_task, status.is_none()).await?
+            .merge(join_reader(stderr_task, status.is_none()).await?);
+        // once per file and project build, so diagnostics of interleaved files aren't overwritten
+        publish_collected(&self.client, project.id, &counts.diagnostics).await;
+        if timed_out {
+            CompilerProgress::notify_stderr(
+                &self.client,
+                format!(
+                    "Project {} timed out after {} s - build killed.",
+                    project.name,
+                    self.configuration.timeout_seconds.unwrap_or_default()
+                ),
+            ).await;
+            return Ok(outcome(false, TIMEOUT_EXIT_CODE, counts));
+        }
+        let Some(status) = status else {
+            if !self.control.is_cancelled() {
+                CompilerProgress::notify_stderr(
+                    &self.client,
+                    format!("Project {} skipped by user.", project.name),
+                ).await;
+            }
+            return Ok(outcome(false, -1, counts));
+        };
+        if status.success() && let Some(hash) = content_hash &&
+           let Err(e) = BuildCache::store(project, hash) {
+            lsp_error!(self.client, "Failed to update build cache: {}", e);
+        }
+        // hashing the whole source tree is only worth it when the cache is in use
+        if status.success() && self.content_hash_cache && let Err(e) = BuildCache::store_sources(project) {
+            lsp_error!(self.client, "Failed to record the built sources: {}", e);
+        }
+        return Ok(outcome(status.success(), status.code().unwrap_or(-1) as isize, counts));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OutputChannel {
+    Stdout,
+    Stderr,
+}
 
-            let status = child_process.wait().await?;
-            stdout_task.await?;
-            stderr_task.await?;
-            SUCCESS.store(status.success(), Ordering::SeqCst);
-            CODE.store(status.code().unwrap_or(-1) as isize, Ordering::SeqCst);
+impl OutputChannel {
+    async fn notify_line(self, client: &tower_lsp::Client, line: String) {
+        match self {
+            OutputChannel::Stdout => CompilerProgress::notify_stdout(client, line).await,
+            OutputChannel::Stderr => CompilerProgress::notify_stderr(client, line).await,
+        }
+    }
+
+    async fn notify_diagnostic(self, client: &tower_lsp::Client, diagnostic: CompilerLineDiagnostic) {
+        match self {
+            OutputChannel::Stdout => CompilerProgress::notify_stdout_diagnostic(client, diagnostic).await,
+            OutputChannel::Stderr => CompilerProgress::notify_stderr_diagnostic(client, diagnostic).await,
         }
-        return Ok(());
     }
 }
 
-async fn publish_diagnostics(
-    client: &tower_lsp::Client,
-    file: &str,
-    diagnostics: &Vec<Diagnostic>,
-) {
-    let uri = Url::from_file_path(file).unwrap_or_else(|_| Url::parse("untitled:unknown").unwrap());
-    client
-        .publish_diagnostics(uri, diagnostics.clone(), None)
-        .await;
+/// What the stdout and stderr readers of a project build share.
+#[derive(Clone)]
+struct OutputReader {
+    client: tower_lsp::Client,
+    compiler_name: String,
+    pattern: regex::Regex,
+    stream: DiagnosticStreamSettings,
+    base_path: Option<PathBuf>,
+    /// The project directory, which relative diagnostic paths are resolved against.
+    directory: PathBuf,
+    log: Option<BuildLog>,
+    collected: Arc<std::sync::Mutex<Vec<CompilerLineDiagnostic>>>,
+    control: Arc<BuildControl>,
+    /// Collect the diagnostics for publishing.
+    publish: bool,
+}
+
+impl OutputReader {
+    /// Logs, parses and forwards the lines of one output channel until it closes or the build
+    /// is cancelled.
+    async fn read<R: AsyncBufRead + Unpin>(self, mut lines: Lines<R>, channel: OutputChannel) -> DiagnosticCounts {
+        let mut counts = DiagnosticCounts::default();
+        let mut suppressions = DiagnosticSuppressions::default();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if self.control.is_cancelled() {
+                break;
+            }
+            if let Some(log) = &self.log {
+                log.append(&line);
+            }
+            if let Some(diagnostic) = CompilerLineDiagnostic::from_line_with(&line, &self.compiler_name, &self.pattern)
+                && !suppressions.is_suppressed(&diagnostic)
+            {
+                record_diagnostic(&diagnostic, &self.collected, self.publish);
+                counts.count(&diagnostic, &self.directory);
+                channel.notify_diagnostic(&self.client, diagnostic.clone()).await;
+                if self.stream.accepts(&diagnostic.kind) {
+                    let streamed = diagnostic.clone().relative_to(self.base_path.as_deref());
+                    DiagnosticStream::notify(&self.client, streamed).await;
+                }
+                if self.publish {
+                    let file = self.directory.join(&diagnostic.file);
+                    let source_line = suppressions.source_line(&diagnostic);
+                    counts.diagnostics.entry(file).or_default().push(diagnostic.to_diagnostic(source_line));
+                }
+                continue;
+            }
+            channel.notify_line(&self.client, line).await;
+        }
+        return counts;
+    }
+}
+
+/// Moves packages ahead of the other projects, each package after the packages it `requires`.
+/// Otherwise the link order is kept. Also returns what each project waits for, as indices into
+/// the ordered projects: a package its required packages, every other project all packages.
+fn order_by_package_dependencies(projects: Vec<&Project>) -> Result<(Vec<&Project>, Vec<Vec<usize>>)> {
+    let package_name = |project: &Project| {
+        project
+            .dpk
+            .as_ref()
+            .and_then(|dpk| PathBuf::from(dpk).file_stem().map(|stem| stem.to_string_lossy().to_lowercase()))
+    };
+    let (packages, others): (Vec<&Project>, Vec<&Project>) =
+        projects.into_iter().partition(|project| package_name(project).is_some());
+    let names: Vec<String> = packages.iter().filter_map(|project| package_name(project)).collect();
+    let dependencies: Vec<Vec<usize>> = packages
+        .iter()
+        .map(|project| {
+            let requires = project
+                .dpk
+                .as_ref()
+                .and_then(|dpk| get_required_packages(&PathBuf::from(dpk)).ok())
+                .unwrap_or_default();
+            requires
+                .iter()
+                .filter_map(|required| names.iter().position(|name| name.eq_ignore_ascii_case(required)))
+                .collect()
+        })
+        .collect();
+
+    let mut ordered: Vec<&Project> = Vec::with_capacity(packages.len() + others.len());
+    // positions in `packages` in the order they are built
+    let mut order: Vec<usize> = Vec::with_capacity(packages.len());
+    let mut built = vec![false; packages.len()];
+    while ordered.len() < packages.len() {
+        // the first package in link order whose requirements are all built
+        let Some(next) = (0..packages.len()).find(|&index| {
+            !built[index] && dependencies[index].iter().all(|&dependency| built[dependency])
+        }) else {
+            let cycle: Vec<&str> = (0..packages.len())
+                .filter(|&index| !built[index])
+                .map(|index| packages[index].name.as_str())
+                .collect();
+            anyhow::bail!("Package dependency cycle between: {}", cycle.join(", "));
+        };
+        built[next] = true;
+        order.push(next);
+        ordered.push(packages[next]);
+    }
+    let mut waits_for: Vec<Vec<usize>> = order
+        .iter()
+        .map(|&package| {
+            dependencies[package]
+                .iter()
+                .filter_map(|dependency| order.iter().position(|built| built == dependency))
+                .collect()
+        })
+        .collect();
+    waits_for.extend(others.iter().map(|_| (0..packages.len()).collect()));
+    ordered.extend(others);
+    return Ok((ordered, waits_for));
+}
+
+async fn publish_collected(client: &tower_lsp::Client, project_id: usize, diagnostics: &BTreeMap<PathBuf, Vec<Diagnostic>>) {
+    for (file, diagnostics) in diagnostics {
+        publish_diagnostics(client, project_id, &file.to_string_lossy(), diagnostics).await;
+    }
+}
+
+/// Adds the diagnostic to its build's, and to the last build's if the build publishes.
+fn record_diagnostic(diagnostic: &CompilerLineDiagnostic, collected: &std::sync::Mutex<Vec<CompilerLineDiagnostic>>, publish: bool) {
+    if let Ok(mut diagnostics) = collected.lock() {
+        diagnostics.push(diagnostic.clone());
+    }
+    if publish && let Ok(mut diagnostics) = LAST_BUILD_DIAGNOSTICS.lock() {
+        diagnostics.push(diagnostic.clone());
+    }
+}
+
+fn banner_border(left: char, right: char, width: usize) -> String {
+    return format!("{}{}{}", left, "═".repeat(width - 2), right);
 }
 
 fn format_line(text: &str, total_width: usize) -> String {
@@ -539,9 +1608,48 @@ fn format_line(text: &str, total_width: usize) -> String {
     format!(" {}{}", " ".repeat(left_padding), text)
 }
 
+/// The `/bl:` switch writing msbuild's binary log to `path`, with a leading space.
+fn binary_log_argument(path: &Path) -> String {
+    return format!(" /bl:\"{}\"", path.display());
+}
+
+/// The `/t:` value: the requested targets, otherwise `Clean,Build` or `Clean,Make`.
+fn msbuild_targets(rebuild: bool, targets: Option<&[String]>) -> String {
+    return match targets {
+        Some(targets) => targets.join(","),
+        None if rebuild => "Clean,Build".to_string(),
+        None => "Clean,Make".to_string(),
+    };
+}
+
+fn action_description(rebuild: bool, targets: Option<&[String]>) -> String {
+    return match targets {
+        Some(targets) => format!("Custom ({})", targets.join(",")),
+        None if rebuild => "Rebuild (Clean,Build)".to_string(),
+        None => "Compile (Clean,Make)".to_string(),
+    };
+}
+
+/// `HH:MM:SS.mmm`
+fn format_elapsed(elapsed: Duration) -> String {
+    let millis = elapsed.as_millis();
+    return format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    );
+}
+
 struct CompilationParameters<'compiler> {
     projects: Vec<&'compiler Project>,
+    /// The projects each project has to wait for, as indices into `projects`.
+    dependencies: Vec<Vec<usize>>,
     configuration: CompilerConfiguration,
+    variant: BuildVariant,
+    /// Name of the workspace (or group project) the projects are built from.
+    workspace_name: Option<String>,
     rebuild: bool,
     single: bool,
     header: CompHeader,
@@ -557,6 +1665,8 @@ struct CompHeader {
     target: String,
     compiler_name: String,
     rebuild: bool,
+    variant: Option<String>,
+    targets: Option<Vec<String>>,
 }
 
 unsafe impl Send for CompHeader {}
@@ -569,6 +1679,7 @@ impl CompHeader {
         target: String,
         compiler_name: String,
         rebuild: bool,
+        variant: Option<String>,
     ) -> Self {
         CompHeader {
             entity_type,
@@ -576,30 +1687,27 @@ impl CompHeader {
             target,
             compiler_name,
             rebuild,
+            variant,
+            targets: None,
         }
     }
 
-    fn into_vec(&self) -> Vec<String> {
+    fn into_vec(&self, width: usize) -> Vec<String> {
         let topline = format_line(
             format!("Compiling {} {}", self.entity_type, self.entity_name).as_str(),
-            72,
+            width,
         );
-        let target = format_line(format!("→ {} ←", self.target.as_str()).as_str(), 70);
-        let compiler = format_line(format!("🛠️ Compiler: {}", self.compiler_name).as_str(), 70);
-        let action_str = if self.rebuild {
-            "Rebuild (Clean,Build)"
-        } else {
-            "Compile (Clean,Make)"
-        };
-        let action = format_line(format!("🗲 Action: {}", action_str).as_str(), 70);
-        vec![
-            "╒══════════════════════════════════════════════════════════════════════╕".to_string(),
-            topline,
-            target,
-            compiler,
-            action,
-            "╘══════════════════════════════════════════════════════════════════════╛".to_string(),
-        ]
+        let target = format_line(format!("→ {} ←", self.target.as_str()).as_str(), width - 2);
+        let compiler = format_line(format!("🛠️ Compiler: {}", self.compiler_name).as_str(), width - 2);
+        let action_str = action_description(self.rebuild, self.targets.as_deref());
+        let action = format_line(format!("🗲 Action: {}", action_str).as_str(), width - 2);
+        let mut lines = vec![banner_border('╒', '╕', width), topline, target, compiler];
+        if let Some(variant) = &self.variant {
+            lines.push(format_line(format!("⚙️ Config | Platform: {}", variant).as_str(), width - 2));
+        }
+        lines.push(action);
+        lines.push(banner_border('╘', '╛', width));
+        return lines;
     }
 }
 
@@ -609,12 +1717,9 @@ struct CompFooter {
     target: String,
     compiler_name: String,
     rebuild: bool,
-    success: Box<dyn Fn() -> bool>,
+    targets: Option<Vec<String>>,
 }
 
-unsafe impl Send for CompFooter {}
-unsafe impl Sync for CompFooter {}
-
 impl CompFooter {
     fn new(
         entity_type: String,
@@ -622,7 +1727,6 @@ impl CompFooter {
         target: String,
         compiler_name: String,
         rebuild: bool,
-        success: Box<dyn Fn() -> bool>,
     ) -> Self {
         CompFooter {
             entity_type,
@@ -630,96 +1734,699 @@ impl CompFooter {
             target,
             compiler_name,
             rebuild,
-            success,
+            targets: None,
         }
     }
 
-    fn into_vec(&self) -> Vec<String> {
+    fn into_vec(
+        &self,
+        width: usize,
+        success: bool,
+        results: &[ProjectResult],
+        elapsed: Duration,
+        log: Option<&Path>,
+    ) -> Vec<String> {
         let topline = format_line(
             format!("Compiling {} {}", self.entity_type, self.entity_name).as_str(),
-            72,
+            width,
         );
-        let target = format_line(format!("→ {} ←", self.target.as_str()).as_str(), 70);
-        let compiler = format_line(format!("🛠️ Compiler: {}", self.compiler_name).as_str(), 70);
-        let action_str = if self.rebuild {
-            "Rebuild (Clean,Build)"
-        } else {
-            "Compile (Clean,Make)"
-        };
-        let action = format_line(format!("🗲 Action: {}", action_str).as_str(), 70);
-        let status_str = if (self.success)() {
+        let target = format_line(format!("→ {} ←", self.target.as_str()).as_str(), width - 2);
+        let compiler = format_line(format!("🛠️ Compiler: {}", self.compiler_name).as_str(), width - 2);
+        let action_str = action_description(self.rebuild, self.targets.as_deref());
+        let action = format_line(format!("🗲 Action: {}", action_str).as_str(), width - 2);
+        let status_str = if success {
             "✅ SUCCESS"
+        } else if results.iter().any(|result| result.code == TIMEOUT_EXIT_CODE) {
+            "⏱️ TIMED OUT"
         } else {
             "❌ FAILED"
         };
-        let status = format_line(format!("Status: {}", status_str).as_str(), 70);
-        vec![
-            "╒══════════════════════════════════════════════════════════════════════╕".to_string(),
+        let status = format_line(format!("Status: {}", status_str).as_str(), width - 2);
+        let (errors, warnings, hints) = results.iter().fold((0, 0, 0), |(errors, warnings, hints), result| {
+            (errors + result.errors, warnings + result.warnings, hints + result.hints)
+        });
+        let summary = format_line(
+            format!("Errors: {}  Warnings: {}  Hints: {}", errors, warnings, hints).as_str(),
+            width - 2,
+        );
+        let elapsed = format_line(format!("⏱ Elapsed: {}", format_elapsed(elapsed)).as_str(), width - 2);
+        let mut lines = vec![
+            banner_border('╒', '╕', width),
             topline,
             target,
             compiler,
             action,
             status,
-            "╘══════════════════════════════════════════════════════════════════════╛".to_string(),
-        ]
+            summary,
+            elapsed,
+        ];
+        if results.len() > 1 {
+            for result in results {
+                let mark = if result.success { "✅" } else { "❌" };
+                lines.push(format_line(format!("{} {}", mark, result.name).as_str(), width - 2));
+            }
+        }
+        if let Some(log) = log {
+            lines.push(format_line(format!("📄 Log: {}", log.display()).as_str(), width - 2));
+        }
+        for binary_log in results.iter().filter_map(|result| result.binary_log.as_ref()) {
+            lines.push(format_line(format!("🗂 Binary log: {}", binary_log).as_str(), width - 2));
+        }
+        lines.push(banner_border('╘', '╛', width));
+        return lines;
     }
 }
 
 struct SingleProjectCompFooter {
     rebuild: bool,
+    targets: Option<Vec<String>>,
     compiler_name: String,
     project_name: String,
     target: String,
-    success: Box<dyn Fn() -> bool>,
+    success: bool,
+    timed_out: bool,
+    elapsed: Duration,
 }
 
-unsafe impl Send for SingleProjectCompFooter {}
-unsafe impl Sync for SingleProjectCompFooter {}
-
 impl SingleProjectCompFooter {
     fn new(
         rebuild: bool,
         compiler_name: String,
         project_name: String,
         target: String,
-        success: Box<dyn Fn() -> bool>,
+        success: bool,
+        timed_out: bool,
+        elapsed: Duration,
     ) -> Self {
         SingleProjectCompFooter {
             rebuild,
+            targets: None,
             compiler_name,
             project_name,
             target,
             success,
+            timed_out,
+            elapsed,
         }
     }
 
-    fn into_vec(&self) -> Vec<String> {
+    fn into_vec(&self, width: usize) -> Vec<String> {
         let topline = format_line(
             format!("Compiling Project: {}", self.project_name).as_str(),
-            72,
+            width,
         );
-        let target = format_line(&format!("→ {} ←", self.target), 70);
-        let compiler = format_line(&format!("🛠️ Compiler: {}", self.compiler_name), 70);
-        let action_str = if self.rebuild {
-            "Rebuild (Clean,Build)"
-        } else {
-            "Compile (Clean,Make)"
-        };
-        let action = format_line(&format!("🗲 Action: {}", action_str), 70);
-        let status_str = if (self.success)() {
+        let target = format_line(&format!("→ {} ←", self.target), width - 2);
+        let compiler = format_line(&format!("🛠️ Compiler: {}", self.compiler_name), width - 2);
+        let action_str = action_description(self.rebuild, self.targets.as_deref());
+        let action = format_line(&format!("🗲 Action: {}", action_str), width - 2);
+        let status_str = if self.success {
             "✅ SUCCESS"
+        } else if self.timed_out {
+            "⏱️ TIMED OUT"
         } else {
             "❌ FAILED"
         };
-        let status = format_line(&format!("Status: {}", status_str), 70);
+        let status = format_line(&format!("Status: {}", status_str), width - 2);
+        let elapsed = format_line(&format!("⏱ Elapsed: {}", format_elapsed(self.elapsed)), width - 2);
         vec![
-            "╒══════════════════════════════════════════════════════════════════════╕".to_string(),
+            banner_border('╒', '╕', width),
             topline,
             target,
             compiler,
             action,
             status,
-            "╘══════════════════════════════════════════════════════════════════════╛".to_string(),
+            elapsed,
+            banner_border('╘', '╛', width),
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{connected_client, test_client, test_session, workspace_with_projects};
+    #[cfg(not(windows))]
+    use crate::state::testing::stub_msbuild;
+
+    fn error_in(file: &str) -> CompilerLineDiagnostic {
+        return CompilerLineDiagnostic {
+            time: Local::now(),
+            file: file.to_string(),
+            line: 1,
+            column: None,
+            message: "Undeclared identifier: 'Foo'".to_string(),
+            code: "E2003".to_string(),
+            kind: DiagnosticKind::ERROR,
+            compiler_name: "Delphi 12".to_string(),
+        };
+    }
+
+    fn project(id: usize, name: &str, dpk: Option<PathBuf>) -> Project {
+        return Project {
+            id,
+            name: name.to_string(),
+            name_customized: false,
+            directory: String::new(),
+            dproj: None,
+            dpr: None,
+            dpk: dpk.map(|dpk| dpk.to_string_lossy().to_string()),
+            exe: None,
+            ini: None,
+            output_dir_override: None,
+            test_project_id: None,
+            project_guid: None,
+            clean: None,
+        };
+    }
+
+    fn output_reader(client: tower_lsp::Client, directory: &Path) -> OutputReader {
+        return OutputReader {
+            client,
+            compiler_name: "Delphi 12".to_string(),
+            pattern: diagnostic_pattern(None).unwrap(),
+            stream: DiagnosticStreamSettings::default(),
+            base_path: None,
+            directory: directory.to_path_buf(),
+            log: None,
+            collected: Arc::default(),
+            control: Arc::default(),
+            publish: false,
+        };
+    }
+
+    /// The params of the `method` notifications sent until the log message `marker`.
+    async fn notifications_until(
+        method: &str,
+        marker: &str,
+        client: &tower_lsp::Client,
+        received: &mut mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> Vec<serde_json::Value> {
+        client.log_message(tower_lsp::lsp_types::MessageType::LOG, marker).await;
+        let mut notifications = Vec::new();
+        while let Some(message) = received.recv().await {
+            if message["params"]["message"] == marker {
+                break;
+            }
+            if message["method"] == method {
+                notifications.push(message["params"].clone());
+            }
+        }
+        return notifications;
+    }
+
+    async fn progress_until(
+        marker: &str,
+        client: &tower_lsp::Client,
+        received: &mut mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> Vec<serde_json::Value> {
+        return notifications_until("notifications/compiler/progress", marker, client, received).await;
+    }
+
+    #[tokio::test]
+    async fn diagnostic_lines_are_sent_structured() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let output: &[u8] = b"Building App.dproj\nUnit1.pas(3,5): error E2003: Undeclared identifier: 'Foo'\n";
+
+        output_reader(client.clone(), Path::new("/projects")).read(BufReader::new(output).lines(), OutputChannel::Stderr).await;
+
+        let progress = progress_until("read", &client, &mut received).await;
+        assert_eq!(progress.len(), 2);
+        assert_eq!(progress[0]["kind"], "Stderr");
+        assert_eq!(progress[0]["line"], "Building App.dproj");
+        assert!(progress[0].get("diagnostic").is_none());
+        assert_eq!(progress[1]["kind"], "Stderr");
+        assert_eq!(progress[1]["diagnostic"]["code"], "E2003");
+        assert_eq!(progress[1]["diagnostic"]["line"], 3);
+        assert_eq!(progress[1]["diagnostic"]["column"], 5);
+        assert_eq!(progress[1]["diagnostic"]["kind"], "ERROR");
+    }
+
+    async fn publishes_until(
+        marker: &str,
+        client: &tower_lsp::Client,
+        received: &mut mpsc::UnboundedReceiver<serde_json::Value>,
+    ) -> Vec<serde_json::Value> {
+        return notifications_until("textDocument/publishDiagnostics", marker, client, received).await;
+    }
+
+    #[tokio::test]
+    async fn only_diagnostics_at_the_threshold_are_streamed() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let output: &[u8] = b"Unit1.pas(3,5): error E2003: Undeclared identifier: 'Foo'\n\
+            Unit1.pas(7,1): warning W1000: Symbol 'Bar' is deprecated\n\
+            Unit1.pas(9,2): hint H2164: Variable 'Baz' is declared but never used\n";
+
+        for (min_severity, expected) in [(DiagnosticKind::ERROR, vec!["E2003"]), (DiagnosticKind::HINT, vec!["E2003", "W1000", "H2164"])] {
+            let stream = DiagnosticStreamSettings { enabled: true, min_severity };
+            let reader = OutputReader { stream, ..output_reader(client.clone(), Path::new("/projects")) };
+            reader.read(BufReader::new(output).lines(), OutputChannel::Stdout).await;
+
+            let streamed = notifications_until("$/notifications/diagnostic/stream", "read", &client, &mut received).await;
+            let codes: Vec<&serde_json::Value> = streamed.iter().map(|params| &params["diagnostic"]["code"]).collect();
+            assert_eq!(codes, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn each_file_is_published_once_per_build_with_the_build_version() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let directory = tempfile::tempdir().unwrap();
+        let reader = OutputReader { publish: true, ..output_reader(client.clone(), directory.path()) };
+        let output: &[u8] = b"Unit1.pas(3,5): error E2003: Undeclared identifier: 'Foo'\n\
+            Unit2.pas(7,1): warning W1000: Symbol 'Bar' is deprecated\n\
+            Unit1.pas(9,2): error E2003: Undeclared identifier: 'Baz'\n";
+
+        let mut versions = Vec::new();
+        for build in ["first", "second"] {
+            versions.push(next_build_version());
+            let counts = reader.clone().read(BufReader::new(output).lines(), OutputChannel::Stdout).await;
+            publish_collected(&client, 1, &counts.diagnostics).await;
+
+            let publishes = publishes_until(build, &client, &mut received).await;
+            let uri = |unit: &str| tower_lsp::lsp_types::Url::from_file_path(directory.path().join(unit)).unwrap().to_string();
+            let published: Vec<(String, usize)> = publishes
+                .iter()
+                .map(|publish| (publish["uri"].as_str().unwrap().to_string(), publish["diagnostics"].as_array().unwrap().len()))
+                .collect();
+            assert_eq!(published, vec![(uri("Unit1.pas"), 2), (uri("Unit2.pas"), 1)]);
+            assert!(publishes.iter().all(|publish| publish["version"] == *versions.last().unwrap()));
+        }
+        assert!(versions[0] < versions[1]);
+        clear_published_diagnostics(&client).await;
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn builds_without_publishing_leave_the_last_build_alone() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let directory = tempfile::tempdir().unwrap();
+        stub_msbuild(directory.path(), "echo \"Unit1.pas(3,5): error E2003: Undeclared identifier: 'Foo'\"; exit 1").await;
+        let (_, project_ids) = workspace_with_projects(directory.path(), &["App"]).await;
+        *LAST_BUILD_DIAGNOSTICS.lock().unwrap() = vec![error_in("Published.pas")];
+
+        let outcome = BuildRequest::new(client.clone(), &CompileProjectParams::project(project_ids[0])).without_publishing().run().await.unwrap();
+
+        assert!(!outcome.success);
+        assert_eq!(outcome.diagnostics.len(), 1);
+        assert!(publishes_until("built", &client, &mut received).await.is_empty());
+        assert_eq!(last_build_diagnostics().keys().collect::<Vec<_>>(), vec!["Published.pas"]);
+        LAST_BUILD_DIAGNOSTICS.lock().unwrap().clear();
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn two_builds_append_two_history_entries_in_order() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        stub_msbuild(directory.path(), "exit 0").await;
+        let (_, project_ids) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+
+        for project_id in &project_ids {
+            BuildRequest::new(test_client(), &CompileProjectParams::project(*project_id)).without_publishing().run().await.unwrap();
+        }
+
+        let history = BuildHistory::new();
+        let names: Vec<&str> = history.entries.iter().rev().take(2).rev().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["First", "Second"]);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn a_selection_builds_each_link_with_its_workspace_compiler() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let built = directory.path().join("built.txt");
+        // the second compiler is the first stub, moved aside before 12.0 gets its own
+        let other = directory.path().join("other");
+        stub_msbuild(&other, &format!("echo \"Other $(basename \"$1\")\" >> '{}'", built.display())).await;
+        let mut other_compiler = COMPILER_CONFIGURATIONS.read().await.get("12.0").unwrap().clone();
+        other_compiler.product_name = "Other".to_string();
+        COMPILER_CONFIGURATIONS.write().await.insert("other".to_string(), other_compiler);
+        stub_msbuild(directory.path(), &format!("echo \"12.0 $(basename \"$1\")\" >> '{}'", built.display())).await;
+        let (workspace_id, project_ids) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+        let (first_link, second_link) = {
+            let mut projects_data = PROJECTS_DATA.write().await;
+            projects_data.new_workspace("Other", "other", &*COMPILER_CONFIGURATIONS.read().await).unwrap();
+            let other_id = projects_data.workspaces.last().unwrap().id;
+            projects_data.add_project_link(project_ids[1], other_id).unwrap();
+            let link_in = |workspace_id: usize, project_id: usize| {
+                return projects_data.get_workspace(workspace_id).unwrap().project_links
+                    .iter()
+                    .find(|link| link.project_id == project_id)
+                    .unwrap()
+                    .id;
+            };
+            (link_in(workspace_id, project_ids[0]), link_in(other_id, project_ids[1]))
+        };
+        let params = CompileProjectParams::Selection {
+            project_link_ids: vec![first_link, second_link],
+            rebuild: false,
+            platform: None,
+            config: None,
+            targets: None,
+            stop_on_error: None,
+            event_id: String::new(),
+        };
+
+        let outcome = BuildRequest::new(test_client(), &params).without_publishing().run().await.unwrap();
+
+        assert!(outcome.success);
+        let results: Vec<&str> = outcome.results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(results, vec!["First", "Second"]);
+        assert_eq!(std::fs::read_to_string(&built).unwrap(), "12.0 First.dpr\nOther Second.dpr\n");
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn build_arguments_name_the_compiling_project() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let arguments = directory.path().join("arguments.txt");
+        stub_msbuild(directory.path(), &format!("shift; echo \"$@\" >> '{}'", arguments.display())).await;
+        COMPILER_CONFIGURATIONS.write().await.get_mut("12.0").unwrap().build_arguments =
+            vec!["/p:DCC_ExeOutput=$(WorkspaceName)/$(ProjectName)".to_string()];
+        let (workspace_id, _) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+
+        BuildRequest::new(test_client(), &build_workspace(workspace_id, None)).without_publishing().run().await.unwrap();
+
+        let built: Vec<bool> = std::fs::read_to_string(&arguments).unwrap()
+            .lines()
+            .zip(["Main/First", "Main/Second"])
+            .map(|(line, output)| line.split(' ').any(|argument| argument == format!("/p:DCC_ExeOutput={output}")))
+            .collect();
+        assert_eq!(built, vec![true, true]);
+    }
+
+    /// Builds all projects of the workspace `workspace_id`.
+    fn build_workspace(workspace_id: usize, stop_on_error: Option<bool>) -> CompileProjectParams {
+        return CompileProjectParams::AllInWorkspace {
+            workspace_id,
+            rebuild: false,
+            platform: None,
+            config: None,
+            targets: None,
+            stop_on_error,
+            event_id: String::new(),
+        };
+    }
+
+    /// An msbuild stub failing the builds of projects whose file name contains `failing`.
+    #[cfg(not(windows))]
+    fn failing_build_of(failing: &str) -> String {
+        return format!(
+            "case \"$1\" in *{failing}*) echo \"{failing}.dpr(2,1): error E2003: Undeclared identifier: 'X'\"; exit 1;; esac"
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn projects_after_the_first_failure_are_skipped_when_stopping_on_it() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        stub_msbuild(directory.path(), &failing_build_of("First")).await;
+        let (workspace_id, _) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+
+        for (stop_on_error, built) in [(true, vec![("First", false)]), (false, vec![("First", false), ("Second", true)])] {
+            let params = build_workspace(workspace_id, Some(stop_on_error));
+            let outcome = BuildRequest::new(test_client(), &params).without_publishing().run().await.unwrap();
+
+            let results: Vec<(&str, bool)> = outcome.results.iter().map(|result| (result.name.as_str(), result.success)).collect();
+            assert_eq!(results, built, "stop_on_error: {stop_on_error}");
+            assert!(!outcome.success);
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn the_workspace_completion_lists_every_project() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let directory = tempfile::tempdir().unwrap();
+        stub_msbuild(directory.path(), &failing_build_of("First")).await;
+        let (workspace_id, _) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+
+        BuildRequest::new(client.clone(), &build_workspace(workspace_id, Some(false))).without_publishing().run().await.unwrap();
+
+        let progress = progress_until("built", &client, &mut received).await;
+        let completed: Vec<&serde_json::Value> = progress.iter().filter(|params| params["kind"] == "Completed").collect();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0]["success"], false);
+        let projects: Vec<(&str, bool, i64, i64)> = completed[0]["projects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|project| (
+                project["name"].as_str().unwrap(),
+                project["success"].as_bool().unwrap(),
+                project["code"].as_i64().unwrap(),
+                project["errors"].as_i64().unwrap(),
+            ))
+            .collect();
+        assert_eq!(projects, vec![("First", false, 1, 1), ("Second", true, 0, 0)]);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn skipping_the_first_project_still_builds_the_second() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let started = directory.path().join("started");
+        stub_msbuild(directory.path(), &format!("case \"$1\" in *First*) touch '{}'; exec sleep 30;; esac", started.display())).await;
+        let (workspace_id, _) = workspace_with_projects(directory.path(), &["First", "Second"]).await;
+
+        let build = tokio::spawn(BuildRequest::new(test_client(), &build_workspace(workspace_id, Some(false))).without_publishing().run());
+        while !started.exists() || !skip_current_project() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let outcome = tokio::time::timeout(Duration::from_secs(10), build).await.unwrap().unwrap().unwrap();
+
+        let results: Vec<(&str, bool)> = outcome.results.iter().map(|result| (result.name.as_str(), result.success)).collect();
+        assert_eq!(results, vec![("First", false), ("Second", true)]);
+        assert!(!skip_current_project());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn the_diagnostics_of_the_last_build_are_kept_by_file() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        stub_msbuild(directory.path(), "echo \"Unit1.pas(3,5): error E2003: Undeclared identifier: 'Foo'\"\n\
+            echo \"Unit2.pas(7,1): warning W1000: Symbol 'Bar' is deprecated\"\n\
+            echo \"Unit1.pas(9,2): error E2003: Undeclared identifier: 'Baz'\"\nexit 1").await;
+        let (_, project_ids) = workspace_with_projects(directory.path(), &["App"]).await;
+
+        BuildRequest::new(test_client(), &CompileProjectParams::project(project_ids[0])).run().await.unwrap();
+
+        let diagnostics = last_build_diagnostics();
+        let lines = |file: &str| diagnostics[file].iter().map(|diagnostic| diagnostic.line).collect::<Vec<_>>();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(lines("Unit1.pas"), vec![3, 9]);
+        assert_eq!(lines("Unit2.pas"), vec![7]);
+        LAST_BUILD_DIAGNOSTICS.lock().unwrap().clear();
+    }
+
+    #[tokio::test]
+    async fn a_workspace_whose_compiler_was_removed_is_not_compiled() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let (workspace_id, _) = workspace_with_projects(directory.path(), &["App"]).await;
+        COMPILER_CONFIGURATIONS.write().await.remove("12.0");
+        let params = build_workspace(workspace_id, None);
+
+        let error = BuildRequest::new(test_client(), &params).without_publishing().run().await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Compiler '12.0' used by workspace 'Main' is not configured. Configure compiler '12.0' or select a different compiler for workspace 'Main'."
+        );
+    }
+
+    #[test]
+    fn the_header_borders_align_at_any_width() {
+        let header = CompHeader {
+            entity_type: "Workspace".to_string(),
+            entity_name: "Main".to_string(),
+            target: "App.dproj".to_string(),
+            compiler_name: "Delphi 12".to_string(),
+            rebuild: false,
+            variant: Some("Debug | Win64".to_string()),
+            targets: None,
+        };
+        for width in [40, 100] {
+            let lines = header.into_vec(width);
+            let (top, bottom) = (lines.first().unwrap(), lines.last().unwrap());
+            assert_eq!(top.chars().count(), width);
+            assert_eq!(bottom.chars().count(), width);
+            assert!(top.starts_with('╒') && top.ends_with('╕'));
+            assert!(bottom.starts_with('╘') && bottom.ends_with('╛'));
+            for line in &lines[1..lines.len() - 1] {
+                assert!(line.chars().count() < width, "{line:?} is wider than the border at width {width}");
+            }
+        }
+    }
+
+    #[test]
+    fn projects_wait_for_the_packages_they_depend_on() {
+        let directory = tempfile::tempdir().unwrap();
+        let package = |name: &str, requires: &str| {
+            let dpk = directory.path().join(format!("{name}.dpk"));
+            std::fs::write(&dpk, format!("package {name};\n{requires}\nend.\n")).unwrap();
+            return Some(dpk);
+        };
+        let app = project(1, "App", None);
+        let ui = project(2, "UI", package("UI", "requires\n  rtl,\n  Core;"));
+        let core = project(3, "Core", package("Core", "requires\n  rtl;"));
+
+        let (ordered, dependencies) = order_by_package_dependencies(vec![&app, &ui, &core]).unwrap();
+
+        let names: Vec<&str> = ordered.iter().map(|project| project.name.as_str()).collect();
+        assert_eq!(names, vec!["Core", "UI", "App"]);
+        assert_eq!(dependencies, vec![vec![], vec![0], vec![0, 1]]);
+    }
+
+    #[test]
+    fn skipping_kills_one_running_project_at_a_time() {
+        let control = BuildControl::default();
+        let first = control.start_project();
+        let second = control.start_project();
+
+        assert!(control.skip_one());
+        assert!(first.load(Ordering::SeqCst) && !second.load(Ordering::SeqCst));
+        assert!(control.skip_one());
+        assert!(second.load(Ordering::SeqCst));
+        assert!(!control.skip_one());
+
+        control.finish_project(&first);
+        control.finish_project(&second);
+        let third = control.start_project();
+        assert!(control.skip_one() && third.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn binary_logs_are_passed_quoted() {
+        let path = Path::new("/logs/binlog-20260101-120000.000-My App.binlog");
+        assert_eq!(binary_log_argument(path), " /bl:\"/logs/binlog-20260101-120000.000-My App.binlog\"");
+    }
+
+    #[test]
+    fn each_failed_unit_is_listed_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+        std::fs::write(root.join("Unit1.pas"), "").unwrap();
+        std::fs::write(root.join("Unit2.pas"), "").unwrap();
+        let unit2 = root.join("Unit2.pas").to_string_lossy().to_string();
+
+        let mut counts = DiagnosticCounts::default();
+        for file in ["Unit1.pas", "Unit1.pas", "./Unit1.pas", unit2.as_str(), "Unit2.pas"] {
+            counts.count(&error_in(file), &root);
+        }
+
+        assert_eq!(counts.errors, 5);
+        let units: Vec<&String> = counts.failed_units.values().collect();
+        assert_eq!(units, vec![&root.join("Unit1.pas").to_string_lossy().to_string(), &unit2]);
+    }
+
+    #[tokio::test]
+    async fn queued_builds_read_the_data_when_they_start() {
+        let _session = test_session().await;
+        // the worker can't start the build while the data is locked, queueing still returns
+        let mut projects_data = PROJECTS_DATA.write().await;
+        let outcome = BuildRequest::new(test_client(), &CompileProjectParams::project(7)).without_publishing().queue().await;
+        projects_data.projects.push(project(7, "Queued", None));
+        drop(projects_data);
+
+        let error = outcome.await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("No workspace contains"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_installation_fails_before_building() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let missing = directory.path().join("Studio").join("23.0");
+        COMPILER_CONFIGURATIONS.write().await.get_mut("12.0").unwrap().installation_path = missing.to_string_lossy().to_string();
+        let dpr = directory.path().join("App.dpr");
+        std::fs::write(&dpr, "program App;\nbegin\nend.\n").unwrap();
+        let project_id = {
+            let mut projects_data = PROJECTS_DATA.write().await;
+            projects_data.new_workspace("Main", "12.0", &*COMPILER_CONFIGURATIONS.read().await).unwrap();
+            let workspace_id = projects_data.workspaces[0].id;
+            projects_data.new_project(&dpr.to_string_lossy().to_string(), workspace_id).unwrap();
+            projects_data.projects[0].id
+        };
+
+        let error = BuildRequest::new(test_client(), &CompileProjectParams::project(project_id)).without_publishing().run().await.unwrap_err();
+        assert!(error.to_string().starts_with("Compiler install path unavailable (is the drive connected?)"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn cancelling_drops_queued_builds() {
+        let _session = test_session().await;
+        let projects_data = PROJECTS_DATA.write().await;
+        let first = BuildRequest::new(test_client(), &CompileProjectParams::project(1)).without_publishing().queue().await;
+        let second = BuildRequest::new(test_client(), &CompileProjectParams::project(1)).without_publishing().queue().await;
+        cancel_compilations();
+        drop(projects_data);
+
+        let error = second.await.unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "Queued compilation cancelled by user.");
+        assert!(first.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn each_build_keeps_its_own_diagnostics() {
+        let _session = test_session().await;
+        let params = CompileProjectParams::project(1);
+        let first = Compiler::new(test_client(), &params, false, 0).await;
+        let second = Compiler::new(test_client(), &params, false, 0).await;
+        record_diagnostic(&error_in("Unit1.pas"), &first.diagnostics, false);
+        record_diagnostic(&error_in("Unit2.pas"), &first.diagnostics, false);
+        record_diagnostic(&error_in("Unit1.pas"), &first.diagnostics, false);
+
+        let outcome = BuildOutcome {
+            diagnostics: first.diagnostics.lock().unwrap().clone(),
+            ..BuildOutcome::default()
+        };
+        let grouped = outcome.diagnostics_by_file();
+        assert_eq!(grouped.keys().collect::<Vec<_>>(), vec!["Unit1.pas", "Unit2.pas"]);
+        assert_eq!(grouped["Unit1.pas"].len(), 2);
+        assert!(second.diagnostics.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_workspace_and_a_group_of_the_same_projects_build_alike() {
+        let _session = test_session().await;
+        let directory = tempfile::tempdir().unwrap();
+        let (workspace_id, project_ids) = workspace_with_projects(directory.path(), &["A", "B"]).await;
+        {
+            let mut projects_data = PROJECTS_DATA.write().await;
+            let mut group_project = GroupProject {
+                name: "Main".to_string(),
+                path: directory.path().join("Main.groupproj").to_string_lossy().to_string(),
+                project_links: Vec::new(),
+            };
+            for project_id in &project_ids {
+                group_project.new_project_link(projects_data.next_id(), *project_id);
+            }
+            projects_data.group_project = Some(group_project);
+        }
+        let compiler = Compiler::new(test_client(), &CompileProjectParams::project(project_ids[0]), false, 0).await;
+
+        let workspace = compiler.get_all_workspace_parameters(workspace_id, true).await.unwrap();
+        let group = compiler.get_all_group_project_parameters(true).await.unwrap();
+        for parameters in [&workspace, &group] {
+            assert_eq!(parameters.projects.iter().map(|p| p.id).collect::<Vec<_>>(), project_ids);
+            assert_eq!(parameters.workspace_name.as_deref(), Some("Main"));
+            assert!(parameters.rebuild && !parameters.single);
+            assert_eq!(parameters.header.entity_name, "Main");
+        }
+        assert_eq!(workspace.configuration, group.configuration);
+        assert_eq!(workspace.header.entity_type, "Workspace");
+        assert_eq!(group.header.entity_type, "Group Project");
+        assert_eq!(group.header.target, "Projects of Group Project 'Main'");
+    }
+}
@@ -1,5 +1,5 @@
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use tokio::sync::RwLock;
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
@@ -7,9 +7,13 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 
 use crate::state::{COMPILER_CONFIGURATIONS, COMPILER_CONFIGURATIONS_CHANGED, Stateful};
-use crate::utils::{FilePath, Load};
+use crate::utils::{FilePath, Load, config_directory, data_file_extension, expand_macros};
+use super::diagnostic_pattern;
 
 pub(crate) const DEFAULT_COMPILERS: &str = include_str!("presets/default_compilers.ron");
+/// RAD Studio registers each installed version as `<key>\<version>` with its `RootDir`.
+#[cfg(windows)]
+const BDS_REGISTRY_KEY: &str = r"HKCU\Software\Embarcadero\BDS";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartialCompilerConfiguration {
@@ -20,6 +24,15 @@ pub struct PartialCompilerConfiguration {
     pub compiler_version: Option<usize>,
     pub installation_path: Option<String>,
     pub build_arguments: Option<Vec<String>>,
+    pub library_paths: Option<Vec<String>>,
+    pub max_parallelism: Option<usize>,
+    pub env_script: Option<String>,
+    /// `0` removes the timeout.
+    pub timeout_seconds: Option<u64>,
+    pub cache_environment: Option<bool>,
+    /// An empty string restores the msbuild format.
+    pub diagnostic_regex: Option<String>,
+    pub binary_log: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -31,8 +44,44 @@ pub struct CompilerConfiguration {
     pub compiler_version: usize,
     pub installation_path: String,
     pub build_arguments: Vec<String>,
+    #[serde(default)]
+    pub library_paths: Vec<String>,
+    /// Number of projects of a multi-project build compiled at the same time.
+    #[serde(default = "default_max_parallelism")]
+    pub max_parallelism: usize,
+    /// Script sourced before msbuild to set up the build environment; macros such as
+    /// `$(BDS)` are expanded. Defaults to `$(BDS)\bin\rsvars.bat`, an empty string skips it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_script: Option<String>,
+    /// Builds running longer than this are killed and reported as timed out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+    /// Run the environment script once and start msbuild directly with the variables it set,
+    /// instead of going through the shell and the script on every build.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cache_environment: bool,
+    /// Pattern for compiler output that doesn't follow the msbuild format, with the named
+    /// captures `file`, `line` and `message`, and optionally `column`, `kind` and `code`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic_regex: Option<String>,
+    /// Have msbuild write a binary log (`/bl`) of every build, for the MSBuild Structured Log Viewer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary_log: bool,
 }
 
+fn default_max_parallelism() -> usize {
+    return 1;
+}
+
+const DEFAULT_LIBRARY_SUBFOLDERS: &[&str] = &[
+    "source/rtl/common",
+    "source/rtl/sys",
+    "source/rtl/win",
+    "source/vcl",
+    "source/data",
+    "lib/$(Platform)/release",
+];
+
 impl CompilerConfiguration {
     pub fn update(&mut self, partial: &PartialCompilerConfiguration) {
         if let Some(condition) = &partial.condition {
@@ -56,6 +105,67 @@ impl CompilerConfiguration {
         if let Some(build_arguments) = &partial.build_arguments {
             self.build_arguments = build_arguments.clone();
         }
+        if let Some(library_paths) = &partial.library_paths {
+            self.library_paths = library_paths.clone();
+        }
+        if let Some(max_parallelism) = partial.max_parallelism {
+            self.max_parallelism = max_parallelism.max(1);
+        }
+        if let Some(env_script) = &partial.env_script {
+            self.env_script = Some(env_script.clone());
+        }
+        if let Some(timeout_seconds) = partial.timeout_seconds {
+            self.timeout_seconds = if timeout_seconds == 0 { None } else { Some(timeout_seconds) };
+        }
+        if let Some(cache_environment) = partial.cache_environment {
+            self.cache_environment = cache_environment;
+        }
+        if let Some(diagnostic_regex) = &partial.diagnostic_regex {
+            self.diagnostic_regex = if diagnostic_regex.is_empty() { None } else { Some(diagnostic_regex.clone()) };
+        }
+        if let Some(binary_log) = partial.binary_log {
+            self.binary_log = binary_log;
+        }
+    }
+
+    /// The pattern build output is parsed with, compiled once per build.
+    pub fn diagnostic_pattern(&self) -> Result<regex::Regex> {
+        return diagnostic_pattern(self.diagnostic_regex.as_deref());
+    }
+
+    pub fn macro_variables(&self, platform: Option<&str>) -> HashMap<&'static str, String> {
+        return HashMap::from([
+            ("BDS", self.installation_path.clone()),
+            ("Platform", platform.unwrap_or("Win32").to_string()),
+            ("ProductVersion", format!("{}.0", self.product_version)),
+        ]);
+    }
+
+    /// The environment script to source before building, `None` if it is disabled.
+    pub fn env_script_path(&self) -> Option<PathBuf> {
+        return match &self.env_script {
+            Some(script) if script.trim().is_empty() => None,
+            Some(script) => Some(PathBuf::from(expand_macros(script, &self.macro_variables(None)))),
+            None => Some(PathBuf::from(&self.installation_path).join("bin").join("rsvars.bat")),
+        };
+    }
+
+    /// The configured library paths with macros expanded; when none are configured,
+    /// the known source/lib subfolders of the installation that actually exist.
+    pub fn resolved_library_paths(&self, platform: Option<&str>) -> Vec<PathBuf> {
+        let variables = self.macro_variables(platform);
+        if !self.library_paths.is_empty() {
+            return self.library_paths
+                .iter()
+                .map(|path| PathBuf::from(expand_macros(path, &variables)))
+                .collect();
+        }
+        let installation_path = PathBuf::from(&self.installation_path);
+        return DEFAULT_LIBRARY_SUBFOLDERS
+            .iter()
+            .map(|subfolder| installation_path.join(expand_macros(subfolder, &variables)))
+            .filter(|path| path.is_dir())
+            .collect();
     }
 }
 
@@ -98,7 +208,12 @@ impl<'de> Deserialize<'de> for CompilerConfigurations {
 
 impl CompilerConfigurations {
     pub fn new() -> Self {
-        Self::load_from_file(&Self::get_file_path())
+        Self::read_only_copy_blocking()
+    }
+
+    /// Like `new`, without blocking the async worker; fails if the file can't be parsed.
+    pub async fn load_current() -> Result<Self> {
+        return Self::read_only_copy().await;
     }
 
     pub async fn first_available_formatter() -> Option<PathBuf> {
@@ -138,31 +253,105 @@ impl CompilerConfigurations {
         self._compilers.insert(key, compiler);
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &CompilerConfiguration)> {
+        self._compilers.iter()
+    }
+
+    /// Key of the configured compiler with the given BDS product version (lowest key if several match).
+    pub fn find_by_product_version(&self, product_version: usize) -> Option<String> {
+        return self._compilers
+            .iter()
+            .filter(|(_, compiler)| compiler.product_version == product_version)
+            .map(|(key, _)| key.clone())
+            .min();
+    }
+
+    /// The RAD Studio versions registered for the current user, as the preset of the same
+    /// product version installed at the registered `RootDir`. Versions without a preset are
+    /// skipped; always empty on platforms other than Windows.
+    pub fn detect_installed() -> CompilerConfigurations {
+        let presets = CompilerConfigurations::default();
+        let mut detected = CompilerMap::new();
+        for (version, root_dir) in registered_bds_versions() {
+            let Some(product_version) = version.split('.').next().and_then(|major| major.parse().ok()) else {
+                continue;
+            };
+            let Some(key) = presets.find_by_product_version(product_version) else {
+                continue;
+            };
+            let Some(preset) = presets.get(&key) else {
+                continue;
+            };
+            let mut compiler = preset.clone();
+            compiler.installation_path = root_dir.trim_end_matches(['\\', '/']).to_string();
+            detected.insert(key, compiler);
+        }
+        return CompilerConfigurations { _compilers: detected };
+    }
+
+    /// Adds detected compilers whose product version isn't configured yet. A configured one
+    /// is only replaced while it is still the unchanged preset; anything the user customized stays.
+    pub fn merge_detected(&mut self, detected: CompilerConfigurations) {
+        let presets = CompilerConfigurations::default();
+        for (key, compiler) in detected._compilers {
+            let configured = self.find_by_product_version(compiler.product_version);
+            let Some(configured) = configured else {
+                self._compilers.insert(key, compiler);
+                continue;
+            };
+            if presets.get(&configured) == self.get(&configured) {
+                self._compilers.remove(&configured);
+                self._compilers.insert(key, compiler);
+            }
+        }
+    }
+
     pub fn validate(&self) -> Result<()> {
         for (key, compiler) in &self._compilers {
-            if key.trim().is_empty() {
-                anyhow::bail!("Compiler key cannot be empty.");
-            }
-            if compiler.condition.trim().is_empty() {
-                anyhow::bail!("Compiler condition cannot be empty for key: {}", key);
-            }
-            if compiler.product_name.trim().is_empty() {
-                anyhow::bail!("Compiler product name cannot be empty for key: {}", key);
-            }
-            if compiler.installation_path.trim().is_empty() {
-                anyhow::bail!("Compiler installation path cannot be empty for key: {}", key);
-            }
-            let path = PathBuf::from(&compiler.installation_path);
-            if !path.exists() {
-                anyhow::bail!("Compiler installation path does not exist for key: {}: {}", key, compiler.installation_path);
-            }
-            if !path.is_dir() {
-                anyhow::bail!("Compiler installation path is not a directory for key: {}: {}", key, compiler.installation_path);
+            Self::validate_compiler(key, compiler)?;
+        }
+        Ok(())
+    }
+
+    /// `validate` limited to the compilers added or changed since `previous`, so presets that
+    /// aren't installed on this machine don't block unrelated changes.
+    pub fn validate_changes(&self, previous: &CompilerConfigurations) -> Result<()> {
+        for (key, compiler) in &self._compilers {
+            if previous.get(key) != Some(compiler) {
+                Self::validate_compiler(key, compiler)?;
             }
-            let rsvars_path = path.join("bin").join("rsvars.bat");
-            if !rsvars_path.exists() {
-                anyhow::bail!("rsvars.bat not found in compiler installation path for key: {}: {}", key, rsvars_path.display());
+        }
+        Ok(())
+    }
+
+    fn validate_compiler(key: &str, compiler: &CompilerConfiguration) -> Result<()> {
+        if key.trim().is_empty() {
+            anyhow::bail!("Compiler key cannot be empty.");
+        }
+        if compiler.condition.trim().is_empty() {
+            anyhow::bail!("Compiler condition cannot be empty for key: {}", key);
+        }
+        if compiler.product_name.trim().is_empty() {
+            anyhow::bail!("Compiler product name cannot be empty for key: {}", key);
+        }
+        if compiler.installation_path.trim().is_empty() {
+            anyhow::bail!("Compiler installation path cannot be empty for key: {}", key);
+        }
+        let path = PathBuf::from(&compiler.installation_path);
+        if !path.exists() {
+            anyhow::bail!("Compiler installation path does not exist for key: {}: {}", key, compiler.installation_path);
+        }
+        if !path.is_dir() {
+            anyhow::bail!("Compiler installation path is not a directory for key: {}: {}", key, compiler.installation_path);
+        }
+        if let Some(env_script) = compiler.env_script_path() && !env_script.exists() {
+            if compiler.env_script.is_none() {
+                anyhow::bail!("rsvars.bat not found in compiler installation path for key: {}: {}", key, env_script.display());
             }
+            anyhow::bail!("Environment script not found for key: {}: {}", key, env_script.display());
+        }
+        if let Err(e) = compiler.diagnostic_pattern() {
+            anyhow::bail!("Invalid diagnostic pattern for key: {}: {}", key, e);
         }
         Ok(())
     }
@@ -183,18 +372,49 @@ impl Default for CompilerConfigurations {
 
 impl Load for CompilerConfigurations {}
 
+/// Set from the `initialize` request to give this session its own compilers file; the
+/// default location is fixed by the first use otherwise.
+pub static COMPILERS_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 impl FilePath for CompilerConfigurations {
     fn get_file_path() -> &'static PathBuf {
-        lazy_static::lazy_static! {
-            static ref PATH: PathBuf = {
-                dirs::config_dir()
-                    .expect("Could not determine config directory")
-                .join("ddk")
-                .join("compilers.ron")
-            };
+        return COMPILERS_PATH.get_or_init(|| {
+            config_directory().join(format!("compilers.{}", data_file_extension()))
+        });
+    }
+}
+
+/// `(version, RootDir)` of every version registered under `BDS_REGISTRY_KEY`, read through `reg.exe`.
+#[cfg(windows)]
+fn registered_bds_versions() -> Vec<(String, String)> {
+    let output = std::process::Command::new("reg")
+        .args(["query", BDS_REGISTRY_KEY, "/s", "/v", "RootDir"])
+        .output();
+    // `reg` fails if the key doesn't exist, i.e. nothing is installed
+    let Ok(output) = output else {
+        return Vec::new();
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut versions = Vec::new();
+    let mut version: Option<&str> = None;
+    for line in stdout.lines() {
+        if line.starts_with("HKEY_") {
+            version = line.trim_end().rsplit('\\').next();
+            continue;
+        }
+        if let Some(version) = version
+            && let Some(value) = line.trim_start().strip_prefix("RootDir")
+            && let Some((_, root_dir)) = value.split_once("REG_SZ")
+        {
+            versions.push((version.to_string(), root_dir.trim().to_string()));
         }
-        return &PATH;
     }
+    return versions;
+}
+
+#[cfg(not(windows))]
+fn registered_bds_versions() -> Vec<(String, String)> {
+    return Vec::new();
 }
 
 pub async fn compiler_exists(key: &str) -> bool {
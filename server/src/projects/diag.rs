@@ -1,16 +1,84 @@
+use anyhow::Result;
 use chrono::{DateTime, Local};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use tower_lsp::lsp_types::*;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::path::Path;
 
-const MSBUILD_OUTPUT_REGEX: &str = r"^(?P<file>.*?)[(](?P<line>\d+)(?:,(?P<column>\d+))?[)]:\s+(?P<kind>.*?)\s+(?P<code>[A-Z]\d+):\s+(?P<message>.*?)(?:\s+\[.*\])?$";
+const MSBUILD_OUTPUT_REGEX: &str = r"^(?P<file>.*?)[(](?P<line>\d+)(?:,(?P<column>\d+))?[)]:\s+(?P<kind>.*?)\s+(?P<code>[A-Z]+\d+):\s+(?P<message>.*?)(?:\s+\[.*\])?$";
+/// Capture groups a custom pattern must have; `column`, `kind` and `code` are optional.
+const REQUIRED_CAPTURES: &[&str] = &["file", "line", "message"];
 
-#[derive(Debug)]
+lazy_static::lazy_static! {
+    static ref MSBUILD_OUTPUT_PATTERN: Regex = Regex::new(MSBUILD_OUTPUT_REGEX).unwrap();
+}
+
+/// The pattern compiler output lines are parsed with: `custom` if given, otherwise the
+/// msbuild format. Custom patterns are checked for the required named captures.
+pub fn diagnostic_pattern(custom: Option<&str>) -> Result<Regex> {
+    let Some(custom) = custom else {
+        return Ok(MSBUILD_OUTPUT_PATTERN.clone());
+    };
+    let pattern = Regex::new(custom)?;
+    let names: Vec<&str> = pattern.capture_names().flatten().collect();
+    let missing: Vec<&str> = REQUIRED_CAPTURES
+        .iter()
+        .filter(|required| !names.contains(required))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!("Diagnostic pattern lacks the named captures: {}", missing.join(", "));
+    }
+    return Ok(pattern);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DiagnosticKind {
     ERROR,
     WARN,
     HINT,
 }
 
+impl DiagnosticKind {
+    /// Delphi codes start with the kind's letter (`E2003`, `F1026`, `W1000`, `H2164`), which
+    /// unlike the word before them doesn't change with the IDE language. Other codes (`MSB3073`)
+    /// fall back to the English word, and to an error if that doesn't help either.
+    fn classify(code: &str, word: &str) -> Self {
+        let mut chars = code.chars();
+        let first = chars.next();
+        if chars.next().is_some_and(|ch| ch.is_ascii_digit()) {
+            match first {
+                Some('H') => return DiagnosticKind::HINT,
+                Some('W') => return DiagnosticKind::WARN,
+                Some('E' | 'F') => return DiagnosticKind::ERROR,
+                _ => {}
+            }
+        }
+        let word = word.to_lowercase();
+        if word.contains("hint") {
+            return DiagnosticKind::HINT;
+        }
+        if word.contains("warn") {
+            return DiagnosticKind::WARN;
+        }
+        return DiagnosticKind::ERROR;
+    }
+
+    fn severity(&self) -> u8 {
+        match self {
+            DiagnosticKind::ERROR => 3,
+            DiagnosticKind::WARN => 2,
+            DiagnosticKind::HINT => 1,
+        }
+    }
+
+    pub fn is_at_least(&self, threshold: &DiagnosticKind) -> bool {
+        return self.severity() >= threshold.severity();
+    }
+}
+
 impl Display for DiagnosticKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -21,6 +89,7 @@ impl Display for DiagnosticKind {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CompilerLineDiagnostic {
     pub time: DateTime<Local>,
     pub file: String,
@@ -55,22 +124,22 @@ impl Display for CompilerLineDiagnostic {
 }
 
 impl CompilerLineDiagnostic {
-    pub fn from_line(line: &str, compiler_name: String) -> Option<Self> {
-        if let Some(captures) = regex::Regex::new(MSBUILD_OUTPUT_REGEX).unwrap().captures(line) {
+    pub fn from_line(line: &str, compiler_name: &str) -> Option<Self> {
+        return Self::from_line_with(line, compiler_name, &MSBUILD_OUTPUT_PATTERN);
+    }
+
+    /// Parses a line with a pattern from `diagnostic_pattern`.
+    pub fn from_line_with(line: &str, compiler_name: &str, pattern: &Regex) -> Option<Self> {
+        if let Some(captures) = pattern.captures(line) {
             let file = captures.name("file")?.as_str().to_string();
             let line = captures.name("line")?.as_str().parse().ok()?;
             let column = captures
                 .name("column")
                 .and_then(|m| m.as_str().parse().ok());
             let message = captures.name("message")?.as_str().to_string();
-            let code = captures.name("code")?.as_str().to_string();
-            let kind = if code.starts_with('H') {
-                DiagnosticKind::HINT
-            } else if code.starts_with('W') {
-                DiagnosticKind::WARN
-            } else {
-                DiagnosticKind::ERROR
-            };
+            let code = captures.name("code").map_or("", |m| m.as_str()).to_string();
+            let word = captures.name("kind").map_or("", |m| m.as_str());
+            let kind = DiagnosticKind::classify(&code, word);
 
             Some(CompilerLineDiagnostic {
                 time: Local::now(),
@@ -80,26 +149,42 @@ impl CompilerLineDiagnostic {
                 message,
                 code,
                 kind,
-                compiler_name
+                compiler_name: compiler_name.to_string(),
             })
         } else {
             None
         }
     }
-}
 
-impl Into<Diagnostic> for CompilerLineDiagnostic {
-    fn into(self) -> Diagnostic {
+    /// `file` relative to `base` if it lies under it, for reports that leave the machine.
+    pub fn relative_to(mut self, base: Option<&Path>) -> Self {
+        if let Some(base) = base && let Some(relative) = relative_path(&self.file, base) {
+            self.file = relative;
+        }
+        return self;
+    }
+
+    /// With the text of the reported line, the range spans the identifier at the column
+    /// instead of a single character; without it, the length of the identifier quoted in
+    /// the message (e.g. `Undeclared identifier: 'Foo'`) is used for the codes in
+    /// `IDENTIFIER_MESSAGE_CODES`.
+    pub fn to_diagnostic(self, source_line: Option<&str>) -> Diagnostic {
+        let line = self.line.saturating_sub(1);
+        let column = self.column.unwrap_or(1).saturating_sub(1);
+        let (start, end) = source_line
+            .and_then(|source_line| token_range(source_line, column as usize))
+            .or_else(|| {
+                if !IDENTIFIER_MESSAGE_CODES.contains(&self.code.as_str()) {
+                    return None;
+                }
+                let identifier = quoted_identifier(&self.message)?;
+                Some((column, column + identifier.encode_utf16().count() as u32))
+            })
+            .unwrap_or((column, column + 1));
         return Diagnostic {
             range: Range {
-                start: Position {
-                    line: self.line.saturating_sub(1),
-                    character: self.column.unwrap_or(1).saturating_sub(1),
-                },
-                end: Position {
-                    line: self.line.saturating_sub(1),
-                    character: self.column.unwrap_or(1).saturating_sub(1) + 1,
-                },
+                start: Position { line, character: start },
+                end: Position { line, character: end },
             },
             severity: match self.kind {
                 DiagnosticKind::ERROR => Some(DiagnosticSeverity::ERROR),
@@ -113,3 +198,304 @@ impl Into<Diagnostic> for CompilerLineDiagnostic {
         };
     }
 }
+
+impl Into<Diagnostic> for CompilerLineDiagnostic {
+    fn into(self) -> Diagnostic {
+        return self.to_diagnostic(None);
+    }
+}
+
+/// `file` relative to `base` with `/` separators; `None` if it doesn't lie under `base`.
+pub fn relative_path(file: &str, base: &Path) -> Option<String> {
+    let relative = Path::new(file).strip_prefix(base).ok()?;
+    return Some(relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"));
+}
+
+/// Diagnostics grouped by file with every path made relative to `base`, see `relative_to`.
+pub fn relative_diagnostics(
+    diagnostics: BTreeMap<String, Vec<CompilerLineDiagnostic>>,
+    base: Option<&Path>,
+) -> BTreeMap<String, Vec<CompilerLineDiagnostic>> {
+    let Some(base) = base else {
+        return diagnostics;
+    };
+    let mut relative: BTreeMap<String, Vec<CompilerLineDiagnostic>> = BTreeMap::new();
+    for (file, file_diagnostics) in diagnostics {
+        let file = relative_path(&file, base).unwrap_or(file);
+        relative
+            .entry(file)
+            .or_default()
+            .extend(file_diagnostics.into_iter().map(|diagnostic| diagnostic.relative_to(Some(base))));
+    }
+    return relative;
+}
+
+/// UTF-16 range of the identifier starting at the 0-based character `column` of `line`;
+/// `None` if the column is past the line or not on an identifier.
+fn token_range(line: &str, column: usize) -> Option<(u32, u32)> {
+    let is_identifier = |ch: &char| ch.is_alphanumeric() || *ch == '_';
+    let mut chars = line.chars();
+    let start: usize = chars.by_ref().take(column).map(char::len_utf16).sum();
+    let first = chars.next().filter(is_identifier)?;
+    let width: usize = first.len_utf16() + chars.take_while(is_identifier).map(char::len_utf16).sum::<usize>();
+    return Some((start as u32, (start + width) as u32));
+}
+
+/// Codes whose message quotes the identifier the column points at. Other messages quote
+/// types or expected tokens (`Incompatible types: 'Integer' and 'string'`), which say
+/// nothing about the length of the code at the column.
+const IDENTIFIER_MESSAGE_CODES: &[&str] = &[
+    "E2003", // Undeclared identifier: '%s'
+    "E2004", // Identifier redeclared: '%s'
+    "E2005", // '%s' is not a type identifier
+    "E2065", // Unsatisfied forward or external declaration: '%s'
+    "W1000", // Symbol '%s' is deprecated
+    "W1001", // Symbol '%s' is specific to a library
+    "W1002", // Symbol '%s' is specific to a platform
+    "W1003", // Symbol '%s' is experimental
+    "W1036", // Variable '%s' might not have been initialized
+    "H2077", // Value assigned to '%s' never used
+    "H2164", // Variable '%s' is declared but never used
+    "H2219", // Private symbol '%s' declared but never used
+];
+
+/// The first single-quoted (optionally dotted) identifier in a compiler message; quoted
+/// tokens like `'('` are skipped.
+fn quoted_identifier(message: &str) -> Option<&str> {
+    return message
+        .split('\'')
+        .skip(1)
+        .step_by(2)
+        .find(|quoted| {
+            !quoted.is_empty()
+                && !quoted.starts_with(|ch: char| ch.is_ascii_digit())
+                && quoted.split('.').all(|part| {
+                    !part.is_empty() && part.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+                })
+        });
+}
+
+const IGNORE_MARKER: &str = "ddk:ignore";
+const IGNORE_NEXT_LINE_MARKER: &str = "ddk:ignore-next-line";
+
+/// Editor-level suppression via source comments: `// ddk:ignore W1002` on the
+/// diagnostic's line or the line above, or `// ddk:ignore-next-line W1002` on the
+/// line above. Without codes every diagnostic on the line is suppressed.
+#[derive(Default)]
+pub struct DiagnosticSuppressions {
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl DiagnosticSuppressions {
+    fn lines(&mut self, file: &str) -> Option<&Vec<String>> {
+        return self.files
+            .entry(file.to_string())
+            .or_insert_with(|| {
+                std::fs::read_to_string(file)
+                    .ok()
+                    .map(|content| content.lines().map(|line| line.to_string()).collect())
+            })
+            .as_ref();
+    }
+
+    /// The source line a diagnostic was reported on, from the files read for suppression checks.
+    pub fn source_line(&mut self, diagnostic: &CompilerLineDiagnostic) -> Option<&str> {
+        let index = diagnostic.line.checked_sub(1)? as usize;
+        return self.lines(&diagnostic.file)?.get(index).map(String::as_str);
+    }
+
+    pub fn is_suppressed(&mut self, diagnostic: &CompilerLineDiagnostic) -> bool {
+        let Some(lines) = self.lines(&diagnostic.file) else {
+            return false;
+        };
+        let index = diagnostic.line.saturating_sub(1) as usize;
+        if let Some(line) = lines.get(index) &&
+           suppresses(line, IGNORE_MARKER, &diagnostic.code) {
+            return true;
+        }
+        if index > 0 && let Some(above) = lines.get(index - 1) {
+            return suppresses(above, IGNORE_NEXT_LINE_MARKER, &diagnostic.code)
+                || suppresses(above, IGNORE_MARKER, &diagnostic.code);
+        }
+        return false;
+    }
+}
+
+fn suppresses(line: &str, marker: &str, code: &str) -> bool {
+    let Some(position) = line.find(marker) else {
+        return false;
+    };
+    let rest = &line[position + marker.len()..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t', ',', '}', '*']) {
+        // `ddk:ignore-next-line` must not be read as `ddk:ignore` with trailing text
+        return false;
+    }
+    let codes: Vec<&str> = rest
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '}' || c == '*' || c == ')')
+        .filter(|part| !part.is_empty())
+        .collect();
+    return codes.is_empty() || codes.iter().any(|candidate| candidate.eq_ignore_ascii_case(code));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_decide_the_kind_before_the_word() {
+        assert_eq!(DiagnosticKind::classify("H2164", "Fehler"), DiagnosticKind::HINT);
+        assert_eq!(DiagnosticKind::classify("W1000", ""), DiagnosticKind::WARN);
+        assert_eq!(DiagnosticKind::classify("F1026", "warning"), DiagnosticKind::ERROR);
+        assert_eq!(DiagnosticKind::classify("MSB3073", "warning"), DiagnosticKind::WARN);
+        assert_eq!(DiagnosticKind::classify("", "Hint"), DiagnosticKind::HINT);
+        assert_eq!(DiagnosticKind::classify("", ""), DiagnosticKind::ERROR);
+    }
+
+    #[test]
+    fn codes_may_start_with_any_character() {
+        assert_eq!(DiagnosticKind::classify("É2003", "warning"), DiagnosticKind::WARN);
+        assert_eq!(DiagnosticKind::classify("W", "hint"), DiagnosticKind::HINT);
+        assert_eq!(DiagnosticKind::classify("Wé", "hint"), DiagnosticKind::HINT);
+    }
+
+    #[test]
+    fn custom_patterns_need_file_line_and_message() {
+        let error = diagnostic_pattern(Some(r"^(?P<file>.*?):(?P<text>.*)$")).unwrap_err();
+        assert_eq!(error.to_string(), "Diagnostic pattern lacks the named captures: line, message");
+        assert!(diagnostic_pattern(Some("(")).is_err());
+
+        let pattern = diagnostic_pattern(Some(r"^(?P<file>[^:]+):(?P<line>\d+): (?P<code>\S+) (?P<message>.*)$")).unwrap();
+        let diagnostic = CompilerLineDiagnostic::from_line_with("Unit1.pas:12: É2003 Inconnu", "dcc32", &pattern).unwrap();
+        assert_eq!(diagnostic.file, "Unit1.pas");
+        assert_eq!(diagnostic.line, 12);
+        assert_eq!(diagnostic.column, None);
+        assert_eq!(diagnostic.code, "É2003");
+        assert_eq!(diagnostic.kind, DiagnosticKind::ERROR);
+    }
+
+    #[test]
+    fn only_identifier_messages_size_the_range() {
+        let range = |line: &str| {
+            let diagnostic = CompilerLineDiagnostic::from_line(line, "dcc32").unwrap();
+            let range = diagnostic.to_diagnostic(None).range;
+            return (range.start.character, range.end.character);
+        };
+        assert_eq!(range("Unit1.pas(3,5): error E2003: Undeclared identifier: 'Counter'"), (4, 11));
+        assert_eq!(range("Unit1.pas(3,5): error E2010: Incompatible types: 'Integer' and 'string'"), (4, 5));
+        assert_eq!(range("Unit1.pas(3,5): error E2029: ';' expected but '.' found"), (4, 5));
+    }
+
+    #[test]
+    fn the_range_spans_the_whole_identifier_at_the_column() {
+        assert_eq!(token_range("  Counter_2 := 1;", 2), Some((2, 11)));
+        assert_eq!(token_range("  Zähler := 1;", 2), Some((2, 8)));
+        // a character outside the BMP counts twice
+        assert_eq!(token_range("s := '😀'; Name := s;", 10), Some((11, 15)));
+        assert_eq!(token_range("  x := 1;", 4), None);
+        assert_eq!(token_range("  x := 1;", 40), None);
+
+        let diagnostic = CompilerLineDiagnostic::from_line("Unit1.pas(3,3): error E2010: Incompatible types: 'Integer' and 'string'", "dcc32").unwrap();
+        let range = |source_line: Option<&str>| {
+            let range = diagnostic.clone().to_diagnostic(source_line).range;
+            return (range.start.character, range.end.character);
+        };
+        assert_eq!(range(Some("  Counter := 'a';")), (2, 9));
+        assert_eq!(range(Some("  ")), (2, 3));
+        assert_eq!(range(None), (2, 3));
+    }
+
+    #[test]
+    fn suppressed_codes_produce_no_diagnostic() {
+        let directory = tempfile::tempdir().unwrap();
+        let unit = directory.path().join("Unit1.pas");
+        std::fs::write(&unit, [
+            "x := Old; // ddk:ignore W1000",
+            "// ddk:ignore-next-line W1000, W1002",
+            "x := Old;",
+            "x := Old; // ddk:ignore W1002",
+            "// ddk:ignore-next-line",
+            "x := Old;",
+        ].join("\n")).unwrap();
+        let mut suppressions = DiagnosticSuppressions::default();
+        let mut published = |line: u32, code: &str| {
+            let diagnostic = CompilerLineDiagnostic::from_line(
+                &format!("{}({line},6): warning {code}: Symbol 'Old' is deprecated", unit.display()),
+                "dcc32",
+            ).unwrap();
+            return (!suppressions.is_suppressed(&diagnostic)).then(|| diagnostic.to_diagnostic(None));
+        };
+        assert!(published(1, "W1000").is_none());
+        assert!(published(1, "W1002").is_some());
+        assert!(published(3, "W1002").is_none());
+        assert!(published(3, "H2164").is_some());
+        assert!(published(4, "W1000").is_some());
+        assert!(published(6, "H2164").is_none());
+    }
+
+    #[test]
+    fn paths_under_the_base_are_reported_relative() {
+        let base = Path::new("/repo");
+        assert_eq!(relative_path("/repo/src/Unit1.pas", base).as_deref(), Some("src/Unit1.pas"));
+        assert_eq!(relative_path("/repository/Unit1.pas", base), None);
+        assert_eq!(relative_path("Unit1.pas", base), None);
+
+        let inside = CompilerLineDiagnostic::from_line("/repo/src/Unit1.pas(3,5): error E2003: Undeclared identifier: 'Counter'", "dcc32").unwrap();
+        let outside = CompilerLineDiagnostic::from_line("/lib/Lib.pas(1): warning W1000: Symbol 'Old' is deprecated", "dcc32").unwrap();
+        assert_eq!(inside.clone().relative_to(None).file, "/repo/src/Unit1.pas");
+        assert_eq!(inside.clone().relative_to(Some(base)).file, "src/Unit1.pas");
+
+        let by_file = BTreeMap::from([(inside.file.clone(), vec![inside]), (outside.file.clone(), vec![outside])]);
+        let grouped = relative_diagnostics(by_file, Some(base));
+        assert_eq!(grouped.keys().collect::<Vec<_>>(), vec!["/lib/Lib.pas", "src/Unit1.pas"]);
+        assert_eq!(grouped["src/Unit1.pas"][0].file, "src/Unit1.pas");
+    }
+
+    /// A build log as msbuild writes it: mostly progress lines, with a diagnostic every few lines.
+    fn captured_log(lines: usize) -> Vec<String> {
+        return (0..lines)
+            .map(|index| match index % 4 {
+                0 => format!(r"C:\Projects\App\Unit{index}.pas({},5): warning W1000: Symbol 'Foo' is deprecated [C:\Projects\App\App.dproj]", index % 900 + 1),
+                1 => format!(r"C:\Projects\App\Unit{index}.pas({}): error E2003: Undeclared identifier: 'Bar'", index % 900 + 1),
+                2 => format!("  Unit{index}.pas({index})"),
+                _ => "  Embarcadero Delphi for Win32 compiler version 36.0".to_string(),
+            })
+            .collect();
+    }
+
+    #[test]
+    fn the_shared_pattern_parses_a_large_log() {
+        let log = captured_log(20_000);
+        let parsed = log
+            .iter()
+            .filter_map(|line| CompilerLineDiagnostic::from_line(line, "dcc32"))
+            .count();
+        assert_eq!(parsed, 10_000);
+    }
+
+    /// Timing depends on the machine, so this only runs on request: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    #[allow(clippy::regex_creation_in_loops)]
+    fn the_shared_pattern_is_faster_than_compiling_it_per_line() {
+        let log = captured_log(20_000);
+        let started = std::time::Instant::now();
+        for line in &log {
+            let _ = CompilerLineDiagnostic::from_line(line, "dcc32");
+        }
+        let shared = started.elapsed() / log.len() as u32;
+
+        // compiling the pattern per line, as before; a sample is enough to compare
+        let sample = &log[..200];
+        let started = std::time::Instant::now();
+        for line in sample {
+            let pattern = Regex::new(MSBUILD_OUTPUT_REGEX).unwrap();
+            let _ = CompilerLineDiagnostic::from_line_with(line, "dcc32", &pattern);
+        }
+        let recompiled = started.elapsed() / sample.len() as u32;
+        assert!(shared * 10 < recompiled, "{shared:?} per line with the shared pattern, {recompiled:?} when compiling it per line");
+    }
+}
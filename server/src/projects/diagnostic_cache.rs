@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI32, Ordering};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+
+lazy_static::lazy_static! {
+    /// Mirror of what was last published to the client, per file.
+    static ref PUBLISHED_DIAGNOSTICS: Mutex<BTreeMap<Url, PublishedDiagnostics>> = Mutex::new(BTreeMap::new());
+}
+
+struct PublishedDiagnostics {
+    /// The project whose build reported them.
+    project_id: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Sent as the `version` of every publish; bumped per build so a client can drop updates
+/// of an older build that arrive after those of a newer one.
+static BUILD_VERSION: AtomicI32 = AtomicI32::new(0);
+
+/// Starts a new build's diagnostics; everything published afterwards carries the returned version.
+pub fn next_build_version() -> i32 {
+    return BUILD_VERSION.fetch_add(1, Ordering::SeqCst) + 1;
+}
+
+pub fn current_build_version() -> i32 {
+    return BUILD_VERSION.load(Ordering::SeqCst);
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeverityCounts {
+    pub errors: usize,
+    pub warnings: usize,
+    pub hints: usize,
+}
+
+impl SeverityCounts {
+    fn add(&mut self, diagnostic: &Diagnostic) {
+        match diagnostic.severity {
+            Some(DiagnosticSeverity::ERROR) => self.errors += 1,
+            Some(DiagnosticSeverity::WARNING) => self.warnings += 1,
+            _ => self.hints += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiagnostics {
+    pub uri: Url,
+    pub counts: SeverityCounts,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AllDiagnostics {
+    pub totals: SeverityCounts,
+    pub files: Vec<FileDiagnostics>,
+}
+
+pub async fn publish_diagnostics(
+    client: &tower_lsp::Client,
+    project_id: usize,
+    file: &str,
+    diagnostics: &Vec<Diagnostic>,
+) {
+    let uri = Url::from_file_path(file).unwrap_or_else(|_| Url::parse("untitled:unknown").unwrap());
+    if let Ok(mut published) = PUBLISHED_DIAGNOSTICS.lock() {
+        if diagnostics.is_empty() {
+            published.remove(&uri);
+        } else {
+            published.insert(uri.clone(), PublishedDiagnostics { project_id, diagnostics: diagnostics.clone() });
+        }
+    }
+    client
+        .publish_diagnostics(uri, diagnostics.clone(), Some(current_build_version()))
+        .await;
+}
+
+/// Withdraws everything published so far.
+pub async fn clear_published_diagnostics(client: &tower_lsp::Client) {
+    let uris: Vec<Url> = match PUBLISHED_DIAGNOSTICS.lock() {
+        Ok(mut published) => std::mem::take(&mut *published).into_keys().collect(),
+        Err(_) => return,
+    };
+    for uri in uris {
+        client.publish_diagnostics(uri, Vec::new(), Some(current_build_version())).await;
+    }
+}
+
+/// Withdraws what the given projects published, so files fixed since their last build don't keep
+/// stale problems while those of projects outside the build stay.
+pub async fn clear_project_diagnostics(client: &tower_lsp::Client, project_ids: &[usize]) {
+    for uri in take_project_diagnostics(project_ids) {
+        client.publish_diagnostics(uri, Vec::new(), Some(current_build_version())).await;
+    }
+}
+
+fn take_project_diagnostics(project_ids: &[usize]) -> Vec<Url> {
+    let Ok(mut published) = PUBLISHED_DIAGNOSTICS.lock() else {
+        return Vec::new();
+    };
+    let uris: Vec<Url> = published
+        .iter()
+        .filter(|(_, entry)| project_ids.contains(&entry.project_id))
+        .map(|(uri, _)| uri.clone())
+        .collect();
+    for uri in &uris {
+        published.remove(uri);
+    }
+    return uris;
+}
+
+/// Withdraws what was published for a single file.
+pub async fn clear_file_diagnostics(client: &tower_lsp::Client, uri: Url) {
+    if let Ok(mut published) = PUBLISHED_DIAGNOSTICS.lock() {
+        published.remove(&uri);
+    }
+    client.publish_diagnostics(uri, Vec::new(), Some(current_build_version())).await;
+}
+
+pub fn all_diagnostics() -> AllDiagnostics {
+    let mut all = AllDiagnostics::default();
+    let Ok(published) = PUBLISHED_DIAGNOSTICS.lock() else {
+        return all;
+    };
+    for (uri, PublishedDiagnostics { diagnostics, .. }) in published.iter() {
+        let mut counts = SeverityCounts::default();
+        for diagnostic in diagnostics {
+            counts.add(diagnostic);
+            all.totals.add(diagnostic);
+        }
+        all.files.push(FileDiagnostics {
+            uri: uri.clone(),
+            counts,
+            diagnostics: diagnostics.clone(),
+        });
+    }
+    return all;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{connected_client, test_client, test_session};
+
+    fn error() -> Vec<Diagnostic> {
+        return vec![Diagnostic {
+            severity: Some(DiagnosticSeverity::ERROR),
+            message: "E2003 Undeclared identifier: 'Foo'".to_string(),
+            ..Default::default()
+        }];
+    }
+
+    #[tokio::test]
+    async fn a_build_clears_only_its_own_projects() {
+        let _session = test_session().await;
+        let client = test_client();
+        let directory = tempfile::tempdir().unwrap();
+        let built = directory.path().join("Built.pas");
+        let other = directory.path().join("Other.pas");
+        publish_diagnostics(&client, 1, built.to_str().unwrap(), &error()).await;
+        publish_diagnostics(&client, 2, other.to_str().unwrap(), &error()).await;
+
+        clear_project_diagnostics(&client, &[1]).await;
+
+        let remaining: Vec<Url> = all_diagnostics().files.into_iter().map(|file| file.uri).collect();
+        assert!(remaining.contains(&Url::from_file_path(&other).unwrap()));
+        assert!(!remaining.contains(&Url::from_file_path(&built).unwrap()));
+        clear_published_diagnostics(&client).await;
+    }
+
+    #[tokio::test]
+    async fn clearing_a_file_withdraws_only_its_diagnostics() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        let directory = tempfile::tempdir().unwrap();
+        let cleared = Url::from_file_path(directory.path().join("Cleared.pas")).unwrap();
+        let kept = Url::from_file_path(directory.path().join("Kept.pas")).unwrap();
+        for uri in [&cleared, &kept] {
+            publish_diagnostics(&client, 1, uri.to_file_path().unwrap().to_str().unwrap(), &error()).await;
+        }
+
+        clear_file_diagnostics(&client, cleared.clone()).await;
+        client.log_message(tower_lsp::lsp_types::MessageType::LOG, "cleared").await;
+
+        let mut published = Vec::new();
+        while let Some(message) = received.recv().await {
+            if message["params"]["message"] == "cleared" {
+                break;
+            }
+            if message["method"] == "textDocument/publishDiagnostics" {
+                published.push((message["params"]["uri"].as_str().unwrap().to_string(), message["params"]["diagnostics"].as_array().unwrap().len()));
+            }
+        }
+        assert_eq!(published, vec![(cleared.to_string(), 1), (kept.to_string(), 1), (cleared.to_string(), 0)]);
+        let remaining: Vec<Url> = all_diagnostics().files.into_iter().map(|file| file.uri).collect();
+        assert!(remaining.contains(&kept));
+        assert!(!remaining.contains(&cleared));
+        clear_published_diagnostics(&client).await;
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc;
+use tower_lsp::Client;
+
+use crate::state::PROJECTS_DATA;
+use crate::utils::comparable_path;
+use crate::{Progress, ProjectsUpdate};
+
+use super::{Change, ChangeSet, ProjectsData, WatcherGuard};
+
+const OPERATION: &str = "discoverProjects";
+/// Files scanned between two progress reports.
+const REPORT_INTERVAL: usize = 500;
+/// IDE backup folders hold stale copies of the project files.
+const SKIPPED_DIRECTORIES: &[&str] = &["__history", "__recovery"];
+
+static CANCEL_DISCOVERY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryResult {
+    pub scanned: usize,
+    pub found: usize,
+    pub added: usize,
+    pub cancelled: bool,
+}
+
+/// Stops a running discovery after the directory it is scanning; nothing is added then.
+pub fn cancel_discovery() {
+    CANCEL_DISCOVERY.store(true, Ordering::SeqCst);
+}
+
+/// Scans `directory` recursively for `.dproj` files and adds the ones not known yet to the
+/// workspace, reporting progress through `$/notifications/progress`.
+pub async fn discover_projects(client: &Client, directory: &Path, workspace_id: usize) -> Result<DiscoveryResult> {
+    if !directory.is_dir() {
+        anyhow::bail!("Not a directory: {}", directory.display());
+    }
+    CANCEL_DISCOVERY.store(false, Ordering::SeqCst);
+    Progress::notify_start(client, OPERATION).await;
+    let outcome = discover(client, directory, workspace_id).await;
+    // the client keeps showing the progress until it ends, failed or not
+    let message = match &outcome {
+        Ok(result) => format!("{} files scanned, {} projects found, {} added", result.scanned, result.found, result.added),
+        Err(e) => format!("Discovery failed: {:#}", e),
+    };
+    let cancelled = outcome.as_ref().is_ok_and(|result| result.cancelled);
+    Progress::notify_completed(client, OPERATION, message, cancelled).await;
+    if outcome.as_ref().is_ok_and(|result| result.added > 0) {
+        ProjectsUpdate::notify(client).await;
+    }
+    return outcome;
+}
+
+async fn discover(client: &Client, directory: &Path, workspace_id: usize) -> Result<DiscoveryResult> {
+    let (progress, mut reports) = mpsc::unbounded_channel::<String>();
+    let directory = directory.to_path_buf();
+    let walk = tokio::task::spawn_blocking(move || walk(&directory, &progress));
+    while let Some(report) = reports.recv().await {
+        Progress::notify_report(client, OPERATION, report).await;
+    }
+    let (mut result, mut found) = walk.await?;
+    result.found = found.len();
+    if result.cancelled {
+        return Ok(result);
+    }
+    found.sort();
+    let changes: Vec<Change> = {
+        let projects_data = PROJECTS_DATA.read().await;
+        found
+            .into_iter()
+            .map(|dproj| dproj.to_string_lossy().to_string())
+            .filter(|dproj| !is_known(&projects_data, dproj))
+            .map(|file_path| Change::NewProject { file_path, workspace_id })
+            .collect()
+    };
+    if changes.is_empty() {
+        return Ok(result);
+    }
+    result.added = changes.len();
+    let _watchers = WatcherGuard::pause(client);
+    ChangeSet { changes, atomic: true }.execute().await?;
+    return Ok(result);
+}
+
+/// Whether the project at `dproj` is already there. A project found by its GUID at another
+/// path is a moved copy; adding it relocates the known project.
+fn is_known(projects_data: &ProjectsData, dproj: &str) -> bool {
+    let wanted = comparable_path(Path::new(dproj));
+    return projects_data
+        .find_project_by_dproj(&dproj.to_string())
+        .and_then(|project| project.dproj.as_ref())
+        .is_some_and(|known| comparable_path(Path::new(known)) == wanted);
+}
+
+/// The `.dproj` files below `directory`, sending a progress message every `REPORT_INTERVAL`
+/// files. Runs on a blocking thread.
+fn walk(directory: &Path, progress: &mpsc::UnboundedSender<String>) -> (DiscoveryResult, Vec<PathBuf>) {
+    let mut result = DiscoveryResult::default();
+    let mut found: Vec<PathBuf> = Vec::new();
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        if CANCEL_DISCOVERY.load(Ordering::SeqCst) {
+            result.cancelled = true;
+            break;
+        }
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.starts_with('.') && !SKIPPED_DIRECTORIES.iter().any(|skipped| skipped.eq_ignore_ascii_case(&name)) {
+                    pending.push(path);
+                }
+                continue;
+            }
+            result.scanned += 1;
+            if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("dproj")) {
+                found.push(path);
+            }
+            if result.scanned % REPORT_INTERVAL == 0 {
+                let _ = progress.send(format!("{} files scanned, {} projects found", result.scanned, found.len()));
+            }
+        }
+    }
+    return (result, found);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{test_client, test_session};
+
+    fn write_project(directory: &Path, name: &str) {
+        std::fs::create_dir_all(directory).unwrap();
+        let dproj = format!("<Project><PropertyGroup><MainSource>{name}.dpr</MainSource></PropertyGroup></Project>");
+        std::fs::write(directory.join(format!("{name}.dproj")), dproj).unwrap();
+        std::fs::write(directory.join(format!("{name}.dpr")), format!("program {name};")).unwrap();
+    }
+
+    async fn new_workspace() -> usize {
+        let change_set = ChangeSet {
+            changes: vec![Change::AddWorkspace { name: "Main".to_string(), compiler: "12.0".to_string() }],
+            atomic: true,
+        };
+        change_set.execute().await.unwrap();
+        return PROJECTS_DATA.read().await.workspaces[0].id;
+    }
+
+    #[tokio::test]
+    async fn projects_are_added_once() {
+        let _session = test_session().await;
+        let client = test_client();
+        let directory = tempfile::tempdir().unwrap();
+        write_project(directory.path(), "A");
+        write_project(&directory.path().join("sub"), "B");
+        write_project(&directory.path().join("__history"), "C");
+        let workspace_id = new_workspace().await;
+
+        let result = discover_projects(&client, directory.path(), workspace_id).await.unwrap();
+        assert_eq!((result.found, result.added), (2, 2));
+        let again = discover_projects(&client, directory.path(), workspace_id).await.unwrap();
+        assert_eq!((again.found, again.added), (2, 0));
+        assert_eq!(PROJECTS_DATA.read().await.projects.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failing_add_adds_nothing() {
+        let _session = test_session().await;
+        let client = test_client();
+        let directory = tempfile::tempdir().unwrap();
+        write_project(directory.path(), "A");
+        write_project(directory.path(), "B");
+        let unknown_workspace = new_workspace().await + 1000;
+
+        assert!(discover_projects(&client, directory.path(), unknown_workspace).await.is_err());
+        assert!(PROJECTS_DATA.read().await.projects.is_empty());
+    }
+}
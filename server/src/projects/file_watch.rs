@@ -1,18 +1,85 @@
 use crate::state::Stateful;
 use anyhow::Result;
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::PathBuf;
-use std::sync::atomic::Ordering;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use tokio::sync::mpsc;
-use tower_lsp::lsp_types::MessageType;
+use tower_lsp::lsp_types::{
+    FileChangeType, FileEvent, FileSystemWatcher, GlobPattern, MessageType, OneOf, RelativePattern, Url,
+};
 use tower_lsp::Client;
-use crate::{CompilersUpdate, ProjectsUpdate};
-use crate::utils::FilePath;
+use crate::{CompilersUpdate, FormatterUpdated, ProjectsUpdate};
+use crate::format::Formatter;
+use crate::utils::{FilePath, data_file_extension};
 
 use super::*;
 
+/// Bulk operations in progress; while there are any, watcher events are only recorded.
+static PAUSED_WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static PROJECTS_CHANGED_WHILE_PAUSED: AtomicBool = AtomicBool::new(false);
+static COMPILERS_CHANGED_WHILE_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Holds back watcher-driven reloads and update notifications while a bulk operation saves
+/// repeatedly. Coarser than the self-write flag: it also covers events that arrive after the
+/// flag was consumed. When the last guard drops, the files that changed in the meantime are
+/// reloaded once and a single update is sent.
+pub struct WatcherGuard {
+    client: Client,
+}
+
+impl WatcherGuard {
+    pub fn pause(client: &Client) -> Self {
+        PAUSED_WATCHERS.fetch_add(1, Ordering::SeqCst);
+        return WatcherGuard { client: client.clone() };
+    }
+}
+
+impl Drop for WatcherGuard {
+    fn drop(&mut self) {
+        if PAUSED_WATCHERS.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        let projects_changed = PROJECTS_CHANGED_WHILE_PAUSED.swap(false, Ordering::SeqCst);
+        let compilers_changed = COMPILERS_CHANGED_WHILE_PAUSED.swap(false, Ordering::SeqCst);
+        if !projects_changed && !compilers_changed {
+            return;
+        }
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if projects_changed {
+                reload_projects_data().await;
+                if let Err(e) = sync_source_watch_roots().await {
+                    eprintln!("Failed to update source watchers: {}", e);
+                }
+                ProjectsUpdate::notify(&client).await;
+            }
+            if compilers_changed {
+                reload_compiler_configurations().await;
+                CompilersUpdate::notify(&client).await;
+            }
+        });
+    }
+}
+
+/// Records the change for the end of the bulk operation if watchers are paused.
+fn hold_back(changed: &AtomicBool) -> bool {
+    if PAUSED_WATCHERS.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+    changed.store(true, Ordering::SeqCst);
+    return true;
+}
+
+fn is_content_change(event: &Event) -> bool {
+    use notify::EventKind;
+
+    return matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_));
+}
+
+/// Watches `path` by name through its parent directory, so saves that replace the file
+/// (write to a temp file, then rename over it) keep being reported after the first one.
 fn create_watcher<F>(
-    path: &PathBuf,
+    path: &Path,
     mut on_event: F,
 ) -> Result<RecommendedWatcher>
 where
@@ -27,11 +94,18 @@ where
         Config::default(),
     )?;
 
-    watcher.watch(&path, RecursiveMode::NonRecursive)?;
+    let directory = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Cannot watch {}: no parent directory", path.display()))?;
+    std::fs::create_dir_all(directory)?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
 
+    let file_name = path.file_name().map(|name| name.to_os_string());
     tokio::spawn(async move {
         while let Some(res) = rx.recv().await {
-            if let Ok(event) = res {
+            if let Ok(event) = res
+                && event.paths.iter().any(|changed| changed.file_name() == file_name.as_deref())
+            {
                 on_event(event);
             }
         }
@@ -65,9 +139,21 @@ pub fn start_file_watchers(client: Client) -> Result<()> {
         },
     )?;
 
+    // Watcher for the formatter config
+    let formatter_client = client.clone();
+    let _formatter_watcher = create_watcher(
+        &Formatter::default_config_path()?,
+        move |event| {
+            let client = formatter_client.clone();
+            tokio::spawn(async move {
+                handle_formatter_config_change(event, &client).await;
+            });
+        },
+    )?;
+
     // Keep watchers alive by storing them
     tokio::spawn(async move {
-        let _keep_alive = (_projects_watcher, _compiler_watcher);
+        let _keep_alive = (_projects_watcher, _compiler_watcher, _formatter_watcher);
         // Wait forever to keep watchers alive
         std::future::pending::<()>().await;
     });
@@ -81,6 +167,9 @@ async fn handle_projects_data_change(event: Event, client: &Client) {
     if ProjectsData::internal_change_flag().swap(false, Ordering::SeqCst) {
         return;
     }
+    if is_content_change(&event) && hold_back(&PROJECTS_CHANGED_WHILE_PAUSED) {
+        return;
+    }
 
     match event.kind {
         EventKind::Modify(_) => {
@@ -103,6 +192,11 @@ async fn handle_projects_data_change(event: Event, client: &Client) {
         }
         _ => { return; }
     }
+    // keep the in-memory state in line with the edited file
+    reload_projects_data().await;
+    if let Err(e) = sync_source_watch_roots().await {
+        eprintln!("Failed to update source watchers: {}", e);
+    }
     ProjectsUpdate::notify(client).await;
 }
 
@@ -112,6 +206,9 @@ async fn handle_compiler_config_change(event: Event, client: &Client) {
     if CompilerConfigurations::internal_change_flag().swap(false, Ordering::SeqCst) {
         return;
     }
+    if is_content_change(&event) && hold_back(&COMPILERS_CHANGED_WHILE_PAUSED) {
+        return;
+    }
 
     match event.kind {
         EventKind::Modify(_) => {
@@ -134,5 +231,301 @@ async fn handle_compiler_config_change(event: Event, client: &Client) {
         }
         _ => { return; }
     }
+    reload_compiler_configurations().await;
     CompilersUpdate::notify(client).await;
-}
\ No newline at end of file
+}
+
+async fn handle_formatter_config_change(event: Event, client: &Client) {
+    use notify::EventKind;
+
+    match event.kind {
+        EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_) => {}
+        _ => { return; }
+    }
+    if let Some(path) = event.paths.first() {
+        client.log_message(
+            MessageType::INFO,
+            "Formatter config changed".to_string()
+        ).await;
+        FormatterUpdated::notify(client, path).await;
+    }
+}
+
+/// Watchers the client registers on our behalf, for environments where the `notify`
+/// watchers above can't see the files (remote or virtual file systems).
+pub fn client_file_watchers() -> Vec<FileSystemWatcher> {
+    let mut watchers = vec![
+        FileSystemWatcher {
+            glob_pattern: GlobPattern::String("**/*.dproj".to_string()),
+            kind: None,
+        },
+        FileSystemWatcher {
+            glob_pattern: GlobPattern::String("**/*.groupproj".to_string()),
+            kind: None,
+        },
+    ];
+    if let Some(config_dir) = ProjectsData::get_file_path().parent() &&
+       let Ok(base_uri) = Url::from_directory_path(config_dir) {
+        watchers.push(FileSystemWatcher {
+            glob_pattern: GlobPattern::Relative(RelativePattern {
+                base_uri: OneOf::Right(base_uri.clone()),
+                pattern: format!("*.{}", data_file_extension()),
+            }),
+            kind: None,
+        });
+        watchers.push(FileSystemWatcher {
+            glob_pattern: GlobPattern::Relative(RelativePattern {
+                base_uri: OneOf::Right(base_uri),
+                pattern: "ddk_formatter.config".to_string(),
+            }),
+            kind: None,
+        });
+    }
+    return watchers;
+}
+
+pub async fn handle_watched_file_changes(changes: Vec<FileEvent>, client: &Client) -> Result<()> {
+    let mut projects_changed = false;
+    let mut compilers_changed = false;
+    let formatter_config = Formatter::default_config_path().ok();
+    for change in changes {
+        let path = match change.uri.to_file_path() {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if &path == ProjectsData::get_file_path() {
+            if !hold_back(&PROJECTS_CHANGED_WHILE_PAUSED) {
+                projects_changed |= reload_projects_data().await;
+            }
+            continue;
+        }
+        if &path == CompilerConfigurations::get_file_path() {
+            if !hold_back(&COMPILERS_CHANGED_WHILE_PAUSED) {
+                compilers_changed |= reload_compiler_configurations().await;
+            }
+            continue;
+        }
+        if formatter_config.as_ref() == Some(&path) {
+            FormatterUpdated::notify(client, &path).await;
+            continue;
+        }
+        if change.typ == FileChangeType::DELETED {
+            continue;
+        }
+        match path.extension().and_then(|ext| ext.to_str()).map(|s| s.to_lowercase()) {
+            Some(ext) if ext == "dproj" => {
+                projects_changed |= refresh_projects_of_dproj(&path).await?;
+            }
+            Some(ext) if ext == "groupproj" => {
+                projects_changed |= reload_group_project(&path).await?;
+            }
+            _ => {}
+        }
+    }
+    if projects_changed && !hold_back(&PROJECTS_CHANGED_WHILE_PAUSED) {
+        sync_source_watch_roots().await?;
+        ProjectsUpdate::notify(client).await;
+    }
+    if compilers_changed && !hold_back(&COMPILERS_CHANGED_WHILE_PAUSED) {
+        CompilersUpdate::notify(client).await;
+    }
+    Ok(())
+}
+
+/// Replaces the in-memory data with the file's. The file is read before taking the write
+/// lock, and one that doesn't parse (e.g. half-saved by an editor) leaves the data as it is.
+async fn reload_projects_data() -> bool {
+    let loaded = match ProjectsData::load_current().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Keeping the current projects data: {:#}", e);
+            return false;
+        }
+    };
+    let mut projects_data = PROJECTS_DATA.write().await;
+    if *projects_data == loaded {
+        return false;
+    }
+    *projects_data = loaded;
+    return true;
+}
+
+async fn reload_compiler_configurations() -> bool {
+    let loaded = match CompilerConfigurations::load_current().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Keeping the current compiler configurations: {:#}", e);
+            return false;
+        }
+    };
+    let mut compilers = COMPILER_CONFIGURATIONS.write().await;
+    if *compilers == loaded {
+        return false;
+    }
+    *compilers = loaded;
+    return true;
+}
+
+async fn refresh_projects_of_dproj(dproj: &Path) -> Result<bool> {
+    let mut projects_data = PROJECTS_DATA.write().await;
+    let lock = projects_data.lock_latest().await?;
+    let dproj = dproj.to_string_lossy().to_string();
+    let project_id = match projects_data.find_project_by_dproj(&dproj) {
+        Some(project) => project.id,
+        None => return Ok(false),
+    };
+    projects_data.relocate_project(project_id, &dproj)?;
+    projects_data.refresh_project_paths(project_id)?;
+    projects_data.commit(&lock)?;
+    return Ok(true);
+}
+
+async fn reload_group_project(groupproj: &Path) -> Result<bool> {
+    let mut projects_data = PROJECTS_DATA.write().await;
+    let lock = projects_data.lock_latest().await?;
+    let groupproj = groupproj.to_string_lossy().to_string();
+    match &projects_data.group_project {
+        Some(group_project) if group_project.path == groupproj => {}
+        _ => return Ok(false),
+    }
+    projects_data.reload_group_project()?;
+    projects_data.commit(&lock)?;
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::{connected_client, test_session};
+    use crate::utils::serialize_data;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    const PROJECTS_UPDATE: &str = "notifications/projects/update";
+
+    /// Number of projects updates sent until the log message `marker`.
+    async fn updates_until(marker: &str, client: &Client, received: &mut UnboundedReceiver<serde_json::Value>) -> usize {
+        client.log_message(MessageType::LOG, marker).await;
+        let mut updates = 0;
+        while let Some(message) = received.recv().await {
+            if message["params"]["message"] == marker {
+                break;
+            }
+            if message["method"] == PROJECTS_UPDATE {
+                updates += 1;
+            }
+        }
+        return updates;
+    }
+
+    #[tokio::test]
+    async fn reads_come_from_memory_until_the_file_is_reloaded() {
+        let _session = test_session().await;
+        let path = ProjectsData::get_file_path();
+        let mut on_disk = PROJECTS_DATA.read().await.clone();
+        on_disk.group_project_compiler_id = "11.0".to_string();
+        std::fs::write(path, serialize_data(path, &on_disk, true).unwrap()).unwrap();
+
+        // what every `ProjectsUpdate` sends: the warm cache, not the file
+        for _ in 0..10 {
+            assert_eq!(PROJECTS_DATA.read().await.group_project_compiler_id, "12.0");
+        }
+        assert!(reload_projects_data().await);
+        assert_eq!(PROJECTS_DATA.read().await.group_project_compiler_id, "11.0");
+        assert!(!reload_projects_data().await);
+    }
+
+    #[tokio::test]
+    async fn a_bulk_operation_sends_one_update() {
+        let _session = test_session().await;
+        let (client, mut received) = connected_client().await;
+        ProjectsData::internal_change_flag().store(false, Ordering::SeqCst);
+        let saved = || Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any))
+            .add_path(ProjectsData::get_file_path().clone());
+
+        let watchers = WatcherGuard::pause(&client);
+        for _ in 0..10 {
+            handle_projects_data_change(saved(), &client).await;
+        }
+        assert_eq!(updates_until("paused", &client, &mut received).await, 0);
+        drop(watchers);
+
+        // the update is sent from a task the guard spawns
+        let update = async {
+            while let Some(message) = received.recv().await {
+                if message["method"] == PROJECTS_UPDATE {
+                    return;
+                }
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), update).await.unwrap();
+        assert_eq!(updates_until("resumed", &client, &mut received).await, 0);
+
+        // without the guard every save is reported
+        handle_projects_data_change(saved(), &client).await;
+        handle_projects_data_change(saved(), &client).await;
+        assert_eq!(updates_until("unpaused", &client, &mut received).await, 2);
+    }
+
+    #[tokio::test]
+    async fn every_save_that_replaces_the_file_is_seen() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("projects.ron");
+        std::fs::write(&path, "initial").unwrap();
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let _watcher = create_watcher(&path, move |event| {
+            let _ = sender.send(event);
+        }).unwrap();
+
+        for content in ["first", "second"] {
+            let temporary = directory.path().join("projects.ron.tmp");
+            std::fs::write(&temporary, content).unwrap();
+            std::fs::rename(&temporary, &path).unwrap();
+
+            let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await
+                .unwrap_or_else(|_| panic!("the save of {content:?} was not seen"))
+                .unwrap();
+            assert!(is_content_change(&event), "{event:?}");
+            // a rename can arrive as several events; they all belong to this save
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            while events.try_recv().is_ok() {}
+        }
+    }
+
+    #[tokio::test]
+    async fn editing_the_formatter_config_notifies_the_client() {
+        let (client, mut received) = connected_client().await;
+        let directory = tempfile::tempdir().unwrap();
+        let config = directory.path().join("ddk_formatter.config");
+        std::fs::write(&config, "<Configuration/>").unwrap();
+        let watcher_client = client.clone();
+        let _watcher = create_watcher(&config, move |event| {
+            let client = watcher_client.clone();
+            tokio::spawn(async move {
+                handle_formatter_config_change(event, &client).await;
+            });
+        }).unwrap();
+
+        std::fs::write(&config, "<Configuration><Indentation/></Configuration>").unwrap();
+
+        let updated = async {
+            while let Some(message) = received.recv().await {
+                if message["method"] == "$/notifications/formatter/updated" {
+                    return message["params"]["path"].as_str().unwrap().to_string();
+                }
+            }
+            return String::new();
+        };
+        let path = tokio::time::timeout(std::time::Duration::from_secs(5), updated).await.unwrap();
+        assert_eq!(path, config.to_string_lossy());
+    }
+
+    #[tokio::test]
+    async fn a_file_that_does_not_parse_keeps_the_data() {
+        let _session = test_session().await;
+        let before = PROJECTS_DATA.read().await.clone();
+        std::fs::write(ProjectsData::get_file_path(), "ProjectsData(id_counter: ").unwrap();
+
+        assert!(!reload_projects_data().await);
+        assert_eq!(*PROJECTS_DATA.read().await, before);
+    }
+}
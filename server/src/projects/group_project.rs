@@ -13,31 +13,46 @@ pub struct GroupProject {
 
 impl GroupProject {
     pub fn fill(&mut self, projects_data: &mut ProjectsData) -> Result<()> {
-        let project_paths = parse_groupproj(PathBuf::from(&self.path))?;
+        for project_id in Self::listed_projects(&self.path, projects_data)? {
+            self.new_project_link(projects_data.next_id(), project_id);
+        }
+        return Ok(());
+    }
+
+    /// The ids of the projects listed in the `.groupproj` file at `path`, in file order;
+    /// projects not known yet are added to `projects_data`.
+    pub(super) fn listed_projects(path: &str, projects_data: &mut ProjectsData) -> Result<Vec<usize>> {
+        let project_paths = parse_groupproj(PathBuf::from(path))?;
+        let mut project_ids = Vec::new();
         for project_path in project_paths {
             let dproj = project_path.to_string_lossy().to_string();
             let existing_project_id = projects_data.find_project_by_dproj(&dproj).map(|p| p.id);
             if let Some(existing_id) = existing_project_id {
-                self.new_project_link(projects_data.next_id(), existing_id);
-                continue;
+                projects_data.relocate_project(existing_id, &dproj)?;
+                project_ids.push(existing_id);
             } else {
                 let project_id = projects_data.next_id();
                 let mut project = Project {
                     id: project_id,
                     name: project_path.file_stem().and_then(|s| s.to_str()).unwrap_or("<name error>").to_string(),
+                    name_customized: false,
                     directory: project_path.parent().and_then(|p| p.to_str()).unwrap_or("<directory error>").to_string(),
                     dproj: Some(dproj.clone()),
                     dpr: None,
                     dpk: None,
                     exe: None,
                     ini: None,
+                    output_dir_override: None,
+                    test_project_id: None,
+                    project_guid: None,
+                    clean: None,
                 };
                 project.discover_paths()?;
                 projects_data.projects.push(project);
-                self.new_project_link(projects_data.next_id(), project_id);
+                project_ids.push(project_id);
             }
         }
-        return Ok(());
+        return Ok(project_ids);
     }
 }
 
@@ -5,12 +5,25 @@ mod workspace;
 mod project;
 mod group_project;
 mod file_watch;
+mod source_watch;
 mod diag;
+mod diagnostic_cache;
 mod compiler;
+mod build_history;
+mod build_cache;
+mod test_runner;
+mod active_project;
+mod path_audit;
+mod build_invocation;
+mod build_log;
+mod discovery;
+mod sarif;
+mod symbols;
+mod clean;
 
 use anyhow::Result;
 use serde_json::Value;
-use crate::{EventDone, lexorank::{HasLexoRank, LexoRank}, state::{COMPILER_CONFIGURATIONS, PROJECTS_DATA}};
+use crate::{EventDone, lexorank::{HasLexoRank, LexoRank}, settings::ServerSettings, state::{COMPILER_CONFIGURATIONS, PROJECTS_DATA, SERVER_SETTINGS}};
 use crate::state::*;
 
 pub use compiler_config::*;
@@ -20,8 +33,21 @@ pub use workspace::*;
 pub use project::*;
 pub use group_project::*;
 pub use file_watch::*;
+pub use source_watch::*;
 pub use diag::*;
+pub use diagnostic_cache::*;
 pub use compiler::*;
+pub use build_history::*;
+pub use build_cache::*;
+pub use test_runner::*;
+pub use active_project::*;
+pub use path_audit::*;
+pub use build_invocation::*;
+pub use build_log::*;
+pub use discovery::*;
+pub use sarif::*;
+pub use symbols::*;
+pub use clean::*;
 
 pub trait Named {
     fn get_name(&self) -> &String;
@@ -98,17 +124,20 @@ pub trait ProjectLinkContainer: Named {
 pub async fn update(json: Value, client: tower_lsp::Client) -> Result<()> {
     if let Some(inner) = json.get("projectsData") {
         let mut projects_data = PROJECTS_DATA.write().await;
-        *projects_data = serde_json::from_value(inner.clone())?;
-        projects_data.validate().await?;
-        projects_data.save().await?;
+        if inner.get("version").is_none() {
+            anyhow::bail!("Projects data without a version can't be checked against the current data. Reload and retry.");
+        }
+        let snapshot: ProjectsData = serde_json::from_value(inner.clone())?;
+        projects_data.replace_with(snapshot).await?;
         EventDone::notify_json(&client, &json).await;
         return Ok(());
     }
     if let Some(inner) = json.get("changeSet") {
         let change_set: ChangeSet = serde_json::from_value(inner.clone())?;
+        let _watchers = WatcherGuard::pause(&client);
         match change_set.execute().await {
             Ok(_) => EventDone::notify_json(&client, &json).await,
-            Err(e) => anyhow::bail!("Failed to execute ChangeSet: {}", e)
+            Err(e) => anyhow::bail!("Failed to execute ChangeSet: {:#}", e)
         }
         return Ok(());
     }
@@ -116,9 +145,16 @@ pub async fn update(json: Value, client: tower_lsp::Client) -> Result<()> {
         let mut compilers = COMPILER_CONFIGURATIONS.write().await;
         let compiler_configurations: CompilerConfigurations =
             serde_json::from_value(inner.clone())?;
+        let lock = compilers.lock_latest().await?;
         compilers.overwrite(compiler_configurations);
         compilers.validate()?;
-        compilers.save().await?;
+        compilers.save_locked(&lock)?;
+        EventDone::notify_json(&client, &json).await;
+        return Ok(());
+    }
+    if let Some(inner) = json.get("settings") {
+        let settings: ServerSettings = serde_json::from_value(inner.clone())?;
+        *SERVER_SETTINGS.write().await = settings;
         EventDone::notify_json(&client, &json).await;
         return Ok(());
     }
@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathCheck {
+    /// Name of the checked field, e.g. `dproj` or `installation_path`.
+    pub field: String,
+    pub path: String,
+    pub exists: bool,
+}
+
+impl PathCheck {
+    fn new(field: &str, path: &Path) -> Self {
+        PathCheck {
+            field: field.to_string(),
+            path: path.to_string_lossy().to_string(),
+            exists: path.exists(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectPathAudit {
+    pub project_id: usize,
+    pub name: String,
+    pub checks: Vec<PathCheck>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompilerPathAudit {
+    pub key: String,
+    pub product_name: String,
+    pub checks: Vec<PathCheck>,
+}
+
+/// Which configured paths exist, for every project and compiler.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathAudit {
+    pub projects: Vec<ProjectPathAudit>,
+    pub compilers: Vec<CompilerPathAudit>,
+    /// Number of checks whose path is missing.
+    pub missing: usize,
+}
+
+/// Checks every path the projects and compilers refer to without changing anything.
+pub fn audit_paths(projects_data: &ProjectsData, compilers: &CompilerConfigurations) -> PathAudit {
+    let projects: Vec<ProjectPathAudit> = projects_data
+        .projects
+        .iter()
+        .map(|project| {
+            let optional_paths = [
+                ("dproj", &project.dproj),
+                ("dpr", &project.dpr),
+                ("dpk", &project.dpk),
+                ("exe", &project.exe),
+                ("ini", &project.ini),
+            ];
+            let mut checks = vec![PathCheck::new("directory", Path::new(&project.directory))];
+            checks.extend(optional_paths.iter().filter_map(|(field, path)| {
+                path.as_ref().map(|path| PathCheck::new(field, Path::new(path)))
+            }));
+            ProjectPathAudit {
+                project_id: project.id,
+                name: project.name.clone(),
+                checks,
+            }
+        })
+        .collect();
+    let mut compilers: Vec<CompilerPathAudit> = compilers
+        .iter()
+        .map(|(key, compiler)| {
+            let installation_path = PathBuf::from(&compiler.installation_path);
+            CompilerPathAudit {
+                key: key.clone(),
+                product_name: compiler.product_name.clone(),
+                checks: std::iter::once(PathCheck::new("installation_path", &installation_path))
+                    .chain(compiler.env_script_path().map(|script| PathCheck::new("env_script", &script)))
+                    .collect(),
+            }
+        })
+        .collect();
+    compilers.sort_by(|a, b| a.key.cmp(&b.key));
+    let missing = projects
+        .iter()
+        .flat_map(|project| &project.checks)
+        .chain(compilers.iter().flat_map(|compiler| &compiler.checks))
+        .filter(|check| !check.exists)
+        .count();
+    return PathAudit {
+        projects,
+        compilers,
+        missing,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn existing(checks: &[PathCheck]) -> Vec<(&str, bool)> {
+        return checks.iter().map(|check| (check.field.as_str(), check.exists)).collect();
+    }
+
+    #[test]
+    fn existing_and_missing_paths_are_told_apart() {
+        let directory = tempfile::tempdir().unwrap();
+        let dpr = directory.path().join("App.dpr");
+        std::fs::write(&dpr, "program App;").unwrap();
+        let mut projects_data = ProjectsData::default();
+        projects_data.projects.push(Project {
+            id: 1,
+            name: "App".to_string(),
+            name_customized: false,
+            directory: directory.path().to_string_lossy().to_string(),
+            dproj: Some(directory.path().join("App.dproj").to_string_lossy().to_string()),
+            dpr: Some(dpr.to_string_lossy().to_string()),
+            dpk: None,
+            exe: Some(directory.path().join("Win32").join("App.exe").to_string_lossy().to_string()),
+            ini: None,
+            output_dir_override: None,
+            test_project_id: None,
+            project_guid: None,
+            clean: None,
+        });
+        let installation = tempfile::tempdir().unwrap();
+        std::fs::create_dir(installation.path().join("bin")).unwrap();
+        std::fs::write(installation.path().join("bin").join("rsvars.bat"), "").unwrap();
+        let mut compilers = CompilerConfigurations::default();
+        let mut installed = compilers.get("12.0").unwrap().clone();
+        installed.installation_path = installation.path().to_string_lossy().to_string();
+        installed.env_script = None;
+        let mut moved = installed.clone();
+        moved.installation_path = directory.path().join("moved").to_string_lossy().to_string();
+        let keys: Vec<String> = compilers.iter().map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            compilers.remove(&key);
+        }
+        compilers.insert("installed".to_string(), installed);
+        compilers.insert("moved".to_string(), moved);
+
+        let audit = audit_paths(&projects_data, &compilers);
+
+        assert_eq!(audit.projects.len(), 1);
+        assert_eq!(
+            existing(&audit.projects[0].checks),
+            vec![("directory", true), ("dproj", false), ("dpr", true), ("exe", false)]
+        );
+        let compiler_checks: Vec<(&str, Vec<(&str, bool)>)> = audit.compilers
+            .iter()
+            .map(|compiler| (compiler.key.as_str(), existing(&compiler.checks)))
+            .collect();
+        assert_eq!(compiler_checks, vec![
+            ("installed", vec![("installation_path", true), ("env_script", true)]),
+            ("moved", vec![("installation_path", false), ("env_script", false)]),
+        ]);
+        assert_eq!(audit.missing, 4);
+    }
+}
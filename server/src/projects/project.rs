@@ -1,9 +1,11 @@
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use crate::lexorank::{LexoRank, HasLexoRank};
 use crate::projects::*;
-use crate::files::dproj::{find_dproj_file, get_main_source, get_exe_path};
+use crate::files::dproj::{find_dproj_file, get_build_matrix, get_main_source, get_exe_path, get_output_directories, get_project_guid, get_unit_search_paths};
+use crate::utils::expand_macros;
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ProjectLink {
@@ -50,15 +52,43 @@ impl HasLexoRank for ProjectLink {
 pub struct Project {
     pub id: usize,
     pub name: String,
+    /// Set once the user renamed the project; otherwise `name` follows the `.dproj` file stem.
+    #[serde(default)]
+    pub name_customized: bool,
     pub directory: String,
     pub dproj: Option<String>,
     pub dpr: Option<String>,
     pub dpk: Option<String>,
     pub exe: Option<String>,
     pub ini: Option<String>,
+    /// Output directory passed to msbuild outside the `.dproj` (e.g. `/p:DCC_ExeOutput=`);
+    /// takes precedence over the `.dproj` output settings. May contain `$(Name)` macros.
+    #[serde(default)]
+    pub output_dir_override: Option<String>,
+    /// Project whose executable runs this project's unit tests (e.g. a DUnitX runner).
+    #[serde(default)]
+    pub test_project_id: Option<usize>,
+    /// `<ProjectGuid>` of the `.dproj`; recognizes the project after its files were moved.
+    #[serde(default)]
+    pub project_guid: Option<String>,
+    /// What the clean command deletes; the usual build leftovers when unset.
+    #[serde(default)]
+    pub clean: Option<CleanSettings>,
 }
 
 impl Project {
+    /// Data saved before `name_customized` existed loads with it unset; a name that differs
+    /// from the `.dproj` stem can only have been set by the user.
+    pub fn migrate_name_customized(&mut self) {
+        if self.name_customized {
+            return;
+        }
+        let stem = self.dproj.as_ref().and_then(|dproj| Path::new(dproj).file_stem().map(|stem| stem.to_string_lossy().to_string()));
+        if stem.is_some_and(|stem| stem != self.name) {
+            self.name_customized = true;
+        }
+    }
+
     pub fn discover_paths(&mut self) -> Result<()> {
         if self.dproj.is_none() {
             if let Some(dpr_path) = &self.dpr {
@@ -73,14 +103,26 @@ impl Project {
             anyhow::bail!("Cannot discover paths - no dproj, dpr or dpk available for project id: {}", self.id);
         }
         let dproj_path = PathBuf::from(self.dproj.as_ref().unwrap());
+        if !self.name_customized && let Some(stem) = dproj_path.file_stem() {
+            self.name = stem.to_string_lossy().to_string();
+        }
+        if let Ok(Some(guid)) = get_project_guid(&dproj_path) {
+            self.project_guid = Some(guid);
+        }
 
+        // the compiler names the executable after the project file, whatever the display name
+        let exe_name = dproj_path.file_stem().unwrap_or_default().to_os_string();
         let main_source = get_main_source(&dproj_path)?;
         match main_source.extension().and_then(|ext| ext.to_str()).map(|s| s.to_lowercase()) {
             Some(ext) if ext == "dpr" => {
                 self.dpr = Some(main_source.to_string_lossy().to_string());
                 self.dpk = None;
-                if let Ok(exe_path) = get_exe_path(&dproj_path) {
-                    let exe_file_name = exe_path.join(self.name.clone()).with_extension("exe");
+                if let Some(output_dir) = self.resolved_output_dir_override(&dproj_path) {
+                    let exe_file_name = output_dir.join(&exe_name).with_extension("exe");
+                    self.exe = Some(exe_file_name.to_string_lossy().to_string());
+                    self.ini = Some(exe_file_name.with_extension("ini").to_string_lossy().to_string());
+                } else if let Ok(exe_path) = get_exe_path(&dproj_path) {
+                    let exe_file_name = exe_path.join(&exe_name).with_extension("exe");
                     self.exe = Some(exe_file_name.to_string_lossy().to_string());
                     self.ini = Some(exe_file_name.with_extension("ini").to_string_lossy().to_string());
                 } else {
@@ -118,6 +160,51 @@ impl Project {
         return Ok(());
     }
 
+    /// The output directory override with macros expanded, relative paths resolved against the `.dproj`.
+    fn resolved_output_dir_override(&self, dproj_path: &Path) -> Option<PathBuf> {
+        let output_dir = self.output_dir_override.as_ref().filter(|dir| !dir.trim().is_empty())?;
+        let variables = HashMap::from([
+            ("ProjectName", self.name.clone()),
+            ("ProjectDir", self.directory.clone()),
+        ]);
+        let output_dir = PathBuf::from(expand_macros(output_dir, &variables));
+        if output_dir.is_absolute() {
+            return Some(output_dir);
+        }
+        return Some(dproj_path.parent().unwrap_or(Path::new(&self.directory)).join(output_dir));
+    }
+
+    /// The directory the project's executable is written to, if it is known.
+    pub fn output_directory(&self) -> Option<PathBuf> {
+        if let Some(exe) = &self.exe {
+            return PathBuf::from(exe).parent().map(Path::to_path_buf);
+        }
+        let dproj = self.dproj.as_ref()?;
+        return self.resolved_output_dir_override(&PathBuf::from(dproj));
+    }
+
+    /// Every directory a build of the project writes to, over all of its platforms and configurations.
+    pub fn output_directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = self.output_directory().into_iter().collect();
+        let Some(dproj) = &self.dproj else {
+            return directories;
+        };
+        let dproj = PathBuf::from(dproj);
+        for (platform, config) in get_build_matrix(&dproj).unwrap_or_default() {
+            let variables = HashMap::from([
+                ("Platform", platform),
+                ("Config", config),
+                ("ProjectName", self.name.clone()),
+            ]);
+            for directory in get_output_directories(&dproj, &variables).unwrap_or_default() {
+                if !directories.contains(&directory) {
+                    directories.push(directory);
+                }
+            }
+        }
+        return directories;
+    }
+
     pub fn get_project_file(&self) -> Result<PathBuf> {
         if let Some(dproj_path) = &self.dproj {
             let path = PathBuf::from(dproj_path);
@@ -139,4 +226,142 @@ impl Project {
         }
         anyhow::bail!("Cannot get project file - no dproj, dpr or dpk available for project id: {}", self.id);
     }
-}
\ No newline at end of file
+    /// The (platform, config) combinations the project's `.dproj` can be built with.
+    pub fn build_matrix(&self) -> Result<Vec<(String, String)>> {
+        let Some(dproj) = &self.dproj else {
+            anyhow::bail!("Cannot get build matrix - no dproj available for project id: {}", self.id);
+        };
+        return get_build_matrix(&PathBuf::from(dproj));
+    }
+}
+impl Project {
+    /// Resolves a unit name (e.g. `System.SysUtils`) to its source file, searching the
+    /// project directory, the `.dproj` unit search paths and finally the compiler's library paths.
+    pub fn resolve_unit(&self, unit_name: &str, compiler: &CompilerConfiguration) -> Option<PathBuf> {
+        let mut search_paths = vec![PathBuf::from(&self.directory)];
+        if let Some(dproj) = &self.dproj {
+            let variables = compiler.macro_variables(None);
+            search_paths.extend(get_unit_search_paths(&PathBuf::from(dproj), &variables).unwrap_or_default());
+        }
+        search_paths.extend(compiler.resolved_library_paths(None));
+        let file_name = format!("{}.pas", unit_name);
+        for directory in search_paths {
+            let candidate = directory.join(&file_name);
+            if candidate.exists() {
+                return Some(candidate);
+            }
+            let Ok(entries) = std::fs::read_dir(&directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().eq_ignore_ascii_case(&file_name) {
+                    return Some(entry.path());
+                }
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A project named `name` for `<directory>/A.dproj`, whose main source is `A.dpr`.
+    fn project_in(directory: &Path, name: &str, name_customized: bool) -> Project {
+        let dproj = directory.join("A.dproj");
+        std::fs::write(&dproj, "<Project><PropertyGroup><MainSource>A.dpr</MainSource></PropertyGroup></Project>").unwrap();
+        std::fs::write(directory.join("A.dpr"), "program A;").unwrap();
+        return Project {
+            id: 1,
+            name: name.to_string(),
+            name_customized,
+            directory: directory.to_string_lossy().to_string(),
+            dproj: Some(dproj.to_string_lossy().to_string()),
+            dpr: None,
+            dpk: None,
+            exe: None,
+            ini: None,
+            output_dir_override: None,
+            test_project_id: None,
+            project_guid: None,
+            clean: None,
+        };
+    }
+
+    #[test]
+    fn refreshing_keeps_customized_names_only() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut customized = project_in(directory.path(), "Custom", true);
+        customized.discover_paths().unwrap();
+        assert_eq!(customized.name, "Custom");
+
+        let mut derived = project_in(directory.path(), "Old", false);
+        derived.discover_paths().unwrap();
+        assert_eq!(derived.name, "A");
+    }
+
+    #[test]
+    fn legacy_names_that_differ_from_the_file_count_as_customized() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut renamed = project_in(directory.path(), "Custom", false);
+        renamed.migrate_name_customized();
+        assert!(renamed.name_customized);
+
+        let mut derived = project_in(directory.path(), "A", false);
+        derived.migrate_name_customized();
+        assert!(!derived.name_customized);
+    }
+
+    #[test]
+    fn the_executable_is_named_after_the_project_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut project = project_in(directory.path(), "Custom", true);
+        project.output_dir_override = Some("out".to_string());
+        project.discover_paths().unwrap();
+        assert_eq!(project.exe, Some(directory.path().join("out").join("A.exe").to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn the_output_directory_override_moves_the_executable() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut project = project_in(directory.path(), "A", false);
+        project.discover_paths().unwrap();
+        assert_eq!(project.exe, None);
+
+        project.output_dir_override = Some("$(ProjectName)_out".to_string());
+        project.discover_paths().unwrap();
+        let exe = directory.path().join("A_out").join("A.exe");
+        assert_eq!(project.exe, Some(exe.to_string_lossy().to_string()));
+        assert_eq!(project.ini, Some(exe.with_extension("ini").to_string_lossy().to_string()));
+        assert_eq!(project.output_directory(), Some(directory.path().join("A_out")));
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        project.output_dir_override = Some(elsewhere.path().to_string_lossy().to_string());
+        project.discover_paths().unwrap();
+        assert_eq!(project.exe, Some(elsewhere.path().join("A.exe").to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn rtl_units_resolve_through_the_library_paths_after_the_search_paths() {
+        let directory = tempfile::tempdir().unwrap();
+        let project = project_in(directory.path(), "A", false);
+        std::fs::write(project.dproj.as_ref().unwrap(), "<Project><PropertyGroup><DCC_UnitSearchPath>shared;$(DCC_UnitSearchPath)</DCC_UnitSearchPath></PropertyGroup></Project>").unwrap();
+        let shared = directory.path().join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::write(shared.join("System.Classes.pas"), "unit System.Classes;").unwrap();
+        let installation = tempfile::tempdir().unwrap();
+        let rtl = installation.path().join("source").join("rtl").join("sys");
+        std::fs::create_dir_all(&rtl).unwrap();
+        std::fs::write(rtl.join("System.SysUtils.pas"), "unit System.SysUtils;").unwrap();
+        std::fs::write(rtl.join("System.Classes.pas"), "unit System.Classes;").unwrap();
+
+        let mut compiler = CompilerConfigurations::default().get("12.0").unwrap().clone();
+        compiler.installation_path = installation.path().to_string_lossy().to_string();
+        compiler.library_paths = vec!["$(BDS)/source/rtl/sys".to_string()];
+
+        assert_eq!(project.resolve_unit("System.SysUtils", &compiler), Some(rtl.join("System.SysUtils.pas")));
+        assert_eq!(project.resolve_unit("System.Classes", &compiler), Some(shared.join("System.Classes.pas")));
+        assert_eq!(project.resolve_unit("System.Missing", &compiler), None);
+    }
+}
@@ -1,11 +1,13 @@
-use crate::state::{PROJECTS_DATA, PROJECTS_DATA_CHANGED, Stateful};
-use crate::utils::{FilePath, Load};
-use std::sync::Arc;
+use crate::state::{COMPILER_CONFIGURATIONS, FileLock, PROJECTS_DATA, PendingSave, PROJECTS_DATA_CHANGED, Stateful};
+use crate::utils::{FilePath, Load, comparable_path, config_directory, data_file_extension};
+use crate::lexorank;
+use crate::files::dproj::{find_dproj_file, get_build_configurations, get_project_guid};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::RwLock;
 use super::*;
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::{HashSet, HashMap};
 use std::sync::atomic::AtomicBool;
 
@@ -15,6 +17,26 @@ enum IdObject {
     ProjectLink,
 }
 
+/// A validated `move_project_link`: the link moves within its container or between two
+/// workspaces. The group project's links mirror its `.groupproj` file, so none move in or out.
+struct PlannedLinkMove {
+    source: LinkContainer,
+    target: LinkContainer,
+    /// The link to insert before; `None` appends.
+    target_link_id: Option<usize>,
+}
+
+/// The drop target id standing for the group project itself, which has no id of its own;
+/// dropping a link there moves it to the end. `next_id` never hands out zero.
+pub const GROUP_PROJECT_DROP_TARGET: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum LinkContainer {
+    Workspace { workspace_id: usize },
+    GroupProject,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ProjectsData {
     pub(super) id_counter: usize,
@@ -23,6 +45,9 @@ pub struct ProjectsData {
     pub projects: Vec<Project>,
     pub group_project: Option<GroupProject>,
     pub group_project_compiler_id: String,
+    /// Bumped on every committed change; writers of the whole data must be based on the current version.
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Stateful for ProjectsData {
@@ -32,6 +57,15 @@ impl Stateful for ProjectsData {
     fn get_state() -> &'static Arc<RwLock<Self>> {
         &PROJECTS_DATA
     }
+
+    fn normalize(mut projects_data: Self) -> Self {
+        projects_data.sort();
+        projects_data.repair_ranks();
+        for project in &mut projects_data.projects {
+            project.migrate_name_customized();
+        }
+        return projects_data;
+    }
 }
 
 impl Default for ProjectsData {
@@ -43,13 +77,19 @@ impl Default for ProjectsData {
             projects: Vec::new(),
             group_project: None,
             group_project_compiler_id: String::from("12.0"),
+            version: 0,
         }
     }
 }
 
 impl ProjectsData {
     pub fn new() -> Self {
-        return Self::load_from_file(&Self::get_file_path());
+        return Self::normalize(Self::read_only_copy_blocking());
+    }
+
+    /// Like `new`, without blocking the async worker; fails if the file can't be parsed.
+    pub async fn load_current() -> Result<Self> {
+        return Ok(Self::normalize(Self::read_only_copy().await?));
     }
 
     pub async fn group_projects_compiler(&self) -> CompilerConfiguration {
@@ -65,13 +105,24 @@ impl ProjectsData {
             .clone();
     }
 
-    async fn validate_compilers(&self) -> Result<()> {
+    /// The compiler of the first workspace linking the project, falling back to the group project's compiler.
+    pub async fn compiler_for_project(&self, project_id: usize) -> CompilerConfiguration {
+        let workspace = self.workspaces
+            .iter()
+            .find(|ws| ws.project_links.iter().any(|link| link.project_id == project_id));
+        if let Some(workspace) = workspace {
+            return workspace.compiler().await;
+        }
+        return self.group_projects_compiler().await;
+    }
+
+    fn validate_compilers(&self, compilers: &CompilerConfigurations) -> Result<()> {
         for workspace in &self.workspaces {
-            if !compiler_exists(&workspace.compiler_id).await {
+            if !compilers.contains_key(&workspace.compiler_id) {
                 anyhow::bail!("Workspace '{}' has invalid compiler id: {}", workspace.name, workspace.compiler_id);
             }
         }
-        if !compiler_exists(&self.group_project_compiler_id).await {
+        if !compilers.contains_key(&self.group_project_compiler_id) {
             anyhow::bail!("Group project compiler has invalid id: {}", self.group_project_compiler_id);
         }
         Ok(())
@@ -134,10 +185,25 @@ impl ProjectsData {
         Ok(())
     }
 
-    pub async fn validate(&self) -> Result<()> {
-        self.validate_compilers().await?;
+    /// Ids are unique and every reference points at an existing project.
+    pub fn validate_references(&self) -> Result<()> {
         let id_map = self.get_id_map()?;
-        self.validate_project_references(&id_map)?;
+        return self.validate_project_references(&id_map);
+    }
+
+    pub async fn validate(&self) -> Result<()> {
+        return self.validate_with(&*COMPILER_CONFIGURATIONS.read().await);
+    }
+
+    /// `validate` against the given compilers rather than the current ones, e.g. those a
+    /// change set is about to save along with this data.
+    pub fn validate_with(&self, compilers: &CompilerConfigurations) -> Result<()> {
+        self.validate_compilers(compilers)?;
+        self.validate_references()?;
+        return self.validate_workspace_names();
+    }
+
+    fn validate_workspace_names(&self) -> Result<()> {
         let mut workspace_names: HashSet<&String> = HashSet::new();
         for workspace in &self.workspaces {
             if workspace_names.contains(&workspace.name) {
@@ -151,9 +217,47 @@ impl ProjectsData {
         Ok(())
     }
 
+    /// A fresh, non-zero id. Ids still in use are skipped, so a counter that fell behind
+    /// (e.g. hand-edited or restored data) can't hand out an existing id again.
     pub fn next_id(&mut self) -> usize {
-        self.id_counter += 1;
-        return self.id_counter;
+        let used = self.used_ids();
+        loop {
+            self.id_counter = self.id_counter.checked_add(1).unwrap_or(1);
+            if !used.contains(&self.id_counter) {
+                return self.id_counter;
+            }
+        }
+    }
+
+    fn used_ids(&self) -> HashSet<usize> {
+        let workspace_ids = self.workspaces.iter().map(|workspace| workspace.id);
+        let link_ids = self.workspaces
+            .iter()
+            .flat_map(|workspace| workspace.project_links.iter())
+            .chain(self.group_project.iter().flat_map(|group_project| group_project.project_links.iter()))
+            .map(|link| link.id);
+        let project_ids = self.projects.iter().map(|project| project.id);
+        return workspace_ids.chain(link_ids).chain(project_ids).collect();
+    }
+
+    /// Ids of links whose project no longer exists, e.g. after the data file was edited by hand.
+    pub fn orphaned_links(&self) -> Vec<usize> {
+        return self.workspaces
+            .iter()
+            .flat_map(|workspace| workspace.project_links.iter())
+            .chain(self.group_project.iter().flat_map(|group_project| group_project.project_links.iter()))
+            .filter(|link| self.get_project(link.project_id).is_none())
+            .map(|link| link.id)
+            .collect();
+    }
+
+    pub fn remove_orphaned_links(&mut self) -> Result<()> {
+        for project_link_id in self.orphaned_links() {
+            if self.take_project_link(project_link_id).is_none() {
+                anyhow::bail!("Orphaned project link with id {} not found", project_link_id);
+            }
+        }
+        return Ok(());
     }
 
     pub fn can_find_any_links(&self, project_id: usize) -> bool {
@@ -175,61 +279,84 @@ impl ProjectsData {
     }
 
     pub fn new_project(&mut self, file_path: &String, workspace_id: usize) -> Result<()> {
-        let (project_id, link_id) = (self.id_counter + 1, self.id_counter + 2);
-        let workspace = match self.workspaces.iter_mut().find(|ws| ws.id == workspace_id) {
-            Some(ws) => ws,
-            _ => anyhow::bail!("Workspace with id {} not found", workspace_id),
-        };
+        if let Some(project_id) = self.find_moved_project(file_path)? {
+            let already_linked = self
+                .get_workspace(workspace_id)
+                .is_some_and(|ws| ws.project_links.iter().any(|link| link.project_id == project_id));
+            if !already_linked {
+                self.add_project_link(project_id, workspace_id)?;
+            }
+            return Ok(());
+        }
+        if self.get_workspace(workspace_id).is_none() {
+            anyhow::bail!("Workspace with id {} not found", workspace_id);
+        }
+        let (project_id, link_id) = (self.next_id(), self.next_id());
         let file = PathBuf::from(file_path);
         let project = match file.extension().and_then(|ext| ext.to_str()).map(|s| s.to_lowercase()) {
             Some(ext) if ext == "dproj" => {
                 Project {
                     id: project_id,
                     name: file.file_stem().and_then(|s| s.to_str()).unwrap_or("<name error>").to_string(),
+                    name_customized: false,
                     directory: file.parent().and_then(|p| p.to_str()).unwrap_or("<directory error>").to_string(),
                     dproj: Some(file_path.clone()),
                     dpr: None,
                     dpk: None,
                     exe: None,
                     ini: None,
+                    output_dir_override: None,
+                    test_project_id: None,
+                    project_guid: get_project_guid(&file).ok().flatten(),
+                    clean: None,
                 }
             },
             Some(ext) if ext == "dpr" => {
                 Project {
                     id: project_id,
                     name: file.file_stem().and_then(|s| s.to_str()).unwrap_or("<name error>").to_string(),
+                    name_customized: false,
                     directory: file.parent().and_then(|p| p.to_str()).unwrap_or("<directory error>").to_string(),
                     dproj: None,
                     dpr: Some(file_path.clone()),
                     dpk: None,
                     exe: None,
                     ini: None,
+                    output_dir_override: None,
+                    test_project_id: None,
+                    project_guid: None,
+                    clean: None,
                 }
             },
             Some(ext) if ext == "dpk" => {
                 Project {
                     id: project_id,
                     name: file.file_stem().and_then(|s| s.to_str()).unwrap_or("<name error>").to_string(),
+                    name_customized: false,
                     directory: file.parent().and_then(|p| p.to_str()).unwrap_or("<directory error>").to_string(),
                     dproj: None,
                     dpr: None,
                     dpk: Some(file_path.clone()),
                     exe: None,
                     ini: None,
+                    output_dir_override: None,
+                    test_project_id: None,
+                    project_guid: None,
+                    clean: None,
                 }
             },
             _ => {
                 anyhow::bail!("Unsupported project file type: {}", file_path);
             }
         };
+        let workspace = self.get_workspace_mut(workspace_id)
+            .ok_or_else(|| anyhow::anyhow!("Workspace with id {} not found", workspace_id))?;
         workspace.project_links.push(ProjectLink {
             id: link_id,
             project_id: project.id,
             sort_rank: LexoRank::default(),
         });
         self.projects.push(project);
-        self.next_id(); // for project_id
-        self.next_id(); // for link_id
 
         return Ok(());
     }
@@ -239,22 +366,23 @@ impl ProjectsData {
         if self.get_project(project_id).is_none() {
             anyhow::bail!("Project with id {} not found", project_id);
         }
-        let id = self.id_counter + 1;
-        let workspace = match self.get_workspace_mut(workspace_id) {
-            Some(ws) => ws,
-            _ => anyhow::bail!("Workspace with id {} not found", workspace_id),
-        };
+        if self.get_workspace(workspace_id).is_none() {
+            anyhow::bail!("Workspace with id {} not found", workspace_id);
+        }
+        let id = self.next_id();
+        let workspace = self.get_workspace_mut(workspace_id)
+            .ok_or_else(|| anyhow::anyhow!("Workspace with id {} not found", workspace_id))?;
         workspace.project_links.push(ProjectLink {
             id,
             project_id,
             sort_rank: LexoRank::default(),
         });
-        self.next_id();
         return Ok(());
     }
 
     pub fn remove_project(&mut self, project_id: usize, remove_links: bool) {
         self.projects.retain(|proj| proj.id != project_id);
+        self.drop_dangling_test_projects();
 
         if Some(project_id) == self.active_project_id {
             self.active_project_id = None;
@@ -270,68 +398,106 @@ impl ProjectsData {
         }
     }
 
-    pub fn remove_project_link(&mut self, project_link_id: usize) {
-        let mut project_id: Option<usize> = None;
-        for workspace in &mut self.workspaces {
-            if let Some(pos) = workspace.project_links.iter().position(|link| link.id == project_link_id) {
-                project_id = Some(workspace.project_links[pos].project_id);
-                workspace.project_links.remove(pos);
-                break;
-            }
+    pub fn remove_project_link(&mut self, project_link_id: usize) -> Result<()> {
+        // the link has to be gone from every container before deciding whether the project is orphaned
+        let Some(link) = self.take_project_link(project_link_id) else {
+            anyhow::bail!("Project link with id {} not found", project_link_id);
+        };
+        if !self.can_find_any_links(link.project_id) {
+            self.remove_project(link.project_id, false);
         }
-        if project_id.is_none() {
-            if let Some(group_project) = &mut self.group_project &&
-               let Some(pos) = group_project.project_links.iter().position(|link| link.id == project_link_id) {
-                project_id = Some(group_project.project_links[pos].project_id);
-                group_project.project_links.remove(pos);
+        return Ok(());
+    }
+
+    fn take_project_link(&mut self, project_link_id: usize) -> Option<ProjectLink> {
+        for workspace in &mut self.workspaces {
+            if let Some(pos) = workspace.index_of(project_link_id) {
+                return Some(workspace.project_links.remove(pos));
             }
         }
-        if project_id.is_some() && !self.can_find_any_links(project_id.unwrap()) {
-            self.remove_project(project_id.unwrap(), false);
+        if let Some(group_project) = &mut self.group_project &&
+           let Some(pos) = group_project.index_of(project_link_id) {
+            return Some(group_project.project_links.remove(pos));
         }
+        return None;
+    }
+
+    /// Checks that `move_project_link` would accept the move, without performing it.
+    pub fn can_move_project_link(&self, project_link_id: usize, drop_target: usize) -> Result<()> {
+        self.plan_project_link_move(project_link_id, drop_target)?;
+        return Ok(());
     }
 
     pub fn move_project_link(&mut self, project_link_id: usize, drop_target: usize) -> Result<()> {
-        let id_map = self.get_id_map()?;
-        if !id_map.contains_key(&drop_target) {
-            anyhow::bail!("Drop target id {} not found", drop_target);
+        let planned = self.plan_project_link_move(project_link_id, drop_target)?;
+        if planned.target_link_id == Some(project_link_id) {
+            // dropped onto itself
+            return Ok(());
+        }
+        if planned.source == planned.target {
+            return self.link_container_mut(planned.source)?.move_project_link(project_link_id, planned.target_link_id);
         }
+        // the link keeps its id and project, so the project stays linked
+        let link = self.link_container_mut(planned.source)?.export_project_link(project_link_id)?;
+        return self.link_container_mut(planned.target)?.import_project_link(link, planned.target_link_id);
+    }
+
+    fn link_container_mut(&mut self, container: LinkContainer) -> Result<&mut dyn ProjectLinkContainer> {
+        return match container {
+            LinkContainer::Workspace { workspace_id } => match self.get_workspace_mut(workspace_id) {
+                Some(workspace) => Ok(workspace),
+                None => anyhow::bail!("Workspace with id {} not found", workspace_id),
+            },
+            LinkContainer::GroupProject => match self.group_project.as_mut() {
+                Some(group_project) => Ok(group_project),
+                None => anyhow::bail!("No group project defined"),
+            },
+        };
+    }
+
+    fn plan_project_link_move(&self, project_link_id: usize, drop_target: usize) -> Result<PlannedLinkMove> {
+        let id_map = self.get_id_map()?;
         match id_map.get(&project_link_id) {
             Some(IdObject::ProjectLink) => {},
             _ => anyhow::bail!("Project link with id {} not found", project_link_id),
         };
+        let group_project_drop = drop_target == GROUP_PROJECT_DROP_TARGET && self.group_project.is_some();
+        if !group_project_drop && !id_map.contains_key(&drop_target) {
+            anyhow::bail!("Drop target id {} not found", drop_target);
+        }
         let target_link_id: Option<usize> = id_map.get(&drop_target).map(|obj| match obj {
             IdObject::ProjectLink => Some(drop_target),
             _ => None,
         }).flatten();
-        let source_workspace_id = self.get_workspace_id_containing_project_link(project_link_id);
-        let target_workspace_id = match id_map.get(&drop_target) {
-            Some(IdObject::Workspace) => Some(drop_target),
-            Some(IdObject::ProjectLink) => self.get_workspace_id_containing_project_link(drop_target),
+        let source = self.container_of_link(project_link_id)
+            .ok_or_else(|| anyhow::anyhow!("Project link with id {} not found", project_link_id))?;
+        let target = match id_map.get(&drop_target) {
+            _ if group_project_drop => LinkContainer::GroupProject,
+            Some(IdObject::Workspace) => LinkContainer::Workspace { workspace_id: drop_target },
+            Some(IdObject::ProjectLink) => self.container_of_link(drop_target)
+                .ok_or_else(|| anyhow::anyhow!("Invalid drop target with id {}.", drop_target))?,
             _ => anyhow::bail!("Invalid drop target with id {}.", drop_target),
         };
-        drop(id_map);
-        if let Some(source_workspace_id) = source_workspace_id {
-            if let Some(target_workspace_id) = target_workspace_id {
-                let source_workspace = self.get_workspace_mut(source_workspace_id)
-                    .ok_or_else(|| anyhow::anyhow!("Unable to find source workspace"))?;
-                if target_workspace_id == source_workspace_id {
-                    return source_workspace.move_project_link(project_link_id, target_link_id);
-                } else {
-                    let link = source_workspace.export_project_link(project_link_id)?;
-                    let target_workspace = self.get_workspace_mut(target_workspace_id)
-                        .ok_or_else(|| anyhow::anyhow!("Unable to find target workspace"))?;
-                    target_workspace.import_project_link(link, target_link_id)?;
-                    return Ok(());
-                }
-            } else {
-                anyhow::bail!("Cannot move project link from workspace to group project.");
-            }
-        } else if let Some(_) = target_workspace_id {
-            anyhow::bail!("Cannot move project link from group project to workspace.");
-        } else {
-            todo!("Move within group project on top of that element");
+        if source != target && (source == LinkContainer::GroupProject || target == LinkContainer::GroupProject) {
+            anyhow::bail!(
+                "Project links can't be moved into or out of the group project, its projects come from the .groupproj file. Add the project to the workspace instead."
+            );
+        }
+        return Ok(PlannedLinkMove {
+            source,
+            target,
+            target_link_id,
+        });
+    }
+
+    pub fn container_of_link(&self, project_link_id: usize) -> Option<LinkContainer> {
+        if let Some(workspace_id) = self.get_workspace_id_containing_project_link(project_link_id) {
+            return Some(LinkContainer::Workspace { workspace_id });
+        }
+        if self.is_project_link_in_group_project(project_link_id) {
+            return Some(LinkContainer::GroupProject);
         }
+        return None;
     }
 
     pub fn get_workspace_id_containing_project_link(&self, project_link_id: usize) -> Option<usize> {
@@ -366,7 +532,16 @@ impl ProjectsData {
             _ => anyhow::bail!("Project with id {} not found", project_id),
         };
         if let Some(name) = data.name {
-            project.name = name;
+            if name.trim().is_empty() {
+                // an empty name reverts to the one derived from the project file
+                project.name_customized = false;
+                if let Ok(file) = project.get_project_file() && let Some(stem) = file.file_stem() {
+                    project.name = stem.to_string_lossy().to_string();
+                }
+            } else {
+                project.name = name;
+                project.name_customized = true;
+            }
         }
         if let Some(directory) = data.directory {
             if !PathBuf::from(&directory).exists() {
@@ -404,9 +579,51 @@ impl ProjectsData {
             }
             project.ini = Some(ini);
         }
+        if let Some(output_dir) = data.output_dir_override {
+            project.output_dir_override = if output_dir.trim().is_empty() { None } else { Some(output_dir) };
+            if project.dproj.is_some() {
+                project.discover_paths()?;
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn set_test_project(&mut self, project_id: usize, test_project_id: Option<usize>) -> Result<()> {
+        if let Some(test_project_id) = test_project_id {
+            if test_project_id == project_id {
+                anyhow::bail!("Project with id {} cannot be its own test project", project_id);
+            }
+            if self.get_project(test_project_id).is_none() {
+                anyhow::bail!("Test project with id {} not found", test_project_id);
+            }
+        }
+        let project = match self.get_project_mut(project_id) {
+            Some(proj) => proj,
+            _ => anyhow::bail!("Project with id {} not found", project_id),
+        };
+        project.test_project_id = test_project_id;
+        return Ok(());
+    }
+
+    /// `None` goes back to deleting the usual build leftovers.
+    pub fn set_clean_settings(&mut self, project_id: usize, settings: Option<CleanSettings>) -> Result<()> {
+        let project = match self.get_project_mut(project_id) {
+            Some(proj) => proj,
+            _ => anyhow::bail!("Project with id {} not found", project_id),
+        };
+        project.clean = settings;
         return Ok(());
     }
 
+    fn drop_dangling_test_projects(&mut self) {
+        let project_ids: HashSet<usize> = self.projects.iter().map(|project| project.id).collect();
+        for project in &mut self.projects {
+            if let Some(test_project_id) = project.test_project_id && !project_ids.contains(&test_project_id) {
+                project.test_project_id = None;
+            }
+        }
+    }
+
     pub fn select_project(&mut self, project_id: usize) -> Result<()> {
         let project = match self.get_project(project_id) {
             Some(proj) => proj,
@@ -416,8 +633,8 @@ impl ProjectsData {
         return Ok(());
     }
 
-    pub async fn new_workspace(&mut self, name: &String, compiler: &String) -> Result<()> {
-        if !compiler_exists(compiler).await {
+    pub fn new_workspace(&mut self, name: &str, compiler: &str, compilers: &CompilerConfigurations) -> Result<()> {
+        if !compilers.contains_key(compiler) {
            anyhow::bail!("Compiler not found: {}", compiler);
         }
         let workspace_id = self.next_id();
@@ -426,24 +643,44 @@ impl ProjectsData {
         } else {
             &LexoRank::default()
         };
-        let workspace = Workspace::new(workspace_id, name.clone(), compiler.clone(), lexo_rank.next());
+        let workspace = Workspace::new(workspace_id, name.to_string(), compiler.to_string(), lexo_rank.next());
         self.workspaces.push(workspace);
         return Ok(());
     }
 
-    pub fn remove_workspace(&mut self, workspace_id: usize) {
-        let project_ids: Vec<usize> = self.workspaces
-            .iter()
-            .find(|ws| ws.id == workspace_id)
-            .map(|ws| ws.project_links.iter().map(|link| link.project_id).collect())
-            .unwrap_or_default();
+    /// Inserts a copy of the workspace right after it, with fresh ids for the workspace and its
+    /// links; the links keep referring to the same projects.
+    pub fn duplicate_workspace(&mut self, workspace_id: usize, new_name: &str) -> Result<()> {
+        let Some(index) = self.get_workspace_index(workspace_id) else {
+            anyhow::bail!("Workspace with id {} not found", workspace_id);
+        };
+        let mut workspace = self.workspaces[index].clone();
+        workspace.id = self.next_id();
+        workspace.name = new_name.to_string();
+        for link in &mut workspace.project_links {
+            link.id = self.next_id();
+        }
+        self.workspaces.insert(index + 1, workspace);
+        if let Err(error) = self.validate_workspace_names() {
+            self.workspaces.remove(index + 1);
+            return Err(error);
+        }
+        let mut workspaces: Vec<&mut dyn HasLexoRank> = self.workspaces.iter_mut().map(|ws| ws as &mut dyn HasLexoRank).collect();
+        LexoRank::apply(&mut workspaces);
+        return Ok(());
+    }
 
-        self.workspaces.retain(|ws| ws.id != workspace_id);
-        for project_id in project_ids {
-            if !self.can_find_any_links(project_id) {
-                self.remove_project(project_id, false);
+    pub fn remove_workspace(&mut self, workspace_id: usize) -> Result<()> {
+        let Some(index) = self.get_workspace_index(workspace_id) else {
+            anyhow::bail!("Workspace with id {} not found", workspace_id);
+        };
+        let workspace = self.workspaces.remove(index);
+        for link in workspace.project_links {
+            if !self.can_find_any_links(link.project_id) {
+                self.remove_project(link.project_id, false);
             }
         }
+        return Ok(());
     }
 
     pub fn move_workspace(&mut self, workspace_id: usize, drop_target_id: usize) -> Result<()> {
@@ -484,7 +721,12 @@ impl ProjectsData {
         return Ok(());
     }
 
-    pub async fn update_workspace(&mut self, workspace_id: usize, data: &WorkspaceUpdateData) -> Result<()> {
+    pub fn update_workspace(&mut self, workspace_id: usize, data: &WorkspaceUpdateData, compilers: &CompilerConfigurations) -> Result<()> {
+        let default_config = match data.default_config.as_deref().map(str::trim) {
+            Some("") => Some(None),
+            Some(config) => Some(Some(self.workspace_build_configuration(workspace_id, config)?)),
+            None => None,
+        };
         let workspace = match self.get_workspace_mut(workspace_id) {
             Some(ws) => ws,
             _ => anyhow::bail!("Workspace with id {} not found", workspace_id),
@@ -493,14 +735,42 @@ impl ProjectsData {
             workspace.name = name.clone();
         }
         if let Some(compiler_id) = &data.compiler {
-            if !compiler_exists(compiler_id).await {
+            if !compilers.contains_key(compiler_id) {
                 anyhow::bail!("Compiler not found: {}", compiler_id);
             }
             workspace.compiler_id = compiler_id.clone();
         }
+        if let Some(platform) = &data.default_platform {
+            workspace.default_platform = match platform.trim() {
+                "" => None,
+                platform => Some(validate_platform(platform)?.to_string()),
+            };
+        }
+        if let Some(config) = default_config {
+            workspace.default_config = config;
+        }
         return Ok(());
     }
 
+    /// `config` as spelled by the build configurations the workspace's projects declare.
+    /// Anything goes while none of them declares one, e.g. for an empty workspace.
+    fn workspace_build_configuration(&self, workspace_id: usize, config: &str) -> Result<String> {
+        let mut configurations: Vec<String> = Vec::new();
+        for (_, project) in self.workspace_projects(workspace_id)? {
+            let Some(dproj) = &project.dproj else {
+                continue;
+            };
+            configurations.extend(get_build_configurations(&PathBuf::from(dproj)).unwrap_or_default());
+        }
+        if configurations.is_empty() {
+            return Ok(config.to_string());
+        }
+        return match configurations.into_iter().find(|known| known.eq_ignore_ascii_case(config)) {
+            Some(known) => Ok(known),
+            None => anyhow::bail!("No project of the workspace has a build configuration named '{}'", config),
+        };
+    }
+
     pub fn set_group_project(&mut self, groupproj_path: &String) -> Result<()> {
         let path = PathBuf::from(groupproj_path);
         if !path.exists() {
@@ -516,6 +786,35 @@ impl ProjectsData {
         return Ok(());
     }
 
+    /// Brings the group project's links in line with its `.groupproj` file: links of projects
+    /// still listed keep their ids and ranks, newly listed projects are appended and projects
+    /// no longer linked anywhere are dropped. Nothing changes if the file can't be read.
+    pub fn reload_group_project(&mut self) -> Result<()> {
+        let Some(group_project) = &self.group_project else {
+            anyhow::bail!("No group project defined");
+        };
+        let mut updated = self.clone();
+        let listed = GroupProject::listed_projects(&group_project.path, &mut updated)?;
+        let mut group_project = updated.group_project.take().unwrap();
+        let (kept, dropped): (Vec<ProjectLink>, Vec<ProjectLink>) = group_project.project_links
+            .drain(..)
+            .partition(|link| listed.contains(&link.project_id));
+        group_project.project_links = kept;
+        for project_id in listed {
+            if !group_project.project_links.iter().any(|link| link.project_id == project_id) {
+                group_project.new_project_link(updated.next_id(), project_id);
+            }
+        }
+        updated.group_project = Some(group_project);
+        for link in dropped {
+            if !updated.can_find_any_links(link.project_id) {
+                updated.remove_project(link.project_id, false);
+            }
+        }
+        *self = updated;
+        return Ok(());
+    }
+
     pub fn remove_group_project(&mut self) {
         self.group_project = None;
 
@@ -526,6 +825,7 @@ impl ProjectsData {
             .collect();
 
         self.projects.retain(|project| linked_project_ids.contains(&project.id));
+        self.drop_dangling_test_projects();
 
         if let Some(active_project_id) = self.active_project_id && !self.can_find_any_links(active_project_id) {
             self.active_project_id = None;
@@ -544,6 +844,19 @@ impl ProjectsData {
         return self.workspaces.iter().find(|ws| ws.id == workspace_id);
     }
 
+    /// The projects linked into a workspace, ordered by their links' `sort_rank`.
+    pub fn workspace_projects(&self, workspace_id: usize) -> Result<Vec<(&ProjectLink, &Project)>> {
+        let Some(workspace) = self.get_workspace(workspace_id) else {
+            anyhow::bail!("Workspace not found: {}", workspace_id);
+        };
+        let mut links: Vec<&ProjectLink> = workspace.project_links.iter().collect();
+        links.sort_by(|a, b| a.sort_rank.cmp(&b.sort_rank));
+        return Ok(links
+            .into_iter()
+            .filter_map(|link| Some((link, self.get_project(link.project_id)?)))
+            .collect());
+    }
+
     pub fn get_workspace_mut(&mut self, workspace_id: usize) -> Option<&mut Workspace> {
         return self.workspaces.iter_mut().find(|ws| ws.id == workspace_id);
     }
@@ -552,10 +865,177 @@ impl ProjectsData {
         return self.workspaces.iter().position(|ws| ws.id == workspace_id);
     }
 
+    /// The project with the given `.dproj` path, or else the project whose `.dproj` carries
+    /// the same `<ProjectGuid>` but no longer exists at its stored path (i.e. was moved).
     pub fn find_project_by_dproj(&self, dproj: &String) -> Option<&Project> {
-        return self.projects.iter().find(|proj| proj.dproj.as_ref().map_or(false, |p| p == dproj));
+        let wanted = comparable_path(Path::new(dproj));
+        let project = self.projects.iter().find(|proj| {
+            proj.dproj.as_ref().is_some_and(|p| comparable_path(Path::new(p)) == wanted)
+        });
+        if project.is_some() {
+            return project;
+        }
+        let guid = get_project_guid(&PathBuf::from(dproj)).ok().flatten()?;
+        return self.projects.iter().find(|proj| {
+            proj.project_guid.as_ref().is_some_and(|project_guid| project_guid.eq_ignore_ascii_case(&guid))
+                && !proj.dproj.as_ref().is_some_and(|p| Path::new(p).exists())
+        });
+    }
+
+    /// The id of an existing project that `file_path` (a `.dproj`, `.dpr` or `.dpk`) is a moved
+    /// copy of, after pointing the project at its new location.
+    fn find_moved_project(&mut self, file_path: &String) -> Result<Option<usize>> {
+        let file = PathBuf::from(file_path);
+        let dproj = match file.extension().and_then(|ext| ext.to_str()).map(|s| s.to_lowercase()) {
+            Some(ext) if ext == "dproj" => file,
+            _ => match find_dproj_file(&file) {
+                Ok(dproj) => dproj,
+                Err(_) => return Ok(None),
+            },
+        };
+        let dproj = dproj.to_string_lossy().to_string();
+        let Some(project_id) = self.find_project_by_dproj(&dproj).map(|project| project.id) else {
+            return Ok(None);
+        };
+        self.relocate_project(project_id, &dproj)?;
+        return Ok(Some(project_id));
+    }
+
+    /// Points the project at `dproj` (no-op if it already is) and rediscovers its other paths.
+    pub fn relocate_project(&mut self, project_id: usize, dproj: &String) -> Result<()> {
+        let project = match self.get_project_mut(project_id) {
+            Some(proj) => proj,
+            None => anyhow::bail!("Project with id {} not found", project_id),
+        };
+        if project.dproj.as_ref() == Some(dproj) {
+            return Ok(());
+        }
+        let path = PathBuf::from(dproj);
+        project.directory = path.parent().and_then(|p| p.to_str()).unwrap_or("<directory error>").to_string();
+        project.dproj = Some(dproj.clone());
+        project.dpr = None;
+        project.dpk = None;
+        return project.discover_paths();
+    }
+
+    /// The projects `file` belongs to: the projects whose main files include it, otherwise
+    /// the projects with the deepest directory containing it. Several projects are returned
+    /// only when they share that directory (or main file), leaving the choice to the caller.
+    pub fn find_project_containing_file(&self, file: &Path) -> Vec<&Project> {
+        let file = comparable_path(file);
+        let same_file = |path: &Option<String>| {
+            path.as_ref().is_some_and(|path| comparable_path(Path::new(path)) == file)
+        };
+        let owners: Vec<&Project> = self
+            .projects
+            .iter()
+            .filter(|proj| same_file(&proj.dproj) || same_file(&proj.dpr) || same_file(&proj.dpk))
+            .collect();
+        if !owners.is_empty() {
+            return owners;
+        }
+        let containing: Vec<(usize, &Project)> = self
+            .projects
+            .iter()
+            .filter(|proj| !proj.directory.is_empty())
+            .map(|proj| (comparable_path(Path::new(&proj.directory)), proj))
+            .filter(|(directory, _)| file.starts_with(directory))
+            .map(|(directory, proj)| (directory.components().count(), proj))
+            .collect();
+        let Some(deepest) = containing.iter().map(|(depth, _)| *depth).max() else {
+            return Vec::new();
+        };
+        return containing
+            .into_iter()
+            .filter(|(depth, _)| *depth == deepest)
+            .map(|(_, proj)| proj)
+            .collect();
+    }
+
+    pub fn active_project_id(&self) -> Option<usize> {
+        return self.active_project_id;
     }
 
+    /// Copies the saved data file into `backups/` next to it before a destructive change,
+    /// keeping the newest `keep` copies. `keep == 0` disables backups.
+    pub fn backup(keep: usize) -> Result<()> {
+        let path = Self::get_file_path();
+        if keep == 0 || !path.exists() {
+            return Ok(());
+        }
+        let directory = path.parent().unwrap_or(Path::new(".")).join("backups");
+        std::fs::create_dir_all(&directory)?;
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("projects");
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("ron");
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+        std::fs::copy(path, directory.join(format!("{stem}-{timestamp}.{extension}")))?;
+
+        let prefix = format!("{stem}-");
+        let mut backups: Vec<PathBuf> = std::fs::read_dir(&directory)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|backup| {
+                backup.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix))
+            })
+            .collect();
+        // timestamps sort chronologically by name
+        backups.sort();
+        let excess = backups.len().saturating_sub(keep);
+        for backup in &backups[..excess] {
+            std::fs::remove_file(backup)?;
+        }
+        return Ok(());
+    }
+
+    /// Sorts, repairs the ranks, bumps the version and persists the data.
+    /// `lock` comes from `lock_latest`, taken before the data was changed.
+    pub fn commit(&mut self, lock: &FileLock<Self>) -> Result<()> {
+        return self.prepare_commit(lock)?.finish();
+    }
+
+    /// `commit` up to writing the temporary file; see `Stateful::prepare_save`.
+    pub fn prepare_commit<'a>(&mut self, lock: &'a FileLock<Self>) -> Result<PendingSave<'a, Self>> {
+        self.sort();
+        // a snapshot or change may bring in duplicate or invalid ranks
+        self.repair_ranks();
+        self.version += 1;
+        return self.prepare_save(lock);
+    }
+
+    /// Replaces the data with a whole snapshot sent by a client. The snapshot has to be based
+    /// on the current version, otherwise it would silently drop changes made in the meantime.
+    pub async fn replace_with(&mut self, snapshot: ProjectsData) -> Result<()> {
+        let lock = self.lock_latest().await?;
+        if snapshot.version != self.version {
+            anyhow::bail!(
+                "Conflict: projects data changed in the meantime (version {} is stale, current is {}). Reload and retry.",
+                snapshot.version,
+                self.version
+            );
+        }
+        // an invalid snapshot must not stay in memory either
+        snapshot.validate().await?;
+        *self = snapshot;
+        return self.commit(&lock);
+    }
+
+    /// Rebalances duplicate or invalid `sort_rank`s among the workspaces and within each
+    /// container's links, keeping the current order. Returns whether anything was rewritten.
+    pub fn repair_ranks(&mut self) -> bool {
+        let mut workspaces: Vec<&mut dyn HasLexoRank> = self.workspaces.iter_mut().map(|ws| ws as &mut dyn HasLexoRank).collect();
+        let mut repaired = lexorank::repair(&mut workspaces);
+        for workspace in &mut self.workspaces {
+            let mut links: Vec<&mut dyn HasLexoRank> = workspace.project_links.iter_mut().map(|link| link as &mut dyn HasLexoRank).collect();
+            repaired |= lexorank::repair(&mut links);
+        }
+        if let Some(group_project) = &mut self.group_project {
+            let mut links: Vec<&mut dyn HasLexoRank> = group_project.project_links.iter_mut().map(|link| link as &mut dyn HasLexoRank).collect();
+            repaired |= lexorank::repair(&mut links);
+        }
+        return repaired;
+    }
+
+    /// Orders workspaces and project links by their `sort_rank`. Idempotent.
     pub fn sort(&mut self) {
         self.workspaces.sort_by(|a: &Workspace, b: &Workspace| a.sort_rank.cmp(&b.sort_rank));
         for workspace in &mut self.workspaces {
@@ -594,18 +1074,407 @@ impl ProjectsData {
     }
 }
 
+/// Set from the `initialize` request to give this session its own projects file; the
+/// default location is fixed by the first use otherwise.
+pub static PROJECTS_DATA_PATH: OnceLock<PathBuf> = OnceLock::new();
+
 impl FilePath for ProjectsData {
     fn get_file_path() -> &'static PathBuf {
-        lazy_static::lazy_static! {
-            static ref PATH: PathBuf = {
-                dirs::config_dir()
-                    .expect("Could not determine config directory")
-                    .join("ddk")
-                    .join("projects.ron")
+        return PROJECTS_DATA_PATH.get_or_init(|| {
+            config_directory().join(format!("projects.{}", data_file_extension()))
+        });
+    }
+}
+
+impl Load for ProjectsData {}
+
+/// The directory of the projects file, which also holds the build history, cache and logs;
+/// a session given its own projects file keeps them apart from other sessions.
+pub fn data_directory() -> &'static Path {
+    return ProjectsData::get_file_path().parent().unwrap_or(config_directory());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::test_session;
+
+    /// A workspace "Main" linking the given projects, in order.
+    fn workspace_with(projects: &[&str]) -> ProjectsData {
+        let mut data = ProjectsData::default();
+        data.new_workspace("Main", "12.0", &CompilerConfigurations::default()).unwrap();
+        let workspace_id = data.workspaces[0].id;
+        for project in projects {
+            data.new_project(&format!("/projects/{project}.dpr"), workspace_id).unwrap();
+        }
+        return data;
+    }
+
+    /// `workspace_with` plus a group project linking the same projects, in order.
+    fn group_with(projects: &[&str]) -> ProjectsData {
+        let mut data = workspace_with(projects);
+        let mut group_project = GroupProject {
+            name: "Group".to_string(),
+            path: "/projects/Group.groupproj".to_string(),
+            project_links: Vec::new(),
+        };
+        for project in projects {
+            let project_id = data.projects.iter().find(|p| p.name == *project).unwrap().id;
+            group_project.new_project_link(data.next_id(), project_id);
+        }
+        data.group_project = Some(group_project);
+        return data;
+    }
+
+    fn group_link_id(data: &ProjectsData, project: &str) -> usize {
+        let project_id = data.projects.iter().find(|p| p.name == project).unwrap().id;
+        let group_project = data.group_project.as_ref().unwrap();
+        return group_project.project_links.iter().find(|link| link.project_id == project_id).unwrap().id;
+    }
+
+    fn group_order(data: &ProjectsData) -> Vec<&str> {
+        return data.group_project.as_ref().unwrap().project_links
+            .iter()
+            .map(|link| data.projects.iter().find(|p| p.id == link.project_id).unwrap().name.as_str())
+            .collect();
+    }
+
+    fn link_id(data: &ProjectsData, project: &str) -> usize {
+        let project_id = data.projects.iter().find(|p| p.name == project).unwrap().id;
+        return data.workspaces[0].project_links.iter().find(|link| link.project_id == project_id).unwrap().id;
+    }
+
+    #[test]
+    fn removing_unknown_ids_fails() {
+        let mut data = workspace_with(&["A"]);
+        let unknown = data.next_id();
+        assert!(data.remove_project_link(unknown).is_err());
+        assert!(data.remove_workspace(unknown).is_err());
+        assert_eq!(data.projects.len(), 1);
+        assert_eq!(data.workspaces.len(), 1);
+
+        data.remove_project_link(link_id(&data, "A")).unwrap();
+        assert!(data.projects.is_empty());
+        data.remove_workspace(data.workspaces[0].id).unwrap();
+        assert!(data.workspaces.is_empty());
+    }
+
+    #[test]
+    fn a_project_linked_twice_is_removed_with_its_last_link() {
+        for (first, second) in [("workspace", "group"), ("group", "workspace")] {
+            let mut data = group_with(&["A"]);
+            let link = |data: &ProjectsData, container: &str| match container {
+                "workspace" => link_id(data, "A"),
+                _ => group_link_id(data, "A"),
             };
+
+            data.remove_project_link(link(&data, first)).unwrap();
+            assert_eq!(data.projects.len(), 1, "removing the {first} link");
+            data.remove_project_link(link(&data, second)).unwrap();
+            assert!(data.projects.is_empty(), "removing the {second} link");
         }
-        return &PATH;
     }
-}
 
-impl Load for ProjectsData {}
\ No newline at end of file
+    #[test]
+    fn links_are_found_in_their_container() {
+        let mut data = group_with(&["A"]);
+        let unknown = data.next_id();
+        let workspace_id = data.workspaces[0].id;
+        assert_eq!(data.container_of_link(link_id(&data, "A")), Some(LinkContainer::Workspace { workspace_id }));
+        assert_eq!(data.container_of_link(group_link_id(&data, "A")), Some(LinkContainer::GroupProject));
+        assert_eq!(data.container_of_link(workspace_id), None);
+        assert_eq!(data.container_of_link(unknown), None);
+    }
+
+    #[test]
+    fn next_id_skips_ids_in_use_behind_a_stale_counter() {
+        let mut data = workspace_with(&["A", "B"]);
+        let used = data.used_ids();
+        data.id_counter = 0;
+        let id = data.next_id();
+        assert!(id != 0 && !used.contains(&id));
+        assert_eq!(id, used.iter().max().unwrap() + 1);
+
+        data.id_counter = usize::MAX;
+        assert_eq!(data.next_id(), 1 + (1..).take_while(|id| used.contains(id)).count());
+    }
+
+    #[test]
+    fn duplicated_workspaces_get_fresh_ids() {
+        let mut data = workspace_with(&["A", "B"]);
+        data.new_workspace("Last", "12.0", &CompilerConfigurations::default()).unwrap();
+        let source_id = data.workspaces[0].id;
+        let used = data.used_ids();
+
+        data.duplicate_workspace(source_id, "Copy").unwrap();
+
+        let names: Vec<&str> = data.workspaces.iter().map(|workspace| workspace.name.as_str()).collect();
+        assert_eq!(names, ["Main", "Copy", "Last"]);
+        let copy = &data.workspaces[1];
+        assert!(!used.contains(&copy.id));
+        assert!(copy.project_links.iter().all(|link| !used.contains(&link.id)));
+        let projects = |workspace: &Workspace| workspace.project_links.iter().map(|link| link.project_id).collect::<Vec<_>>();
+        assert_eq!(projects(copy), projects(&data.workspaces[0]));
+        assert!(data.workspaces[0].sort_rank < copy.sort_rank && copy.sort_rank < data.workspaces[2].sort_rank);
+        // every id is used once
+        data.get_id_map().unwrap();
+        data.validate_references().unwrap();
+    }
+
+    #[test]
+    fn duplicates_need_a_new_name() {
+        let mut data = workspace_with(&["A"]);
+        let before = data.clone();
+        assert!(data.duplicate_workspace(data.workspaces[0].id, "Main").is_err());
+        assert_eq!(data.workspaces, before.workspaces);
+    }
+
+    #[test]
+    fn group_project_links_move_to_the_front() {
+        let mut data = group_with(&["A", "B", "C"]);
+        data.move_project_link(group_link_id(&data, "C"), group_link_id(&data, "A")).unwrap();
+        assert_eq!(group_order(&data), ["C", "A", "B"]);
+        data.validate_references().unwrap();
+    }
+
+    #[test]
+    fn group_project_links_move_to_the_back() {
+        let mut data = group_with(&["A", "B", "C"]);
+        data.move_project_link(group_link_id(&data, "A"), GROUP_PROJECT_DROP_TARGET).unwrap();
+        assert_eq!(group_order(&data), ["B", "C", "A"]);
+        // the ranks follow the new order, so it survives sorting on the next commit
+        data.sort();
+        assert_eq!(group_order(&data), ["B", "C", "A"]);
+    }
+
+    #[test]
+    fn group_project_links_dropped_onto_themselves_stay() {
+        let mut data = group_with(&["A", "B", "C"]);
+        let before = data.clone();
+        data.move_project_link(group_link_id(&data, "B"), group_link_id(&data, "B")).unwrap();
+        assert_eq!(data, before);
+    }
+
+    #[test]
+    fn the_group_project_drop_target_needs_a_group_project() {
+        let mut data = workspace_with(&["A"]);
+        assert!(data.move_project_link(link_id(&data, "A"), GROUP_PROJECT_DROP_TARGET).is_err());
+    }
+
+    #[test]
+    fn links_move_between_workspaces() {
+        let mut data = workspace_with(&["A", "B"]);
+        data.new_workspace("Other", "12.0", &CompilerConfigurations::default()).unwrap();
+        let other_id = data.workspaces[1].id;
+        data.move_project_link(link_id(&data, "A"), other_id).unwrap();
+        assert_eq!(data.workspaces[0].project_links.len(), 1);
+        assert_eq!(data.workspaces[1].project_links.len(), 1);
+        // moved, not removed
+        assert_eq!(data.projects.len(), 2);
+        data.validate_references().unwrap();
+    }
+
+    #[test]
+    fn links_do_not_move_from_a_workspace_into_the_group_project() {
+        let mut data = group_with(&["A", "B"]);
+        let before = data.clone();
+        assert!(data.move_project_link(link_id(&data, "A"), group_link_id(&data, "B")).is_err());
+        assert!(data.move_project_link(link_id(&data, "A"), GROUP_PROJECT_DROP_TARGET).is_err());
+        assert_eq!(data, before);
+    }
+
+    #[test]
+    fn links_do_not_move_from_the_group_project_into_a_workspace() {
+        let mut data = group_with(&["A", "B"]);
+        let before = data.clone();
+        let workspace_id = data.workspaces[0].id;
+        assert!(data.move_project_link(group_link_id(&data, "A"), workspace_id).is_err());
+        assert!(data.move_project_link(group_link_id(&data, "A"), link_id(&data, "B")).is_err());
+        assert_eq!(data, before);
+    }
+
+    #[test]
+    fn previewing_a_move_into_the_group_project_reports_why_it_is_refused() {
+        let data = group_with(&["A", "B"]);
+        let before = data.clone();
+        let error = data.can_move_project_link(link_id(&data, "A"), GROUP_PROJECT_DROP_TARGET).unwrap_err();
+        assert!(error.to_string().starts_with("Project links can't be moved into or out of the group project"));
+        assert!(data.can_move_project_link(link_id(&data, "A"), link_id(&data, "B")).is_ok());
+        assert_eq!(data, before);
+    }
+
+    /// Writes `<name>.dproj` and `<name>.dpr` for each project and a `.groupproj` listing them.
+    fn write_group_files(directory: &Path, projects: &[&str]) -> String {
+        let mut includes = String::new();
+        for project in projects {
+            let dproj = format!("<Project><PropertyGroup><MainSource>{project}.dpr</MainSource></PropertyGroup></Project>");
+            std::fs::write(directory.join(format!("{project}.dproj")), dproj).unwrap();
+            std::fs::write(directory.join(format!("{project}.dpr")), format!("program {project};")).unwrap();
+            includes.push_str(&format!("<Projects Include=\"{project}.dproj\"/>"));
+        }
+        let groupproj = directory.join("Group.groupproj");
+        std::fs::write(&groupproj, format!("<Project><ItemGroup>{includes}</ItemGroup></Project>")).unwrap();
+        return groupproj.to_string_lossy().to_string();
+    }
+
+    #[test]
+    fn reloading_the_group_project_keeps_the_links_still_listed() {
+        let directory = tempfile::tempdir().unwrap();
+        let groupproj = write_group_files(directory.path(), &["A", "B"]);
+        let mut data = ProjectsData::default();
+        data.set_group_project(&groupproj).unwrap();
+        data.move_project_link(group_link_id(&data, "B"), group_link_id(&data, "A")).unwrap();
+        let b_link = data.group_project.as_ref().unwrap().project_links[0].clone();
+
+        write_group_files(directory.path(), &["C", "B"]);
+        data.reload_group_project().unwrap();
+        assert_eq!(group_order(&data), ["B", "C"]);
+        assert_eq!(data.group_project.as_ref().unwrap().project_links[0], b_link);
+        assert!(!data.projects.iter().any(|p| p.name == "A"));
+        data.validate_references().unwrap();
+    }
+
+    #[test]
+    fn a_group_project_file_that_does_not_parse_changes_nothing() {
+        let directory = tempfile::tempdir().unwrap();
+        let groupproj = write_group_files(directory.path(), &["A", "B"]);
+        let mut data = ProjectsData::default();
+        data.set_group_project(&groupproj).unwrap();
+        let before = data.clone();
+
+        std::fs::write(&groupproj, "<Project><ItemGroup>").unwrap();
+        assert!(data.reload_group_project().is_err());
+        assert_eq!(data, before);
+    }
+
+    #[tokio::test]
+    async fn invalid_snapshots_do_not_replace_the_data() {
+        let _session = test_session().await;
+        let mut data = PROJECTS_DATA.read().await.clone();
+        let before = data.clone();
+
+        let mut stale = workspace_with(&["A"]);
+        stale.version = data.version + 1;
+        assert!(data.replace_with(stale).await.is_err());
+        assert_eq!(data, before);
+
+        let mut duplicate_names = workspace_with(&["A"]);
+        duplicate_names.workspaces.push(duplicate_names.workspaces[0].clone());
+        duplicate_names.workspaces[1].id = duplicate_names.next_id();
+        duplicate_names.workspaces[1].project_links.clear();
+        duplicate_names.version = data.version;
+        assert!(data.replace_with(duplicate_names).await.is_err());
+        assert_eq!(data, before);
+
+        let mut valid = workspace_with(&["A"]);
+        valid.version = data.version;
+        data.replace_with(valid).await.unwrap();
+        assert_eq!(data.version, before.version + 1);
+        assert_eq!(ProjectsData::load_current().await.unwrap(), data);
+    }
+
+    #[test]
+    fn workspace_default_configs_must_exist_in_its_projects() {
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = directory.path().join("A.dproj");
+        std::fs::write(
+            &dproj,
+            r#"<Project><ItemGroup><BuildConfiguration Include="Base"/><BuildConfiguration Include="Debug"/><BuildConfiguration Include="Release"/></ItemGroup></Project>"#,
+        ).unwrap();
+        let mut data = workspace_with(&["A"]);
+        data.projects[0].dproj = Some(dproj.to_string_lossy().to_string());
+        let workspace_id = data.workspaces[0].id;
+        let compilers = CompilerConfigurations::default();
+        let default_config = |config: &str| WorkspaceUpdateData {
+            name: None,
+            compiler: None,
+            default_platform: None,
+            default_config: Some(config.to_string()),
+        };
+
+        data.update_workspace(workspace_id, &default_config("release"), &compilers).unwrap();
+        assert_eq!(data.workspaces[0].default_config.as_deref(), Some("Release"));
+        assert!(data.update_workspace(workspace_id, &default_config("Profiling"), &compilers).is_err());
+        assert!(data.update_workspace(workspace_id, &default_config("Base"), &compilers).is_err());
+        assert_eq!(data.workspaces[0].default_config.as_deref(), Some("Release"));
+        data.update_workspace(workspace_id, &default_config(""), &compilers).unwrap();
+        assert_eq!(data.workspaces[0].default_config, None);
+    }
+
+    #[test]
+    fn equal_ranks_are_made_distinct_on_load() {
+        let mut data = workspace_with(&["A", "B", "C"]);
+        let rank = data.workspaces[0].project_links[0].sort_rank.clone();
+        for link in &mut data.workspaces[0].project_links {
+            link.sort_rank = rank.clone();
+        }
+        let data = ProjectsData::normalize(data);
+        let links = &data.workspaces[0].project_links;
+        assert!(links.windows(2).all(|pair| pair[0].sort_rank < pair[1].sort_rank));
+        let order: Vec<&str> = links.iter().map(|link| data.get_project(link.project_id).unwrap().name.as_str()).collect();
+        assert_eq!(order, ["A", "B", "C"]);
+    }
+
+    #[tokio::test]
+    async fn ranks_are_repaired_on_commit() {
+        let _session = test_session().await;
+        let mut data = PROJECTS_DATA.read().await.clone();
+        let mut snapshot = workspace_with(&["A", "B"]);
+        snapshot.workspaces[0].project_links[1].sort_rank = snapshot.workspaces[0].project_links[0].sort_rank.clone();
+        snapshot.version = data.version;
+        data.replace_with(snapshot).await.unwrap();
+        let links = &data.workspaces[0].project_links;
+        assert_ne!(links[0].sort_rank, links[1].sort_rank);
+    }
+
+    #[test]
+    fn projects_are_found_by_an_equivalent_dproj_path() {
+        let directory = tempfile::tempdir().unwrap();
+        let dproj = directory.path().join("A.dproj");
+        std::fs::write(&dproj, "<Project/>").unwrap();
+        let mut data = workspace_with(&["A"]);
+        data.projects[0].dproj = Some(dproj.to_string_lossy().to_string());
+
+        let roundabout = directory.path().join(".").join("A.dproj");
+        let found = data.find_project_by_dproj(&roundabout.to_string_lossy().to_string());
+        assert_eq!(found.map(|p| p.id), Some(data.projects[0].id));
+    }
+
+    #[test]
+    fn files_belong_to_the_projects_of_the_deepest_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let mut data = workspace_with(&["A", "B", "C", "D"]);
+        for (project, directory) in data.projects.iter_mut().zip(["", "nested", "shared", "shared"]) {
+            let directory = root.path().join(directory);
+            std::fs::create_dir_all(&directory).unwrap();
+            project.directory = directory.to_string_lossy().to_string();
+            project.dpr = Some(directory.join(format!("{}.dpr", project.name)).to_string_lossy().to_string());
+        }
+        let owners = |file: &str| -> Vec<&str> {
+            return data.find_project_containing_file(&root.path().join(file)).iter().map(|p| p.name.as_str()).collect();
+        };
+
+        assert_eq!(owners("Unit1.pas"), vec!["A"]);
+        assert_eq!(owners("nested/deeper/Unit1.pas"), vec!["B"]);
+        assert_eq!(owners("nestedness/Unit1.pas"), vec!["A"]);
+        assert_eq!(owners("shared/Unit1.pas"), vec!["C", "D"]);
+        assert_eq!(owners("shared/D.dpr"), vec!["D"]);
+        assert!(data.find_project_containing_file(Path::new("/elsewhere/Unit1.pas")).is_empty());
+    }
+
+    #[test]
+    fn links_to_a_missing_project_are_reported_then_removed() {
+        let mut data = group_with(&["A", "B"]);
+        let orphaned = vec![link_id(&data, "A"), group_link_id(&data, "A")];
+        let kept = [link_id(&data, "B"), group_link_id(&data, "B")];
+        // a hand edit of the data file dropped project A but not its links
+        data.projects.retain(|p| p.name != "A");
+
+        assert_eq!(data.orphaned_links(), orphaned);
+        data.remove_orphaned_links().unwrap();
+        assert!(data.orphaned_links().is_empty());
+        assert_eq!(data.workspaces[0].project_links.iter().map(|link| link.id).collect::<Vec<_>>(), vec![kept[0]]);
+        let group_project = data.group_project.as_ref().unwrap();
+        assert_eq!(group_project.project_links.iter().map(|link| link.id).collect::<Vec<_>>(), vec![kept[1]]);
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use tower_lsp::lsp_types::Url;
+
+use super::*;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+/// Base id of locations relative to `base_path`, i.e. the root of the checked out sources.
+const SARIF_SOURCE_ROOT: &str = "%SRCROOT%";
+
+/// A minimal SARIF 2.1.0 log of `diagnostics`: one rule per distinct code and one result per
+/// diagnostic. Relative file paths are resolved against `base_dir`; files under `base_path`
+/// are located relative to it instead of by absolute URI.
+pub fn diagnostics_to_sarif(
+    diagnostics: &BTreeMap<String, Vec<CompilerLineDiagnostic>>,
+    base_dir: &Path,
+    base_path: Option<&Path>,
+) -> Value {
+    let mut rules: BTreeSet<&str> = BTreeSet::new();
+    let mut results: Vec<Value> = Vec::new();
+    for (file, file_diagnostics) in diagnostics {
+        let path = base_dir.join(file);
+        let relative = base_path.and_then(|base_path| relative_path(&path.to_string_lossy(), base_path));
+        let artifact_location = match relative {
+            Some(relative) => json!({ "uri": relative, "uriBaseId": SARIF_SOURCE_ROOT }),
+            None => json!({
+                "uri": Url::from_file_path(&path)
+                    .map(|uri| uri.to_string())
+                    .unwrap_or_else(|_| file.replace('\\', "/")),
+            }),
+        };
+        for diagnostic in file_diagnostics {
+            rules.insert(&diagnostic.code);
+            let mut region = json!({ "startLine": diagnostic.line.max(1) });
+            if let Some(column) = diagnostic.column {
+                region["startColumn"] = json!(column.max(1));
+            }
+            results.push(json!({
+                "ruleId": diagnostic.code,
+                "level": sarif_level(&diagnostic.kind),
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact_location.clone(),
+                        "region": region,
+                    }
+                }],
+            }));
+        }
+    }
+    let rules: Vec<Value> = rules.into_iter().map(|code| json!({ "id": code })).collect();
+    return json!({
+        "$schema": SARIF_SCHEMA,
+        "version": SARIF_VERSION,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "DDK",
+                    "informationUri": "https://www.github.com/valentin-baron/delphi-devkit",
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    });
+}
+
+fn sarif_level(kind: &DiagnosticKind) -> &'static str {
+    return match kind {
+        DiagnosticKind::ERROR => "error",
+        DiagnosticKind::WARN => "warning",
+        DiagnosticKind::HINT => "note",
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    fn diagnostic(file: &str, line: u32, column: Option<u32>, code: &str) -> CompilerLineDiagnostic {
+        return CompilerLineDiagnostic {
+            time: Local::now(),
+            file: file.to_string(),
+            line,
+            column,
+            message: format!("{code} in {file}"),
+            code: code.to_string(),
+            kind: DiagnosticKind::ERROR,
+            compiler_name: "dcc32".to_string(),
+        };
+    }
+
+    #[test]
+    fn each_diagnostic_is_one_result_at_its_position() {
+        let directory = tempfile::tempdir().unwrap();
+        let diagnostics = BTreeMap::from([
+            ("Unit1.pas".to_string(), vec![diagnostic("Unit1.pas", 3, Some(5), "E2003"), diagnostic("Unit1.pas", 9, None, "E2003")]),
+            ("Unit2.pas".to_string(), vec![diagnostic("Unit2.pas", 12, Some(1), "E2010")]),
+        ]);
+
+        let sarif = diagnostics_to_sarif(&diagnostics, directory.path(), Some(directory.path()));
+
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"], json!([{ "id": "E2003" }, { "id": "E2010" }]));
+        let locations: Vec<(&str, &Value)> = run["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                let location = &result["locations"][0]["physicalLocation"];
+                return (location["artifactLocation"]["uri"].as_str().unwrap(), &location["region"]);
+            })
+            .collect();
+        assert_eq!(locations, vec![
+            ("Unit1.pas", &json!({ "startLine": 3, "startColumn": 5 })),
+            ("Unit1.pas", &json!({ "startLine": 9 })),
+            ("Unit2.pas", &json!({ "startLine": 12, "startColumn": 1 })),
+        ]);
+    }
+}
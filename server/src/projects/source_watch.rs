@@ -0,0 +1,187 @@
+use anyhow::Result;
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc};
+use tower_lsp::Client;
+use tower_lsp::lsp_types::{FileChangeType, FileEvent, Url};
+
+use crate::{ProjectSourcesChanged, lsp_error};
+
+use super::*;
+
+const SOURCE_EXTENSIONS: &[&str] = &["pas", "inc", "dproj"];
+/// Events arriving within this window of each other are handled as one batch.
+const COALESCE_WINDOW: Duration = Duration::from_millis(300);
+
+struct SourceWatcher {
+    watcher: RecommendedWatcher,
+    roots: Vec<PathBuf>,
+}
+
+lazy_static::lazy_static! {
+    static ref SOURCE_WATCHER: Mutex<Option<SourceWatcher>> = Mutex::new(None);
+}
+
+/// Starts the recursive watcher over all project directories.
+pub async fn start_source_watcher(client: Client) -> Result<()> {
+    let (tx, rx) = mpsc::unbounded_channel::<Event>();
+    let watcher = RecommendedWatcher::new(
+        move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                let _ = tx.send(event);
+            }
+        },
+        Config::default(),
+    )?;
+    *SOURCE_WATCHER.lock().await = Some(SourceWatcher {
+        watcher,
+        roots: Vec::new(),
+    });
+    tokio::spawn(coalesce_events(rx, client));
+    return sync_source_watch_roots().await;
+}
+
+/// Brings the watched roots in line with the current projects, watching each
+/// project directory once and skipping directories nested in another root.
+pub async fn sync_source_watch_roots() -> Result<()> {
+    let roots = watch_roots(&*PROJECTS_DATA.read().await);
+    let mut guard = SOURCE_WATCHER.lock().await;
+    let Some(source_watcher) = guard.as_mut() else {
+        return Ok(());
+    };
+    for root in source_watcher.roots.iter().filter(|root| !roots.contains(root)) {
+        let _ = source_watcher.watcher.unwatch(root);
+    }
+    let mut watched = Vec::new();
+    for root in roots {
+        if source_watcher.roots.contains(&root) {
+            watched.push(root);
+            continue;
+        }
+        match source_watcher.watcher.watch(&root, RecursiveMode::Recursive) {
+            Ok(_) => watched.push(root),
+            Err(e) => eprintln!("Failed to watch {}: {}", root.to_string_lossy(), e),
+        }
+    }
+    source_watcher.roots = watched;
+    return Ok(());
+}
+
+fn watch_roots(projects_data: &ProjectsData) -> Vec<PathBuf> {
+    let mut directories: Vec<PathBuf> = projects_data
+        .projects
+        .iter()
+        .map(|project| PathBuf::from(&project.directory))
+        .filter(|directory| directory.is_dir())
+        .collect();
+    directories.sort();
+    directories.dedup();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for directory in directories {
+        // sorted order puts a parent before all of its subdirectories
+        if roots.last().is_some_and(|root| directory.starts_with(root)) {
+            continue;
+        }
+        roots.push(directory);
+    }
+    return roots;
+}
+
+fn is_source_file(path: &Path) -> bool {
+    return path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SOURCE_EXTENSIONS.iter().any(|source| ext.eq_ignore_ascii_case(source)));
+}
+
+async fn coalesce_events(mut rx: mpsc::UnboundedReceiver<Event>, client: Client) {
+    while let Some(event) = rx.recv().await {
+        let mut changes: BTreeMap<PathBuf, FileChangeType> = BTreeMap::new();
+        collect_changes(event, &mut changes);
+        while let Ok(Some(event)) = tokio::time::timeout(COALESCE_WINDOW, rx.recv()).await {
+            collect_changes(event, &mut changes);
+        }
+        if !changes.is_empty() {
+            handle_source_changes(changes, &client).await;
+        }
+    }
+}
+
+fn collect_changes(event: Event, changes: &mut BTreeMap<PathBuf, FileChangeType>) {
+    let typ = match event.kind {
+        EventKind::Create(_) => FileChangeType::CREATED,
+        EventKind::Modify(_) => FileChangeType::CHANGED,
+        EventKind::Remove(_) => FileChangeType::DELETED,
+        _ => return,
+    };
+    for path in event.paths.into_iter().filter(|path| is_source_file(path)) {
+        changes.insert(path, typ);
+    }
+}
+
+async fn handle_source_changes(changes: BTreeMap<PathBuf, FileChangeType>, client: &Client) {
+    let mut project_files = Vec::new();
+    let mut sources = Vec::new();
+    for (path, typ) in changes {
+        let is_dproj = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dproj"));
+        if is_dproj {
+            if let Ok(uri) = Url::from_file_path(&path) {
+                project_files.push(FileEvent { uri, typ });
+            }
+        } else {
+            sources.push(path.to_string_lossy().to_string());
+        }
+    }
+    if !project_files.is_empty() &&
+       let Err(e) = handle_watched_file_changes(project_files, client).await {
+        lsp_error!(client, "Failed to handle project file changes: {}", e);
+    }
+    if !sources.is_empty() {
+        ProjectSourcesChanged::notify(client, sources).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::testing::connected_client;
+
+    #[tokio::test]
+    async fn changes_in_nested_subfolders_are_detected() {
+        let (client, mut received) = connected_client().await;
+        let root = tempfile::tempdir().unwrap();
+        // events carry the resolved path, e.g. under /private on macOS
+        let root_path = root.path().canonicalize().unwrap();
+        let nested = root_path.join("source").join("core");
+        std::fs::create_dir_all(&nested).unwrap();
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        let mut watcher = RecommendedWatcher::new(
+            move |res: notify::Result<Event>| {
+                if let Ok(event) = res {
+                    let _ = tx.send(event);
+                }
+            },
+            Config::default(),
+        ).unwrap();
+        watcher.watch(&root_path, RecursiveMode::Recursive).unwrap();
+        tokio::spawn(coalesce_events(rx, client));
+
+        let unit = nested.join("Unit1.pas");
+        std::fs::write(&unit, "unit Unit1;").unwrap();
+        std::fs::write(nested.join("notes.txt"), "not a source").unwrap();
+
+        let files = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(message) = received.recv().await {
+                if message["method"] == "notifications/projects/sources-changed" {
+                    return message["params"]["files"].clone();
+                }
+            }
+            panic!("the server stopped before reporting the change");
+        }).await.expect("the nested change was not reported");
+        assert_eq!(files, serde_json::json!([unit.to_string_lossy()]));
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use tower_lsp::lsp_types::{Location, Range, SymbolInformation, SymbolKind, Url};
+
+use crate::utils::FilePath;
+
+use super::*;
+
+/// `workspace/symbol` results: the workspaces, projects and the group project whose name or
+/// project file name contains `query`, ignoring case. Workspaces have no file of their own
+/// and point at the projects file.
+pub fn workspace_symbols(projects_data: &ProjectsData, query: &str) -> Vec<SymbolInformation> {
+    let query = query.to_lowercase();
+    let matches = |candidates: &[Option<&str>]| {
+        candidates.iter().flatten().any(|candidate| candidate.to_lowercase().contains(&query))
+    };
+    let mut symbols = Vec::new();
+    if let Some(location) = file_location(ProjectsData::get_file_path()) {
+        for workspace in &projects_data.workspaces {
+            if matches(&[Some(&workspace.name)]) {
+                symbols.push(symbol(&workspace.name, SymbolKind::NAMESPACE, location.clone(), None));
+            }
+        }
+    }
+    if let Some(group_project) = &projects_data.group_project
+        && matches(&[Some(&group_project.name), file_name(&group_project.path)])
+        && let Some(location) = file_location(Path::new(&group_project.path))
+    {
+        symbols.push(symbol(&group_project.name, SymbolKind::PACKAGE, location, None));
+    }
+    for project in &projects_data.projects {
+        let files = [&project.dproj, &project.dpr, &project.dpk];
+        let file_names: Vec<Option<&str>> = files.iter().map(|file| file.as_deref().and_then(file_name)).collect();
+        if !matches(&[Some(&project.name)]) && !matches(&file_names) {
+            continue;
+        }
+        let Some(location) = files.iter().find_map(|file| file.as_deref().and_then(|file| file_location(Path::new(file)))) else {
+            continue;
+        };
+        let container = projects_data.workspaces
+            .iter()
+            .find(|workspace| workspace.project_links.iter().any(|link| link.project_id == project.id))
+            .map(|workspace| workspace.name.clone());
+        symbols.push(symbol(&project.name, SymbolKind::MODULE, location, container));
+    }
+    return symbols;
+}
+
+fn file_name(path: &str) -> Option<&str> {
+    return Path::new(path).file_name().and_then(|name| name.to_str());
+}
+
+fn file_location(path: &Path) -> Option<Location> {
+    let uri = Url::from_file_path(path).ok()?;
+    return Some(Location::new(uri, Range::default()));
+}
+
+#[allow(deprecated)]
+fn symbol(name: &str, kind: SymbolKind, location: Location, container_name: Option<String>) -> SymbolInformation {
+    return SymbolInformation {
+        name: name.to_string(),
+        kind,
+        tags: None,
+        deprecated: None,
+        location,
+        container_name,
+    };
+}
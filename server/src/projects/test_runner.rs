@@ -0,0 +1,165 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::state::PROJECTS_DATA;
+use crate::{CompileProjectParams, CompilerProgress};
+
+use super::*;
+
+/// Counters from the DUnitX console logger's closing summary.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestSummary {
+    pub found: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub errored: usize,
+    pub ignored: usize,
+}
+
+impl TestSummary {
+    /// Parses `Tests Found   : 3` style lines; `None` if the output has no summary.
+    pub fn from_output(lines: &[String]) -> Option<Self> {
+        let mut summary = TestSummary::default();
+        let mut seen = false;
+        for line in lines {
+            let Some((label, value)) = line.split_once(':') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<usize>() else {
+                continue;
+            };
+            let counter = match label.trim().to_lowercase().as_str() {
+                "tests found" => &mut summary.found,
+                "tests passed" => &mut summary.passed,
+                "tests failed" => &mut summary.failed,
+                "tests errored" => &mut summary.errored,
+                "tests ignored" => &mut summary.ignored,
+                _ => continue,
+            };
+            *counter = value;
+            seen = true;
+        }
+        return seen.then_some(summary);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestRun {
+    pub test_project_id: usize,
+    pub exe: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub output: Vec<String>,
+    pub summary: Option<TestSummary>,
+}
+
+/// Builds the test project linked to `project_id` and runs its executable,
+/// streaming the runner's output as compiler progress.
+pub async fn run_tests(client: &tower_lsp::Client, project_id: usize) -> Result<TestRun> {
+    let test_project_id = {
+        let projects_data = PROJECTS_DATA.read().await;
+        let project = projects_data
+            .get_project(project_id)
+            .ok_or_else(|| anyhow::anyhow!("Project with id {} not found", project_id))?;
+        project
+            .test_project_id
+            .ok_or_else(|| anyhow::anyhow!("Project {} has no test project configured", project.name))?
+    };
+
+    let params = CompileProjectParams::project(test_project_id);
+    if !BuildRequest::new(client.clone(), &params).run().await?.success {
+        anyhow::bail!("Test project with id {} failed to build", test_project_id);
+    }
+
+    let exe = PROJECTS_DATA
+        .read()
+        .await
+        .get_project(test_project_id)
+        .and_then(|project| project.exe.clone())
+        .map(PathBuf::from)
+        .filter(|exe| exe.is_file())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Test project with id {} has no executable - refresh its paths after building",
+                test_project_id
+            )
+        })?;
+
+    let mut child_process = Command::new(&exe)
+        .current_dir(exe.parent().unwrap_or(&exe))
+        // console runners waiting for a key press at the end read EOF instead
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut out_lines = BufReader::new(child_process.stdout.take().unwrap()).lines();
+    let mut err_lines = BufReader::new(child_process.stderr.take().unwrap()).lines();
+    let stdout_client = client.clone();
+    let stderr_client = client.clone();
+    let stdout_task = tokio::spawn(async move {
+        let mut lines = Vec::new();
+        while let Ok(Some(line)) = out_lines.next_line().await {
+            CompilerProgress::notify_stdout(&stdout_client, line.clone()).await;
+            lines.push(line);
+        }
+        return lines;
+    });
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = Vec::new();
+        while let Ok(Some(line)) = err_lines.next_line().await {
+            CompilerProgress::notify_stderr(&stderr_client, line.clone()).await;
+            lines.push(line);
+        }
+        return lines;
+    });
+
+    let status = child_process.wait().await?;
+    let mut output = stdout_task.await?;
+    output.extend(stderr_task.await?);
+    let summary = TestSummary::from_output(&output);
+    return Ok(TestRun {
+        test_project_id,
+        exe: exe.to_string_lossy().to_string(),
+        success: status.success(),
+        exit_code: status.code(),
+        output,
+        summary,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(lines: &[&str]) -> Vec<String> {
+        return lines.iter().map(|line| line.to_string()).collect();
+    }
+
+    #[test]
+    fn the_dunitx_summary_is_parsed_from_the_console_output() {
+        let summary = TestSummary::from_output(&output(&[
+            "DUnitX - [Tests.exe] - Starting Tests.",
+            "Fixture : Tests.Calculator",
+            "  Test : Tests.Calculator.Adds - PASS",
+            "  Test : Tests.Calculator.Divides - FAIL: Expected 2 but got 3",
+            "Tests Found   : 4",
+            "Tests Ignored : 1",
+            "Tests Passed  : 2",
+            "Tests Leaked  : 0",
+            "Tests Failed  : 1",
+            "Tests Errored : 0",
+        ]));
+        assert_eq!(summary, Some(TestSummary { found: 4, passed: 2, failed: 1, errored: 0, ignored: 1 }));
+    }
+
+    #[test]
+    fn output_without_a_summary_has_none() {
+        assert_eq!(TestSummary::from_output(&output(&["Access violation at address 00401234", "Fixture : Tests"])), None);
+        assert_eq!(TestSummary::from_output(&[]), None);
+    }
+}
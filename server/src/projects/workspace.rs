@@ -10,6 +10,12 @@ pub struct Workspace {
     pub compiler_id: String,
     pub project_links: Vec<ProjectLink>,
     pub sort_rank: LexoRank,
+    /// Used when a compile request for this workspace doesn't specify a platform.
+    #[serde(default)]
+    pub default_platform: Option<String>,
+    /// Used when a compile request for this workspace doesn't specify a configuration.
+    #[serde(default)]
+    pub default_config: Option<String>,
 }
 
 impl Workspace {
@@ -20,6 +26,8 @@ impl Workspace {
             compiler_id,
             project_links: Vec::new(),
             sort_rank: lexo_rank,
+            default_platform: None,
+            default_config: None,
         }
     }
 
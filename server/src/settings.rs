@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::projects::DiagnosticKind;
+
+pub const DEFAULT_BANNER_WIDTH: usize = 72;
+pub const MIN_BANNER_WIDTH: usize = 32;
+pub const DEFAULT_INDENT_WIDTH: usize = 2;
+pub const DEFAULT_CONFIG_BACKUPS: usize = 10;
+pub const DEFAULT_BUILD_LOGS: usize = 20;
+pub const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub diagnostic_stream: DiagnosticStreamSettings,
+    /// Abort multi-project builds as soon as one project fails.
+    pub stop_on_first_failure: bool,
+    /// Total width of the compile header/footer banners, borders included.
+    pub banner_width: usize,
+    /// Formatter.exe to use instead of the one shipped with the configured compilers.
+    pub formatter_path: Option<String>,
+    /// Spaces per nesting level used by `ddk.reindent`.
+    pub indent_width: usize,
+    /// Skip building projects whose sources and build arguments hash the same as at their last successful build.
+    pub content_hash_cache: bool,
+    /// Make the project of the opened or saved document the active one.
+    pub auto_select_active_project: bool,
+    /// Backups of the projects file kept before destructive changes; 0 disables them.
+    pub config_backups: usize,
+    /// Build logs kept in the `logs` directory; 0 disables writing them.
+    pub build_logs: usize,
+    /// Directory (e.g. the repository root) that diagnostic file paths are reported relative to
+    /// in lint results, SARIF exports and the diagnostic stream. Published diagnostics keep
+    /// absolute paths since editors need them.
+    pub diagnostic_base_path: Option<String>,
+    /// Formatting and symbol requests running longer are answered with an error instead of
+    /// keeping the client waiting; 0 removes the limit.
+    pub request_timeout_seconds: u64,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            diagnostic_stream: DiagnosticStreamSettings::default(),
+            stop_on_first_failure: false,
+            banner_width: DEFAULT_BANNER_WIDTH,
+            formatter_path: None,
+            indent_width: DEFAULT_INDENT_WIDTH,
+            content_hash_cache: false,
+            auto_select_active_project: false,
+            config_backups: DEFAULT_CONFIG_BACKUPS,
+            build_logs: DEFAULT_BUILD_LOGS,
+            diagnostic_base_path: None,
+            request_timeout_seconds: DEFAULT_REQUEST_TIMEOUT_SECONDS,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn banner_width(&self) -> usize {
+        return self.banner_width.max(MIN_BANNER_WIDTH);
+    }
+
+    pub fn diagnostic_base_path(&self) -> Option<PathBuf> {
+        return self.diagnostic_base_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiagnosticStreamSettings {
+    pub enabled: bool,
+    pub min_severity: DiagnosticKind,
+}
+
+impl Default for DiagnosticStreamSettings {
+    fn default() -> Self {
+        DiagnosticStreamSettings {
+            enabled: false,
+            min_severity: DiagnosticKind::HINT,
+        }
+    }
+}
+
+impl DiagnosticStreamSettings {
+    pub fn accepts(&self, kind: &DiagnosticKind) -> bool {
+        return self.enabled && kind.is_at_least(&self.min_severity);
+    }
+}
+
+/// Typed `initializationOptions` of the `initialize` request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InitializationOptions {
+    pub features: Features,
+    pub log_level: Option<LogLevel>,
+    /// Applied before the first `didChangeConfiguration`.
+    pub settings: Option<ServerSettings>,
+    /// Directory for all of the server's files instead of `ddk` in the user's config directory.
+    pub config_dir: Option<String>,
+    /// Projects file of this session instead of the one in the user's config directory.
+    pub projects_data_path: Option<String>,
+    /// Compilers file of this session instead of the one in the user's config directory.
+    pub compilers_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// Advertise the `ddk.*` commands through `workspace/executeCommand`.
+    pub execute_commands: bool,
+    /// Ask the client to watch `.dproj`/`.groupproj` and the configuration files for us.
+    pub client_file_watchers: bool,
+    /// Serve `textDocument/formatting`; off for clients that format through `custom/document/format`.
+    pub document_formatting: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            execute_commands: true,
+            client_file_watchers: true,
+            document_formatting: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error = 0,
+    Info = 1,
+    Debug = 2,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Debug as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::SeqCst);
+}
+
+pub fn log_enabled(level: LogLevel) -> bool {
+    return level as u8 <= LOG_LEVEL.load(Ordering::SeqCst);
+}
@@ -1,9 +1,11 @@
 use crate::projects::*;
-use crate::utils::{FilePath, Load};
-use anyhow::Result;
+use crate::settings::ServerSettings;
+use crate::utils::{FilePath, Load, deserialize_data, serialize_data};
+use anyhow::{Context, Result};
 use fslock::LockFile;
-use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -15,18 +17,15 @@ pub trait Stateful {
 
     fn initialize() -> Result<()>
     where
-        Self: FilePath + Load + Serialize + Default,
+        Self: FilePath + Load + Serialize + Default + for<'de> Deserialize<'de>,
     {
 
         let _lock = obtain_lock_blocking::<Self>()?;
         if !<Self as FilePath>::get_file_path().exists() {
             let path = <Self as FilePath>::get_file_path();
-            if let Ok(serialized) = ron::ser::to_string_pretty(
-                &Self::default(),
-                PrettyConfig::default()
-                    .struct_names(true)
-                    .escape_strings(false)
-            ) {
+            // after switching the data format, carry over the file kept in the other one
+            let data = other_format_copy::<Self>(path).unwrap_or_default();
+            if let Ok(serialized) = serialize_data(path, &data, true) {
                 Self::mark_state_changed(true);
                 if let Err(e) = std::fs::write(&path, serialized) {
                     Self::mark_state_changed(false);
@@ -45,19 +44,109 @@ pub trait Stateful {
         return <Self as Load>::load_from_file(&path);
     }
 
-    async fn save(&self) -> Result<()>
-        where Self: FilePath + Stateful + Serialize + Sized
+    /// Loads a snapshot of the file under a brief lock, so a concurrent save isn't read
+    /// half-written. Falls back to `T::default()` like `Load::load_from_file` if the file is
+    /// missing, and reads it unlocked if the lock can't be obtained in time.
+    /// Blocks the thread while waiting for the lock; async code uses `read_only_copy`.
+    fn read_only_copy_blocking() -> Self
+    where
+        Self: FilePath + Load + Serialize + Default + for<'de> Deserialize<'de>,
+    {
+        let _lock = obtain_lock_blocking::<Self>().ok();
+        return Self::load();
+    }
+
+    /// `read_only_copy_blocking` for async code. A missing file is read as `T::default()`,
+    /// but one that can't be read or parsed is an error rather than silently replaced.
+    async fn read_only_copy() -> Result<Self>
+    where
+        Self: FilePath + Default + Sized + for<'de> Deserialize<'de>,
+    {
+        let path = <Self as FilePath>::get_file_path();
+        let _lock = obtain_lock::<Self>().await.ok();
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        return deserialize_data(path, &data).with_context(|| format!("Failed to parse {:?}", path));
+    }
+
+    /// Brings data read from the file into the shape it is kept in memory.
+    fn normalize(data: Self) -> Self
+        where Self: Sized
+    {
+        return data;
+    }
+
+    async fn lock_file() -> Result<FileLock<Self>>
+        where Self: FilePath + Send + Sized
     {
+        return Ok(FileLock {
+            _lock: obtain_lock::<Self>().await?,
+            _data: PhantomData,
+        });
+    }
+
+    /// Takes the file lock for a read-modify-write, first catching up with anything another
+    /// process saved since the data was loaded. Save with `save_locked` before dropping it.
+    async fn lock_latest(&mut self) -> Result<FileLock<Self>>
+        where Self: FilePath + PartialEq + Send + Sized + for<'de> Deserialize<'de>
+    {
+        let lock = Self::lock_file().await?;
         let path = <Self as FilePath>::get_file_path();
-        let _lock = obtain_lock::<Self>().await?;
-        if let Ok(serialized) = ron::to_string(&self) {
-            Self::mark_state_changed(true);
-            if let Err(e) = std::fs::write(&path, serialized) {
-                Self::mark_state_changed(false);
-                eprintln!("Failed to save state to {:?}: {}", path, e);
+        // an unreadable file has nothing to catch up with; the save replaces it
+        if let Ok(data) = std::fs::read_to_string(path)
+            && let Ok(on_disk) = deserialize_data::<Self>(path, &data)
+        {
+            let on_disk = Self::normalize(on_disk);
+            if *self != on_disk {
+                *self = on_disk;
             }
         }
-        Ok(())
+        return Ok(lock);
+    }
+
+    async fn save(&self) -> Result<()>
+        where Self: FilePath + Stateful + Serialize + Send + Sync + Sized + for<'de> Deserialize<'de>
+    {
+        let lock = Self::lock_file().await?;
+        return self.save_locked(&lock);
+    }
+
+    /// Writes to a temporary file next to the data file and renames it into place, so a crash
+    /// mid-write can't leave a truncated file. The replaced version is kept as `<file>.bak`
+    /// if it still parses.
+    fn save_locked(&self, lock: &FileLock<Self>) -> Result<()>
+        where Self: FilePath + Stateful + Serialize + Sized + for<'de> Deserialize<'de>
+    {
+        return self.prepare_save(lock)?.finish();
+    }
+
+    /// The first half of `save_locked`: writes the temporary file but leaves the data file
+    /// alone until `PendingSave::finish`, so saves of several files can all be prepared
+    /// before any of them is replaced.
+    fn prepare_save<'a>(&self, _lock: &'a FileLock<Self>) -> Result<PendingSave<'a, Self>>
+        where Self: FilePath + Serialize + Sized
+    {
+        let path = <Self as FilePath>::get_file_path();
+        let serialized = serialize_data(path, &self, false)?;
+        let temp_path = temp_file_path(path);
+        std::fs::write(&temp_path, serialized)
+            .with_context(|| format!("Failed to save state to {:?}", path))?;
+        return Ok(PendingSave { path, temp_path, _lock });
+    }
+
+    /// The version kept by the last save, to be put back in place of the current data.
+    fn load_backup() -> Result<Self>
+        where Self: FilePath + Sized + for<'de> Deserialize<'de>
+    {
+        let path = <Self as FilePath>::get_file_path();
+        let backup = backup_file_path(path);
+        let data = std::fs::read_to_string(&backup)
+            .with_context(|| format!("No backup to restore at {:?}", backup))?;
+        return deserialize_data(path, &data)
+            .with_context(|| format!("Backup at {:?} can't be read", backup));
     }
 
     fn mark_state_changed(changed: bool) {
@@ -65,6 +154,40 @@ pub trait Stateful {
     }
 }
 
+/// The cross-process lock on a data file, so that another server process sharing the file
+/// can't save between this one reading and writing it. Released on drop.
+pub struct FileLock<T> {
+    _lock: LockFile,
+    _data: PhantomData<fn() -> T>,
+}
+
+/// A save written to its temporary file by `Stateful::prepare_save`. Dropping it without
+/// calling `finish` discards the temporary file.
+pub struct PendingSave<'a, T> {
+    path: &'static Path,
+    temp_path: PathBuf,
+    _lock: &'a FileLock<T>,
+}
+
+impl<T: Stateful + for<'de> Deserialize<'de>> PendingSave<'_, T> {
+    /// Puts the prepared file in place of the data file.
+    pub fn finish(self) -> Result<()> {
+        T::mark_state_changed(true);
+        if let Err(e) = replace_with_temp_file::<T>(self.path, &self.temp_path) {
+            T::mark_state_changed(false);
+            return Err(e).with_context(|| format!("Failed to save state to {:?}", self.path));
+        }
+        return Ok(());
+    }
+}
+
+impl<T> Drop for PendingSave<'_, T> {
+    fn drop(&mut self) {
+        // already renamed away once finished
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
+
 pub static PROJECTS_DATA_CHANGED: AtomicBool = AtomicBool::new(false);
 pub static COMPILER_CONFIGURATIONS_CHANGED: AtomicBool = AtomicBool::new(false);
 
@@ -78,6 +201,64 @@ lazy_static::lazy_static! {
         CompilerConfigurations::initialize().expect("Failed to initialize compiler configurations");
         Arc::new(RwLock::new(CompilerConfigurations::new()))
     };
+
+    pub static ref SERVER_SETTINGS: Arc<RwLock<ServerSettings>> = Arc::new(RwLock::new(ServerSettings::default()));
+}
+
+/// The data of `path` saved in the other format (`.ron` for `.json` and vice versa), if any.
+fn other_format_copy<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let other = path.with_extension(if is_json { "ron" } else { "json" });
+    let data = std::fs::read_to_string(&other).ok()?;
+    return deserialize_data(&other, &data).ok();
+}
+
+fn backup_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    return path.with_file_name(file_name);
+}
+
+fn temp_file_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    return path.with_file_name(temp_name);
+}
+
+/// Writes `content` to `path` through a temporary file, keeping the replaced version as
+/// `<file>.bak` if it still parses as `T`.
+pub fn write_replacing<T: for<'de> Deserialize<'de>>(path: &Path, content: &str) -> Result<()> {
+    let temp_path = temp_file_path(path);
+    std::fs::write(&temp_path, content)?;
+    return replace_with_temp_file::<T>(path, &temp_path);
+}
+
+fn replace_with_temp_file<T: for<'de> Deserialize<'de>>(path: &Path, temp_path: &Path) -> Result<()> {
+    // a file that doesn't parse would otherwise replace the last good backup
+    if let Ok(previous) = std::fs::read_to_string(path)
+        && deserialize_data::<T>(path, &previous).is_ok()
+    {
+        std::fs::copy(path, backup_file_path(path))?;
+    }
+    std::fs::rename(temp_path, path)?;
+    return Ok(());
+}
+
+/// Locks `<data file>.lock` rather than the data file itself: on Windows a locked file can't
+/// be written, not even by the process holding the lock.
+fn lock_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".lock");
+    return path.with_file_name(file_name);
+}
+
+/// The exclusive lock on `path`, `None` while another process holds it. Released on drop.
+fn try_lock(path: &Path) -> Option<LockFile> {
+    let mut lock = LockFile::open(&lock_file_path(path)).ok()?;
+    return match lock.try_lock() {
+        Ok(true) => Some(lock),
+        _ => None,
+    };
 }
 
 fn obtain_lock_blocking<T: FilePath>() -> Result<LockFile> {
@@ -87,11 +268,11 @@ fn obtain_lock_blocking<T: FilePath>() -> Result<LockFile> {
 
     while tries > 0 {
         tries -= 1;
-        match LockFile::open(path) {
-            Ok(lock) => {
+        match try_lock(path) {
+            Some(lock) => {
                 return Ok(lock);
             }
-            Err(_) => {
+            None => {
                 std::thread::sleep(std::time::Duration::from_millis(50));
             }
         }
@@ -106,14 +287,226 @@ async fn obtain_lock<T: FilePath>() -> Result<LockFile> {
 
     while tries > 0 {
         tries -= 1;
-        match LockFile::open(path) {
-            Ok(lock) => {
+        match try_lock(path) {
+            Some(lock) => {
                 return Ok(lock);
             }
-            Err(_) => {
+            None => {
                 tokio::time::sleep(std::time::Duration::from_millis(50)).await;
             }
         }
     }
     anyhow::bail!("Failed to acquire lock for file {:?}", path);
-}
\ No newline at end of file
+}
+
+/// Global state for tests: the tests touching the data files share one temporary config
+/// directory and take turns.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::settings::InitializationOptions;
+    use crate::utils::{CONFIG_DIRECTORY, config_directory};
+    use std::sync::OnceLock;
+
+    /// Fresh default data in the test config directory; hold the guard for the whole test.
+    pub async fn test_session() -> tokio::sync::MutexGuard<'static, ()> {
+        static DIRECTORY: OnceLock<tempfile::TempDir> = OnceLock::new();
+        static TURN: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+        let guard = TURN.lock().await;
+        let directory = DIRECTORY.get_or_init(|| tempfile::tempdir().expect("Failed to create a test directory"));
+        if CONFIG_DIRECTORY.get().is_none() {
+            // like a client starting an isolated session
+            let session = directory.path().join("session");
+            let options = InitializationOptions {
+                config_dir: Some(directory.path().to_string_lossy().to_string()),
+                projects_data_path: Some(session.join("projects.ron").to_string_lossy().to_string()),
+                compilers_path: Some(session.join("compilers.ron").to_string_lossy().to_string()),
+                ..InitializationOptions::default()
+            };
+            crate::use_data_files(&options).expect("the data files were used before the tests");
+        }
+        assert_eq!(config_directory(), directory.path(), "the config directory was fixed before the tests");
+
+        let mut projects_data = PROJECTS_DATA.write().await;
+        *projects_data = ProjectsData::default();
+        projects_data.save().await.expect("Failed to reset the projects data");
+        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
+        *compilers = CompilerConfigurations::default();
+        compilers.save().await.expect("Failed to reset the compilers");
+        *SERVER_SETTINGS.write().await = ServerSettings::default();
+        return guard;
+    }
+
+    /// A client for code that sends notifications. It is never initialized, so they go nowhere.
+    pub fn test_client() -> tower_lsp::Client {
+        let (service, _socket) = tower_lsp::LspService::new(crate::DelphiLsp::new);
+        return service.inner().client.clone();
+    }
+
+    /// Points compiler `12.0` at `directory` and makes its builds run `script` as `msbuild`, with
+    /// the project file as the first argument. The environment script puts the stub on the `PATH`.
+    #[cfg(not(windows))]
+    pub async fn stub_msbuild(directory: &Path, script: &str) {
+        let bin = directory.join("bin");
+        stub_executable(&bin.join("msbuild"), script);
+        let env_script = directory.join("env.sh");
+        std::fs::write(&env_script, format!("export PATH='{}':\"$PATH\"\n", bin.display())).expect("Failed to write the environment script");
+
+        let mut compilers = COMPILER_CONFIGURATIONS.write().await;
+        let compiler = compilers.get_mut("12.0").expect("compiler 12.0 is a default compiler");
+        compiler.installation_path = directory.to_string_lossy().to_string();
+        compiler.env_script = Some(env_script.to_string_lossy().to_string());
+    }
+
+    /// Makes `script` the formatter, called as `formatter -e utf-8 -config <config> <file>` and
+    /// expected to format `<file>` in place.
+    #[cfg(not(windows))]
+    pub async fn stub_formatter(directory: &Path, script: &str) {
+        let formatter = directory.join("formatter");
+        stub_executable(&formatter, script);
+        SERVER_SETTINGS.write().await.formatter_path = Some(formatter.to_string_lossy().to_string());
+    }
+
+    #[cfg(not(windows))]
+    fn stub_executable(path: &Path, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create the stub directory");
+        }
+        std::fs::write(path, format!("#!/bin/sh\n{script}\n")).expect("Failed to write the stub");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).expect("Failed to make the stub executable");
+    }
+
+    /// A workspace `Main` on compiler `12.0` with a `<name>.dpr` project in `directory` for each
+    /// name, in order. Returns the workspace id and the project ids.
+    pub async fn workspace_with_projects(directory: &Path, names: &[&str]) -> (usize, Vec<usize>) {
+        let mut projects_data = PROJECTS_DATA.write().await;
+        projects_data
+            .new_workspace("Main", "12.0", &*COMPILER_CONFIGURATIONS.read().await)
+            .expect("Failed to add the workspace");
+        let workspace_id = projects_data.workspaces.last().expect("the workspace was added").id;
+        let mut project_ids = Vec::new();
+        for name in names {
+            let dpr = directory.join(format!("{name}.dpr"));
+            std::fs::write(&dpr, format!("program {name};\nbegin\nend.\n")).expect("Failed to write the project");
+            projects_data
+                .new_project(&dpr.to_string_lossy().to_string(), workspace_id)
+                .expect("Failed to add the project");
+            project_ids.push(projects_data.projects.last().expect("the project was added").id);
+        }
+        return (workspace_id, project_ids);
+    }
+
+    /// A client of a server initialized over an in-memory connection, with the notifications
+    /// it sends as they arrive. The server doesn't ask the client to register file watchers.
+    pub async fn connected_client() -> (tower_lsp::Client, tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>) {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let (service, socket) = tower_lsp::LspService::new(crate::DelphiLsp::new);
+        let client = service.inner().client.clone();
+        let (client_end, server_end) = tokio::io::duplex(1 << 16);
+        let (server_input, server_output) = tokio::io::split(server_end);
+        tokio::spawn(tower_lsp::Server::new(server_input, server_output, socket).serve(service));
+
+        let (client_input, mut client_output) = tokio::io::split(client_end);
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "capabilities": {}, "initializationOptions": { "features": { "client_file_watchers": false } } },
+        });
+        let initialized = serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        for message in [initialize, initialized] {
+            let body = message.to_string();
+            let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+            client_output.write_all(frame.as_bytes()).await.expect("Failed to write to the server");
+        }
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (ready, initialized) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let _output = client_output;
+            let mut ready = Some(ready);
+            let mut input = BufReader::new(client_input);
+            loop {
+                let mut length = 0;
+                let mut header = String::new();
+                while input.read_line(&mut header).await.is_ok_and(|read| read > 0) && header != "\r\n" {
+                    if let Some(value) = header.strip_prefix("Content-Length:") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                    header.clear();
+                }
+                let mut body = vec![0; length];
+                if length == 0 || input.read_exact(&mut body).await.is_err() {
+                    return;
+                }
+                let Ok(message) = serde_json::from_slice::<serde_json::Value>(&body) else {
+                    continue;
+                };
+                if message.get("id").is_some() {
+                    // the response to `initialize`
+                    if let Some(ready) = ready.take() {
+                        let _ = ready.send(());
+                    }
+                } else {
+                    let _ = sender.send(message);
+                }
+            }
+        });
+        initialized.await.expect("The server did not answer `initialize`");
+        return (client, receiver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_is_carried_over_to_the_other_format() {
+        let directory = tempfile::tempdir().unwrap();
+        let ron_path = directory.path().join("projects.ron");
+        let mut data = ProjectsData::default();
+        data.group_project_compiler_id = "11.0".to_string();
+        std::fs::write(&ron_path, serialize_data(&ron_path, &data, true).unwrap()).unwrap();
+
+        let json_path = directory.path().join("projects.json");
+        assert_eq!(other_format_copy::<ProjectsData>(&json_path), Some(data));
+        assert_eq!(other_format_copy::<ProjectsData>(&directory.path().join("compilers.json")), None::<ProjectsData>);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers_lose_no_updates() {
+        let _session = testing::test_session().await;
+        let mut data = ProjectsData::default();
+        let lock = data.lock_latest().await.unwrap();
+        data.new_workspace("Main", "12.0", &CompilerConfigurations::default()).unwrap();
+        data.commit(&lock).unwrap();
+        drop(lock);
+        let workspace_id = data.workspaces[0].id;
+
+        // every writer starts from the same data, like separate server processes would
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let mut data = data.clone();
+                tokio::spawn(async move {
+                    for index in 0..5 {
+                        let lock = data.lock_latest().await.unwrap();
+                        data.new_project(&format!("/projects/{writer}-{index}.dpr"), workspace_id).unwrap();
+                        data.commit(&lock).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        let saved = ProjectsData::load_current().await.unwrap();
+        assert_eq!(saved.projects.len(), 40);
+        assert_eq!(saved.workspaces[0].project_links.len(), 40);
+        saved.validate_references().unwrap();
+    }
+}
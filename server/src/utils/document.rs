@@ -1,4 +1,4 @@
-use tower_lsp::lsp_types::Range;
+use tower_lsp::lsp_types::{Position, Range};
 
 
 pub struct Document<'str> {
@@ -11,21 +11,144 @@ impl<'str> Document<'str> {
     }
 
     pub fn range(&self, range: Range) -> &str {
+        let start_offset = self.offset(range.start);
+        let end_offset = self.offset(range.end);
+        // offsets always fall on char boundaries; `get` keeps a slip from turning into a panic
+        return self.content.get(start_offset..end_offset).unwrap_or("");
+    }
+
+    /// Byte offset of `position`; characters are UTF-16 code units as in LSP, clamped to the line.
+    /// A position inside a surrogate pair rounds up to the end of that character, positions past
+    /// the last line map to the end of the document.
+    pub fn offset(&self, position: Position) -> usize {
         let mut offset = 0;
-        let mut start_offset = 0;
-        let mut end_offset = self.content.len();
+        for (i, line) in self.lines().enumerate() {
+            if i == position.line as usize {
+                let text = line.trim_end_matches(['\r', '\n']);
+                let mut units = 0;
+                for (index, ch) in text.char_indices() {
+                    if units >= position.character as usize {
+                        return offset + index;
+                    }
+                    units += ch.len_utf16();
+                }
+                return offset + text.len();
+            }
+            offset += line.len();
+        }
+        return self.content.len();
+    }
 
-        for (i, line) in self.content.lines().enumerate() {
-            if i == range.start.line as usize {
-                start_offset = offset + range.start.character as usize;
+    /// Position just past the last character of the document.
+    pub fn end_position(&self) -> Position {
+        let mut line = 0;
+        let mut last_line = "";
+        for text in self.lines() {
+            if text.ends_with(['\r', '\n']) {
+                line += 1;
+                last_line = "";
+            } else {
+                last_line = text;
             }
-            if i == range.end.line as usize {
-                end_offset = offset + range.end.character as usize;
-                break;
+        }
+        Position::new(line as u32, last_line.encode_utf16().count() as u32)
+    }
+
+    /// The lines of the document including their terminators; like LSP, `\r\n`, `\n` and a
+    /// lone `\r` all end a line, so mixed line endings number lines the same way the client does.
+    fn lines(&self) -> impl Iterator<Item = &'str str> {
+        let content = self.content;
+        let mut start = 0;
+        return std::iter::from_fn(move || {
+            if start >= content.len() {
+                return None;
             }
-            offset += line.len() + 1; // +1 for '\n'
+            let rest = &content[start..];
+            let end = match rest.find(['\r', '\n']) {
+                Some(index) if rest[index..].starts_with("\r\n") => index + 2,
+                Some(index) => index + 1,
+                None => rest.len(),
+            };
+            start += end;
+            return Some(&rest[..end]);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        return Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1));
+    }
+
+    #[test]
+    fn a_range_may_start_mid_line() {
+        let document = Document::new("begin\n  x := 1;\nend.\n");
+        assert_eq!(document.range(range((1, 2), (2, 0))), "x := 1;\n");
+    }
+
+    #[test]
+    fn the_last_line_needs_no_line_break() {
+        let document = Document::new("begin\nend.");
+        assert_eq!(document.range(range((1, 0), (1, 4))), "end.");
+        assert_eq!(document.range(range((1, 0), (5, 0))), "end.");
+        assert_eq!(document.end_position(), Position::new(1, 4));
+    }
+
+    #[test]
+    fn crlf_lines_do_not_shift_the_slice() {
+        let document = Document::new("unit A;\r\n\r\nbegin\r\n  x := 1;\r\nend.\r\n");
+        assert_eq!(document.range(range((3, 2), (3, 9))), "x := 1;");
+        assert_eq!(document.range(range((2, 0), (4, 0))), "begin\r\n  x := 1;\r\n");
+        // past the end of a line stops before its terminator
+        assert_eq!(document.range(range((3, 0), (3, 40))), "  x := 1;");
+        assert_eq!(document.end_position(), Position::new(5, 0));
+    }
+
+    #[test]
+    fn lone_carriage_returns_end_lines_too() {
+        let document = Document::new("a\rbb\r\nccc\nd");
+        assert_eq!(document.range(range((1, 0), (1, 2))), "bb");
+        assert_eq!(document.range(range((2, 1), (3, 1))), "cc\nd");
+        assert_eq!(document.range(range((0, 0), (2, 0))), "a\rbb\r\n");
+        assert_eq!(document.end_position(), Position::new(3, 1));
+    }
+
+    #[test]
+    fn characters_are_utf16_code_units() {
+        // 'é' is 2 bytes and 1 unit, '😀' is 4 bytes and 2 units
+        let document = Document::new("// café 😀 ok\nx := 'ü';\n");
+        assert_eq!(document.range(range((0, 3), (0, 7))), "café");
+        assert_eq!(document.range(range((0, 8), (0, 10))), "😀");
+        assert_eq!(document.range(range((0, 11), (0, 13))), "ok");
+        assert_eq!(document.range(range((1, 6), (1, 7))), "ü");
+    }
+
+    #[test]
+    fn positions_never_split_a_character() {
+        let document = Document::new("😀é\n");
+        // inside the surrogate pair: rounds up to the end of the emoji
+        assert_eq!(document.range(range((0, 1), (0, 3))), "é");
+        // past the end of the line: clamped to it
+        assert_eq!(document.range(range((0, 0), (0, 40))), "😀é");
+    }
+
+    #[test]
+    fn blank_documents_have_no_text_beyond_their_content() {
+        for content in ["", "\n", " \t\r\n  "] {
+            let document = Document::new(content);
+            assert_eq!(document.range(range((0, 0), (9, 9))), content);
+            assert_eq!(document.range(range((3, 0), (9, 9))), "");
         }
+        assert_eq!(Document::new("").end_position(), Position::new(0, 0));
+        assert_eq!(Document::new("\n").end_position(), Position::new(1, 0));
+    }
 
-        &self.content[start_offset..end_offset]
+    #[test]
+    fn a_zero_width_range_is_empty() {
+        let document = Document::new("begin\nend.\n");
+        assert_eq!(document.range(range((1, 2), (1, 2))), "");
     }
-}
\ No newline at end of file
+}
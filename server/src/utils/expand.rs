@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+/// Expands `$(Name)` macros in `value`, first from `variables` and then from the
+/// environment. Unknown macros are left untouched.
+pub fn expand_macros(value: &str, variables: &HashMap<&str, String>) -> String {
+    return expand(value, variables, true);
+}
+
+/// Expands `$(Name)` macros in `value` from `variables` only, leaving every other macro
+/// untouched (e.g. for msbuild to resolve).
+pub fn expand_variables(value: &str, variables: &HashMap<&str, String>) -> String {
+    return expand(value, variables, false);
+}
+
+fn expand(value: &str, variables: &HashMap<&str, String>, environment: bool) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("$(") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find(')') else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
+        let name = &after[..end];
+        let replacement = variables
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .or_else(|| environment.then(|| std::env::var(name).ok()).flatten());
+        match replacement {
+            Some(replacement) => result.push_str(&replacement),
+            None => result.push_str(&rest[start..start + 2 + end + 1]),
+        }
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<&'static str, String> {
+        return HashMap::from([("ProjectName", "App".to_string()), ("Platform", "Win64".to_string())]);
+    }
+
+    #[test]
+    fn known_macros_expand_whatever_their_case() {
+        assert_eq!(expand_variables(r"/p:DCC_ExeOutput=$(ProjectName)\$(platform)", &variables()), r"/p:DCC_ExeOutput=App\Win64");
+        assert_eq!(expand_variables("$(PROJECTNAME)$(ProjectName)", &variables()), "AppApp");
+    }
+
+    #[test]
+    fn unknown_and_unterminated_macros_stay_literal() {
+        assert_eq!(expand_variables("$(Config)/$(ProjectName)", &variables()), "$(Config)/App");
+        assert_eq!(expand_variables("$(ProjectName)/$(Platform", &variables()), "App/$(Platform");
+        assert_eq!(expand_variables("$$(ProjectName)$", &variables()), "$App$");
+    }
+
+    #[test]
+    fn only_macro_expansion_falls_back_to_the_environment() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_macros("$(PATH)", &variables()), path);
+        assert_eq!(expand_variables("$(PATH)", &variables()), "$(PATH)");
+    }
+}
@@ -0,0 +1,70 @@
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Fuzzy,
+    Substring,
+    Exact,
+}
+
+/// Scores `candidate` against `query`; higher is better, `None` means no match.
+/// All modes ignore case.
+pub fn match_score(matcher: &SkimMatcherV2, query: &str, candidate: &str, mode: MatchMode) -> Option<i64> {
+    match mode {
+        MatchMode::Fuzzy => return matcher.fuzzy_match(candidate, query),
+        MatchMode::Substring => {
+            let position = candidate.to_lowercase().find(&query.to_lowercase())?;
+            // earlier matches rank higher
+            return Some(-(position as i64));
+        }
+        MatchMode::Exact => return (candidate.to_lowercase() == query.to_lowercase()).then_some(0),
+    }
+}
+
+/// Filters `items` by `query` and sorts them by descending score.
+/// Items with equal scores keep their original order.
+pub fn rank_matches<T>(
+    query: &str,
+    items: impl IntoIterator<Item = T>,
+    key: impl Fn(&T) -> &str,
+    mode: MatchMode,
+) -> Vec<(T, i64)> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut matches: Vec<(T, i64)> = items
+        .into_iter()
+        .filter_map(|item| {
+            let score = match_score(&matcher, query, key(&item), mode)?;
+            Some((item, score))
+        })
+        .collect();
+    matches.sort_by(|(_, a), (_, b)| b.cmp(a));
+    return matches;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abbreviations_rank_the_closer_name_first() {
+        let names = ["UserValidator", "UserService", "OrderService"];
+        let ranked: Vec<&str> = rank_matches("usvc", names, |name| name, MatchMode::Fuzzy)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(ranked.first(), Some(&"UserService"));
+        assert!(!ranked.contains(&"OrderService"));
+    }
+
+    #[test]
+    fn all_modes_fold_case_alike() {
+        let matcher = SkimMatcherV2::default().ignore_case();
+        for mode in [MatchMode::Fuzzy, MatchMode::Substring, MatchMode::Exact] {
+            assert!(match_score(&matcher, "ÄNDERUNG", "Änderung", mode).is_some(), "{mode:?}");
+        }
+    }
+}
@@ -1,8 +1,14 @@
 use serde::{Serialize, Deserialize};
-use std::path::PathBuf;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 mod document;
+mod expand;
+mod fuzzy;
 pub use document::*;
+pub use expand::*;
+pub use fuzzy::*;
 
 #[macro_export]
 macro_rules! defer_async {
@@ -15,6 +21,19 @@ macro_rules! defer_async {
     };
 }
 
+/// Set from the `initialize` request to keep all of the server's files somewhere other than
+/// the user's config directory.
+pub static CONFIG_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// The directory the server keeps its files in, `ddk` in the user's config directory by default.
+pub fn config_directory() -> &'static PathBuf {
+    return CONFIG_DIRECTORY.get_or_init(|| {
+        dirs::config_dir()
+            .expect("Could not determine config directory")
+            .join("ddk")
+    });
+}
+
 pub trait FilePath {
     fn get_file_path() -> &'static PathBuf;
 }
@@ -25,10 +44,64 @@ pub trait Load {
         Self: Serialize + Default + for<'de> Deserialize<'de>,
     {
         if let Ok(data) = std::fs::read_to_string(path) {
-            if let Ok(obj) = ron::from_str(&data) {
+            if let Ok(obj) = deserialize_data(path, &data) {
                 return obj;
             }
         }
         return Self::default();
     }
-}
\ No newline at end of file
+}
+
+/// `path` in a form that compares equal for the same file: canonicalized when it exists,
+/// and lowercased on Windows, whose file systems are case-insensitive.
+pub fn comparable_path(path: &Path) -> PathBuf {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if cfg!(windows) {
+        return PathBuf::from(path.to_string_lossy().to_lowercase());
+    }
+    return path;
+}
+
+/// Extension of the projects and compilers files: `json` when the `DDK_DATA_FORMAT`
+/// environment variable is `json`, `ron` otherwise.
+pub fn data_file_extension() -> &'static str {
+    lazy_static::lazy_static! {
+        static ref JSON: bool = std::env::var("DDK_DATA_FORMAT")
+            .is_ok_and(|format| format.trim().eq_ignore_ascii_case("json"));
+    }
+    return if *JSON { "json" } else { "ron" };
+}
+
+fn is_json(path: &Path) -> bool {
+    return path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+}
+
+/// Parses `data` as JSON or RON, depending on the extension of `path`.
+pub fn deserialize_data<T: for<'de> Deserialize<'de>>(path: &Path, data: &str) -> Result<T> {
+    if is_json(path) {
+        return Ok(serde_json::from_str(data)?);
+    }
+    return Ok(ron::from_str(data)?);
+}
+
+/// Serializes `value` as JSON or RON, depending on the extension of `path`.
+pub fn serialize_data<T: Serialize>(path: &Path, value: &T, pretty: bool) -> Result<String> {
+    if is_json(path) {
+        return Ok(match pretty {
+            true => serde_json::to_string_pretty(value)?,
+            false => serde_json::to_string(value)?,
+        });
+    }
+    return Ok(match pretty {
+        true => ron::ser::to_string_pretty(
+            value,
+            ron::ser::PrettyConfig::default()
+                .struct_names(true)
+                .escape_strings(false),
+        )?,
+        false => ron::to_string(value)?,
+    });
+}
@@ -1,7 +1,7 @@
 import {
     LanguageClient, LanguageClientOptions, ServerOptions, TransportKind
 } from 'vscode-languageclient/node';
-import { Disposable, DocumentFormattingEditProvider, DocumentRangeFormattingEditProvider, languages, Range, TextDocument, TextEdit, window, workspace } from 'vscode';
+import { window, workspace } from 'vscode';
 import { Runtime } from './runtime';
 import { Entities } from './projects/entities';
 import { UUID } from 'crypto';
@@ -10,24 +10,33 @@ export type Change =
     | { type: 'NewProject', file_path: string, workspace_id: number }
     | { type: 'AddProject', project_id: number, workspace_id: number }
     | { type: 'RemoveProject', project_link_id: number }
+    | { type: 'RemoveOrphanedLinks' }
     | { type: 'MoveProject', project_link_id: number, drop_target: number }
     | { type: 'RefreshProject', project_id: number }
     | { type: 'UpdateProject', project_id: number, data: Partial<Entities.Project> }
     | { type: 'SelectProject', project_id: number }
+    | { type: 'SetTestProject', project_id: number, test_project_id: number | null }
+    | { type: 'SetCleanSettings', project_id: number, settings: Entities.CleanSettings | null }
     | { type: 'AddWorkspace', name: string, compiler: string }
     | { type: 'RemoveWorkspace', workspace_id: number }
+    | { type: 'DuplicateWorkspace', workspace_id: number, new_name: string }
     | { type: 'MoveWorkspace', workspace_id: number, drop_target: number }
-    | { type: 'UpdateWorkspace', workspace_id: number, data: { name?: string; compiler?: string; } }
+    | { type: 'UpdateWorkspace', workspace_id: number, data: { name?: string; compiler?: string; default_platform?: string; default_config?: string; } }
     | { type: 'AddCompiler', key: string, config: Entities.CompilerConfiguration }
     | { type: 'RemoveCompiler', compiler: string }
     | { type: 'UpdateCompiler', key: string, data: Partial<Entities.CompilerConfiguration> }
+    | { type: 'DetectCompilers' }
     | { type: 'SetGroupProject', groupproj_path: string }
     | { type: 'RemoveGroupProject' }
-    | { type: 'SetGroupProjectCompiler', compiler: string };
+    | { type: 'SetGroupProjectCompiler', compiler: string }
+    | { type: 'RestoreProjectsBackup' }
+    | { type: 'RestoreCompilersBackup' };
 
 
 export interface Changes {
     changes: Change[];
+    /** Defaults to true: all changes are saved together or not at all. */
+    atomic?: boolean;
 }
 
 export interface ChangeSet {
@@ -40,23 +49,66 @@ export function newChanges(changes: Change[], timeout: number = 5000): ChangeSet
     return { changeSet: { changes: changes }, event_id: id };
 }
 
+export interface CompilerLineDiagnostic {
+    time: string,
+    file: string,
+    line: number,
+    column?: number,
+    message: string,
+    code: string,
+    kind: 'ERROR' | 'WARN' | 'HINT',
+    compiler_name: string,
+}
+
+/** Optional build settings sent along with a compile request. */
+export interface CompileOptions {
+    /** MSBuild configuration, e.g. `Debug` or `Release`. */
+    config?: string;
+    /** Target platform: `Win32`, `Win64`, `Linux64`, `OSX64`, `Android` or `iOSDevice64`. */
+    platform?: string;
+    /** MSBuild targets replacing the default `Clean,Build` / `Clean,Make`, e.g. `['Clean', 'Build', 'Deploy']`. */
+    targets?: string[];
+    /** Stop a multi-project build at its first failing project; defaults to the server setting. */
+    stop_on_error?: boolean;
+}
+
+export interface ProjectResult {
+    project_id: number,
+    name: string,
+    success: boolean,
+    code: number,
+    errors: number,
+    warnings: number,
+    hints: number,
+    duration_ms: number,
+    failed_units?: string[],
+    binary_log?: string,
+}
+
 export type CompilerProgressParams = {
+    type: 'Queued',
+    position: number,
+} | {
     type: 'Start',
     lines: string[],
 } | {
     type: 'Stdout' | 'Stderr',
     line: string,
+    diagnostic?: CompilerLineDiagnostic,
 } | {
     type: 'Completed',
     success: boolean,
     code: number,
     lines: string[],
+    projects?: ProjectResult[],
+    binary_logs?: string[],
 } | {
     type: 'SingleProjectCompleted',
     project_id: number,
     success: boolean,
     code: number,
     lines: string[],
+    failed_units?: string[],
 } | never;
 
 interface ConfigurationData {
@@ -73,8 +125,10 @@ export class DDK_Client {
             run: { command: serverPath, transport: TransportKind.stdio },
             debug: { command: serverPath, transport: TransportKind.stdio }
         };
-        const clientOptions: LanguageClientOptions = {};
-        // we can't set the documentSelector until we implement the actual LSP
+        const clientOptions: LanguageClientOptions = {
+            // open/save notifications let the server follow the active project
+            documentSelector: [{ scheme: 'file', pattern: '**/*.{dpr,dpk,pas,inc,dproj}' }],
+        };
         clientOptions.outputChannelName = 'DDK Server';
         this.client = new LanguageClient(
             'ddk_server',
@@ -117,7 +171,6 @@ export class DDK_Client {
         );
         await this.client.start();
         await this.refresh();
-        Runtime.extension.subscriptions.push(...this.createFormattingProvider());
     }
 
     public async refresh(): Promise<void> {
@@ -130,25 +183,6 @@ export class DDK_Client {
         }
     }
 
-    private createFormattingProvider(): Disposable[] {
-        return [
-            languages.registerDocumentFormattingEditProvider(
-                {
-                    scheme: 'file',
-                    pattern: '**/*.{dpr,dpk,pas,inc}',
-                },
-                new DelphiFormattingProvider(this.client)
-            ),
-            languages.registerDocumentRangeFormattingEditProvider(
-                {
-                    scheme: 'file',
-                    pattern: '**/*.{dpr,dpk,pas,inc}',
-                },
-                new DelphiFormattingProvider(this.client)
-            )
-        ];
-    }
-
     public async projectsDataOverride(data: Entities.ProjectsData): Promise<boolean> {
         const id = Runtime.addEvent();
         Runtime.projectsData = data;
@@ -181,10 +215,11 @@ export class DDK_Client {
         return await Runtime.waitForEvent(changes.event_id);
     }
 
-    public async compileProject(rebuild: boolean, projectId: number, projectLinkId?: number): Promise<boolean> {
+    public async compileProject(rebuild: boolean, projectId: number, projectLinkId?: number, options: CompileOptions = {}): Promise<boolean> {
         const event = Runtime.addEvent();
         await this.client.sendRequest('projects/compile', {
             type: 'Project',
+            ...options,
             project_id: projectId,
             project_link_id: projectLinkId,
             rebuild: rebuild,
@@ -193,10 +228,11 @@ export class DDK_Client {
         return await Runtime.waitForEvent(event);
     }
 
-    public async compileAllInWorkspace(rebuild: boolean, workspaceId: number): Promise<boolean> {
+    public async compileAllInWorkspace(rebuild: boolean, workspaceId: number, options: CompileOptions = {}): Promise<boolean> {
         const event = Runtime.addEvent();
         await this.client.sendRequest('projects/compile', {
             type: 'AllInWorkspace',
+            ...options,
             workspace_id: workspaceId,
             rebuild: rebuild,
             event_id: event,
@@ -204,20 +240,22 @@ export class DDK_Client {
         return await Runtime.waitForEvent(event);
     }
 
-    public async compileAllInGroupProject(rebuild: boolean): Promise<boolean> {
+    public async compileAllInGroupProject(rebuild: boolean, options: CompileOptions = {}): Promise<boolean> {
         const event = Runtime.addEvent();
         await this.client.sendRequest('projects/compile', {
             type: 'AllInGroupProject',
+            ...options,
             rebuild: rebuild,
             event_id: event,
         });
         return await Runtime.waitForEvent(event);
     }
 
-    public async compileFromLink(rebuild: boolean, linkId: number): Promise<boolean> {
+    public async compileFromLink(rebuild: boolean, linkId: number, options: CompileOptions = {}): Promise<boolean> {
         const event = Runtime.addEvent();
         await this.client.sendRequest('projects/compile', {
             type: 'FromLink',
+            ...options,
             link_id: linkId,
             rebuild: rebuild,
             event_id: event
@@ -225,8 +263,23 @@ export class DDK_Client {
         return await Runtime.waitForEvent(event);
     }
 
+    public async compileSelection(rebuild: boolean, linkIds: number[], options: CompileOptions = {}): Promise<boolean> {
+        const event = Runtime.addEvent();
+        await this.client.sendRequest('projects/compile', {
+            type: 'Selection',
+            ...options,
+            project_link_ids: linkIds,
+            rebuild: rebuild,
+            event_id: event
+        });
+        return await Runtime.waitForEvent(event);
+    }
+
     public async onCompilerProgress(params: CompilerProgressParams): Promise<void> {
         switch (params.type) {
+            case 'Queued':
+                window.showInformationMessage(`Compilation queued behind ${params.position} other compilation(s).`);
+                break;
             case 'Start':
                 await workspace.getConfiguration('output.smartScroll').update('enabled', false);
                 Runtime.compilerOutputChannel.clear();
@@ -257,38 +310,4 @@ export class DDK_Client {
                 break;
         }
     }
-}
-
-class DelphiFormattingProvider implements DocumentFormattingEditProvider, DocumentRangeFormattingEditProvider {
-    constructor(private readonly client: LanguageClient) { }
-
-    async provideDocumentRangeFormattingEdits(
-        document: TextDocument,
-        range: Range,
-    ): Promise<TextEdit[]> {
-        return [
-            await this.client.sendRequest('custom/document/format', {
-                content: document.getText(range),
-                range: range,
-            }) as TextEdit
-        ];
-    }
-
-    async provideDocumentFormattingEdits(
-        document: TextDocument,
-    ): Promise<TextEdit[]> {
-        const content = document.getText();
-        const range = new Range(
-            document.positionAt(0),
-            document.positionAt(content.length)
-        );
-        const textEdit: TextEdit =
-            await this.client.sendRequest('custom/document/format', {
-                content: content,
-                range: range
-            });
-        return [
-            new TextEdit(range, textEdit.newText)
-        ];
-    }
 }
\ No newline at end of file
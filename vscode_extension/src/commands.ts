@@ -44,7 +44,8 @@ export class GeneralCommands {
 
   private static async importConfigurationV2_0(data: ExtensionDataExport.FileContent): Promise<void> {
     await Runtime.client.compilersOverride(data.compilers);
-    await Runtime.client.projectsDataOverride(data.projectsData);
+    // an import replaces whatever is there, so it is based on the current version
+    await Runtime.client.projectsDataOverride({ ...data.projectsData, version: Runtime.projectsData?.version });
   }
 
   private static async importConfiguration(): Promise<void> {
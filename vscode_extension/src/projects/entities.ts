@@ -8,6 +8,7 @@ export namespace Entities {
     group_project?: Option<GroupProject>;
     active_project_id?: Option<number>;
     group_project_compiler_id?: Option<string>;
+    version?: number;
   }
 
   export class Workspace {
@@ -16,6 +17,8 @@ export namespace Entities {
     compiler_id: string;
     project_links: ProjectLink[];
     sort_rank: string;
+    default_platform?: Option<string>;
+    default_config?: Option<string>;
   }
 
   export class GroupProject {
@@ -27,12 +30,17 @@ export namespace Entities {
   export class Project {
     id: number;
     name: string;
+    name_customized?: boolean;
     directory: string;
     dproj?: Option<string>;
     dpr?: Option<string>;
     dpk?: Option<string>;
     exe?: Option<string>;
     ini?: Option<string>;
+    output_dir_override?: Option<string>;
+    test_project_id?: Option<number>;
+    project_guid?: Option<string>;
+    clean?: Option<CleanSettings>;
 
     public get links(): ProjectLink[] {
       const workspaceLinks = Runtime.projectsData?.workspaces
@@ -45,6 +53,11 @@ export namespace Entities {
     }
   }
 
+  export class CleanSettings {
+    extensions: string[];
+    folders: string[];
+  }
+
   export class ProjectLink {
     id: number;
     project_id: number;
@@ -59,6 +72,12 @@ export namespace Entities {
     compiler_version: number;
     installation_path: string;
     build_arguments: string[];
+    max_parallelism?: number;
+    env_script?: string;
+    timeout_seconds?: number;
+    cache_environment?: boolean;
+    diagnostic_regex?: string;
+    binary_log?: boolean;
   }
 
   export type CompilerConfigurations = {